@@ -6,10 +6,18 @@
 // [dev-dependencies]
 // criterion = {version = "0.3", features=['html_reports']}
 
-use criterion::{criterion_group, criterion_main, Bencher, Criterion};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use omnicolor_rust::palettes::UniformPalette;
-use omnicolor_rust::GrowthImageBuilder;
+use criterion::{
+    black_box, criterion_group, criterion_main, Bencher, Criterion,
+};
+
+use omnicolor_rust::palettes::{Palette, SphericalPalette, UniformPalette};
+use omnicolor_rust::{
+    GrowthImageBuilder, KDTreeOptions, RectangularArray, SplitStrategy,
+    Topology, RGB,
+};
 
 fn generate_flat_image(b: &mut Bencher) {
     let mut builder = GrowthImageBuilder::new();
@@ -37,5 +45,114 @@ fn bench_flat_image(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, bench_flat_image);
+fn generate_with_kdtree_options(
+    b: &mut Bencher,
+    palette: impl Palette + 'static,
+    options: KDTreeOptions,
+) {
+    let mut builder = GrowthImageBuilder::new();
+    builder
+        .add_layer(512, 512)
+        .epsilon(5.0)
+        .seed(0)
+        .kdtree_options(options)
+        .palette(palette);
+
+    b.iter(|| {
+        let mut image = builder.build().unwrap();
+        image.fill_until_done();
+    });
+}
+
+fn bench_kdtree_options(c: &mut Criterion) {
+    let mut group = c.benchmark_group("KD-tree");
+    group
+        .noise_threshold(0.07)
+        .sample_size(10)
+        .sampling_mode(criterion::SamplingMode::Flat);
+
+    let strategies = vec![
+        ("cycle", SplitStrategy::CycleDimensions),
+        ("max-spread", SplitStrategy::MaxSpread),
+        ("surface-area", SplitStrategy::SurfaceArea),
+    ];
+    let leaf_sizes = vec![10, 50, 200];
+
+    strategies
+        .iter()
+        .for_each(|&(strategy_name, split_strategy)| {
+            leaf_sizes.iter().for_each(|&max_leaf_size| {
+                let options = KDTreeOptions {
+                    max_leaf_size,
+                    split_strategy,
+                };
+                let name = format!("{}-leaf{}", strategy_name, max_leaf_size);
+
+                group.bench_function(format!("uniform-{}", name), |b| {
+                    generate_with_kdtree_options(b, UniformPalette, options)
+                });
+                group.bench_function(format!("spherical-{}", name), |b| {
+                    generate_with_kdtree_options(
+                        b,
+                        SphericalPalette {
+                            central_color: RGB {
+                                vals: [200, 60, 40],
+                            },
+                            color_radius: 40.0,
+                        },
+                        options,
+                    )
+                });
+            });
+        });
+
+    group.finish();
+}
+
+// Look up the location of every index, then the index of every
+// location, as is done when seeding and starting stages.
+fn lookup_all_locations(b: &mut Bencher, num_layers: usize, table: bool) {
+    let layers = (0..num_layers)
+        .map(|_| RectangularArray {
+            width: 256,
+            height: 256,
+        })
+        .collect();
+    let mut topology = Topology::new(layers, Arc::new(HashMap::new()));
+    if table {
+        topology.build_location_table();
+    }
+
+    b.iter(|| {
+        (0..topology.len())
+            .flat_map(|index| topology.get_loc(black_box(index)))
+            .flat_map(|loc| topology.get_index(loc))
+            .sum::<usize>()
+    });
+}
+
+fn bench_topology(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Topology");
+    group.noise_threshold(0.07).sample_size(20);
+
+    vec![1, 8, 64].into_iter().for_each(|num_layers| {
+        vec![("search", false), ("table", true)]
+            .into_iter()
+            .for_each(|(name, table)| {
+                group.bench_function(
+                    format!("layers{}-{}", num_layers, name),
+                    |b| lookup_all_locations(b, num_layers, table),
+                );
+            });
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_flat_image,
+    bench_kdtree_options,
+    bench_topology
+);
 criterion_main!(benches);
@@ -127,7 +127,7 @@ struct PixelLocInfo {
     anti_intersection_distance: f64,
 }
 
-fn parse_celtic_knot(opt: &Options) -> CelticKnotDetails {
+fn parse_celtic_knot(opt: &Options) -> Result<CelticKnotDetails, Error> {
     // Read the path of the knot from file
     let svg_text =
         std::fs::read_to_string("Celtic-knot-basic-linear.svg").unwrap();
@@ -162,7 +162,7 @@ fn parse_celtic_knot(opt: &Options) -> CelticKnotDetails {
     // Break up the path into subpaths whose start and ends are
     // halfway between intersection points.
     let (subpaths, intersections) =
-        knotpath.divide_between_intersections(&knotpath);
+        knotpath.divide_between_intersections(&knotpath)?;
 
     // Record the points on the path that are furthest from any
     // intersection.
@@ -277,18 +277,18 @@ fn parse_celtic_knot(opt: &Options) -> CelticKnotDetails {
         .map(|info| info.loc)
         .collect::<Vec<_>>();
 
-    CelticKnotDetails {
+    Ok(CelticKnotDetails {
         exterior_points_mainlayer,
         exterior_points_underlayer,
         forbidden_points_outline,
         connected_points,
-    }
+    })
 }
 
 fn main() -> Result<(), Error> {
     let opt = Options::from_args();
 
-    let knot_details = parse_celtic_knot(&opt);
+    let knot_details = parse_celtic_knot(&opt)?;
 
     // Define the builder, with main layer (0) and underlayer (1).
     let mut builder = GrowthImageBuilder::new();
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use structopt::StructOpt;
+
+use omnicolor_rust::palettes::{ClusterMethod, ImagePalette};
+use omnicolor_rust::{Error, GrowthImage, RGB};
+
+// Print how a PNG written by GrowthImage::write() was made, and
+// summarize its pixels.  Only PNG files are supported, since the
+// crate has no checkpoint format.
+#[derive(Debug, StructOpt)]
+struct Options {
+    #[structopt(parse(from_os_str))]
+    filenames: Vec<PathBuf>,
+
+    // Number of hue bands in the color summary.
+    #[structopt(long, default_value = "12")]
+    hue_bins: usize,
+}
+
+// Metadata written by GrowthImageBuilder, in the order to print it.
+const KNOWN_KEYS: [(&str, &str); 6] = [
+    ("Software", "Software"),
+    ("omnicolor:seed", "Seed"),
+    ("omnicolor:epsilon", "Epsilon"),
+    ("omnicolor:enforce_unique_colors", "Unique colors"),
+    ("omnicolor:layers", "Layers"),
+    ("omnicolor:reseed", "Reseeded"),
+];
+
+// Colors with saturation below this are counted as grays, rather
+// than binned by hue.
+const GRAY_SATURATION: f32 = 0.1;
+
+fn print_config(metadata: &HashMap<String, String>) {
+    KNOWN_KEYS.iter().for_each(|(key, label)| {
+        if let Some(value) = metadata.get(*key) {
+            println!("{:<16}{}", format!("{}:", label), value);
+        }
+    });
+
+    if let Some(stages) = metadata.get("omnicolor:stages") {
+        println!("Stages:");
+        stages.lines().for_each(|line| println!("  {}", line));
+    }
+
+    let other = metadata
+        .keys()
+        .filter(|key| {
+            *key != "omnicolor:stages"
+                && KNOWN_KEYS.iter().all(|(known, _)| key != known)
+        })
+        .sorted()
+        .collect::<Vec<_>>();
+    if !other.is_empty() {
+        println!("Other metadata:");
+        other
+            .iter()
+            .for_each(|key| println!("  {}: {}", key, metadata[*key]));
+    }
+}
+
+fn print_pixels(colors: &[RGB], num_pixels: usize, hue_bins: usize) {
+    let percent = if num_pixels == 0 {
+        0.0
+    } else {
+        100.0 * (colors.len() as f64) / (num_pixels as f64)
+    };
+    println!(
+        "{:<16}{} of {} filled ({:.1}%)",
+        "Pixels:",
+        colors.len(),
+        num_pixels,
+        percent
+    );
+
+    let counts = colors.iter().counts();
+    let num_repeated = counts.values().filter(|&&count| count > 1).count();
+    println!(
+        "{:<16}{} distinct, {} used more than once",
+        "Colors:",
+        counts.len(),
+        num_repeated
+    );
+    if colors.is_empty() {
+        return;
+    }
+
+    let mut sums = [0.0f64; 3];
+    colors.iter().for_each(|color| {
+        (0..3).for_each(|c| sums[c] += color.vals[c] as f64);
+    });
+    let mean_channel = |c: usize| (sums[c] / (colors.len() as f64)).round();
+    let mean = RGB {
+        vals: [
+            mean_channel(0) as u8,
+            mean_channel(1) as u8,
+            mean_channel(2) as u8,
+        ],
+    };
+    println!("{:<16}{}", "Mean color:", mean);
+
+    let hsv = colors
+        .iter()
+        .map(|color| color.to_hsv())
+        .collect::<Vec<_>>();
+    let (min_v, max_v) = hsv
+        .iter()
+        .map(|hsv| hsv.v)
+        .fold((1.0f32, 0.0f32), |(min, max), v| (min.min(v), max.max(v)));
+    println!("{:<16}{:.2} to {:.2}", "Brightness:", min_v, max_v);
+
+    let hue_bins = hue_bins.max(1);
+    let bin_width = 360.0 / (hue_bins as f32);
+    let mut bins = vec![0usize; hue_bins];
+    let mut num_gray = 0;
+    hsv.iter().for_each(|hsv| {
+        if hsv.s < GRAY_SATURATION {
+            num_gray += 1;
+        } else {
+            let bin = ((hsv.h / bin_width) as usize).min(hue_bins - 1);
+            bins[bin] += 1;
+        }
+    });
+
+    println!("Hues:");
+    let largest = bins.iter().copied().chain(Some(num_gray)).max().unwrap();
+    let bar = |count: usize| "#".repeat((40 * count + largest - 1) / largest);
+    bins.iter().enumerate().for_each(|(i, &count)| {
+        let start = (i as f32) * bin_width;
+        println!(
+            "  {:>3.0}-{:<3.0} {:>8} {}",
+            start,
+            start + bin_width,
+            count,
+            bar(count)
+        );
+    });
+    println!("  {:<7} {:>8} {}", "gray", num_gray, bar(num_gray));
+}
+
+fn inspect(filename: &Path, hue_bins: usize) -> Result<(), Error> {
+    let metadata = GrowthImage::read_metadata(filename)?;
+
+    let decoder = png::Decoder::new(std::fs::File::open(filename)?);
+    let (info, _reader) = decoder.read_info()?;
+    let num_pixels = (info.width as usize) * (info.height as usize);
+
+    // Transparent pixels are skipped, leaving the filled pixels.
+    let colors =
+        ImagePalette::from_png(filename, ClusterMethod::MedianCut)?.colors;
+
+    println!("{:<16}{}", "File:", filename.display());
+    println!("{:<16}{}x{}", "Size:", info.width, info.height);
+    if !metadata.contains_key("omnicolor:seed") {
+        println!("No omnicolor metadata found");
+    }
+    print_config(&metadata);
+    print_pixels(&colors, num_pixels, hue_bins);
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let opt = Options::from_args();
+
+    opt.filenames
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, filename)| {
+            if i > 0 {
+                println!();
+            }
+            inspect(filename, opt.hue_bins)
+        })
+}
@@ -67,7 +67,7 @@ struct PointDetails {
     point: kurbo::Point,
 }
 
-fn parse_octoml_logo(opt: &Options) -> LogoDetails {
+fn parse_octoml_logo(opt: &Options) -> Result<LogoDetails, Error> {
     // Outline of OctoML logo, from https://octoml.ai/img/logo.svg
     let logo_path_text = "
 m19.9349 48.1127-1.1899 1.1887c-2.0644 2.0631-4.7878 3.0948-7.5109 3.0948l-.009.009c-2.72278 0-5.44948-1.0353-7.51963-3.1038l-.00072-.0003c-2.06436-2.0631-3.096723-4.7848-3.097084-7.5064v-.0174c.000361-2.2479.704984-4.4962 2.113144-6.3729.50191-.6687 1.19641-1.0468 2.03112-1.1061 2.29237-.1639 4.23423 2.6705 2.72092 4.7158-.6067.8197-.91131 1.7962-.91348 2.7719.00253 1.1942.45819 2.3895 1.36625 3.2969l.00072.0008c.90481.9042 2.10156 1.3566 3.29876 1.3566v.0087c1.1949-.0025 2.3913-.4579 3.2994-1.3653l1.1895-1.1892zm15.347-41.79541.0004.00037c3.6427 3.64036 5.4639 8.43624 5.4642 13.22854-.0003 4.7934-1.8215 9.589-5.4642 13.229l-6.9064 6.9019h-.0004l-4.2201-4.2178h-.0007l7.0151-7.0109c2.4051-2.4621 3.6081-5.6814 3.6081-8.9022 0-3.268-1.2387-6.535-3.7158-9.0104l-.0007-.0007c-2.4774-2.47581-5.7461-3.71333-9.0166-3.71369-3.2705 0-6.5396 1.23788-9.0166 3.71399l-.0008.0004c-2.477 2.4754-3.71603 5.742-3.71603 9.0104 0 3.2374 1.21553 6.4733 3.64593 8.9401l9.0875 9.0819.0004.0004 7.5199 7.5151c.9081.9074 2.1041 1.3631 3.2991 1.3653v-.0087c1.1975 0 2.3939-.4528 3.2994-1.357l.0004-.0004c.9084-.9078 1.364-2.1034 1.3659-3.2969-.0015-.9754-.3065-1.9525-.9132-2.7719-2.1698-2.9304 2.4745-6.6437 4.7517-3.61 1.4082 1.877 2.1128 4.1253 2.1135 6.3732v.0174c-.0007 2.7216-1.0331 5.4426-3.0974 7.5067h-.0007c-2.0698 2.0685-4.7965 3.1038-7.5196 3.1038l-.0083-.0083c-2.7239-.0007-5.4477-1.0324-7.5117-3.0955l-5.4097-5.4058v-.0007l-11.12757-11.1197c-3.64235-3.6404-5.46389-8.4356-5.46425-13.229.00036-4.7923 1.8219-9.58818 5.46425-13.22854l.00037-.00037c3.6427-3.64 8.4414-5.460356 13.2375-5.460356s9.5948 1.819996 13.2371 5.460356z
@@ -145,9 +145,10 @@ m19.9349 48.1127-1.1899 1.1887c-2.0644 2.0631-4.7878 3.0948-7.5109 3.0948l-.009.
     let portal_path = BezPath::from_path_segments(portal_lines.into_iter());
     let connected_points = point_details
         .iter()
-        .filter(|d| portal_path.distance_to_nearest(d.point) < 5.0)
-        .map(|d| (d.loc, PixelLoc { layer: 1, ..d.loc }))
-        .collect::<Vec<_>>();
+        .map(|d| Ok((d, portal_path.distance_to_nearest(d.point)?)))
+        .filter_ok(|(_d, dist)| *dist < 5.0)
+        .map_ok(|(d, _dist)| (d.loc, PixelLoc { layer: 1, ..d.loc }))
+        .collect::<Result<Vec<_>, Error>>()?;
 
     // The interior of the underworld, as defined by the underworld
     // bounds.  The underworld side of the connected points are
@@ -197,18 +198,18 @@ m19.9349 48.1127-1.1899 1.1887c-2.0644 2.0631-4.7878 3.0948-7.5109 3.0948l-.009.
     };
     let initial_points = vec![p_loc_left, p_loc_right];
 
-    LogoDetails {
+    Ok(LogoDetails {
         interior_points,
         underworld_exterior_points,
         connected_points,
         initial_points,
-    }
+    })
 }
 
 fn main() -> Result<(), Error> {
     let opt = Options::from_args();
 
-    let details = parse_octoml_logo(&opt);
+    let details = parse_octoml_logo(&opt)?;
 
     // Define the builder, with main layer (0) and underlayer (1).
     let mut builder = GrowthImageBuilder::new();
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use itertools::iproduct;
+use structopt::StructOpt;
+
+use omnicolor_rust::palettes::*;
+use omnicolor_rust::{Error, GrowthImageBuilder, LayoutSpec, RGB};
+
+// Renders small images across a grid of parameter values, and
+// assembles them into a single labeled contact sheet.  Useful for
+// choosing settings before a full-size run.
+#[derive(Debug, StructOpt)]
+struct Options {
+    #[structopt(short = "o", long)]
+    output: PathBuf,
+
+    #[structopt(short, long, default_value = "160")]
+    width: u32,
+
+    #[structopt(short, long, default_value = "90")]
+    height: u32,
+
+    #[structopt(short, long, use_delimiter = true, default_value = "5.0")]
+    epsilon: Vec<f64>,
+
+    // If given, uses a spherical palette with each radius.
+    // Otherwise, uses a uniform palette.
+    #[structopt(long, use_delimiter = true)]
+    color_radius: Vec<f32>,
+
+    #[structopt(long, default_value = "#808080")]
+    central_color: RGB,
+
+    #[structopt(short, long, use_delimiter = true, default_value = "0")]
+    seed: Vec<u64>,
+
+    // Number of columns in the contact sheet.  Defaults to one column
+    // per epsilon value.
+    #[structopt(long)]
+    columns: Option<u32>,
+}
+
+fn main() -> Result<(), Error> {
+    let opt = Options::from_args();
+
+    let color_radii = if opt.color_radius.is_empty() {
+        vec![None]
+    } else {
+        opt.color_radius.iter().copied().map(Some).collect()
+    };
+
+    let images =
+        iproduct!(opt.seed.iter(), color_radii.iter(), opt.epsilon.iter())
+            .map(|(&seed, &color_radius, &epsilon)| {
+                let mut builder = GrowthImageBuilder::new();
+                builder
+                    .add_layer(opt.width, opt.height)
+                    .epsilon(epsilon)
+                    .seed(seed);
+                let label = match color_radius {
+                    Some(color_radius) => {
+                        builder.palette(SphericalPalette {
+                            central_color: opt.central_color,
+                            color_radius,
+                        });
+                        format!("E{} R{} S{}", epsilon, color_radius, seed)
+                    }
+                    None => {
+                        builder.palette(UniformPalette);
+                        format!("E{} S{}", epsilon, seed)
+                    }
+                };
+
+                let mut image = builder.build()?;
+                image.fill_until_done();
+                Ok((image, label))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+    let cells = images
+        .iter()
+        .map(|(image, label)| (image, label.clone()))
+        .collect::<Vec<_>>();
+    LayoutSpec {
+        columns: Some(opt.columns.unwrap_or(opt.epsilon.len() as u32)),
+        label_scale: 2,
+        ..Default::default()
+    }
+    .write_contact_sheet(opt.output, &cells);
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use omnicolor_rust::palettes::*;
+use omnicolor_rust::{Error, GrowthImageBuilder, SeedEditor};
+
+// Opens a window to place seed points and draw walls, then grows the
+// image from them.
+#[derive(Debug, StructOpt)]
+struct Options {
+    #[structopt(short = "o", long)]
+    output: PathBuf,
+
+    #[structopt(short, long, default_value = "320")]
+    width: u32,
+
+    #[structopt(short, long, default_value = "180")]
+    height: u32,
+
+    #[structopt(short, long, default_value = "5.0")]
+    epsilon: f64,
+}
+
+fn main() -> Result<(), Error> {
+    let opt = Options::from_args();
+
+    let layout = SeedEditor::new(opt.width, opt.height).run()?;
+
+    let mut builder = GrowthImageBuilder::new();
+    builder
+        .add_layer(opt.width, opt.height)
+        .epsilon(opt.epsilon)
+        .show_progress_bar();
+    layout.apply(builder.new_stage().palette(UniformPalette));
+
+    let mut image = builder.build()?;
+    image.fill_until_done();
+    image.write(opt.output);
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use structopt::StructOpt;
+
+use omnicolor_rust::scene::SceneSpec;
+use omnicolor_rust::Error;
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    // Scene description, as .toml or .json
+    #[structopt(parse(from_os_str))]
+    scene: PathBuf,
+
+    #[structopt(short = "o", long)]
+    output: PathBuf,
+
+    // Re-render a low-resolution preview whenever the scene file
+    // changes, until interrupted.
+    #[structopt(long)]
+    watch: bool,
+
+    // Factor by which to shrink the scene in watch mode.
+    #[structopt(long, default_value = "4")]
+    preview_scale: u32,
+
+    #[structopt(long)]
+    show_progress_bar: bool,
+
+    // Instead of writing the output, render the scene several times
+    // and check that each render is identical.
+    #[structopt(long)]
+    verify_determinism: bool,
+}
+
+// How often to check the scene file for changes in watch mode.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn load_scene(path: &Path) -> Result<SceneSpec, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        _ => toml::from_str(&text).map_err(|e| e.to_string()),
+    }
+}
+
+fn render(
+    scene: &SceneSpec,
+    output: &Path,
+    show_progress_bar: bool,
+) -> Result<(), Error> {
+    let mut builder = scene.try_to_builder()?;
+    if show_progress_bar {
+        builder.show_progress_bar();
+    }
+    let mut image = builder.build()?;
+    image.fill_until_done();
+    image.write(output.to_path_buf());
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+fn watch(opt: &Options) {
+    let mut last_modified = None;
+    loop {
+        let modified = modified_time(&opt.scene);
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+
+            let start = Instant::now();
+            let res = load_scene(&opt.scene).and_then(|scene| {
+                render(
+                    &scene.downscaled(opt.preview_scale),
+                    &opt.output,
+                    opt.show_progress_bar,
+                )
+                .map_err(|e| e.to_string())
+            });
+            match res {
+                Ok(()) => println!(
+                    "Wrote preview to {} in {:.2} s",
+                    opt.output.display(),
+                    start.elapsed().as_secs_f64()
+                ),
+                Err(err) => {
+                    eprintln!("Error in {}: {}", opt.scene.display(), err)
+                }
+            }
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let opt = Options::from_args();
+
+    if opt.watch {
+        watch(&opt);
+        return Ok(());
+    }
+
+    let scene = load_scene(&opt.scene).unwrap_or_else(|err| {
+        eprintln!("Error in {}: {}", opt.scene.display(), err);
+        std::process::exit(1);
+    });
+    if opt.verify_determinism {
+        let seed = scene.try_to_builder()?.verify_determinism()?;
+        println!("Output is deterministic for seed {}", seed);
+        return Ok(());
+    }
+
+    render(&scene, &opt.output, opt.show_progress_bar)
+}
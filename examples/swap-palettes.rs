@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 
+use kurbo::BezPath;
 use structopt::StructOpt;
 
 use omnicolor_rust::palettes::*;
@@ -57,6 +58,13 @@ struct Options {
     )]
     wall_location: Vec<i32>,
 
+    #[structopt(
+        long,
+        help = "Thickness of the wall, in pixels",
+        default_value = "0"
+    )]
+    wall_thickness: f64,
+
     #[structopt(
         long,
         help = "(x1,y1,x2,y2), endpoints of a portal during first stage",
@@ -116,18 +124,10 @@ fn main() -> Result<(), Error> {
 
     if opt.wall_location.len() == 4 {
         let v = &opt.wall_location;
-        stage_builder.forbidden_points(
-            PixelLoc {
-                layer: 0,
-                i: v[0],
-                j: v[1],
-            }
-            .line_to(PixelLoc {
-                layer: 0,
-                i: v[2],
-                j: v[3],
-            }),
-        );
+        let mut wall = BezPath::new();
+        wall.move_to((v[0] as f64, v[1] as f64));
+        wall.line_to((v[2] as f64, v[3] as f64));
+        stage_builder.wall_from_path(0, &wall, opt.wall_thickness);
     }
 
     if opt.portal_location.len() == 4 {
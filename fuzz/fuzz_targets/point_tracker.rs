@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Run with "cargo fuzz run point_tracker" from the repository root.
+fuzz_target!(|data: &[u8]| {
+    omnicolor_rust::fuzzing::point_tracker_ops(data);
+});
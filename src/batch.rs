@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use crate::color::ColorSet;
+use crate::errors::Error;
+use crate::growth_image::GrowthImage;
+use crate::growth_image_builder::GrowthImageBuilder;
+
+// Renders several images, either one after another or each on its
+// own thread.  With a shared palette, each color is used by at most
+// one of the images, so that a diptych or triptych can collectively
+// cover the color space without repeats.
+pub struct BatchRenderer {
+    builders: Vec<GrowthImageBuilder>,
+    shared_palette: bool,
+    parallel: bool,
+}
+
+impl BatchRenderer {
+    pub fn new() -> Self {
+        Self {
+            builders: Vec::new(),
+            shared_palette: false,
+            parallel: false,
+        }
+    }
+
+    pub fn add_image(&mut self, builder: GrowthImageBuilder) -> &mut Self {
+        self.builders.push(builder);
+        self
+    }
+
+    // Skip any palette color that was already used by any of the
+    // images, including earlier stages of the same image.  Each
+    // image's palettes should have enough colors to cover for the
+    // colors skipped, such as by using a fallback palette.
+    pub fn shared_palette(&mut self, shared: bool) -> &mut Self {
+        self.shared_palette = shared;
+        self
+    }
+
+    // Render each image on its own thread.  With a shared palette,
+    // which image gets a contested color depends on the timing of the
+    // threads, so the results are not reproducible even with a fixed
+    // seed.
+    pub fn parallel(&mut self, parallel: bool) -> &mut Self {
+        self.parallel = parallel;
+        self
+    }
+
+    // Fill each image until done, returning them in the order they
+    // were added.
+    pub fn render(&self) -> Result<Vec<GrowthImage>, Error> {
+        let shared_colors = if self.shared_palette {
+            Some(Arc::new(ColorSet::new()))
+        } else {
+            None
+        };
+
+        let render_one = |builder: &GrowthImageBuilder| {
+            let mut image = match &shared_colors {
+                Some(colors) => {
+                    builder.build_with_used_colors(Some(Arc::clone(colors)))?
+                }
+                None => builder.build()?,
+            };
+            image.fill_until_done();
+            Ok(image)
+        };
+
+        if self.parallel {
+            std::thread::scope(|scope| {
+                let handles = self
+                    .builders
+                    .iter()
+                    .map(|builder| scope.spawn(move || render_one(builder)))
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect()
+            })
+        } else {
+            self.builders.iter().map(render_one).collect()
+        }
+    }
+}
+
+impl BatchRenderer {
+    pub fn with_image(mut self, builder: GrowthImageBuilder) -> Self {
+        self.add_image(builder);
+        self
+    }
+
+    pub fn with_shared_palette(mut self, shared: bool) -> Self {
+        self.shared_palette(shared);
+        self
+    }
+
+    pub fn with_parallel(mut self, parallel: bool) -> Self {
+        self.parallel(parallel);
+        self
+    }
+}
+
+impl Default for BatchRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use crate::palettes::SphericalPalette;
+    use crate::RGB;
+
+    #[test]
+    fn test_shared_palette() -> Result<(), Error> {
+        let make_builder = |seed| {
+            GrowthImageBuilder::new()
+                .with_layer(16, 16)
+                .with_seed(seed)
+                .with_palette(SphericalPalette {
+                    central_color: RGB {
+                        vals: [128, 128, 128],
+                    },
+                    color_radius: 4.0,
+                })
+        };
+
+        let images = BatchRenderer::new()
+            .with_image(make_builder(0))
+            .with_image(make_builder(1))
+            .with_shared_palette(true)
+            .with_parallel(true)
+            .render()?;
+        assert_eq!(images.len(), 2);
+
+        let colors = images
+            .iter()
+            .map(|image| {
+                image.pixels.iter().flatten().copied().collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let first = colors[0].iter().collect::<HashSet<_>>();
+        assert_eq!(first.len(), colors[0].len());
+        assert!(colors[1].iter().all(|color| !first.contains(color)));
+
+        Ok(())
+    }
+}
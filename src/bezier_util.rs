@@ -1,33 +1,87 @@
 use itertools::Itertools;
 use kurbo::{
-    BezPath, Line, ParamCurve, ParamCurveArclen, ParamCurveNearest, PathSeg,
-    Point, Shape,
+    BezPath, Line, ParamCurve, ParamCurveArclen, ParamCurveNearest, PathEl,
+    PathSeg, Point, Rect, Shape, Vec2,
 };
 
+use crate::errors::Error;
+use crate::topology::PixelLoc;
+
 pub trait BezPathExt {
     fn divide_at_intersections(
         &self,
         other: &BezPath,
-    ) -> (Vec<BezPath>, Vec<Point>);
+    ) -> Result<(Vec<BezPath>, Vec<Point>), Error>;
     fn divide_between_intersections(
         &self,
         other: &BezPath,
-    ) -> (Vec<BezPath>, Vec<Point>);
+    ) -> Result<(Vec<BezPath>, Vec<Point>), Error>;
     fn as_flat(&self, tolerance: f64) -> BezPath;
-    fn subsegment(&self, t: f64) -> (BezPath, BezPath);
+    fn subsegment(&self, t: f64) -> Result<(BezPath, BezPath), Error>;
 
     fn regions(&self) -> Vec<BezPath>;
 
     fn contains_by_intersection_count(&self, point: Point) -> bool;
-    fn distance_to_nearest(&self, point: Point) -> f64;
+    fn distance_to_nearest(&self, point: Point) -> Result<f64, Error>;
+
+    fn offset(&self, distance: f64) -> BezPath;
+    fn rasterize_stroke(&self, layer: u8, thickness: f64) -> Vec<PixelLoc>;
+
+    fn union(&self, other: &BezPath) -> PathRegion;
+    fn intersection(&self, other: &BezPath) -> PathRegion;
+    fn difference(&self, other: &BezPath) -> PathRegion;
+}
+
+// Boolean combination of closed paths.  Rather than computing the
+// outline of the result, which is error-prone for curves, each
+// operation is applied when testing whether a point is inside the
+// region.  Paths use the even-odd rule, as in
+// contains_by_intersection_count.
+#[derive(Debug, Clone)]
+pub enum PathRegion {
+    Path(BezPath),
+    Union(Box<PathRegion>, Box<PathRegion>),
+    Intersection(Box<PathRegion>, Box<PathRegion>),
+    Difference(Box<PathRegion>, Box<PathRegion>),
 }
 
+// Tolerance used to flatten curves before intersecting with them.
+const INTERSECTION_TOLERANCE: f64 = 0.25;
+
+// Sections of a path between intersections must be longer than this,
+// to avoid spurious intersections where a path meets itself.
+const MIN_SECTION_LENGTH: f64 = 5.0;
+
+// Intersections within this parametric distance of either end of a
+// segment are treated as being at the boundary between segments.
+const BOUNDARY_T: f64 = 1e-6;
+
+// Tolerance used to flatten curves before offsetting them.
+const OFFSET_TOLERANCE: f64 = 0.1;
+
+// Tolerance used to flatten curves before rasterizing their stroke.
+const STROKE_TOLERANCE: f64 = 0.25;
+
+// Longest allowed miter at a sharp corner of an offset path, relative
+// to the offset distance.  Sharper corners are truncated.
+const OFFSET_MITER_LIMIT: f64 = 4.0;
+
 impl BezPathExt for BezPath {
+    // Split the path at each point where it crosses the other path,
+    // returning the sections along with the crossing points.  For an
+    // open path, the section after the last crossing is separate, and
+    // there is one more section than crossing.  For a closed path, it
+    // is joined to the first section, and the i-th section ends at
+    // the i-th crossing.  Crossings closer together than
+    // MIN_SECTION_LENGTH along the path are ignored.
     fn divide_at_intersections(
         &self,
         other: &BezPath,
-    ) -> (Vec<BezPath>, Vec<Point>) {
-        let min_distance_adjacent = 5.0;
+    ) -> Result<(Vec<BezPath>, Vec<Point>), Error> {
+        let segments = self.segments().collect::<Vec<_>>();
+        if segments.is_empty() {
+            return Err(Error::EmptyPath);
+        }
 
         let mut output_sections: Vec<BezPath> = Vec::new();
         let mut output_points: Vec<Point> = Vec::new();
@@ -36,103 +90,126 @@ impl BezPathExt for BezPath {
 
         // Called for each potential intersection.  Makes sure the
         // path length is non-trivial to avoid spurious intersections.
-        let mut flush = |current: &mut Vec<PathSeg>, is_last: bool| {
+        let mut flush = |current: &mut Vec<PathSeg>| {
             let pathlen =
                 current.iter().map(|seg| seg.arclen(1e-3)).sum::<f64>();
-            if pathlen > min_distance_adjacent {
-                let completed = std::mem::replace(current, Vec::new());
-                if is_last {
-                    output_sections[0] = BezPath::from_path_segments(
-                        completed
-                            .into_iter()
-                            .chain(output_sections[0].segments()),
-                    );
-                } else {
-                    output_points.push(completed.last().unwrap().eval(1.0));
-                    let path =
-                        BezPath::from_path_segments(completed.into_iter());
-                    output_sections.push(path);
-                }
+            if pathlen > MIN_SECTION_LENGTH {
+                output_points.push(current.last().unwrap().end());
+                output_sections
+                    .push(BezPath::from_path_segments(current.drain(..)));
             }
         };
 
-        self.segments().for_each(|seg| {
+        segments.iter().for_each(|&seg| {
             // Exclude intersections from the segment itself, or from
             // adjacent segments, in the case of looking for
-            // self-intersections.  Could cause missed intersections
-            // that occur directly at boundary between segments.
+            // self-intersections.
             let split_by =
                 BezPath::from_path_segments(other.segments().filter(|&os| {
                     (os != seg)
                         && (os.start() != seg.end())
                         && (os.end() != seg.start())
-                }))
-                .as_flat(0.25);
+                }));
 
-            // List of intersections with this particular segment.
-            let mut t_list: Vec<_> = split_by
-                .segments()
-                .flat_map(|line| {
-                    if let PathSeg::Line(line) = line {
-                        return seg.intersect_line(line);
-                    }
-                    panic!();
-                })
-                .map(|intersection| intersection.segment_t)
-                .collect();
+            // List of intersections with this particular segment.  An
+            // intersection at the boundary between two segments is
+            // found for both of them, so intersections near either
+            // end are moved to the end.  Cutting at the same point
+            // twice then has no effect.
+            let mut t_list: Vec<_> =
+                flattened_lines(&split_by, INTERSECTION_TOLERANCE)
+                    .into_iter()
+                    .flat_map(|line| seg.intersect_line(line))
+                    .map(|intersection| intersection.segment_t)
+                    .filter(|t| t.is_finite())
+                    .map(|t| {
+                        if t < BOUNDARY_T {
+                            0.0
+                        } else if t > 1.0 - BOUNDARY_T {
+                            1.0
+                        } else {
+                            t
+                        }
+                    })
+                    .collect();
             t_list.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+            t_list.dedup();
 
-            // Push either segment or subsegment to the current chunk.
-            if t_list.is_empty() {
-                current.push(seg);
-            } else {
-                current.push(seg.subsegment(0.0..*t_list.first().unwrap()));
-                flush(&mut current, false);
-
-                t_list.iter().tuple_windows().for_each(|(&t1, &t2)| {
-                    current.push(seg.subsegment(t1..t2));
-                    flush(&mut current, false);
-                });
-                current.push(seg.subsegment(*t_list.last().unwrap()..1.0));
+            // Push either segment or subsegments to the current chunk.
+            let mut t_prev = 0.0;
+            t_list.into_iter().for_each(|t| {
+                if t > t_prev {
+                    current.push(seg.subsegment(t_prev..t));
+                }
+                flush(&mut current);
+                t_prev = t;
+            });
+            if t_prev < 1.0 {
+                current.push(seg.subsegment(t_prev..1.0));
             }
         });
 
-        flush(&mut current, true);
-        (output_sections, output_points)
+        // The remainder of a closed path continues into the first
+        // section.
+        if !current.is_empty() {
+            if is_closed(self) && !output_sections.is_empty() {
+                output_sections[0] = BezPath::from_path_segments(
+                    current.into_iter().chain(output_sections[0].segments()),
+                );
+            } else {
+                output_sections
+                    .push(BezPath::from_path_segments(current.into_iter()));
+            }
+        }
+
+        Ok((output_sections, output_points))
     }
 
+    // As divide_at_intersections, but with each section running from
+    // halfway between crossings to halfway between the next pair of
+    // crossings, so that each section contains one crossing.  For an
+    // open path, the first and last sections extend to the ends of
+    // the path.
     fn divide_between_intersections(
         &self,
         other: &BezPath,
-    ) -> (Vec<BezPath>, Vec<Point>) {
-        let (subpaths, intersections) = self.divide_at_intersections(other);
-        let path_halves: Vec<_> = subpaths
-            .into_iter()
-            .flat_map(|path| {
-                let (a, b) = path.subsegment(0.5);
-                vec![a, b].into_iter()
-            })
-            .collect();
+    ) -> Result<(Vec<BezPath>, Vec<Point>), Error> {
+        let (subpaths, intersections) = self.divide_at_intersections(other)?;
+        if subpaths.len() == 1 && !is_closed(self) {
+            return Ok((subpaths, intersections));
+        }
 
-        let mut output: Vec<BezPath> = Vec::new();
+        let split_in_halves = |paths: &[BezPath]| {
+            paths
+                .iter()
+                .map(|path| path.subsegment(0.5).map(|(a, b)| vec![a, b]))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|halves| halves.into_iter().flatten())
+        };
+        let path_halves: Vec<BezPath> = if is_closed(self) {
+            // Each section is split in half, with the first half of
+            // the first section ending the last output.
+            let mut halves = split_in_halves(&subpaths)?.collect::<Vec<_>>();
+            halves.rotate_left(1);
+            halves
+        } else {
+            let n = subpaths.len();
+            std::iter::once(subpaths[0].clone())
+                .chain(split_in_halves(&subpaths[1..n - 1])?)
+                .chain(std::iter::once(subpaths[n - 1].clone()))
+                .collect()
+        };
 
-        let first = path_halves.first().unwrap().clone();
-        path_halves
-            .iter()
-            .skip(1)
-            .tuple_windows()
-            .step_by(2)
-            .for_each(|(a, b)| {
-                output.push(BezPath::from_path_segments(
-                    a.segments().chain(b.segments()),
-                ));
-            });
-        let last = path_halves.last().unwrap().clone();
-        output.push(BezPath::from_path_segments(
-            last.segments().chain(first.segments()),
-        ));
+        let output = path_halves
+            .chunks(2)
+            .map(|pair| {
+                BezPath::from_path_segments(
+                    pair.iter().flat_map(|path| path.segments()),
+                )
+            })
+            .collect();
 
-        (output, intersections)
+        Ok((output, intersections))
     }
 
     fn as_flat(&self, tolerance: f64) -> BezPath {
@@ -141,39 +218,48 @@ impl BezPathExt for BezPath {
         BezPath::from_vec(elements)
     }
 
-    fn subsegment(&self, t: f64) -> (BezPath, BezPath) {
+    // Split the path at the fraction t of its length.
+    fn subsegment(&self, t: f64) -> Result<(BezPath, BezPath), Error> {
         let accuracy = 1e-3;
 
-        let length = self.segments().map(|s| s.arclen(accuracy)).sum::<f64>();
-        let target_length = length * t;
-        let (split_i, split_seg_a, split_seg_b) = self
-            .segments()
-            .enumerate()
-            .scan(0.0, |state, (i, seg)| {
+        let segments = self.segments().collect::<Vec<_>>();
+        let last_i = segments.len().checked_sub(1).ok_or(Error::EmptyPath)?;
+
+        let length = segments.iter().map(|s| s.arclen(accuracy)).sum::<f64>();
+        let target_length = length * t.clamp(0.0, 1.0);
+
+        // Rounding error in the arc lengths may leave the target just
+        // past the end of the last segment.
+        let (split_i, length_pre) = segments
+            .iter()
+            .scan(0.0, |state, seg| {
                 let length_pre = *state;
                 *state += seg.arclen(accuracy);
-                let length_post = *state;
-                Some((length_pre, i, seg, length_post))
+                Some((length_pre, *state))
             })
-            .filter(|(_, _, _, length_post)| length_post >= &target_length)
-            .next()
-            .map(|(length_pre, i, seg, _)| {
-                let t = seg.inv_arclen(target_length - length_pre, accuracy);
-                (i, seg.subsegment(0.0..t), seg.subsegment(t..1.0))
-            })
-            .unwrap();
+            .enumerate()
+            .find(|(_, (_, length_post))| *length_post >= target_length)
+            .map(|(i, (length_pre, _))| (i, length_pre))
+            .unwrap_or_else(|| {
+                (last_i, length - segments[last_i].arclen(accuracy))
+            });
 
-        (
+        let split_seg = segments[split_i];
+        let t = split_seg
+            .inv_arclen((target_length - length_pre).max(0.0), accuracy);
+
+        Ok((
             BezPath::from_path_segments(
-                self.segments()
-                    .take(split_i)
-                    .chain(std::iter::once(split_seg_a)),
+                segments[..split_i]
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(split_seg.subsegment(0.0..t))),
             ),
             BezPath::from_path_segments(
-                std::iter::once(split_seg_b)
-                    .chain(self.segments().skip(split_i + 1)),
+                std::iter::once(split_seg.subsegment(t..1.0))
+                    .chain(segments[split_i + 1..].iter().copied()),
             ),
-        )
+        ))
     }
 
     fn regions(&self) -> Vec<BezPath> {
@@ -198,11 +284,425 @@ impl BezPathExt for BezPath {
         }
     }
 
-    fn distance_to_nearest(&self, point: Point) -> f64 {
+    fn distance_to_nearest(&self, point: Point) -> Result<f64, Error> {
         self.segments()
             .map(|seg| seg.nearest(point, 1e-3).distance_sq)
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap()
-            .sqrt()
+            .fold(None, |min: Option<f64>, dist_sq| {
+                Some(min.map_or(dist_sq, |min| min.min(dist_sq)))
+            })
+            .map(f64::sqrt)
+            .ok_or(Error::EmptyPath)
+    }
+
+    fn union(&self, other: &BezPath) -> PathRegion {
+        PathRegion::from(self.clone()).union(other.clone())
+    }
+
+    fn intersection(&self, other: &BezPath) -> PathRegion {
+        PathRegion::from(self.clone()).intersection(other.clone())
+    }
+
+    fn difference(&self, other: &BezPath) -> PathRegion {
+        PathRegion::from(self.clone()).difference(other.clone())
+    }
+
+    // Path at a constant distance from this path, made of straight
+    // lines.  Positive distances are to the left of the direction of
+    // travel, with y pointing down, so a path drawn clockwise on
+    // screen is offset outward.  Calling with both signs gives the
+    // inner and outer edges of a rope of width 2*distance, which can
+    // be rasterized with contains_by_intersection_count.  Loops in
+    // the offset path, which occur where the radius of curvature is
+    // smaller than the distance, are not removed.
+    fn offset(&self, distance: f64) -> BezPath {
+        let mut output = BezPath::new();
+        flattened_polylines(self, OFFSET_TOLERANCE)
+            .into_iter()
+            .for_each(|(points, closed)| {
+                let offset = offset_polyline(&points, closed, distance);
+                output.move_to(offset[0]);
+                offset.iter().skip(1).for_each(|&p| output.line_to(p));
+                if closed {
+                    output.close_path();
+                }
+            });
+        output
+    }
+
+    // Pixels within half the thickness of the path, suitable for use
+    // as a wall with GrowthImageStageBuilder::forbidden_points.  The
+    // pixels along each flattened segment are always included, with
+    // no diagonal openings, so that even a thin wall can't be grown
+    // through.  Pixels with negative coordinates are skipped.
+    fn rasterize_stroke(&self, layer: u8, thickness: f64) -> Vec<PixelLoc> {
+        let radius = 0.5 * thickness.max(0.0);
+        let to_loc = |p: Point| PixelLoc {
+            layer,
+            i: p.x.round() as i32,
+            j: p.y.round() as i32,
+        };
+
+        flattened_lines(self, STROKE_TOLERANCE)
+            .into_iter()
+            .flat_map(|line| {
+                let (a, b) = (line.p0, line.p1);
+                let bbox = Rect::from_points(a, b).inflate(radius, radius);
+                let nearby = (bbox.min_y().floor() as i32
+                    ..=bbox.max_y().ceil() as i32)
+                    .cartesian_product(
+                        bbox.min_x().floor() as i32
+                            ..=bbox.max_x().ceil() as i32,
+                    )
+                    .map(|(j, i)| PixelLoc { layer, i, j })
+                    .filter(move |loc| {
+                        let p = Point::new(loc.i as f64, loc.j as f64);
+                        line.nearest(p, 1e-6).distance_sq <= radius * radius
+                    });
+                to_loc(a).line_to(to_loc(b)).into_iter().chain(nearby)
+            })
+            .filter(|loc| loc.i >= 0 && loc.j >= 0)
+            .unique()
+            .collect()
+    }
+}
+
+impl PathRegion {
+    pub fn union(self, other: impl Into<PathRegion>) -> Self {
+        PathRegion::Union(Box::new(self), Box::new(other.into()))
+    }
+
+    pub fn intersection(self, other: impl Into<PathRegion>) -> Self {
+        PathRegion::Intersection(Box::new(self), Box::new(other.into()))
+    }
+
+    pub fn difference(self, other: impl Into<PathRegion>) -> Self {
+        PathRegion::Difference(Box::new(self), Box::new(other.into()))
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        match self {
+            PathRegion::Path(path) => {
+                path.contains_by_intersection_count(point)
+            }
+            PathRegion::Union(a, b) => a.contains(point) || b.contains(point),
+            PathRegion::Intersection(a, b) => {
+                a.contains(point) && b.contains(point)
+            }
+            PathRegion::Difference(a, b) => {
+                a.contains(point) && !b.contains(point)
+            }
+        }
+    }
+
+    // Rectangle containing the entire region.  May be larger than
+    // needed, since an intersection or difference can remove parts
+    // of the region that determined the bounds.
+    pub fn bounding_box(&self) -> Rect {
+        match self {
+            PathRegion::Path(path) => path.bounding_box(),
+            PathRegion::Union(a, b) => a.bounding_box().union(b.bounding_box()),
+            PathRegion::Intersection(a, b) => {
+                a.bounding_box().intersect(b.bounding_box())
+            }
+            PathRegion::Difference(a, _) => a.bounding_box(),
+        }
+    }
+
+    // All pixels of a layer whose location lies inside the region,
+    // suitable for GrowthImageStageBuilder::allowed_points or
+    // forbidden_points.  Only pixels within the bounding box are
+    // tested.
+    pub fn rasterize(
+        &self,
+        layer: u8,
+        width: u32,
+        height: u32,
+    ) -> Vec<PixelLoc> {
+        let bbox = self.bounding_box();
+        let imin = bbox.min_x().floor().max(0.0) as i32;
+        let jmin = bbox.min_y().floor().max(0.0) as i32;
+        let imax = bbox.max_x().ceil().min(width as f64 - 1.0) as i32;
+        let jmax = bbox.max_y().ceil().min(height as f64 - 1.0) as i32;
+
+        (jmin..=jmax)
+            .cartesian_product(imin..=imax)
+            .map(|(j, i)| PixelLoc { layer, i, j })
+            .filter(|loc| self.contains(Point::new(loc.i as f64, loc.j as f64)))
+            .collect()
+    }
+}
+
+impl From<BezPath> for PathRegion {
+    fn from(path: BezPath) -> Self {
+        PathRegion::Path(path)
+    }
+}
+
+// Each subpath as a list of points, along with whether it is closed.
+// Repeated points, including those that differ only by rounding
+// error, are removed, and subpaths with fewer than two
+// distinct points are skipped.
+fn flattened_polylines(
+    path: &BezPath,
+    tolerance: f64,
+) -> Vec<(Vec<Point>, bool)> {
+    let mut output = Vec::new();
+    let mut current: Vec<Point> = Vec::new();
+    let mut closed = false;
+
+    let mut flush = |current: &mut Vec<Point>, closed: bool| {
+        let mut points = std::mem::replace(current, Vec::new());
+        let is_repeat = |a: &Point, b: &Point| (*a - *b).hypot2() < 1e-12;
+        points.dedup_by(|a, b| is_repeat(a, b));
+        if closed
+            && points.len() > 1
+            && is_repeat(&points[0], &points[points.len() - 1])
+        {
+            points.pop();
+        }
+        if points.len() > 1 {
+            output.push((points, closed));
+        }
+    };
+
+    path.as_flat(tolerance)
+        .elements()
+        .iter()
+        .for_each(|&el| match el {
+            PathEl::MoveTo(p) => {
+                flush(&mut current, closed);
+                closed = false;
+                current.push(p);
+            }
+            PathEl::LineTo(p) => current.push(p),
+            PathEl::ClosePath => closed = true,
+            // Flattened paths contain only lines.
+            PathEl::QuadTo(..) | PathEl::CurveTo(..) => unreachable!(),
+        });
+    flush(&mut current, closed);
+
+    output
+}
+
+// Each straight line of the flattened path, including those that
+// close a subpath.
+fn flattened_lines(path: &BezPath, tolerance: f64) -> Vec<Line> {
+    flattened_polylines(path, tolerance)
+        .into_iter()
+        .flat_map(|(points, closed)| {
+            let closing = if closed {
+                Some(Line::new(points[points.len() - 1], points[0]))
+            } else {
+                None
+            };
+            points
+                .iter()
+                .tuple_windows()
+                .map(|(&a, &b)| Line::new(a, b))
+                .chain(closing)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Whether the path ends where it starts.
+fn is_closed(path: &BezPath) -> bool {
+    let mut segments = path.segments();
+    segments.next().is_some_and(|first| {
+        let last = segments.last().unwrap_or(first);
+        (first.start() - last.end()).hypot2() < 1e-12
+    })
+}
+
+fn offset_polyline(
+    points: &[Point],
+    closed: bool,
+    distance: f64,
+) -> Vec<Point> {
+    let n = points.len();
+    let normal = |a: Point, b: Point| {
+        let dir = (b - a).normalize();
+        Vec2::new(dir.y, -dir.x)
+    };
+    let closing_normal = if closed {
+        Some(normal(points[n - 1], points[0]))
+    } else {
+        None
+    };
+
+    (0..n)
+        .map(|i| {
+            let before = if i > 0 {
+                Some(normal(points[i - 1], points[i]))
+            } else {
+                closing_normal
+            };
+            let after = if i + 1 < n {
+                Some(normal(points[i], points[i + 1]))
+            } else {
+                closing_normal
+            };
+
+            // At a corner, move along the bisector far enough that
+            // both adjacent edges are offset by the full distance.
+            let offset = match (before, after) {
+                (Some(a), Some(b)) if (a + b).hypot2() > 1e-12 => {
+                    let bisector = (a + b).normalize();
+                    let cos = bisector.dot(a).max(1.0 / OFFSET_MITER_LIMIT);
+                    bisector * (distance / cos)
+                }
+                (Some(a), _) | (None, Some(a)) => a * distance,
+                (None, None) => Vec2::ZERO,
+            };
+            points[i] + offset
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn polyline(points: &[(f64, f64)], closed: bool) -> BezPath {
+        let mut path = BezPath::new();
+        path.move_to(points[0]);
+        points[1..].iter().for_each(|&p| path.line_to(p));
+        if closed {
+            path.close_path();
+        }
+        path
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_divide_at_intersections() -> Result<(), Error> {
+        let crossing = polyline(&[(10.0, -10.0), (10.0, 10.0)], false);
+
+        let line = polyline(&[(0.0, 0.0), (20.0, 0.0)], false);
+        let (sections, points) = line.divide_at_intersections(&crossing)?;
+        assert_eq!(points, vec![Point::new(10.0, 0.0)]);
+        assert_eq!(sections.len(), 2);
+        assert_close(sections[0].perimeter(1e-3), 10.0);
+        assert_close(sections[1].perimeter(1e-3), 10.0);
+
+        // Crossing exactly at the boundary between two segments is
+        // found once.
+        let line = polyline(&[(0.0, 0.0), (10.0, 0.0), (20.0, 0.0)], false);
+        let (sections, points) = line.divide_at_intersections(&crossing)?;
+        assert_eq!(points, vec![Point::new(10.0, 0.0)]);
+        assert_eq!(sections.len(), 2);
+        assert_close(sections[0].perimeter(1e-3), 10.0);
+        assert_close(sections[1].perimeter(1e-3), 10.0);
+
+        // Without any crossings, the path is returned whole.
+        let (sections, points) = line.divide_at_intersections(&line)?;
+        assert!(points.is_empty());
+        assert_eq!(sections.len(), 1);
+        assert_close(sections[0].perimeter(1e-3), 20.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_divide_closed_path() -> Result<(), Error> {
+        let square = polyline(
+            &[(0.0, 0.0), (20.0, 0.0), (20.0, 20.0), (0.0, 20.0)],
+            true,
+        );
+        let crossing = polyline(&[(10.0, -10.0), (10.0, 30.0)], false);
+
+        // The section after the last crossing wraps around to the
+        // first.
+        let (sections, points) = square.divide_at_intersections(&crossing)?;
+        assert_eq!(points, vec![Point::new(10.0, 0.0), Point::new(10.0, 20.0)]);
+        assert_eq!(sections.len(), 2);
+        sections
+            .iter()
+            .for_each(|section| assert_close(section.perimeter(1e-3), 40.0));
+
+        // Each section has one crossing at its midpoint.
+        let (sections, _points) =
+            square.divide_between_intersections(&crossing)?;
+        assert_eq!(sections.len(), 2);
+        sections
+            .iter()
+            .zip(points.iter())
+            .for_each(|(section, &p)| {
+                assert_close(section.perimeter(1e-3), 40.0);
+                let (first_half, _) = section.subsegment(0.5).unwrap();
+                let midpoint = first_half.segments().last().unwrap().end();
+                assert_close((midpoint - p).hypot(), 0.0);
+            });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_divide_between_open_path() -> Result<(), Error> {
+        let line = polyline(&[(0.0, 0.0), (40.0, 0.0)], false);
+        let crossings = BezPath::from_path_segments(
+            [10.0, 30.0]
+                .iter()
+                .map(|&x| Line::new((x, -10.0), (x, 10.0)).into()),
+        );
+
+        // The first and last sections extend to the ends of the path.
+        let (sections, points) =
+            line.divide_between_intersections(&crossings)?;
+        assert_eq!(points.len(), 2);
+        assert_eq!(sections.len(), 2);
+        assert_close(sections[0].perimeter(1e-3), 20.0);
+        assert_close(sections[1].perimeter(1e-3), 20.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subsegment() -> Result<(), Error> {
+        let line = polyline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)], false);
+
+        let (a, b) = line.subsegment(0.25)?;
+        assert_close(a.perimeter(1e-3), 5.0);
+        assert_close(b.perimeter(1e-3), 15.0);
+
+        // Splitting at either end leaves an empty section.
+        let (a, b) = line.subsegment(1.0)?;
+        assert_close(a.perimeter(1e-3), 20.0);
+        assert_close(b.perimeter(1e-3), 0.0);
+        let (a, b) = line.subsegment(0.0)?;
+        assert_close(a.perimeter(1e-3), 0.0);
+        assert_close(b.perimeter(1e-3), 20.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance_to_nearest() -> Result<(), Error> {
+        let line = polyline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)], false);
+        assert_close(line.distance_to_nearest(Point::new(5.0, 3.0))?, 3.0);
+        assert_close(line.distance_to_nearest(Point::new(13.0, 14.0))?, 5.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_path() {
+        let empty = BezPath::new();
+        assert!(matches!(
+            empty.divide_at_intersections(&empty),
+            Err(Error::EmptyPath)
+        ));
+        assert!(matches!(
+            empty.divide_between_intersections(&empty),
+            Err(Error::EmptyPath)
+        ));
+        assert!(matches!(empty.subsegment(0.5), Err(Error::EmptyPath)));
+        assert!(matches!(
+            empty.distance_to_nearest(Point::ORIGIN),
+            Err(Error::EmptyPath)
+        ));
     }
 }
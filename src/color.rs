@@ -1,9 +1,12 @@
 use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::errors::Error;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RGB {
     pub vals: [u8; 3],
 }
@@ -18,6 +21,323 @@ impl RGB {
     pub fn b(&self) -> u8 {
         self.vals[2]
     }
+
+    // Convert from sRGB to linear light, with each channel in the
+    // range [0, 1].
+    pub fn to_linear(&self) -> [f32; 3] {
+        let mut output = [0.0; 3];
+        output
+            .iter_mut()
+            .zip(self.vals.iter())
+            .for_each(|(out, &val)| {
+                let val = (val as f32) / 255.0;
+                *out = if val <= 0.04045 {
+                    val / 12.92
+                } else {
+                    ((val + 0.055) / 1.055).powf(2.4)
+                };
+            });
+        output
+    }
+
+    // Convert from linear light, with each channel in the range [0,
+    // 1], to sRGB.
+    pub fn from_linear(linear: [f32; 3]) -> Self {
+        let mut vals = [0u8; 3];
+        vals.iter_mut().zip(linear.iter()).for_each(|(out, &val)| {
+            let val = val.clamp(0.0, 1.0);
+            let val = if val <= 0.0031308 {
+                val * 12.92
+            } else {
+                1.055 * val.powf(1.0 / 2.4) - 0.055
+            };
+            *out = (255.0 * val).round() as u8;
+        });
+        RGB { vals }
+    }
+
+    pub fn to_hsl(&self) -> HSL {
+        (*self).into()
+    }
+
+    pub fn to_hsv(&self) -> HSV {
+        (*self).into()
+    }
+
+    pub fn to_lab(&self) -> Lab {
+        (*self).into()
+    }
+
+    // Increase the HSL lightness by the amount given, in the range
+    // [0, 1].  Negative amounts darken the color.
+    pub fn lighten(&self, amount: f32) -> Self {
+        let hsl = self.to_hsl();
+        HSL {
+            l: (hsl.l + amount).clamp(0.0, 1.0),
+            ..hsl
+        }
+        .into()
+    }
+
+    pub fn darken(&self, amount: f32) -> Self {
+        self.lighten(-amount)
+    }
+
+    // Rotate the hue by the angle given, in degrees.
+    pub fn rotate_hue(&self, degrees: f32) -> Self {
+        let hsl = self.to_hsl();
+        HSL {
+            h: (hsl.h + degrees).rem_euclid(360.0),
+            ..hsl
+        }
+        .into()
+    }
+
+    // Interpolate between this color, at t=0, and the other, at
+    // t=1, as sRGB values.
+    pub fn lerp(&self, other: &RGB, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let (a, b) = (self.to_unit(), other.to_unit());
+        Self::from_unit([
+            a[0] + t * (b[0] - a[0]),
+            a[1] + t * (b[1] - a[1]),
+            a[2] + t * (b[2] - a[2]),
+        ])
+    }
+
+    // Interpolate as in lerp(), but in linear light.  Midpoints are
+    // brighter than with lerp(), matching a physical mix of the two
+    // colors.
+    pub fn lerp_linear(&self, other: &RGB, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let (a, b) = (self.to_linear(), other.to_linear());
+        Self::from_linear([
+            a[0] + t * (b[0] - a[0]),
+            a[1] + t * (b[1] - a[1]),
+            a[2] + t * (b[2] - a[2]),
+        ])
+    }
+
+    // Blend a color over this one, as in an image editor's layer
+    // blend modes.  Channels are blended as sRGB values in the range
+    // [0, 1], then mixed with this color by the opacity.
+    pub fn blend(&self, top: &RGB, mode: BlendMode, opacity: f32) -> Self {
+        let base = self.to_unit();
+        let top = top.to_unit();
+        let opacity = opacity.clamp(0.0, 1.0);
+
+        let mut vals = [0.0; 3];
+        vals.iter_mut().enumerate().for_each(|(i, out)| {
+            let (b, t) = (base[i], top[i]);
+            let blended = match mode {
+                BlendMode::Normal => t,
+                BlendMode::Multiply => b * t,
+                BlendMode::Screen => 1.0 - (1.0 - b) * (1.0 - t),
+                BlendMode::Overlay => {
+                    if b < 0.5 {
+                        2.0 * b * t
+                    } else {
+                        1.0 - 2.0 * (1.0 - b) * (1.0 - t)
+                    }
+                }
+                BlendMode::Darken => b.min(t),
+                BlendMode::Lighten => b.max(t),
+                BlendMode::Add => (b + t).min(1.0),
+                BlendMode::Difference => (b - t).abs(),
+            };
+            *out = b + opacity * (blended - b);
+        });
+        Self::from_unit(vals)
+    }
+
+    // Channels as floats in the range [0, 1], without any gamma
+    // conversion.
+    fn to_unit(self) -> [f32; 3] {
+        [
+            (self.r() as f32) / 255.0,
+            (self.g() as f32) / 255.0,
+            (self.b() as f32) / 255.0,
+        ]
+    }
+
+    fn from_unit(vals: [f32; 3]) -> Self {
+        RGB {
+            vals: [
+                (255.0 * vals[0].clamp(0.0, 1.0)).round() as u8,
+                (255.0 * vals[1].clamp(0.0, 1.0)).round() as u8,
+                (255.0 * vals[2].clamp(0.0, 1.0)).round() as u8,
+            ],
+        }
+    }
+}
+
+// How a stage's colors are combined with the image beneath them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Add,
+    Difference,
+}
+
+impl Display for RGB {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r(), self.g(), self.b())
+    }
+}
+
+// Hue in degrees [0, 360), saturation and lightness in [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HSL {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+// Hue in degrees [0, 360), saturation and value in [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HSV {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+// CIE L*a*b*, using the D65 white point.  Lightness is in [0, 100].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+// Hue in degrees, along with the max and min channel values.
+fn hue_and_range(rgb: &RGB) -> (f32, f32, f32) {
+    let [r, g, b] = rgb.to_unit();
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    (hue, max, min)
+}
+
+// Convert from hue, chroma, and the offset to add to each channel.
+fn from_hue_chroma(h: f32, chroma: f32, offset: f32) -> RGB {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    RGB::from_unit([r + offset, g + offset, b + offset])
+}
+
+impl From<RGB> for HSL {
+    fn from(rgb: RGB) -> Self {
+        let (h, max, min) = hue_and_range(&rgb);
+        let l = (max + min) / 2.0;
+        let s = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+        };
+        HSL { h, s, l }
+    }
+}
+
+impl From<HSL> for RGB {
+    fn from(hsl: HSL) -> Self {
+        let chroma = (1.0 - (2.0 * hsl.l - 1.0).abs()) * hsl.s;
+        from_hue_chroma(hsl.h, chroma, hsl.l - chroma / 2.0)
+    }
+}
+
+impl From<RGB> for HSV {
+    fn from(rgb: RGB) -> Self {
+        let (h, max, min) = hue_and_range(&rgb);
+        let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+        HSV { h, s, v: max }
+    }
+}
+
+impl From<HSV> for RGB {
+    fn from(hsv: HSV) -> Self {
+        let chroma = hsv.v * hsv.s;
+        from_hue_chroma(hsv.h, chroma, hsv.v - chroma)
+    }
+}
+
+// Reference white point for D65 illumination, in XYZ.
+const D65_WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+impl From<RGB> for Lab {
+    fn from(rgb: RGB) -> Self {
+        let [r, g, b] = rgb.to_linear();
+        let xyz = [
+            0.4124 * r + 0.3576 * g + 0.1805 * b,
+            0.2126 * r + 0.7152 * g + 0.0722 * b,
+            0.0193 * r + 0.1192 * g + 0.9505 * b,
+        ];
+
+        let f = |t: f32| {
+            let delta: f32 = 6.0 / 29.0;
+            if t > delta.powi(3) {
+                t.cbrt()
+            } else {
+                t / (3.0 * delta * delta) + 4.0 / 29.0
+            }
+        };
+        let fx = f(xyz[0] / D65_WHITE[0]);
+        let fy = f(xyz[1] / D65_WHITE[1]);
+        let fz = f(xyz[2] / D65_WHITE[2]);
+
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
+
+impl From<Lab> for RGB {
+    fn from(lab: Lab) -> Self {
+        let finv = |t: f32| {
+            let delta: f32 = 6.0 / 29.0;
+            if t > delta {
+                t.powi(3)
+            } else {
+                3.0 * delta * delta * (t - 4.0 / 29.0)
+            }
+        };
+        let fy = (lab.l + 16.0) / 116.0;
+        let x = D65_WHITE[0] * finv(fy + lab.a / 500.0);
+        let y = D65_WHITE[1] * finv(fy);
+        let z = D65_WHITE[2] * finv(fy - lab.b / 200.0);
+
+        RGB::from_linear([
+            3.2406 * x - 1.5372 * y - 0.4986 * z,
+            -0.9689 * x + 1.8758 * y + 0.0415 * z,
+            0.0557 * x - 0.2040 * y + 1.0570 * z,
+        ])
+    }
 }
 
 impl FromStr for RGB {
@@ -31,6 +351,166 @@ impl FromStr for RGB {
             .map(|s| u8::from_str_radix(&format!("{}{}", s[0], s[1]), 16))
             .collect::<Result<Vec<_>, _>>()?
             .try_into()?;
-        Ok(RGB { vals: vals })
+        Ok(RGB { vals })
+    }
+}
+
+// Set of colors, stored as a bitset over the full 24-bit color space.
+// Insertion is atomic, so a set can be shared between images that
+// are generated in parallel.
+pub(crate) struct ColorSet {
+    bits: Vec<AtomicU64>,
+}
+
+impl Clone for ColorSet {
+    fn clone(&self) -> Self {
+        Self {
+            bits: self
+                .bits
+                .iter()
+                .map(|word| AtomicU64::new(word.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+}
+
+impl ColorSet {
+    pub(crate) fn new() -> Self {
+        Self {
+            bits: (0..(1 << 24) / 64).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn position(color: &RGB) -> (usize, u64) {
+        let index = ((color.r() as usize) << 16)
+            | ((color.g() as usize) << 8)
+            | (color.b() as usize);
+        (index / 64, 1 << (index % 64))
+    }
+
+    // Returns true if the color was not previously in the set.
+    pub(crate) fn insert(&self, color: &RGB) -> bool {
+        let (word, mask) = Self::position(color);
+        self.bits[word].fetch_or(mask, Ordering::Relaxed) & mask == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: RGB, b: RGB) {
+        let max_diff = a
+            .vals
+            .iter()
+            .zip(b.vals.iter())
+            .map(|(&x, &y)| ((x as i32) - (y as i32)).abs())
+            .max()
+            .unwrap();
+        assert!(max_diff <= 1, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_hex_roundtrip() -> Result<(), Error> {
+        let color: RGB = "#ff6680".parse()?;
+        assert_eq!(color.vals, [255, 102, 128]);
+        assert_eq!(color.to_string(), "#ff6680");
+        assert_eq!(color.to_string().parse::<RGB>()?, color);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conversion_roundtrip() {
+        let colors = (0..=255u32)
+            .step_by(15)
+            .flat_map(|r| (0..=255u32).step_by(51).map(move |g| (r, g)))
+            .flat_map(|(r, g)| {
+                (0..=255u32).step_by(85).map(move |b| RGB {
+                    vals: [r as u8, g as u8, b as u8],
+                })
+            });
+
+        colors.for_each(|color| {
+            assert_close(color.to_hsl().into(), color);
+            assert_close(color.to_hsv().into(), color);
+            assert_close(color.to_lab().into(), color);
+            assert_close(RGB::from_linear(color.to_linear()), color);
+        });
+    }
+
+    #[test]
+    fn test_known_values() {
+        let red = RGB { vals: [255, 0, 0] };
+        let hsl = red.to_hsl();
+        assert_eq!((hsl.h, hsl.s, hsl.l), (0.0, 1.0, 0.5));
+
+        let white = RGB {
+            vals: [255, 255, 255],
+        };
+        let lab = white.to_lab();
+        assert!((lab.l - 100.0).abs() < 0.1);
+        assert!(lab.a.abs() < 0.1);
+        assert!(lab.b.abs() < 0.1);
+
+        assert_close(red.rotate_hue(120.0), RGB { vals: [0, 255, 0] });
+        assert_close(red.lighten(0.5), white);
+        assert_close(red.darken(0.5), RGB { vals: [0, 0, 0] });
+    }
+
+    #[test]
+    fn test_blend_modes() {
+        let base = RGB {
+            vals: [255, 128, 0],
+        };
+        let top = RGB {
+            vals: [128, 128, 128],
+        };
+
+        assert_eq!(base.blend(&top, BlendMode::Normal, 1.0), top);
+        assert_eq!(base.blend(&top, BlendMode::Normal, 0.0), base);
+        assert_close(
+            base.blend(&top, BlendMode::Multiply, 1.0),
+            RGB { vals: [128, 64, 0] },
+        );
+        assert_close(
+            base.blend(&top, BlendMode::Screen, 1.0),
+            RGB {
+                vals: [255, 192, 128],
+            },
+        );
+        assert_close(
+            base.blend(&top, BlendMode::Difference, 1.0),
+            RGB {
+                vals: [127, 0, 128],
+            },
+        );
+        assert_close(
+            base.blend(&top, BlendMode::Multiply, 0.5),
+            RGB { vals: [191, 96, 0] },
+        );
+    }
+    #[test]
+    fn test_lerp() {
+        let black = RGB { vals: [0, 0, 0] };
+        let white = RGB {
+            vals: [255, 255, 255],
+        };
+
+        assert_eq!(black.lerp(&white, 0.0), black);
+        assert_eq!(black.lerp(&white, 1.0), white);
+        assert_eq!(black.lerp(&white, 2.0), white);
+        assert_close(
+            black.lerp(&white, 0.5),
+            RGB {
+                vals: [128, 128, 128],
+            },
+        );
+        assert_close(
+            black.lerp_linear(&white, 0.5),
+            RGB {
+                vals: [188, 188, 188],
+            },
+        );
+        assert_eq!(black.lerp_linear(&white, 1.0), white);
     }
 }
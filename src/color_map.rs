@@ -0,0 +1,92 @@
+use crate::color::RGB;
+
+// Maps the value stored in each pixel to the color shown in generated
+// images.  Without one, stored values are shown as-is.  With one,
+// the three channels of each palette entry can be treated as
+// abstract data, such as a height and two material weights, so that
+// the growth produces a texture or heightmap rather than an image.
+// Palettes then describe the distribution of data values, and
+// similarity is still measured between the stored values.
+pub trait ColorMap: Send + Sync {
+    // Each channel of the stored value is scaled to [0, 1].
+    fn map(&self, data: [f32; 3]) -> RGB;
+}
+
+impl<F> ColorMap for F
+where
+    F: Fn([f32; 3]) -> RGB + Send + Sync,
+{
+    fn map(&self, data: [f32; 3]) -> RGB {
+        self(data)
+    }
+}
+
+// Stored value of a pixel, with each channel scaled to [0, 1].
+pub(crate) fn to_data(color: RGB) -> [f32; 3] {
+    let [r, g, b] = color.vals;
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]
+}
+
+// Shows a single channel in grayscale, such as for a heightmap.
+#[derive(Copy, Clone)]
+pub struct Grayscale(pub usize);
+
+impl ColorMap for Grayscale {
+    fn map(&self, data: [f32; 3]) -> RGB {
+        let val = (255.0 * data[self.0.min(2)].clamp(0.0, 1.0)).round() as u8;
+        RGB {
+            vals: [val, val, val],
+        }
+    }
+}
+
+// Shows a single channel through a gradient, with the colors evenly
+// spaced from 0.0 to 1.0, as in the color maps of plotting tools.
+#[derive(Clone)]
+pub struct Gradient {
+    pub channel: usize,
+    pub colors: Vec<RGB>,
+}
+
+impl ColorMap for Gradient {
+    fn map(&self, data: [f32; 3]) -> RGB {
+        let num_colors = self.colors.len();
+        if num_colors < 2 {
+            return self
+                .colors
+                .first()
+                .copied()
+                .unwrap_or(RGB { vals: [0, 0, 0] });
+        }
+        let pos =
+            data[self.channel.min(2)].clamp(0.0, 1.0) * (num_colors - 1) as f32;
+        let index = (pos.floor() as usize).min(num_colors - 2);
+        self.colors[index].lerp(&self.colors[index + 1], pos - index as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_color_maps() {
+        let color = |r, g, b| RGB { vals: [r, g, b] };
+
+        assert_eq!(Grayscale(1).map([0.0, 0.5, 1.0]), color(128, 128, 128));
+
+        let gradient = Gradient {
+            channel: 0,
+            colors: vec![color(0, 0, 0), color(200, 0, 0), color(200, 100, 0)],
+        };
+        assert_eq!(gradient.map([0.0, 1.0, 1.0]), color(0, 0, 0));
+        assert_eq!(gradient.map([0.25, 0.0, 0.0]), color(100, 0, 0));
+        assert_eq!(gradient.map([0.75, 0.0, 0.0]), color(200, 50, 0));
+        assert_eq!(gradient.map([1.0, 0.0, 0.0]), color(200, 100, 0));
+
+        // Closures can be used as color maps.
+        let invert: &dyn ColorMap =
+            &|data: [f32; 3]| Grayscale(0).map([1.0 - data[0], 0.0, 0.0]);
+        assert_eq!(invert.map(to_data(color(255, 0, 0))), color(0, 0, 0));
+    }
+}
@@ -0,0 +1,307 @@
+use crate::bezier_util::PathRegion;
+use crate::color::RGB;
+use crate::growth_image_builder::GrowthImageBuilder;
+use crate::palettes::{Palette, SphericalPalette, UniformPalette};
+use crate::topology::PixelLoc;
+
+// Higher-level description of a single-layer image, for generating
+// wallpapers without setting up stages and regions by hand.  Focal
+// points are grown first, then the vignette, then the background
+// fills in the remainder of the image.
+pub struct Composition {
+    width: u32,
+    height: u32,
+    focal_points: Vec<FocalPoint>,
+    vignette: Option<Vignette>,
+    background: Box<dyn Palette>,
+}
+
+struct FocalPoint {
+    loc: PixelLoc,
+    palette: Box<dyn Palette>,
+    radius: f64,
+}
+
+struct Vignette {
+    color: RGB,
+    width: u32,
+}
+
+// Spread of the colors used for the vignette.
+const VIGNETTE_COLOR_RADIUS: f32 = 30.0;
+
+// Number of seed points along the border, for the vignette.
+const VIGNETTE_SEED_POINTS: u32 = 8;
+
+impl Composition {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            focal_points: Vec::new(),
+            vignette: None,
+            background: Box::new(UniformPalette),
+        }
+    }
+
+    // A disk of the given radius, grown outward from its center.
+    pub fn focal_point<T>(
+        &mut self,
+        loc: PixelLoc,
+        palette: T,
+        radius: f64,
+    ) -> &mut Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.focal_points.push(FocalPoint {
+            loc,
+            palette: Box::new(palette),
+            radius,
+        });
+        self
+    }
+
+    pub fn background<T>(&mut self, palette: T) -> &mut Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.background = Box::new(palette);
+        self
+    }
+
+    // A border of the given width around the image, using colors near
+    // the given color.
+    pub fn vignette(&mut self, color: RGB, width: u32) -> &mut Self {
+        self.vignette = Some(Vignette { color, width });
+        self
+    }
+
+    fn all_points(&self) -> impl Iterator<Item = PixelLoc> {
+        let width = self.width as i32;
+        (0..self.height as i32).flat_map(move |j| {
+            (0..width).map(move |i| PixelLoc { layer: 0, i, j })
+        })
+    }
+
+    // Convert to a builder.  Additional options (epsilon, seed,
+    // animations) can be set on the builder before running.
+    pub fn into_builder(self) -> GrowthImageBuilder {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(self.width, self.height);
+
+        let all_points = self.all_points().collect::<Vec<_>>();
+
+        self.focal_points.into_iter().for_each(|focal| {
+            let radius2 = focal.radius * focal.radius;
+            let disk = all_points
+                .iter()
+                .filter(|loc| {
+                    let di = (loc.i - focal.loc.i) as f64;
+                    let dj = (loc.j - focal.loc.j) as f64;
+                    di * di + dj * dj <= radius2
+                })
+                .copied()
+                .collect::<Vec<_>>();
+
+            builder
+                .new_stage()
+                .palette(focal.palette)
+                .n_colors(disk.len() as u32)
+                .grow_from_previous(false)
+                .seed_points(vec![focal.loc])
+                .allowed_points(disk);
+        });
+
+        if let Some(vignette) = self.vignette {
+            let border = vignette.width as i32;
+            let (width, height) = (self.width as i32, self.height as i32);
+            let band = all_points
+                .iter()
+                .filter(|loc| {
+                    loc.i < border
+                        || loc.j < border
+                        || loc.i >= width - border
+                        || loc.j >= height - border
+                })
+                .copied()
+                .collect::<Vec<_>>();
+
+            builder
+                .new_stage()
+                .palette(SphericalPalette {
+                    central_color: vignette.color,
+                    color_radius: VIGNETTE_COLOR_RADIUS,
+                })
+                .n_colors(band.len() as u32)
+                .grow_from_previous(false)
+                .num_random_seed_points(VIGNETTE_SEED_POINTS)
+                .allowed_points(band);
+        }
+
+        // The background fills in the rest of the image, growing
+        // outward from everything placed so far.  If nothing has been
+        // placed, the first stage starts from a random point.
+        builder.new_stage().palette(self.background);
+
+        builder
+    }
+}
+
+// Text grown one glyph at a time, with one stage per glyph, so that
+// a word appears letter by letter in an animation.  Outlines are
+// given in pixel coordinates, such as from a font library's outline
+// builder, since fonts are not read here.  Each glyph uses colors
+// near its own shade of the base color, rotated in hue from the
+// previous glyph, and grows from the pixel nearest its centroid.
+pub struct Lettering {
+    layer: u8,
+    width: u32,
+    height: u32,
+    glyphs: Vec<PathRegion>,
+    base_color: RGB,
+    color_radius: f32,
+    hue_step: f32,
+}
+
+impl Lettering {
+    // The size is that of the layer the text is drawn on.
+    pub fn new(layer: u8, width: u32, height: u32) -> Self {
+        Self {
+            layer,
+            width,
+            height,
+            glyphs: Vec::new(),
+            base_color: RGB {
+                vals: [255, 255, 255],
+            },
+            color_radius: 30.0,
+            hue_step: 30.0,
+        }
+    }
+
+    pub fn glyph(&mut self, outline: impl Into<PathRegion>) -> &mut Self {
+        self.glyphs.push(outline.into());
+        self
+    }
+
+    pub fn color(&mut self, base_color: RGB, color_radius: f32) -> &mut Self {
+        self.base_color = base_color;
+        self.color_radius = color_radius;
+        self
+    }
+
+    // Hue rotation, in degrees, between the shades of adjacent glyphs.
+    pub fn hue_step(&mut self, degrees: f32) -> &mut Self {
+        self.hue_step = degrees;
+        self
+    }
+
+    // Pixels of each glyph, in order.  Glyphs with no pixels, such as
+    // spaces, are empty.
+    pub fn glyph_points(&self) -> Vec<Vec<PixelLoc>> {
+        self.glyphs
+            .iter()
+            .map(|glyph| glyph.rasterize(self.layer, self.width, self.height))
+            .collect()
+    }
+
+    // Add one stage per glyph to the builder, in order.  Glyphs with
+    // no pixels are skipped, but still advance the hue.  Later stages
+    // can fill in the background around the text.
+    pub fn add_stages(&self, builder: &mut GrowthImageBuilder) {
+        self.glyph_points()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, points)| !points.is_empty())
+            .for_each(|(index, points)| {
+                let n = points.len() as f64;
+                let ci = points.iter().map(|loc| loc.i as f64).sum::<f64>() / n;
+                let cj = points.iter().map(|loc| loc.j as f64).sum::<f64>() / n;
+                // The centroid may lie outside the glyph, such as for
+                // an "O", so seed from the nearest pixel inside it.
+                let seed = *points
+                    .iter()
+                    .min_by(|a, b| {
+                        let dist2 = |loc: &PixelLoc| {
+                            let di = loc.i as f64 - ci;
+                            let dj = loc.j as f64 - cj;
+                            di * di + dj * dj
+                        };
+                        dist2(a).partial_cmp(&dist2(b)).unwrap()
+                    })
+                    .unwrap();
+
+                builder
+                    .new_stage()
+                    .palette(SphericalPalette {
+                        central_color: self
+                            .base_color
+                            .rotate_hue(self.hue_step * index as f32),
+                        color_radius: self.color_radius,
+                    })
+                    .n_colors(points.len() as u32)
+                    .grow_from_previous(false)
+                    .seed_points(vec![seed])
+                    .allowed_points(points);
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kurbo::{BezPath, Rect, Shape};
+
+    use crate::errors::Error;
+
+    #[test]
+    fn test_lettering() -> Result<(), Error> {
+        let rect = |x0, x1| Rect::new(x0, 2.0, x1, 8.0).to_path(0.1);
+        let ring = PathRegion::from(rect(12.0, 18.0))
+            .difference(Rect::new(13.5, 3.5, 16.5, 6.5).to_path(0.1));
+
+        let mut lettering = Lettering::new(0, 20, 10);
+        lettering
+            .glyph(rect(1.0, 5.0))
+            .glyph(BezPath::new())
+            .glyph(ring);
+        let glyphs = lettering.glyph_points();
+        assert_eq!(glyphs.len(), 3);
+        assert!(glyphs[1].is_empty());
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 10).seed(0);
+        lettering.add_stages(&mut builder);
+
+        // The empty glyph has no stage, and each seed lies within its
+        // glyph.
+        let stages = builder.scene_spec()?.stages;
+        assert_eq!(stages.len(), 2);
+        let seed = |stage: usize| stages[stage].seed_points.clone().unwrap()[0];
+        assert!(glyphs[0].contains(&seed(0)));
+        assert!(glyphs[2].contains(&seed(1)));
+
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Only the glyph pixels are filled, since there is no
+        // background stage.
+        let filled = |loc: &PixelLoc| image.get_pixel(*loc).is_some();
+        assert!(glyphs.iter().flatten().all(filled));
+        assert!(!filled(&PixelLoc {
+            layer: 0,
+            i: 15,
+            j: 5
+        }));
+        assert_eq!(
+            (0..20)
+                .flat_map(|i| (0..10).map(move |j| PixelLoc { layer: 0, i, j }))
+                .filter(filled)
+                .count(),
+            glyphs[0].len() + glyphs[2].len()
+        );
+        Ok(())
+    }
+}
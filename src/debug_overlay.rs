@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+
+use crate::color::RGB;
+
+// Features of the growth state that can be marked on animation
+// frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverlayKind {
+    // Points that may be filled next.
+    Frontier,
+
+    // Empty points that cannot be filled during the current stage.
+    Forbidden,
+
+    // Endpoints of each portal.
+    Portals,
+
+    // Seed points of the current stage, drawn as small crosses so
+    // that they remain visible once surrounded.
+    Seeds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlayStyle {
+    pub color: RGB,
+    pub enabled: bool,
+}
+
+// Markers drawn on top of the per-layer images of an animation, to
+// help debug why growth is stuck without needing to export separate
+// image types.  All overlays start out disabled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugOverlay {
+    pub frontier: OverlayStyle,
+    pub forbidden: OverlayStyle,
+    pub portals: OverlayStyle,
+    pub seeds: OverlayStyle,
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        let style = |vals| OverlayStyle {
+            color: RGB { vals },
+            enabled: false,
+        };
+        Self {
+            frontier: style([255, 255, 255]),
+            forbidden: style([128, 0, 0]),
+            portals: style([0, 255, 255]),
+            seeds: style([255, 0, 255]),
+        }
+    }
+}
+
+impl DebugOverlay {
+    pub fn style(&self, kind: OverlayKind) -> &OverlayStyle {
+        match kind {
+            OverlayKind::Frontier => &self.frontier,
+            OverlayKind::Forbidden => &self.forbidden,
+            OverlayKind::Portals => &self.portals,
+            OverlayKind::Seeds => &self.seeds,
+        }
+    }
+
+    pub fn style_mut(&mut self, kind: OverlayKind) -> &mut OverlayStyle {
+        match kind {
+            OverlayKind::Frontier => &mut self.frontier,
+            OverlayKind::Forbidden => &mut self.forbidden,
+            OverlayKind::Portals => &mut self.portals,
+            OverlayKind::Seeds => &mut self.seeds,
+        }
+    }
+
+    pub fn any_enabled(&self) -> bool {
+        [
+            OverlayKind::Frontier,
+            OverlayKind::Forbidden,
+            OverlayKind::Portals,
+            OverlayKind::Seeds,
+        ]
+        .iter()
+        .any(|&kind| self.style(kind).enabled)
+    }
+}
+
+// Shared handle to a DebugOverlay, so that overlays can be toggled
+// while the image is growing, such as from a FrameSink's key
+// handler or from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct DebugOverlayHandle(Arc<Mutex<DebugOverlay>>);
+
+impl DebugOverlayHandle {
+    pub fn new(overlay: DebugOverlay) -> Self {
+        Self(Arc::new(Mutex::new(overlay)))
+    }
+
+    // Copy of the current settings.
+    pub fn get(&self) -> DebugOverlay {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set_enabled(&self, kind: OverlayKind, enabled: bool) {
+        self.0.lock().unwrap().style_mut(kind).enabled = enabled;
+    }
+
+    // Returns whether the overlay is enabled after toggling.
+    pub fn toggle(&self, kind: OverlayKind) -> bool {
+        let mut overlay = self.0.lock().unwrap();
+        let style = overlay.style_mut(kind);
+        style.enabled = !style.enabled;
+        style.enabled
+    }
+
+    pub fn set_color(&self, kind: OverlayKind, color: RGB) {
+        self.0.lock().unwrap().style_mut(kind).color = color;
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::Path;
+
+use crate::errors::Error;
+use crate::growth_image::SaveImageData;
+
+// Tile size and overlap conventionally used for Deep Zoom images, so
+// that a tile with overlap on both sides is 256 pixels across.
+pub(crate) const DEEPZOOM_TILE_SIZE: u32 = 254;
+pub(crate) const DEEPZOOM_OVERLAP: u32 = 1;
+
+// Write a Deep Zoom (DZI) tile pyramid, as read by browser viewers
+// such as OpenSeadragon.  The directory holds "image.dzi", describing
+// the pyramid, and "image_files/{level}/{col}_{row}.png".  The
+// highest level is the full image, and each level below it is half
+// the size, down to level 0 at a single pixel.
+pub(crate) fn write_deepzoom(
+    image: &SaveImageData,
+    dir: &Path,
+    tile_size: u32,
+    overlap: u32,
+) -> Result<(), Error> {
+    let tile_size = tile_size.max(1);
+    let max_level = num_levels(image.width, image.height) - 1;
+
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(
+        dir.join("image.dzi"),
+        format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\"",
+                " Format=\"png\" Overlap=\"{}\" TileSize=\"{}\">\n",
+                "  <Size Width=\"{}\" Height=\"{}\"/>\n",
+                "</Image>\n"
+            ),
+            overlap, tile_size, image.width, image.height
+        ),
+    )?;
+
+    // Each level is made from the one above it, rather than from the
+    // full image.
+    let mut level_image = None;
+    for level in (0..=max_level).rev() {
+        let current = match level_image.take() {
+            None => image.crop(0, 0, image.width, image.height),
+            Some(above) => downsample(&above),
+        };
+
+        let level_dir = dir.join("image_files").join(level.to_string());
+        std::fs::create_dir_all(&level_dir)?;
+
+        let cols = current.width.div_ceil(tile_size);
+        let rows = current.height.div_ceil(tile_size);
+        for row in 0..rows {
+            for col in 0..cols {
+                let (i0, i1) =
+                    tile_range(col, tile_size, overlap, current.width);
+                let (j0, j1) =
+                    tile_range(row, tile_size, overlap, current.height);
+                current
+                    .crop(i0, j0, i1 - i0, j1 - j0)
+                    .write_png(level_dir.join(format!("{}_{}.png", col, row)));
+            }
+        }
+
+        level_image = Some(current);
+    }
+
+    Ok(())
+}
+
+// Number of levels needed to halve the image down to a single pixel.
+fn num_levels(width: u32, height: u32) -> u32 {
+    let max_dim = width.max(height).max(1);
+    let mut levels = 1;
+    while (1u64 << (levels - 1)) < max_dim as u64 {
+        levels += 1;
+    }
+    levels
+}
+
+// Start and end of a tile along one axis, including the overlap with
+// its neighbors.
+fn tile_range(
+    index: u32,
+    tile_size: u32,
+    overlap: u32,
+    size: u32,
+) -> (u32, u32) {
+    let start = (index * tile_size).saturating_sub(overlap);
+    let end = ((index + 1) * tile_size + overlap).min(size);
+    (start, end)
+}
+
+// Half the size, rounding up, with each pixel the average of up to
+// four pixels.  Colors are weighted by alpha, so that transparent
+// pixels don't darken the edges of the filled region.
+fn downsample(image: &SaveImageData) -> SaveImageData {
+    let width = image.width.div_ceil(2);
+    let height = image.height.div_ceil(2);
+    let data = (0..height)
+        .flat_map(|j| (0..width).map(move |i| (i, j)))
+        .flat_map(|(i, j)| {
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                let (si, sj) = (2 * i + di, 2 * j + dj);
+                if si >= image.width || sj >= image.height {
+                    continue;
+                }
+                let start = 4 * (sj * image.width + si) as usize;
+                let p = &image.data[start..start + 4];
+                let alpha = p[3] as u32;
+                (0..3).for_each(|c| sum[c] += p[c] as u32 * alpha);
+                sum[3] += alpha;
+                count += 1;
+            }
+            let color = |c: usize| match sum[3] {
+                0 => 0,
+                total => ((sum[c] + total / 2) / total) as u8,
+            };
+            vec![
+                color(0),
+                color(1),
+                color(2),
+                ((sum[3] + count / 2) / count) as u8,
+            ]
+        })
+        .collect();
+    SaveImageData {
+        data,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_deepzoom() -> Result<(), Error> {
+        let (width, height) = (10, 5);
+        let image = SaveImageData {
+            data: (0..width * height)
+                .flat_map(|index| vec![(index * 5) as u8, 0, 255, 255])
+                .collect(),
+            width,
+            height,
+        };
+        let dir = std::env::temp_dir()
+            .join(format!("omnicolor-test-deepzoom-{}", std::process::id()));
+        write_deepzoom(&image, &dir, 4, 1)?;
+
+        let dzi = std::fs::read_to_string(dir.join("image.dzi"))?;
+        assert!(dzi.contains("TileSize=\"4\""));
+        assert!(dzi.contains("<Size Width=\"10\" Height=\"5\"/>"));
+
+        // 10x5, 5x3, 3x2, 2x1, 1x1
+        let tile_size = |level: u32, col: u32, row: u32| {
+            let path = dir
+                .join("image_files")
+                .join(level.to_string())
+                .join(format!("{}_{}.png", col, row));
+            SaveImageData::read_png(&path)
+                .ok()
+                .map(|tile| (tile.width, tile.height))
+        };
+        assert_eq!(tile_size(4, 0, 0), Some((5, 5)));
+        assert_eq!(tile_size(4, 1, 0), Some((6, 5)));
+        assert_eq!(tile_size(4, 2, 0), Some((3, 5)));
+        assert_eq!(tile_size(4, 3, 0), None);
+        assert_eq!(tile_size(3, 1, 0), Some((2, 3)));
+        assert_eq!(tile_size(0, 0, 0), Some((1, 1)));
+        assert_eq!(tile_size(5, 0, 0), None);
+
+        // The full image is unchanged at the top level.
+        let top = SaveImageData::read_png(
+            &dir.join("image_files").join("4").join("0_0.png"),
+        )?;
+        assert_eq!(top.data[..20], image.crop(0, 0, 5, 1).data[..]);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsample_ignores_transparent() {
+        let image = SaveImageData {
+            data: vec![200, 100, 50, 255, 0, 0, 0, 0, 10, 20, 30, 255],
+            width: 3,
+            height: 1,
+        };
+        let half = downsample(&image);
+        assert_eq!((half.width, half.height), (2, 1));
+        assert_eq!(half.data, vec![200, 100, 50, 128, 10, 20, 30, 255]);
+    }
+}
@@ -0,0 +1,533 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::{RngCore, SeedableRng};
+
+use crate::color::RGB;
+use crate::errors::Error;
+use crate::growth_image::SaveImageData;
+use crate::growth_image_builder::GrowthImageBuilder;
+use crate::palettes::{IndexedPalette, Palette};
+
+// Rendering of a large image as separate tiles, split across several
+// processes or machines.  The queue is a directory on a filesystem
+// shared by all of them, with one file per tile in each of the
+// pending/, claimed/ and done/ subdirectories.  A worker claims a
+// tile by renaming its job file from pending/ to claimed/, which
+// only one worker can succeed at, and writes the finished tile to
+// done/ as a PNG.
+//
+// Each tile grows independently, so seams between tiles are visible
+// unless the tiles' stages are arranged to hide them.  Colors are
+// still unique across the full image, so long as each tile takes its
+// colors from TileJob::palette().  Nothing is checked about the
+// coordinator and workers agreeing on the palette, or on how a tile
+// is set up, so each should run the same program.
+pub struct Coordinator {
+    queue_dir: PathBuf,
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    seed: u64,
+}
+
+// One tile of the image, as read by a worker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileJob {
+    pub index: usize,
+    pub num_tiles: usize,
+    // Top-left corner of the tile within the full image.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub seed: u64,
+}
+
+// Colors of a palette sized to the full image, in a shuffled order,
+// of which each tile uses a range as long as its number of pixels.
+// Every process uses the same seed for the palette and the shuffle,
+// so the tiles' colors are disjoint without communicating, and each
+// tile's colors are spread over the whole palette.  Only the tile's
+// own colors are generated, by looking up where each position of
+// its range lands in the shuffle.
+#[derive(Clone)]
+pub struct PalettePartition {
+    palette: Arc<dyn IndexedPalette>,
+    shuffle: Permutation,
+    total_colors: u64,
+    seed: u64,
+    offset: u64,
+    len: u64,
+}
+
+// Seeded shuffle of 0..len, as a Feistel network over the smallest
+// power of four that holds len.  Values that land outside of 0..len
+// are shuffled again until they are inside it, which keeps it a
+// bijection on 0..len.
+#[derive(Clone)]
+struct Permutation {
+    len: u64,
+    half_bits: u32,
+    keys: [u64; 4],
+}
+
+fn job_name(index: usize) -> String {
+    format!("tile-{:05}.job", index)
+}
+
+fn tile_name(index: usize) -> String {
+    format!("tile-{:05}.png", index)
+}
+
+impl Coordinator {
+    pub fn new(queue_dir: impl AsRef<Path>, width: u32, height: u32) -> Self {
+        Self {
+            queue_dir: queue_dir.as_ref().to_path_buf(),
+            width,
+            height,
+            tile_width: 1024,
+            tile_height: 1024,
+            seed: 0,
+        }
+    }
+
+    // Tiles at the right and bottom edges may be smaller.
+    pub fn tile_size(&mut self, width: u32, height: u32) -> &mut Self {
+        self.tile_width = width.max(1);
+        self.tile_height = height.max(1);
+        self
+    }
+
+    // Seed of the shared palette, also available to the workers for
+    // seeding each tile.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn jobs(&self) -> Vec<TileJob> {
+        let cols = self.width.div_ceil(self.tile_width);
+        let rows = self.height.div_ceil(self.tile_height);
+        let num_tiles = (cols * rows) as usize;
+        (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| (row, col)))
+            .enumerate()
+            .map(|(index, (row, col))| {
+                let x = col * self.tile_width;
+                let y = row * self.tile_height;
+                TileJob {
+                    index,
+                    num_tiles,
+                    x,
+                    y,
+                    width: self.tile_width.min(self.width - x),
+                    height: self.tile_height.min(self.height - y),
+                    image_width: self.width,
+                    image_height: self.height,
+                    seed: self.seed,
+                }
+            })
+            .collect()
+    }
+
+    // Write a job file for each tile, returning the number of tiles.
+    // Any tiles from a previous submission are removed.
+    pub fn submit(&self) -> Result<usize, Error> {
+        vec!["pending", "claimed", "done"]
+            .into_iter()
+            .try_for_each(|subdir| {
+                let dir = self.queue_dir.join(subdir);
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+                std::fs::create_dir_all(&dir)
+            })?;
+
+        let jobs = self.jobs();
+        jobs.iter().try_for_each(|job| {
+            // Written under another name and then renamed, so that a
+            // worker never reads a partial job file.
+            let tmp =
+                self.queue_dir.join(format!("{}.tmp", job_name(job.index)));
+            std::fs::write(&tmp, job.to_text())?;
+            std::fs::rename(
+                &tmp,
+                self.queue_dir.join("pending").join(job_name(job.index)),
+            )
+        })?;
+        Ok(jobs.len())
+    }
+
+    pub fn num_done(&self) -> Result<usize, Error> {
+        Ok(std::fs::read_dir(self.queue_dir.join("done"))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry.path().extension().is_some_and(|ext| ext == "png")
+            })
+            .count())
+    }
+
+    pub fn is_done(&self) -> Result<bool, Error> {
+        Ok(self.num_done()? == self.jobs().len())
+    }
+
+    // Return claimed tiles to the queue, such as after a worker has
+    // stopped without finishing its tile.  Returns the number of
+    // tiles requeued.
+    pub fn requeue_claimed(&self) -> Result<usize, Error> {
+        let mut num_requeued = 0;
+        for entry in std::fs::read_dir(self.queue_dir.join("claimed"))? {
+            let path = entry?.path();
+            let name = path.file_name().ok_or(Error::InvalidJobFile)?;
+            if self
+                .queue_dir
+                .join("done")
+                .join(Path::new(name).with_extension("png"))
+                .exists()
+            {
+                std::fs::remove_file(&path)?;
+            } else {
+                std::fs::rename(
+                    &path,
+                    self.queue_dir.join("pending").join(name),
+                )?;
+                num_requeued += 1;
+            }
+        }
+        Ok(num_requeued)
+    }
+
+    // Combine the finished tiles into a PNG of the full image.  Fails
+    // if any tile is missing, or is not the size of its job.
+    pub fn stitch(&self, filename: impl AsRef<Path>) -> Result<(), Error> {
+        let mut image = SaveImageData {
+            data: vec![0; 4 * (self.width * self.height) as usize],
+            width: self.width,
+            height: self.height,
+        };
+
+        self.jobs().iter().try_for_each(|job| {
+            let tile = SaveImageData::read_png(
+                &self.queue_dir.join("done").join(tile_name(job.index)),
+            )?;
+            if (tile.width, tile.height) != (job.width, job.height) {
+                return Err(Error::ImageSizeMismatch);
+            }
+            let row_bytes = 4 * job.width as usize;
+            tile.data.chunks_exact(row_bytes).enumerate().for_each(
+                |(j, row)| {
+                    let start =
+                        4 * ((job.y + j as u32) * self.width + job.x) as usize;
+                    image.data[start..start + row_bytes].copy_from_slice(row);
+                },
+            );
+            Ok(())
+        })?;
+
+        image.write_png(filename.as_ref().to_path_buf());
+        Ok(())
+    }
+}
+
+impl TileJob {
+    // Partition of the palette for this tile.  The palette must be
+    // the same on every worker.
+    pub fn palette<P>(&self, palette: P) -> PalettePartition
+    where
+        P: IndexedPalette + Sized + 'static,
+    {
+        let total_colors = self.image_width as u64 * self.image_height as u64;
+        PalettePartition {
+            palette: Arc::new(palette),
+            shuffle: Permutation::new(total_colors, self.seed),
+            total_colors,
+            seed: self.seed,
+            offset: self.color_offset(),
+            len: self.width as u64 * self.height as u64,
+        }
+    }
+
+    // Number of pixels in the tiles before this one.  Tiles are in
+    // row-major order, and all tiles of a row have the same height.
+    fn color_offset(&self) -> u64 {
+        self.y as u64 * self.image_width as u64
+            + self.x as u64 * self.height as u64
+    }
+
+    fn to_text(&self) -> String {
+        format!(
+            "index {}\nnum_tiles {}\nx {}\ny {}\nwidth {}\nheight {}\n\
+             image_width {}\nimage_height {}\nseed {}\n",
+            self.index,
+            self.num_tiles,
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+            self.image_width,
+            self.image_height,
+            self.seed
+        )
+    }
+
+    fn from_text(text: &str) -> Result<Self, Error> {
+        let values = text
+            .lines()
+            .map(|line| {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some(key), Some(value), None) => Ok((key, value)),
+                    _ => Err(Error::InvalidJobFile),
+                }
+            })
+            .collect::<Result<std::collections::HashMap<_, _>, _>>()?;
+        let get = |key: &str| -> Result<u64, Error> {
+            Ok(values.get(key).ok_or(Error::InvalidJobFile)?.parse()?)
+        };
+
+        Ok(Self {
+            index: get("index")? as usize,
+            num_tiles: get("num_tiles")? as usize,
+            x: get("x")? as u32,
+            y: get("y")? as u32,
+            width: get("width")? as u32,
+            height: get("height")? as u32,
+            image_width: get("image_width")? as u32,
+            image_height: get("image_height")? as u32,
+            seed: get("seed")?,
+        })
+    }
+}
+
+impl Palette for PalettePartition {
+    // Returns fewer colors than requested if the partition doesn't
+    // have enough.
+    fn generate(&self, n_colors: u32, _: &mut dyn RngCore) -> Vec<RGB> {
+        (self.offset..self.offset + self.len.min(n_colors as u64))
+            .flat_map(|pos| {
+                self.palette.color_at(
+                    self.shuffle.apply(pos),
+                    self.total_colors,
+                    self.seed,
+                )
+            })
+            .collect()
+    }
+}
+
+impl Permutation {
+    fn new(len: u64, seed: u64) -> Self {
+        let bits = 64 - len.saturating_sub(1).leading_zeros();
+        // Kept apart from the random values used by the palette.
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(1);
+        Self {
+            len,
+            half_bits: bits.div_ceil(2).max(1),
+            keys: [
+                rng.next_u64(),
+                rng.next_u64(),
+                rng.next_u64(),
+                rng.next_u64(),
+            ],
+        }
+    }
+
+    fn apply(&self, index: u64) -> u64 {
+        let mut value = self.feistel(index);
+        while value >= self.len {
+            value = self.feistel(value);
+        }
+        value
+    }
+
+    fn feistel(&self, value: u64) -> u64 {
+        let mask = (1u64 << self.half_bits) - 1;
+        let (mut left, mut right) = (value >> self.half_bits, value & mask);
+        self.keys.iter().for_each(|&key| {
+            let next = left ^ (mix(right ^ key) & mask);
+            left = right;
+            right = next;
+        });
+        (left << self.half_bits) | right
+    }
+}
+
+// Round function of the Feistel network, from the SplitMix64
+// finalizer.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Renders tiles from the queue until none are left.  Every worker
+// uses the same function to set up a tile's builder, which should
+// have a single layer of the tile's size.
+pub struct Worker {
+    queue_dir: PathBuf,
+}
+
+impl Worker {
+    pub fn new(queue_dir: impl AsRef<Path>) -> Self {
+        Self {
+            queue_dir: queue_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // Claim the next pending tile, if any remain.
+    pub fn claim(&self) -> Result<Option<TileJob>, Error> {
+        let mut pending = std::fs::read_dir(self.queue_dir.join("pending"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        pending.sort();
+
+        for path in pending {
+            let claimed = self
+                .queue_dir
+                .join("claimed")
+                .join(path.file_name().ok_or(Error::InvalidJobFile)?);
+            // Another worker claimed it first.
+            if std::fs::rename(&path, &claimed).is_err() {
+                continue;
+            }
+            return Ok(Some(TileJob::from_text(&std::fs::read_to_string(
+                &claimed,
+            )?)?));
+        }
+        Ok(None)
+    }
+
+    // Render tiles until the queue is empty, returning the number of
+    // tiles rendered by this worker.
+    pub fn run<F>(&self, build: F) -> Result<usize, Error>
+    where
+        F: Fn(&TileJob) -> Result<GrowthImageBuilder, Error>,
+    {
+        let mut num_rendered = 0;
+        while let Some(job) = self.claim()? {
+            let mut image = build(&job)?.build()?;
+            image.fill_until_done();
+
+            let done = self.queue_dir.join("done");
+            let tmp = done.join(format!("{}.tmp", tile_name(job.index)));
+            image.write(tmp.clone());
+            std::fs::rename(&tmp, done.join(tile_name(job.index)))?;
+            std::fs::remove_file(
+                self.queue_dir.join("claimed").join(job_name(job.index)),
+            )?;
+            num_rendered += 1;
+        }
+        Ok(num_rendered)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use crate::palettes::UniformPalette;
+
+    #[test]
+    fn test_job_text() -> Result<(), Error> {
+        let mut coordinator = Coordinator::new("unused", 10, 7);
+        coordinator.tile_size(4, 4).seed(5);
+        let jobs = coordinator.jobs();
+        assert_eq!(jobs.len(), 6);
+        assert_eq!((jobs[5].x, jobs[5].y), (8, 4));
+        assert_eq!((jobs[5].width, jobs[5].height), (2, 3));
+        assert_eq!(jobs[5].color_offset(), 4 * 10 + 8 * 3);
+        for job in &jobs {
+            assert_eq!(&TileJob::from_text(&job.to_text())?, job);
+        }
+        assert!(matches!(
+            TileJob::from_text("index 1"),
+            Err(Error::InvalidJobFile)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_permutation() {
+        [1, 2, 7, 64, 1000].iter().for_each(|&len| {
+            let shuffle = Permutation::new(len, 4);
+            let values = (0..len)
+                .map(|index| shuffle.apply(index))
+                .collect::<HashSet<_>>();
+            assert_eq!(values.len() as u64, len);
+            assert!(values.iter().all(|&value| value < len));
+        });
+
+        // Tiles far past u32::MAX pixels into a large image still get
+        // their own colors.
+        let job = TileJob {
+            index: 0,
+            num_tiles: 1,
+            x: 60_000,
+            y: 90_000,
+            width: 4,
+            height: 4,
+            image_width: 100_000,
+            image_height: 100_000,
+            seed: 0,
+        };
+        assert_eq!(job.color_offset(), 9_000_240_000);
+        let colors = job
+            .palette(UniformPalette)
+            .generate(100, &mut rand_chacha::ChaCha8Rng::seed_from_u64(0));
+        assert_eq!(colors.len(), 16);
+    }
+
+    #[test]
+    fn test_distributed_render() -> Result<(), Error> {
+        let queue_dir = std::env::temp_dir()
+            .join(format!("omnicolor-test-distributed-{}", std::process::id()));
+        let mut coordinator = Coordinator::new(&queue_dir, 12, 10);
+        coordinator.tile_size(5, 5).seed(3);
+        assert_eq!(coordinator.submit()?, 6);
+
+        let build = |job: &TileJob| -> Result<GrowthImageBuilder, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder
+                .add_layer(job.width, job.height)
+                .seed(job.index as u64);
+            builder.new_stage().palette(job.palette(UniformPalette));
+            Ok(builder)
+        };
+
+        // Two workers sharing the queue.
+        let rendered = std::thread::scope(|scope| {
+            let handles = (0..2)
+                .map(|_| scope.spawn(|| Worker::new(&queue_dir).run(build)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        assert_eq!(rendered.iter().sum::<usize>(), 6);
+        assert!(coordinator.is_done()?);
+        assert_eq!(coordinator.requeue_claimed()?, 0);
+
+        let filename = queue_dir.join("stitched.png");
+        coordinator.stitch(&filename)?;
+        let image = SaveImageData::read_png(&filename)?;
+        assert_eq!((image.width, image.height), (12, 10));
+        let colors = image
+            .data
+            .chunks_exact(4)
+            .map(|p| (p[0], p[1], p[2]))
+            .collect::<HashSet<_>>();
+        assert_eq!(colors.len(), 12 * 10);
+
+        std::fs::remove_dir_all(&queue_dir)?;
+        Ok(())
+    }
+}
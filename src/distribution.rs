@@ -0,0 +1,108 @@
+use rand::Rng;
+
+// Shape of the randomness used by stage options such as
+// color_jitter_distribution(), seed_point_jitter(), and
+// selection_noise(), in place of the uniform randomness used
+// elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RandomDistribution {
+    // Equally likely to be anywhere from min to max.
+    Uniform { min: f64, max: f64 },
+
+    // Most often near the mean, with about two thirds of samples
+    // within one standard deviation of it.
+    Normal { mean: f64, std_dev: f64 },
+
+    // Always positive, with small values most likely, and occasional
+    // values several times larger than the mean.
+    Exponential { mean: f64 },
+}
+
+impl RandomDistribution {
+    pub fn sample(&self, rng: &mut impl Rng) -> f64 {
+        match *self {
+            RandomDistribution::Uniform { min, max } => {
+                if max > min {
+                    rng.gen_range(min..max)
+                } else {
+                    min
+                }
+            }
+            RandomDistribution::Normal { mean, std_dev } => {
+                // Box-Muller transform.  1-u is in (0,1], so the log
+                // is finite.
+                let u: f64 = rng.gen();
+                let v: f64 = rng.gen();
+                let z = (-2.0 * (1.0 - u).ln()).sqrt()
+                    * (2.0 * std::f64::consts::PI * v).cos();
+                mean + std_dev * z
+            }
+            RandomDistribution::Exponential { mean } => {
+                let u: f64 = rng.gen();
+                -mean * (1.0 - u).ln()
+            }
+        }
+    }
+
+    // The distribution of samples multiplied by the factor, such as
+    // for a distance in pixels when shrinking an image.
+    pub fn scaled(&self, factor: f64) -> Self {
+        match *self {
+            RandomDistribution::Uniform { min, max } => {
+                RandomDistribution::Uniform {
+                    min: min * factor,
+                    max: max * factor,
+                }
+            }
+            RandomDistribution::Normal { mean, std_dev } => {
+                RandomDistribution::Normal {
+                    mean: mean * factor,
+                    std_dev: std_dev * factor,
+                }
+            }
+            RandomDistribution::Exponential { mean } => {
+                RandomDistribution::Exponential {
+                    mean: mean * factor,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    fn mean_and_std_dev(dist: RandomDistribution) -> (f64, f64) {
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        let n = 20000;
+        let samples = (0..n).map(|_| dist.sample(&mut rng)).collect::<Vec<_>>();
+        let mean = samples.iter().sum::<f64>() / (n as f64);
+        let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>()
+            / (n as f64);
+        (mean, var.sqrt())
+    }
+
+    #[test]
+    fn test_sample() {
+        let close = |(a, b): (f64, f64), (c, d): (f64, f64)| {
+            (a - c).abs() < 0.05 && (b - d).abs() < 0.05
+        };
+
+        let uniform = RandomDistribution::Uniform { min: 1.0, max: 3.0 };
+        assert!(close(mean_and_std_dev(uniform), (2.0, 1.0 / 3f64.sqrt())));
+
+        let normal = RandomDistribution::Normal {
+            mean: -1.0,
+            std_dev: 2.0,
+        };
+        assert!(close(mean_and_std_dev(normal), (-1.0, 2.0)));
+
+        let exponential = RandomDistribution::Exponential { mean: 1.5 };
+        assert!(close(mean_and_std_dev(exponential), (1.5, 1.5)));
+        assert!(close(mean_and_std_dev(exponential.scaled(2.0)), (3.0, 3.0)));
+    }
+}
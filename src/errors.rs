@@ -1,5 +1,8 @@
 use std::fmt::{Display, Formatter};
 
+use crate::memory::MemoryEstimate;
+use crate::topology::PixelLoc;
+
 #[derive(Debug)]
 pub enum Error {
     NoStagesDefined,
@@ -7,7 +10,35 @@ pub enum Error {
     ParseIntError(std::num::ParseIntError),
     ParseFloatError(std::num::ParseFloatError),
     VecLengthError(usize),
+    InvalidRegionFile,
+    InvalidKDTreeFile,
+    InvalidJobFile,
+    NaNCoordinate,
+    EmptyPath,
     IoError(std::io::Error),
+    PngDecodingError(png::DecodingError),
+    UnsupportedImageFormat,
+    ImageSizeMismatch,
+    UnreachablePixels {
+        stage: usize,
+        num_pixels: usize,
+    },
+    UnsupportedSceneOption(&'static str),
+    #[cfg(feature = "expressions")]
+    InvalidExpression(String),
+    NondeterministicOutput {
+        seed: u64,
+        first_difference: PixelLoc,
+    },
+    MemoryBudgetExceeded {
+        estimate: MemoryEstimate,
+        budget: usize,
+    },
+    FrameWriterPanicked,
+    #[cfg(feature = "exr")]
+    ExrError(exr::error::Error),
+    #[cfg(feature = "minifb")]
+    WindowError(minifb::Error),
     //NoneError,
 }
 
@@ -36,6 +67,26 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self {
+        Error::PngDecodingError(e)
+    }
+}
+
+#[cfg(feature = "exr")]
+impl From<exr::error::Error> for Error {
+    fn from(e: exr::error::Error) -> Self {
+        Error::ExrError(e)
+    }
+}
+
+#[cfg(feature = "minifb")]
+impl From<minifb::Error> for Error {
+    fn from(e: minifb::Error) -> Self {
+        Error::WindowError(e)
+    }
+}
+
 impl From<Vec<u8>> for Error {
     fn from(e: Vec<u8>) -> Self {
         Error::VecLengthError(e.len())
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::errors::Error;
+
+// Arithmetic expression, such as "0.3 * allowed_pixels", for options
+// of a SceneSpec that depend on the rest of the scene.  Supports
+// numbers, named variables, + - * / % ^, parentheses, and the
+// functions min, max, floor, ceil, round, abs, sqrt, sin, and cos.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+    Call(String, Vec<Expression>),
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+            text,
+        };
+        let expr = parser.sum()?;
+        parser.skip_whitespace();
+        match parser.chars.peek().copied() {
+            Some(c) => Err(parser.error(&format!("unexpected '{}'", c))),
+            None => Ok(expr),
+        }
+    }
+
+    pub fn eval(&self, variables: &HashMap<&str, f64>) -> Result<f64, Error> {
+        let invalid = |msg: String| Error::InvalidExpression(msg);
+        Ok(match self {
+            Expression::Number(val) => *val,
+            Expression::Variable(name) => {
+                *variables.get(name.as_str()).ok_or_else(|| {
+                    invalid(format!("unknown variable '{}'", name))
+                })?
+            }
+            Expression::Negate(expr) => -expr.eval(variables)?,
+            Expression::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(variables)?, rhs.eval(variables)?);
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    '%' => a.rem_euclid(b),
+                    _ => a.powf(b),
+                }
+            }
+            Expression::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(variables))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match (name.as_str(), args.as_slice()) {
+                    ("min", [a, b]) => a.min(*b),
+                    ("max", [a, b]) => a.max(*b),
+                    ("floor", [a]) => a.floor(),
+                    ("ceil", [a]) => a.ceil(),
+                    ("round", [a]) => a.round(),
+                    ("abs", [a]) => a.abs(),
+                    ("sqrt", [a]) => a.sqrt(),
+                    ("sin", [a]) => a.to_radians().sin(),
+                    ("cos", [a]) => a.to_radians().cos(),
+                    _ => {
+                        return Err(invalid(format!(
+                            "no function '{}' with {} arguments",
+                            name,
+                            args.len()
+                        )))
+                    }
+                }
+            }
+        })
+    }
+}
+
+// Recursive descent, with one function per level of precedence.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    text: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> Error {
+        Error::InvalidExpression(format!("{} in \"{}\"", msg, self.text))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    // Consume the next character if it is one of the options.
+    fn next_of(&mut self, options: &str) -> Option<char> {
+        self.skip_whitespace();
+        let c = *self.chars.peek()?;
+        if options.contains(c) {
+            self.chars.next();
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression, Error> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.next_of("+-") {
+            expr = Expression::Binary(
+                op,
+                Box::new(expr),
+                Box::new(self.product()?),
+            );
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expression, Error> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.next_of("*/%") {
+            expr =
+                Expression::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expression, Error> {
+        if self.next_of("-").is_some() {
+            Ok(Expression::Negate(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    // Right-associative, and binds tighter than negation on its left,
+    // so that -2^2 is -4.
+    fn power(&mut self) -> Result<Expression, Error> {
+        let base = self.atom()?;
+        if self.next_of("^").is_some() {
+            Ok(Expression::Binary(
+                '^',
+                Box::new(base),
+                Box::new(self.unary()?),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expression, Error> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('(') => {
+                self.chars.next();
+                let expr = self.sum()?;
+                self.next_of(")").ok_or_else(|| self.error("missing ')'"))?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let text = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                text.parse().map(Expression::Number).map_err(|_| {
+                    self.error(&format!("invalid number '{}'", text))
+                })
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                if self.next_of("(").is_none() {
+                    return Ok(Expression::Variable(name));
+                }
+                let mut args = Vec::new();
+                if self.next_of(")").is_none() {
+                    loop {
+                        args.push(self.sum()?);
+                        match self.next_of(",)") {
+                            Some(',') => {}
+                            Some(_) => break,
+                            None => return Err(self.error("missing ')'")),
+                        }
+                    }
+                }
+                Ok(Expression::Call(name, args))
+            }
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let mut output = String::new();
+        while let Some(&c) = self.chars.peek().filter(|&&c| pred(c)) {
+            output.push(c);
+            self.chars.next();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(text: &str) -> Result<f64, Error> {
+        let variables = vec![("width", 30.0), ("allowed_pixels", 200.0)]
+            .into_iter()
+            .collect();
+        Expression::parse(text)?.eval(&variables)
+    }
+
+    #[test]
+    fn test_eval() -> Result<(), Error> {
+        assert_eq!(eval("0.3 * allowed_pixels")?, 60.0);
+        assert_eq!(eval("1 + 2 * 3 - 4 / 2")?, 5.0);
+        assert_eq!(eval("(1 + 2) * 3")?, 9.0);
+        assert_eq!(eval("-2^2")?, -4.0);
+        assert_eq!(eval("2^3^2")?, 512.0);
+        assert_eq!(eval("-7 % 3")?, 2.0);
+        assert_eq!(eval("max(width / 4, 10) + floor(2.5)")?, 12.0);
+        assert!((eval("cos(60)")? - 0.5).abs() < 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        vec!["", "1 +", "(1 + 2", "2 3", "height", "max(1)", "1..2", "#"]
+            .into_iter()
+            .for_each(|text| {
+                assert!(
+                    matches!(eval(text), Err(Error::InvalidExpression(_))),
+                    "{}",
+                    text
+                );
+            });
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::Error;
+use crate::frame_sink::{FrameInfo, FrameSink};
+use crate::growth_image::SaveImageData;
+use crate::growth_image_builder::spawn_ffmpeg;
+
+// Animation frames held in memory, to be encoded once the image is
+// finished, rather than streamed to an ffmpeg process as they are
+// made.  The framerate can then be chosen knowing how many frames
+// there are.  Holds at most max_frames frames, dropping the oldest
+// once full.  Frames larger than the maximum size are downsampled by
+// an integer factor to fit.
+//
+// Clones share the same frames, so one clone can be given to
+// GrowthImageBuilder::add_output_buffer() and another kept for
+// encoding.
+#[derive(Clone)]
+pub struct FrameBuffer(Arc<Mutex<BufferedFrames>>);
+
+struct BufferedFrames {
+    frames: VecDeque<SaveImageData>,
+    max_frames: usize,
+    max_size: Option<(u32, u32)>,
+    num_dropped: usize,
+}
+
+impl FrameBuffer {
+    pub fn new(max_frames: usize, max_size: Option<(u32, u32)>) -> Self {
+        Self(Arc::new(Mutex::new(BufferedFrames {
+            frames: VecDeque::new(),
+            max_frames: max_frames.max(1),
+            max_size,
+            num_dropped: 0,
+        })))
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.0.lock().unwrap().frames.len()
+    }
+
+    // Number of frames dropped from the start, to stay within
+    // max_frames.
+    pub fn num_dropped(&self) -> usize {
+        self.0.lock().unwrap().num_dropped
+    }
+
+    // Size of the frame, and its 8-bit RGBA data.
+    pub fn frame(&self, index: usize) -> Option<(u32, u32, Vec<u8>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .frames
+            .get(index)
+            .map(|frame| (frame.width, frame.height, frame.data.clone()))
+    }
+
+    pub fn clear(&self) {
+        let mut buffer = self.0.lock().unwrap();
+        buffer.frames.clear();
+        buffer.num_dropped = 0;
+    }
+
+    // Encode the frames held so far with ffmpeg.  The frames are
+    // kept, so a video may be encoded again with other settings.
+    pub fn encode(&self, filename: &Path, fps: f64) -> Result<(), Error> {
+        let buffer = self.0.lock().unwrap();
+        let mut proc = spawn_ffmpeg(filename, fps)?;
+        buffer.frames.iter().for_each(|frame| {
+            frame.write_png_to_writer(&mut proc.stdin.as_ref().unwrap())
+        });
+        proc.wait()?;
+        Ok(())
+    }
+
+    // Encode the frames with the framerate that gives a video of the
+    // given length.
+    pub fn encode_with_duration(
+        &self,
+        filename: &Path,
+        seconds: f64,
+    ) -> Result<(), Error> {
+        let fps = (self.num_frames() as f64) / seconds;
+        self.encode(filename, fps)
+    }
+}
+
+impl FrameSink for FrameBuffer {
+    fn write_frame(&mut self, rgba: &[u8], info: &FrameInfo) {
+        let mut buffer = self.0.lock().unwrap();
+        let factor = buffer.max_size.map_or(1, |(max_width, max_height)| {
+            let factor = |size: u32, max: u32| size.div_ceil(max.max(1));
+            factor(info.width, max_width)
+                .max(factor(info.height, max_height))
+                .max(1)
+        });
+        let frame = downsample(rgba, info.width, info.height, factor);
+
+        if buffer.frames.len() >= buffer.max_frames {
+            buffer.frames.pop_front();
+            buffer.num_dropped += 1;
+        }
+        buffer.frames.push_back(frame);
+    }
+}
+
+// Average each block of factor x factor pixels.  Partial blocks at
+// the right and bottom edges average the pixels they contain.
+fn downsample(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    factor: u32,
+) -> SaveImageData {
+    if factor == 1 {
+        return SaveImageData {
+            data: rgba.to_vec(),
+            width,
+            height,
+        };
+    }
+
+    let out_width = width.div_ceil(factor);
+    let out_height = height.div_ceil(factor);
+    let data = (0..out_height)
+        .flat_map(|y| (0..out_width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            let mut sums = [0u32; 4];
+            let mut count = 0;
+            (y * factor..((y + 1) * factor).min(height))
+                .flat_map(|j| {
+                    (x * factor..((x + 1) * factor).min(width))
+                        .map(move |i| (i, j))
+                })
+                .for_each(|(i, j)| {
+                    let index = 4 * (j * width + i) as usize;
+                    sums.iter_mut()
+                        .zip(rgba[index..index + 4].iter())
+                        .for_each(|(sum, &val)| *sum += val as u32);
+                    count += 1;
+                });
+            sums.iter()
+                .map(|&sum| ((sum + count / 2) / count) as u8)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    SaveImageData {
+        data,
+        width: out_width,
+        height: out_height,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::growth_image_builder::GrowthImageBuilder;
+
+    #[test]
+    fn test_frame_buffer() -> Result<(), Error> {
+        let buffer = FrameBuffer::new(3, Some((5, 5)));
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 8).seed(0);
+        builder.new_stage().animation_iter_per_second(10.0);
+        builder.add_output_buffer(&buffer).fps(1.0);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        assert_eq!(buffer.num_frames(), 3);
+        assert_eq!(buffer.num_dropped(), 8 - 3);
+        let (width, height, data) = buffer.frame(2).unwrap();
+        assert_eq!((width, height), (5, 4));
+        assert_eq!(data.len(), 4 * 5 * 4);
+        assert!(buffer.frame(3).is_none());
+
+        buffer.clear();
+        assert_eq!(buffer.num_frames(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsample() {
+        let rgba = vec![
+            0, 0, 0, 255, 100, 0, 0, 255, 200, 0, 0, 255, //
+            0, 0, 0, 255, 100, 0, 0, 255, 50, 0, 0, 255, //
+        ];
+        let frame = downsample(&rgba, 3, 2, 2);
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!(frame.data, vec![50, 0, 0, 255, 125, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,26 @@
+// Metadata passed along with each animation frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameInfo {
+    // Number of frames previously sent to this sink.
+    pub frame_index: usize,
+
+    // Number of pixels filled so far.
+    pub num_filled_pixels: usize,
+
+    // Stage that was active when the frame was generated.
+    pub stage: Option<usize>,
+
+    pub width: u32,
+    pub height: u32,
+}
+
+// In-process consumer of animation frames, as an alternative to
+// piping frames to ffmpeg.  Frames are sent at the same cadence as
+// for an ffmpeg output, as 8-bit RGBA data in row-major order.  Sinks
+// must be Send, so that images can be generated on other threads.
+pub trait FrameSink: Send {
+    fn write_frame(&mut self, rgba: &[u8], info: &FrameInfo);
+
+    // Called once, when the image is dropped.
+    fn finish(&mut self) {}
+}
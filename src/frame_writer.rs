@@ -0,0 +1,87 @@
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+use crate::errors::Error;
+use crate::frame_sink::FrameInfo;
+use crate::growth_image::{AnimationOutput, SaveImageData};
+
+// How animation frames are handed to their output.  Writing a frame
+// to ffmpeg includes PNG-encoding it, which can take longer than
+// filling the pixels between frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameWritePolicy {
+    // Write each frame before continuing to fill the image.
+    Inline,
+
+    // Write frames on a separate thread, with up to this many frames
+    // waiting in between.  Once that many are waiting, filling waits
+    // for the writer to catch up.
+    Block(usize),
+
+    // As Block, but once full, frames are dropped rather than waiting,
+    // so the animation may skip ahead.
+    DropFrames(usize),
+}
+
+// Thread that writes frames to an output, fed through a bounded
+// channel.
+pub(crate) struct FrameWriter {
+    sender: Option<SyncSender<(SaveImageData, FrameInfo)>>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+    drop_when_full: bool,
+}
+
+impl FrameWriter {
+    pub(crate) fn spawn(
+        mut output: AnimationOutput,
+        capacity: usize,
+        drop_when_full: bool,
+    ) -> Self {
+        let (sender, receiver) =
+            sync_channel::<(SaveImageData, FrameInfo)>(capacity.max(1));
+        let thread = std::thread::spawn(move || {
+            receiver.iter().for_each(|(data, info)| {
+                output.write_frame(data, info);
+            });
+            output.finish()
+        });
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            drop_when_full,
+        }
+    }
+
+    // Returns false if the frame was dropped.
+    pub(crate) fn send(
+        &mut self,
+        data: SaveImageData,
+        info: FrameInfo,
+    ) -> bool {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return false,
+        };
+        if self.drop_when_full {
+            match sender.try_send((data, info)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_))
+                | Err(TrySendError::Disconnected(_)) => false,
+            }
+        } else {
+            sender.send((data, info)).is_ok()
+        }
+    }
+
+    // Wait for the waiting frames to be written, then finish the
+    // output.
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        self.sender.take();
+        match self.thread.take() {
+            Some(thread) => {
+                thread.join().map_err(|_| Error::FrameWriterPanicked)?
+            }
+            None => Ok(()),
+        }
+    }
+}
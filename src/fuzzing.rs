@@ -0,0 +1,38 @@
+use crate::point_tracker::{run_tracker_ops, TrackerOp};
+use crate::topology::PixelLoc;
+
+// Entry points for the fuzz targets in fuzz/, enabled by the
+// "fuzzing" feature.  Each interprets arbitrary bytes as a sequence
+// of operations, and panics if an internal inconsistency is found.
+
+// The first two bytes give the layer size, and each following group
+// of three bytes gives one PointTracker operation.  Locations may be
+// up to two pixels outside of the layer.
+pub fn point_tracker_ops(data: &[u8]) {
+    if data.len() < 2 {
+        return;
+    }
+    let width = (data[0] % 16) as u32 + 1;
+    let height = (data[1] % 16) as u32 + 1;
+
+    let ops = data[2..]
+        .chunks_exact(3)
+        .map(|chunk| {
+            let loc = PixelLoc {
+                layer: 0,
+                i: (chunk[1] as i32) % (width as i32 + 4) - 2,
+                j: (chunk[2] as i32) % (height as i32 + 4) - 2,
+            };
+            match chunk[0] % 6 {
+                0 => TrackerOp::AddToFrontier(loc),
+                1 => TrackerOp::Fill(loc),
+                2 => TrackerOp::FillChosen,
+                3 => TrackerOp::MarkAsUsed(loc),
+                4 => TrackerOp::MarkAsUnused(loc),
+                _ => TrackerOp::ThinFrontier((chunk[1] % 8) as f64),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    run_tracker_ops(width, height, &ops);
+}
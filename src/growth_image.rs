@@ -1,12 +1,34 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 
-use indicatif::ProgressBar;
-use rand::Rng;
+use itertools::Either;
+use rand::{Rng, SeedableRng};
 
-use crate::color::RGB;
-use crate::kd_tree::{KDTree, PerformanceStats, Point};
-use crate::point_tracker::PointTracker;
+use crate::color::{BlendMode, ColorSet, HSV, RGB};
+use crate::color_map::{to_data, ColorMap};
+use crate::debug_overlay::{DebugOverlay, DebugOverlayHandle};
+use crate::deepzoom::{write_deepzoom, DEEPZOOM_OVERLAP, DEEPZOOM_TILE_SIZE};
+use crate::distribution::RandomDistribution;
+use crate::errors::Error;
+use crate::frame_sink::{FrameInfo, FrameSink};
+use crate::frame_writer::FrameWriter;
+use crate::kd_tree::{
+    KDTree, KDTreeOptions, KdtreeResult, PerformanceStats, Point,
+    SerializablePoint,
+};
+use crate::kernels::{AdjacentMean, FilledPixels, TargetColorKernel};
+use crate::layout::{CompositeLayout, LayoutSpec};
+use crate::palettes::{exclude_colors, Palette};
+use crate::performance::{StagePerformance, StageTiming};
+use crate::png_text::{itxt_chunk, read_png_text};
+use crate::point_tracker::{GrowthCone, PointTracker, SelectionWeight};
+use crate::progress::Progress;
+use crate::region::Region;
+use crate::similarity::Metric;
+use crate::stage_condition::StageCondition;
 use crate::topology::{PixelLoc, Topology};
 
 impl Point for RGB {
@@ -26,425 +48,4829 @@ impl Point for RGB {
     }
 }
 
+impl SerializablePoint for RGB {
+    fn write_point(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&self.vals)
+    }
+
+    fn read_point(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut vals = [0u8; 3];
+        reader.read_exact(&mut vals)?;
+        Ok(RGB { vals })
+    }
+
+    fn write_val(val: u8, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&[val])
+    }
+
+    fn read_val(reader: &mut impl Read) -> std::io::Result<u8> {
+        let mut val = [0u8; 1];
+        reader.read_exact(&mut val)?;
+        Ok(val[0])
+    }
+}
+
 pub struct GrowthImage {
     pub(crate) topology: Topology,
     pub(crate) pixels: Vec<Option<RGB>>,
+    pub(crate) pixel_stages: Vec<Option<usize>>,
+    pub(crate) pixel_fill_order: Vec<Option<usize>>,
+    pub(crate) pixel_origins: Vec<Option<PixelLoc>>,
+    pub(crate) num_fill_events: usize,
     pub(crate) stats: Vec<Option<PerformanceStats>>,
     pub(crate) num_filled_pixels: usize,
 
+    // While a blended stage is active, its pixels grow in their own
+    // buffer, and the image beneath them is kept here.
+    pub(crate) blend_base: Option<Vec<Option<RGB>>>,
+
     pub(crate) stages: Vec<GrowthImageStage>,
     pub(crate) active_stage: Option<usize>,
+    pub(crate) num_stages_started: usize,
+
+    // Stages running concurrently with the active stage, in the order
+    // that they take turns.
+    pub(crate) parked_stages: VecDeque<ParkedStage>,
+
+    // Expected number of pixels filled by each stage.  Estimated from
+    // the stage regions when built, refined as each stage starts, and
+    // replaced by the actual count as each stage finishes.
+    pub(crate) stage_fill_estimates: Vec<usize>,
+
+    // Start and end of each stage that has started, indexed by
+    // stage.
+    pub(crate) stage_timeline: Vec<StageTiming>,
+
+    // Number of pixels that each stage could fill, but that were
+    // unreachable from the stage's seed points when it started.
+    pub(crate) unreachable_pixels: Vec<Option<usize>>,
+    // Stage and number of unreachable pixels that stopped the image,
+    // when using strict_regions.
+    pub(crate) region_error: Option<(usize, usize)>,
     pub(crate) current_stage_iter: usize,
+    pub(crate) next_reveal: usize,
+
+    // Bands of ring growth that have yet to be revealed, with the
+    // next band last.
+    pub(crate) ring_bands: Vec<Vec<PixelLoc>>,
+    // Pixels closer than the inner radius of ring growth, which stay
+    // forbidden for the rest of the stage.
+    pub(crate) ring_core: HashSet<PixelLoc>,
+    // Lookups used by clear_pixel() for each running stage, indexed
+    // by stage.
+    pub(crate) stage_lookups: HashMap<usize, StageLookup>,
+
+    // Only used by stages with best_match_first().  Rebuilt from the
+    // frontier whenever it is None, so should be reset whenever the
+    // frontier changes other than by filling a pixel.
+    pub(crate) best_match_queue: Option<BestMatchQueue>,
 
     pub(crate) point_tracker: PointTracker,
     pub(crate) epsilon: f64,
+    // Epsilon and whether to record search statistics, for each layer.
+    pub(crate) layer_epsilon: Vec<f64>,
+    pub(crate) layer_stats: Vec<bool>,
+    pub(crate) target_kernel: Arc<dyn TargetColorKernel>,
+    pub(crate) color_map: Option<Arc<dyn ColorMap>>,
+    // Seed of the random number generator, also used by target color
+    // kernels that make random choices.
+    pub(crate) seed: u64,
+    pub(crate) linear_light_averaging: bool,
+    pub(crate) strict_regions: bool,
+    pub(crate) background: Background,
+    pub(crate) alpha_mode: AlphaMode,
+    pub(crate) precompute_adjacency: bool,
+    pub(crate) kdtree_options: KDTreeOptions,
+    pub(crate) used_colors: Option<Arc<ColorSet>>,
+    pub(crate) num_duplicate_colors: usize,
     pub(crate) rng: rand_chacha::ChaCha8Rng,
 
     pub(crate) is_done: bool,
-    pub(crate) progress_bar: Option<ProgressBar>,
+    pub(crate) progress: Option<Progress>,
     pub(crate) animation_outputs: Vec<GrowthImageAnimation>,
+    pub(crate) live_output: Option<LiveOutput>,
+
+    // Description of how the image was built, written into each PNG.
+    pub(crate) metadata: Vec<(String, String)>,
+}
+
+// Result of GrowthImage::fill_n.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FillReport {
+    // Number of pixels filled.
+    pub num_filled: usize,
+    // Index of each stage that started, in order.  A stage that
+    // started and finished without filling any pixels is included.
+    pub stages_started: Vec<usize>,
+    // Index of each stage that started with pixels it could not
+    // reach, along with the number of unreachable pixels.
+    pub unreachable_pixels: Vec<(usize, usize)>,
+    // Whether the image is finished.
+    pub is_done: bool,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SaveImageType {
     Generated,
     Statistics,
     ColorPalette,
+    Frontier,
+    StageMap,
+    PaletteDepletion,
+}
+
+impl SaveImageType {
+    // Whether the image shows a single layer of the image, as opposed
+    // to a view of the palette.
+    pub fn is_per_layer(&self) -> bool {
+        !matches!(
+            self,
+            SaveImageType::ColorPalette | SaveImageType::PaletteDepletion
+        )
+    }
+}
+
+// Appearance of unfilled pixels in generated images.  Formats without
+// alpha, including animations, show the background color, with black
+// in place of transparency.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Background {
+    #[default]
+    Transparent,
+    // A color, with an opacity from 0 (transparent) to 255 (opaque).
+    Color(RGB, u8),
+    // Opaque checkerboard of light and dark gray squares, with sides
+    // of the given number of pixels, as image editors use to show
+    // transparency.
+    Checkerboard(u32),
+}
+
+// How a partially transparent background is stored.  With straight
+// alpha, the color is stored as-is.  With premultiplied alpha, the
+// color is scaled by the opacity.  Filled pixels are always opaque,
+// and are the same either way.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AlphaMode {
+    #[default]
+    Straight,
+    Premultiplied,
+}
+
+impl Background {
+    // RGBA value of the background at the pixel.
+    pub(crate) fn rgba(&self, loc: PixelLoc, alpha_mode: AlphaMode) -> [u8; 4] {
+        match *self {
+            Background::Transparent => [0, 0, 0, 0],
+            Background::Color(color, alpha) => match alpha_mode {
+                AlphaMode::Straight => [color.r(), color.g(), color.b(), alpha],
+                AlphaMode::Premultiplied => {
+                    let scale = |val: u8| {
+                        ((val as u32 * alpha as u32 + 127) / 255) as u8
+                    };
+                    [
+                        scale(color.r()),
+                        scale(color.g()),
+                        scale(color.b()),
+                        alpha,
+                    ]
+                }
+            },
+            Background::Checkerboard(size) => {
+                let size = size.max(1) as i32;
+                let is_light =
+                    (loc.i.div_euclid(size) + loc.j.div_euclid(size)) % 2 == 0;
+                let val = if is_light { 204 } else { 153 };
+                [val, val, val, 255]
+            }
+        }
+    }
+}
+
+// Uncompressed pixel formats for GrowthImage::write_raw.  RGBA8 and
+// RGB8 are bare pixel data in row-major order, with no header.  PPM
+// is binary (P6) PPM, which is RGB8 with a short text header.
+// Unfilled pixels show the image's background, with a fully
+// transparent background shown as black in RGB8 and PPM.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RawFormat {
+    RGBA8,
+    RGB8,
+    PPM,
+}
+
+pub(crate) struct SaveImageData {
+    pub(crate) data: Vec<u8>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
 }
 
-struct SaveImageData {
-    data: Vec<u8>,
-    width: u32,
-    height: u32,
+impl SaveImageData {
+    pub(crate) fn write_png(&self, filename: PathBuf) {
+        self.write_png_with_text(filename, &[]);
+    }
+
+    pub(crate) fn write_png_with_text(
+        &self,
+        filename: PathBuf,
+        text: &[(String, String)],
+    ) {
+        let file = std::fs::File::create(filename).unwrap();
+        self.write_png_to_writer_with_text(
+            &mut std::io::BufWriter::new(file),
+            text,
+        );
+    }
+
+    pub(crate) fn write_png_to_writer(&self, writer: &mut impl std::io::Write) {
+        self.write_png_to_writer_with_text(writer, &[]);
+    }
+
+    // Each (keyword, text) pair is stored in an iTXt chunk.
+    pub(crate) fn write_png_to_writer_with_text(
+        &self,
+        writer: &mut impl std::io::Write,
+        text: &[(String, String)],
+    ) {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+
+        text.iter().for_each(|(keyword, text)| {
+            writer
+                .write_chunk(*b"iTXt", &itxt_chunk(keyword, text))
+                .unwrap();
+        });
+        writer.write_image_data(&self.data).unwrap();
+    }
+
+    pub(crate) fn write_raw_to_writer(
+        &self,
+        writer: &mut impl std::io::Write,
+        format: RawFormat,
+    ) -> Result<(), Error> {
+        let rgb = || {
+            self.data
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    // Unfilled pixels may hold a color with zero alpha.
+                    let alpha = (p[3] > 0) as u8;
+                    vec![p[0] * alpha, p[1] * alpha, p[2] * alpha]
+                })
+                .collect::<Vec<u8>>()
+        };
+
+        match format {
+            RawFormat::RGBA8 => writer.write_all(&self.data)?,
+            RawFormat::RGB8 => writer.write_all(&rgb())?,
+            RawFormat::PPM => {
+                write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+                writer.write_all(&rgb())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Copy of the rectangle with its top-left corner at (i0, j0).
+    pub(crate) fn crop(
+        &self,
+        i0: u32,
+        j0: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let data = (j0..j0 + height)
+            .flat_map(|j| {
+                let start = 4 * (j * self.width + i0) as usize;
+                self.data[start..start + 4 * (width as usize)]
+                    .iter()
+                    .copied()
+            })
+            .collect();
+        Self {
+            data,
+            width,
+            height,
+        }
+    }
+
+    // Read a PNG file, converting to 8-bit RGBA.
+    pub(crate) fn read_png(filename: &Path) -> Result<Self, Error> {
+        let mut decoder = png::Decoder::new(std::fs::File::open(filename)?);
+        decoder.set_transformations(
+            png::Transformations::EXPAND | png::Transformations::STRIP_16,
+        );
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf)?;
+
+        let data = match info.color_type {
+            png::ColorType::RGBA => buf,
+            png::ColorType::RGB => buf
+                .chunks_exact(3)
+                .flat_map(|p| vec![p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => {
+                buf.iter().flat_map(|&p| vec![p, p, p, 255]).collect()
+            }
+            png::ColorType::Indexed => {
+                return Err(Error::UnsupportedImageFormat);
+            }
+        };
+
+        Ok(Self {
+            data,
+            width: info.width,
+            height: info.height,
+        })
+    }
 }
 
 #[derive(Clone)]
 pub enum RestrictedRegion {
-    Allowed(Vec<PixelLoc>),
-    Forbidden(Vec<PixelLoc>),
+    Allowed(Arc<Vec<PixelLoc>>),
+    Forbidden(Arc<Vec<PixelLoc>>),
 }
 
+#[derive(Clone)]
 pub struct GrowthImageStage {
     pub(crate) palette: KDTree<RGB>,
+    // If set, the palette is generated when the stage starts, sized
+    // to the region to be filled.
+    pub(crate) exact_palette: Option<Arc<dyn Palette>>,
+    pub(crate) seed_palettes: HashMap<PixelLoc, KDTree<RGB>>,
+    pub(crate) fallback_palette: Option<KDTree<RGB>>,
+    pub(crate) reserved_colors: Option<KDTree<RGB>>,
+    pub(crate) reserved_release_iter: Option<usize>,
+    pub(crate) reserved_released: bool,
+    pub(crate) excluded_colors: Vec<RGB>,
+    pub(crate) exclusion_tolerance: f32,
+    // Every color the stage had available when it started, recorded
+    // so that it can be inspected after the palettes are used up.
+    pub(crate) initial_colors: Option<Arc<Vec<RGB>>>,
     pub(crate) max_iter: Option<usize>,
+    pub(crate) advance_conditions: Vec<StageCondition>,
+    pub(crate) condition_check_period: usize,
     pub(crate) grow_from_previous: bool,
     pub(crate) selected_seed_points: Vec<PixelLoc>,
+    pub(crate) growth_cones: Arc<HashMap<PixelLoc, GrowthCone>>,
     pub(crate) num_random_seed_points: u32,
     pub(crate) restricted_region: RestrictedRegion,
-    pub(crate) portals: HashMap<PixelLoc, PixelLoc>,
+    pub(crate) selection_weights: Vec<Arc<dyn SelectionWeight>>,
+    pub(crate) reveal_schedule: Arc<Vec<(usize, Vec<PixelLoc>)>>,
+    pub(crate) portals: Arc<HashMap<PixelLoc, PixelLoc>>,
+    // Probability of growing through the portal from each point, for
+    // portals that aren't always used.
+    pub(crate) portal_probabilities: Arc<HashMap<PixelLoc, f32>>,
     pub(crate) animation_iter_per_second: f64,
+    pub(crate) frontier_thinning: Option<FrontierThinning>,
+    pub(crate) frontier_max_age: Option<usize>,
+    pub(crate) ring_growth: Option<(u32, u32)>,
+    pub(crate) best_match_first: bool,
+    pub(crate) concurrent_with_previous: bool,
+    pub(crate) target_kernel: Option<Arc<dyn TargetColorKernel>>,
+    pub(crate) color_jitter: u8,
+    pub(crate) color_jitter_distribution: Option<RandomDistribution>,
+    pub(crate) seed_point_jitter: Option<RandomDistribution>,
+    pub(crate) blend: Option<(BlendMode, f32)>,
+    pub(crate) palette_depletion: Option<PaletteDepletion>,
+    #[cfg_attr(not(feature = "exr"), allow(dead_code))]
+    pub(crate) hdr_intensity: f32,
 }
 
-pub struct GrowthImageAnimation {
-    pub(crate) proc: std::process::Child,
+// Fixed layout of a stage's palette, sorted by hue, with the time at
+// which each color was used.  Only tracked when an animation shows
+// SaveImageType::PaletteDepletion.
+#[derive(Clone)]
+pub(crate) struct PaletteDepletion {
+    // Index of the color in the palette's KD-tree, and the color
+    // itself, for each position in the layout.
+    layout: Vec<(usize, RGB)>,
+    // Fill event at which each color of the KD-tree was used.
+    used_at: Vec<Option<usize>>,
+}
 
-    pub(crate) fps: f64,
-    pub(crate) iter_per_frame: usize,
-    pub(crate) iter_since_frame: usize,
+// Number of hue bands in the PaletteDepletion layout.  Within a band,
+// colors are sorted from dark to bright.
+const DEPLETION_HUE_BANDS: f32 = 24.0;
 
-    pub(crate) image_type: SaveImageType,
-    pub(crate) layer: u8,
-}
+// Fraction of the palette's size, in fill events, over which a used
+// color fades out.
+const DEPLETION_FADE_FRACTION: f64 = 0.02;
 
-impl GrowthImage {
-    pub fn is_done(&self) -> bool {
-        self.is_done
+impl GrowthImageStage {
+    // Colors not yet used from the stage's palette, seed palettes,
+    // reserved colors, and fallback palette.
+    pub(crate) fn iter_colors(&self) -> impl Iterator<Item = RGB> + '_ {
+        std::iter::once(&self.palette)
+            .chain(self.seed_palettes.values())
+            .chain(self.reserved_colors.iter())
+            .chain(self.fallback_palette.iter())
+            .flat_map(|palette| palette.iter_points())
+            .flatten()
+            .copied()
     }
 
-    pub fn fill_until_done(&mut self) {
-        while !self.is_done {
-            self.fill();
+    // Number of pixels the stage will fill, given the number it could
+    // fill, limited by max_iter and by the number of colors available.
+    pub(crate) fn limit_num_fills(&self, num_fillable: usize) -> usize {
+        let num_fills = num_fillable.min(self.max_iter.unwrap_or(usize::MAX));
+        if self.fallback_palette.is_some() || self.exact_palette.is_some() {
+            return num_fills;
         }
+        let num_colors = std::iter::once(&self.palette)
+            .chain(self.seed_palettes.values())
+            .chain(self.reserved_colors.iter())
+            .map(|palette| palette.num_points())
+            .sum::<usize>();
+        num_fills.min(num_colors)
     }
+}
 
-    pub fn fill(&mut self) {
-        let res = self.try_fill();
-        self.is_done = res.is_none();
-
-        if let Some(bar) = &self.progress_bar {
-            bar.inc(1);
-            if self.is_done {
-                bar.finish();
-            }
-        }
+impl PaletteDepletion {
+    pub(crate) fn new(palette: &KDTree<RGB>) -> Self {
+        let mut layout = palette
+            .iter_points()
+            .enumerate()
+            .filter_map(|(i, p)| p.map(|rgb| (i, rgb)))
+            .collect::<Vec<_>>();
+        let sort_key = |rgb: &RGB| {
+            let hsv = rgb.to_hsv();
+            let band = (hsv.h / 360.0 * DEPLETION_HUE_BANDS).floor();
+            (band, hsv.v, hsv.s)
+        };
+        layout.sort_by(|(_, a), (_, b)| {
+            sort_key(a).partial_cmp(&sort_key(b)).unwrap()
+        });
 
-        self._write_to_animations();
+        let used_at = vec![None; palette.iter_points().count()];
+        Self { layout, used_at }
     }
+}
 
-    pub fn get_adjacent_color(&self, loc: PixelLoc) -> Option<RGB> {
-        let (count, rsum, gsum, bsum) = self
-            .topology
-            .iter_adjacent(loc)
-            .flat_map(|loc| self.topology.get_index(loc))
-            .flat_map(|index| self.pixels[index])
-            .fold(
-                (0u32, 0u32, 0u32, 0u32),
-                |(count, rsum, gsum, bsum), rgb| {
-                    (
-                        count + 1,
-                        rsum + rgb.r() as u32,
-                        gsum + rgb.g() as u32,
-                        bsum + rgb.b() as u32,
-                    )
-                },
-            );
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct FrontierThinning {
+    pub(crate) min_distance: f64,
+    pub(crate) iter_period: usize,
+}
 
-        if count > 0 {
-            Some(RGB {
-                vals: [
-                    (rsum / count) as u8,
-                    (gsum / count) as u8,
-                    (bsum / count) as u8,
-                ],
-            })
-        } else {
-            None
+// Frontier points ordered by the distance from their target color to
+// the closest palette color, for GrowthImageStageBuilder's
+// best_match_first().  Entries are invalidated, rather than removed,
+// when a neighboring pixel is filled and the target color changes.
+// Removing colors from the palette can only make matches worse, so
+// those entries are re-checked as they reach the top of the queue.
+#[derive(Clone, Default)]
+pub(crate) struct BestMatchQueue {
+    // Distance squared, as bits so that it can be ordered, then a
+    // random tie-breaker, the pixel index, and the version of that
+    // pixel's entry.
+    heap: BinaryHeap<Reverse<(u64, u32, usize, u32)>>,
+    versions: Vec<u32>,
+}
+
+impl BestMatchQueue {
+    fn new(num_pixels: usize) -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            versions: vec![0; num_pixels],
         }
     }
 
-    fn current_stage_finished(&self) -> bool {
-        let active_stage = &self.stages[self.active_stage.unwrap()];
-        let reached_max_stage_iter = match active_stage.max_iter {
-            Some(max_iter) => self.current_stage_iter >= max_iter,
-            None => false,
-        };
-        let empty_palette = active_stage.palette.num_points() == 0;
-
-        let empty_frontier = self.point_tracker.is_done();
-
-        reached_max_stage_iter || empty_palette || empty_frontier
+    // Make room for pixels in newly allocated chunks.
+    fn grow(&mut self, num_pixels: usize) {
+        self.versions.resize(num_pixels, 0);
     }
 
-    fn start_stage(&mut self, stage_index: usize) {
-        // Advance stage number
-        self.active_stage = Some(stage_index);
-        self.current_stage_iter = 0;
-        let active_stage = &self.stages[stage_index];
-
-        // Recalculate the iterations per frame for each animation.
-        self.animation_outputs.iter_mut().for_each(|anim| {
-            anim.iter_per_frame =
-                (active_stage.animation_iter_per_second / anim.fps) as usize;
-        });
+    // Add an entry for the pixel, replacing any previous entry.
+    fn push(&mut self, index: usize, dist2: f64, rng: &mut impl Rng) {
+        self.versions[index] += 1;
+        self.push_current(index, dist2, rng);
+    }
 
-        // Update the geometry with new portals.  Long-term, should
-        // forbidden points go here as well?  Conceptually, they fit
-        // really well with the geometry tracking class, but the
-        // implementation is much cleaner with them being part of the
-        // PointTracker's "used" array.
-        self.topology.portals = active_stage.portals.clone();
+    fn push_current(&mut self, index: usize, dist2: f64, rng: &mut impl Rng) {
+        self.heap.push(Reverse((
+            dist2.to_bits(),
+            rng.gen(),
+            index,
+            self.versions[index],
+        )));
+    }
 
-        // Remake the PointTracker, so that we can clear any forbidden
-        // points from the previous stage, as well as removing any
-        // newly forbidden points from the frontier.
-        let mut point_tracker = PointTracker::new(self.topology.clone());
+    fn peek_dist2(&self) -> Option<f64> {
+        self.heap
+            .peek()
+            .map(|Reverse((bits, ..))| f64::from_bits(*bits))
+    }
 
-        match &active_stage.restricted_region {
-            RestrictedRegion::Allowed(points) => {
-                point_tracker.mark_all_as_used();
-                points
-                    .iter()
-                    .for_each(|&loc| point_tracker.mark_as_unused(loc));
-            }
-            RestrictedRegion::Forbidden(points) => {
-                points
-                    .iter()
-                    .for_each(|&loc| point_tracker.mark_as_used(loc));
+    // Pop the closest entry that hasn't been replaced, returning the
+    // pixel index and the distance when it was pushed.
+    fn pop(&mut self) -> Option<(usize, f64)> {
+        while let Some(Reverse((bits, _, index, version))) = self.heap.pop() {
+            if version == self.versions[index] {
+                return Some((index, f64::from_bits(bits)));
             }
         }
+        None
+    }
+}
 
-        // All filled pixels are either forbidden, or forbidden with a
-        // frontier.
-        let filled_locs = self
-            .pixels
-            .iter()
-            .enumerate()
-            .filter(|(_i, p)| p.is_some())
-            .flat_map(|(i, _p)| self.topology.get_loc(i));
+// Points of the stage's region, reveal schedule, and ring growth
+// bands, for checking whether the stage allows a pixel without
+// searching each list.  Built the first time that a pixel is cleared
+// while the stage is running, since the ring growth bands are only
+// known once the stage has started.
+#[derive(Clone)]
+pub(crate) struct StageLookup {
+    region: HashSet<PixelLoc>,
+    // First reveal with a nonzero iteration, and last reveal, that
+    // include each point.
+    reveals: HashMap<PixelLoc, (Option<usize>, usize)>,
+    // Index of the band of ring growth that includes each point.
+    // Bands are revealed from the end, so only bands with an index
+    // less than the number of remaining bands are still pending.
+    ring_bands: HashMap<PixelLoc, usize>,
+}
 
-        if active_stage.grow_from_previous {
-            filled_locs.for_each(|loc| point_tracker.fill(loc));
-        } else {
-            filled_locs.for_each(|loc| point_tracker.mark_as_used(loc));
+impl StageLookup {
+    fn new(stage: &GrowthImageStage, ring_bands: &[Vec<PixelLoc>]) -> Self {
+        let region = match &stage.restricted_region {
+            RestrictedRegion::Allowed(points)
+            | RestrictedRegion::Forbidden(points) => {
+                points.iter().copied().collect()
+            }
         };
-
-        // Add in any selected seed points
-        active_stage
-            .selected_seed_points
+        let mut reveals = HashMap::new();
+        stage.reveal_schedule.iter().enumerate().for_each(
+            |(reveal, (iter, points))| {
+                points.iter().for_each(|&loc| {
+                    let entry = reveals.entry(loc).or_insert((None, reveal));
+                    if *iter > 0 && entry.0.is_none() {
+                        entry.0 = Some(reveal);
+                    }
+                    entry.1 = reveal;
+                });
+            },
+        );
+        let ring_bands = ring_bands
             .iter()
-            .for_each(|&loc| point_tracker.add_to_frontier(loc));
+            .enumerate()
+            .flat_map(|(band, points)| {
+                points.iter().map(move |&loc| (loc, band))
+            })
+            .collect();
+        Self {
+            region,
+            reveals,
+            ring_bands,
+        }
+    }
+}
 
-        // Randomly pick N seed points from those remaining.
-        // Implementation assumes that N is relatively small, may be
-        // inefficient for large N.
-        point_tracker.add_random_to_frontier(
-            active_stage.num_random_seed_points as usize,
-            &mut self.rng,
-        );
+// State of a stage that is running concurrently with the active
+// stage, while it waits for its turn.
+#[derive(Clone)]
+pub(crate) struct ParkedStage {
+    stage_index: usize,
+    current_stage_iter: usize,
+    next_reveal: usize,
+    ring_bands: Vec<Vec<PixelLoc>>,
+    ring_core: HashSet<PixelLoc>,
+    point_tracker: PointTracker,
+    best_match_queue: Option<BestMatchQueue>,
+}
 
-        // Set the new point tracker as the one to use
-        self.point_tracker = point_tracker;
-    }
+pub(crate) enum AnimationOutput {
+    Ffmpeg(std::process::Child),
+    Sink(Arc<Mutex<Box<dyn FrameSink>>>),
+    Threaded(FrameWriter),
+}
 
-    fn try_fill(&mut self) -> Option<(PixelLoc, RGB)> {
-        // Start of the first stage
-        if self.active_stage.is_none() {
-            self.start_stage(0);
+impl AnimationOutput {
+    // Returns false if the frame was dropped.
+    pub(crate) fn write_frame(
+        &mut self,
+        data: SaveImageData,
+        info: FrameInfo,
+    ) -> bool {
+        match self {
+            AnimationOutput::Ffmpeg(proc) => {
+                data.write_png_to_writer(&mut proc.stdin.as_ref().unwrap());
+                true
+            }
+            AnimationOutput::Sink(sink) => {
+                sink.lock().unwrap().write_frame(&data.data, &info);
+                true
+            }
+            AnimationOutput::Threaded(writer) => writer.send(data, info),
         }
+    }
 
-        // Advance to the next stage, if needed.
-        while self.current_stage_finished() {
-            let next_stage = self.active_stage.unwrap() + 1;
-            if next_stage < self.stages.len() {
-                self.start_stage(next_stage);
-            } else {
-                return None;
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        match self {
+            AnimationOutput::Ffmpeg(proc) => {
+                proc.wait()?;
+            }
+            // A poisoned lock still holds the sink, which should be
+            // told that no more frames are coming.
+            AnimationOutput::Sink(sink) => {
+                sink.lock().unwrap_or_else(PoisonError::into_inner).finish()
             }
+            AnimationOutput::Threaded(writer) => writer.finish()?,
         }
+        Ok(())
+    }
+}
 
-        let point_tracker_index = (self.point_tracker.frontier_size() as f32
-            * self.rng.gen::<f32>()) as usize;
-        let next_loc =
-            self.point_tracker.get_frontier_point(point_tracker_index);
-        self.point_tracker.fill(next_loc);
+#[cfg(feature = "tracing")]
+const TRACE_SEARCH_PERIOD: usize = 1024;
 
-        let next_index = self.topology.get_index(next_loc)?;
+// Time between batches of GrowthImage::fill_at_rate(), about one per
+// frame of a 60 Hz display.
+const RATE_BATCH_INTERVAL: std::time::Duration =
+    std::time::Duration::from_millis(16);
 
-        let target_color =
-            self.get_adjacent_color(next_loc).unwrap_or_else(|| RGB {
-                vals: [
-                    self.rng.gen::<u8>(),
-                    self.rng.gen::<u8>(),
-                    self.rng.gen::<u8>(),
-                ],
-            });
+pub(crate) struct LiveOutput {
+    pub(crate) filename: PathBuf,
+    pub(crate) iter_period: usize,
+    pub(crate) iter_since_write: usize,
+}
 
-        let active_stage = &mut self.stages[self.active_stage.unwrap()];
-        let res = active_stage
-            .palette
-            .pop_closest(&target_color, self.epsilon);
-        self.stats[next_index] = Some(res.stats);
+pub struct GrowthImageAnimation {
+    pub(crate) output: AnimationOutput,
+    pub(crate) frames_written: usize,
+    pub(crate) frames_dropped: usize,
 
-        let next_color = res.res?;
-        self.pixels[next_index] = Some(next_color);
+    pub(crate) fps: f64,
+    pub(crate) iter_per_frame: usize,
+    pub(crate) iter_since_frame: usize,
 
-        self.current_stage_iter += 1;
-        self.num_filled_pixels += 1;
+    pub(crate) image_types: Vec<SaveImageType>,
+    pub(crate) composite: CompositeLayout,
+    pub(crate) layer: u8,
 
-        Some((next_loc, next_color))
-    }
+    // Index of the first frame of each stage, and the file to list
+    // them in once the animation is finished.
+    pub(crate) chapters: Vec<(usize, usize)>,
+    pub(crate) chapter_file: Option<PathBuf>,
 
-    pub fn write(&self, filename: PathBuf) {
-        self.write_image(filename, SaveImageType::Generated, 0);
-    }
+    pub(crate) debug_overlay: Option<DebugOverlayHandle>,
 
-    pub fn write_image(
-        &self,
-        filename: PathBuf,
-        image_type: SaveImageType,
-        layer: u8,
-    ) {
-        self._write_image_data(filename, &self._image_data(image_type, layer));
-    }
+    // Overrides the image's background for generated frames.
+    pub(crate) background: Option<Background>,
 
-    fn _write_to_animations(&mut self) {
-        // Steal the animation vector to mutate it.
-        let mut animations = std::mem::take(&mut self.animation_outputs);
+    // Range of fill events during which frames are written.
+    pub(crate) start_at_iter: usize,
+    pub(crate) stop_at_iter: Option<usize>,
+}
 
-        // Increment the iterations since last frame write.
-        animations
-            .iter_mut()
-            .for_each(|anim| anim.iter_since_frame += 1);
+impl GrowthImageAnimation {
+    // Chapters in the FFMETADATA format, which can be added to the
+    // video with "ffmpeg -i video.mp4 -i chapters.txt -map_metadata 1
+    // -codec copy output.mp4".
+    fn write_chapters(&self, filename: &Path) -> Result<(), Error> {
+        let ms_at_frame =
+            |frame: usize| ((frame as f64) * 1000.0 / self.fps).round() as u64;
 
-        // Write to it, which requires immutable borrow of other parts
-        // of self.
-        animations
-            .iter_mut()
-            .filter(|anim| anim.iter_since_frame >= anim.iter_per_frame)
-            .for_each(|anim| {
-                let data = self._image_data(anim.image_type, anim.layer);
-                self._write_image_data_to_writer(
-                    &mut anim.proc.stdin.as_ref().unwrap(),
-                    &data,
+        let mut text = String::from(";FFMETADATA1\n");
+        let ends = self
+            .chapters
+            .iter()
+            .skip(1)
+            .map(|&(_, frame)| frame)
+            .chain(std::iter::once(self.frames_written));
+        self.chapters
+            .iter()
+            .zip(ends)
+            .for_each(|(&(stage, start), end)| {
+                text += &format!(
+                    "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\n\
+                     title=Stage {} (frames {}-{})\n",
+                    ms_at_frame(start),
+                    ms_at_frame(end),
+                    stage,
+                    start,
+                    end - 1,
                 );
-                anim.iter_since_frame = 0;
             });
 
-        // Put the animation vector back
-        std::mem::swap(&mut animations, &mut self.animation_outputs);
+        std::fs::write(filename, text)?;
+        Ok(())
     }
+}
 
-    fn _image_data(
-        &self,
-        image_type: SaveImageType,
-        layer: u8,
-    ) -> SaveImageData {
-        match image_type {
-            SaveImageType::Generated => self._generated_image_data(layer),
-            SaveImageType::Statistics => self._statistics_image_data(layer),
-            SaveImageType::ColorPalette => self._color_palette_image_data(),
+impl GrowthImage {
+    pub fn is_done(&self) -> bool {
+        self.is_done
+    }
+
+    // Finish each animation and write its chapter file, if any.
+    // Animations are also finished when the image is dropped, but any
+    // error in writing a chapter file is then ignored.  No more frames
+    // are written afterwards.
+    pub fn finish_animations(&mut self) -> Result<(), Error> {
+        let mut res = Ok(());
+        for mut anim in std::mem::take(&mut self.animation_outputs) {
+            res = res.and(anim.output.finish());
+            if let Some(filename) = &anim.chapter_file {
+                res = res.and(anim.write_chapters(filename));
+            }
+        }
+        res
+    }
+
+    // Expected number of pixels filled over all stages, as used for
+    // the progress bar.  Only an estimate until the last stage has
+    // started, since a stage may be unable to reach all of its
+    // allowed pixels.
+    pub fn estimated_total_fills(&self) -> usize {
+        self.stage_fill_estimates.iter().sum()
+    }
+
+    fn update_progress_length(&mut self) {
+        let len = self.estimated_total_fills() as u64;
+        if let Some(progress) = &mut self.progress {
+            progress.set_length(len);
         }
     }
 
-    fn _generated_image_data(&self, layer: u8) -> SaveImageData {
-        let index_range = self.topology.get_layer_bounds(layer).unwrap();
-        let size = self.topology.layers[layer as usize];
-        let data = self.pixels[index_range]
+    // Initial estimate of the number of pixels filled by each stage,
+    // assuming that each stage fills every empty pixel in its allowed
+    // region, up to the limits of the stage.  Pixels that are part of
+    // a reveal schedule are counted as allowed.
+    pub(crate) fn estimate_stage_fills(
+        topology: &Topology,
+        stages: &[GrowthImageStage],
+    ) -> Vec<usize> {
+        let mut filled = vec![false; topology.len()];
+        stages
             .iter()
-            .map(|p| match p {
-                Some(rgb) => vec![rgb.r(), rgb.g(), rgb.b(), 255],
-                None => vec![0, 0, 0, 0],
+            .map(|stage| {
+                let mut allowed = match &stage.restricted_region {
+                    RestrictedRegion::Allowed(points) => {
+                        let mut allowed = vec![false; topology.len()];
+                        points
+                            .iter()
+                            .flat_map(|&loc| topology.get_index(loc))
+                            .for_each(|index| allowed[index] = true);
+                        allowed
+                    }
+                    RestrictedRegion::Forbidden(points) => {
+                        let mut allowed = (0..topology.len())
+                            .map(|index| topology.get_loc(index).is_some())
+                            .collect::<Vec<_>>();
+                        points
+                            .iter()
+                            .flat_map(|&loc| topology.get_index(loc))
+                            .for_each(|index| allowed[index] = false);
+                        allowed
+                    }
+                };
+                stage
+                    .reveal_schedule
+                    .iter()
+                    .flat_map(|(_iter, points)| points.iter())
+                    .flat_map(|&loc| topology.get_index(loc))
+                    .for_each(|index| allowed[index] = true);
+
+                // A blended stage grows over an empty buffer, so can
+                // fill pixels that are already filled.
+                let fillable = allowed
+                    .iter()
+                    .zip(filled.iter())
+                    .enumerate()
+                    .filter(|(_, (&allowed, &filled))| {
+                        allowed && (stage.blend.is_some() || !filled)
+                    })
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+
+                // If the stage is limited, which pixels it fills isn't
+                // known.  Any of them will do for the estimate.
+                let num_fills = stage.limit_num_fills(fillable.len());
+                fillable
+                    .iter()
+                    .take(num_fills)
+                    .for_each(|&index| filled[index] = true);
+                num_fills
             })
-            .flat_map(|p| p.into_iter())
-            .collect();
-        SaveImageData {
-            data,
-            width: size.width,
-            height: size.height,
+            .collect()
+    }
+
+    pub fn fill_until_done(&mut self) {
+        while !self.is_done {
+            self.fill();
         }
     }
 
-    fn _statistics_image_data(&self, layer: u8) -> SaveImageData {
-        let index_range = self.topology.get_layer_bounds(layer).unwrap();
-        let size = self.topology.layers[layer as usize];
-        let max = self.stats[index_range.clone()]
+    pub fn fill(&mut self) {
+        self.fill_n(1);
+    }
+
+    // Run up to n iterations, stopping early if the image is
+    // finished.  Allows an external loop, such as a GUI or a server,
+    // to advance the image in time slices.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self))
+    )]
+    pub fn fill_n(&mut self, n: usize) -> FillReport {
+        let mut report = FillReport::default();
+        for _ in 0..n {
+            let prev_started = self.num_stages_started;
+            let res = self.try_fill();
+            self.is_done = res.is_none();
+            if res.is_some() {
+                report.num_filled += 1;
+            }
+
+            let started = prev_started..self.num_stages_started;
+            report.stages_started.extend(started.clone());
+            report.unreachable_pixels.extend(
+                started
+                    .flat_map(|stage| {
+                        Some((stage, self.unreachable_pixels[stage]?))
+                    })
+                    .filter(|&(_, num_pixels)| num_pixels > 0),
+            );
+
+            self._write_to_animations();
+            self._write_live_output();
+            if self.is_done {
+                break;
+            }
+        }
+        report.is_done = self.is_done;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            num_filled = report.num_filled,
+            num_filled_pixels = self.num_filled_pixels,
+            is_done = report.is_done,
+        );
+
+        if let Some(progress) = &mut self.progress {
+            progress.inc(report.num_filled as u64);
+            if self.is_done {
+                progress.finish();
+            }
+        }
+
+        report
+    }
+
+    // Fill until the image is finished, at no more than the given
+    // number of pixels per second, for live previews and screen
+    // recordings.  Pixels are filled in batches, sleeping between
+    // them.  Batches are timed from the start, so time spent filling
+    // or writing output doesn't slow the average rate, unless the
+    // image can't be filled that fast.
+    pub fn fill_at_rate(&mut self, pixels_per_second: f64) -> FillReport {
+        let start = std::time::Instant::now();
+        let mut report = FillReport::default();
+        let mut num_batches = 0u32;
+        while !self.is_done() {
+            num_batches += 1;
+            let deadline = start + RATE_BATCH_INTERVAL * num_batches;
+            let target = (pixels_per_second.max(0.0)
+                * (deadline - start).as_secs_f64())
+                as usize;
+            let batch = self.fill_n(target.saturating_sub(report.num_filled));
+            report.num_filled += batch.num_filled;
+            report.stages_started.extend(batch.stages_started);
+            report.unreachable_pixels.extend(batch.unreachable_pixels);
+            report.is_done = batch.is_done;
+
+            if let Some(remaining) =
+                deadline.checked_duration_since(std::time::Instant::now())
+            {
+                std::thread::sleep(remaining);
+            }
+        }
+        report.is_done = true;
+        report
+    }
+
+    // Every color available to the stage when it started, including
+    // seed palettes, reserved colors, and the fallback palette.  For
+    // a stage that hasn't started yet, this is the colors it will
+    // start with, except that a palette sized with
+    // n_colors_exact_for_region is only generated once the stage
+    // starts.  Panics if there is no such stage.
+    pub fn stage_palette(
+        &self,
+        stage_index: usize,
+    ) -> impl Iterator<Item = RGB> + '_ {
+        let stage = &self.stages[stage_index];
+        let initial = stage.initial_colors.as_ref();
+        initial
+            .into_iter()
+            .flat_map(|colors| colors.iter().copied())
+            .chain(
+                initial
+                    .is_none()
+                    .then(|| stage.iter_colors())
+                    .into_iter()
+                    .flatten(),
+            )
+    }
+
+    // Colors of the stage that have not yet been used.  Panics if
+    // there is no such stage.
+    pub fn remaining_palette(
+        &self,
+        stage_index: usize,
+    ) -> impl Iterator<Item = RGB> + '_ {
+        self.stages[stage_index].iter_colors()
+    }
+
+    // Distribution of the palette search statistics of each stage that
+    // has filled pixels, in order of the stages.
+    pub fn performance_summary(&self) -> Vec<StagePerformance> {
+        let mut per_stage = vec![Vec::new(); self.stages.len()];
+        self.pixel_stages.iter().zip(self.stats.iter()).for_each(
+            |(stage, stats)| {
+                if let (Some(stage), Some(stats)) = (stage, stats) {
+                    per_stage[*stage].push(*stats);
+                }
+            },
+        );
+        per_stage
             .iter()
-            .filter_map(|s| *s)
-            .fold(PerformanceStats::default(), |a, b| PerformanceStats {
-                nodes_checked: a.nodes_checked.max(b.nodes_checked),
-                leaf_nodes_checked: a
-                    .leaf_nodes_checked
-                    .max(b.leaf_nodes_checked),
-                points_checked: a.points_checked.max(b.points_checked),
-            });
+            .enumerate()
+            .flat_map(|(stage, stats)| StagePerformance::new(stage, stats))
+            .collect()
+    }
+
+    // Number of pixels that the stage could fill, but that couldn't be
+    // reached from its seed points, as of when the stage started.
+    // None if the stage hasn't started.  Panics if there is no such
+    // stage.
+    pub fn num_unreachable_pixels(&self, stage_index: usize) -> Option<usize> {
+        self.unreachable_pixels[stage_index]
+    }
+
+    // With strict_regions, returns an error if filling stopped because
+    // a stage had unreachable pixels.
+    pub fn check_regions(&self) -> Result<(), Error> {
+        match self.region_error {
+            Some((stage, num_pixels)) => {
+                Err(Error::UnreachablePixels { stage, num_pixels })
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Number of palette colors that were discarded because they had
+    // already been used, when unique colors are enforced.
+    pub fn num_duplicate_colors(&self) -> usize {
+        self.num_duplicate_colors
+    }
 
-        let data = self.stats[index_range]
+    // Iteration and time at which each stage started and finished,
+    // for stages that have started, in order.
+    pub fn stage_timeline(&self) -> &[StageTiming] {
+        &self.stage_timeline
+    }
+
+    // Number of animation frames dropped because the output's writer
+    // had fallen behind, with FrameWritePolicy::DropFrames, summed
+    // over all animations.
+    pub fn num_dropped_frames(&self) -> usize {
+        self.animation_outputs
             .iter()
-            .map(|s| match s {
-                Some(stats) => vec![
-                    (255.0
-                        * ((stats.nodes_checked as f32).ln()
-                            / (max.nodes_checked as f32).ln()))
-                        as u8,
-                    (255.0
-                        * ((stats.leaf_nodes_checked as f32).ln()
-                            / (max.leaf_nodes_checked as f32).ln()))
-                        as u8,
-                    (255.0
-                        * ((stats.points_checked as f32).ln()
-                            / (max.points_checked as f32).ln()))
-                        as u8,
-                    255,
-                ],
-                None => vec![0, 0, 0, 0],
+            .map(|anim| anim.frames_dropped)
+            .sum()
+    }
+
+    pub fn get_pixel(&self, loc: PixelLoc) -> Option<RGB> {
+        self.topology
+            .get_index(loc)
+            .and_then(|index| self.pixels[index])
+    }
+
+    // Stored value of the pixel, with each channel scaled to [0, 1],
+    // for images whose colors are data shown through a color_map().
+    pub fn get_data(&self, loc: PixelLoc) -> Option<[f32; 3]> {
+        self.get_pixel(loc).map(to_data)
+    }
+
+    // Set the color of a pixel, adding its neighbors to the frontier.
+    // Returns the previous color of the pixel, if any.
+    pub fn set_pixel(&mut self, loc: PixelLoc, color: RGB) -> Option<RGB> {
+        self.allocate_around(loc);
+        let index = self.topology.get_index(loc)?;
+        let previous = self.pixels[index].replace(color);
+        if previous.is_none() {
+            self.num_filled_pixels += 1;
+        }
+        self.pixel_stages[index] = self.active_stage;
+        self.pixel_fill_order[index] = Some(self.num_fill_events);
+        self.num_fill_events += 1;
+
+        self.point_tracker.mark_as_used(loc);
+        self.point_tracker.fill(loc);
+        self.best_match_queue = None;
+        self.parked_stages.iter_mut().for_each(|parked| {
+            parked.point_tracker.mark_as_used(loc);
+        });
+
+        previous
+    }
+
+    // Remove the color from a pixel, allowing it to be filled again
+    // by the running stages that are allowed to fill it.  Pixels that
+    // are forbidden to a stage, or not yet revealed, stay blocked for
+    // that stage.  Returns the previous color of the pixel, if any.
+    pub fn clear_pixel(&mut self, loc: PixelLoc) -> Option<RGB> {
+        let index = self.topology.get_index(loc)?;
+        let previous = self.pixels[index].take()?;
+        self.num_filled_pixels -= 1;
+        self.pixel_stages[index] = None;
+        self.pixel_fill_order[index] = None;
+        self.pixel_origins[index] = None;
+        self.stats[index] = None;
+
+        // Only pixels next to a filled pixel belong in the frontier.
+        // Otherwise, the pixel is left available to be reached later.
+        let adjacent_filled = self
+            .topology
+            .iter_adjacent_indices(loc)
+            .any(|adj_index| self.pixels[adj_index].is_some());
+        let release = |point_tracker: &mut PointTracker| {
+            point_tracker.mark_as_unused(loc);
+            if adjacent_filled {
+                point_tracker.add_to_frontier(loc);
+            }
+        };
+
+        let new_lookups = self
+            .active_stage
+            .map(|stage_index| (stage_index, &self.ring_bands))
+            .into_iter()
+            .chain(
+                self.parked_stages
+                    .iter()
+                    .map(|parked| (parked.stage_index, &parked.ring_bands)),
+            )
+            .filter(|(stage_index, _)| {
+                !self.stage_lookups.contains_key(stage_index)
             })
-            .flat_map(|p| p.into_iter())
-            .collect();
+            .map(|(stage_index, ring_bands)| {
+                let lookup =
+                    StageLookup::new(&self.stages[stage_index], ring_bands);
+                (stage_index, lookup)
+            })
+            .collect::<Vec<_>>();
+        self.stage_lookups.extend(new_lookups);
 
-        SaveImageData {
-            data,
-            width: size.width,
-            height: size.height,
+        // Before the first stage, the PointTracker is remade when the
+        // stage starts.
+        let active_allows = self.active_stage.is_none_or(|stage_index| {
+            self.stage_allows(
+                stage_index,
+                self.next_reveal,
+                self.ring_bands.len(),
+                &self.ring_core,
+                loc,
+            )
+        });
+        if active_allows {
+            release(&mut self.point_tracker);
+        }
+        let parked_allows = self
+            .parked_stages
+            .iter()
+            .map(|parked| {
+                self.stage_allows(
+                    parked.stage_index,
+                    parked.next_reveal,
+                    parked.ring_bands.len(),
+                    &parked.ring_core,
+                    loc,
+                )
+            })
+            .collect::<Vec<_>>();
+        self.parked_stages
+            .iter_mut()
+            .zip(parked_allows)
+            .filter(|(_parked, allows)| *allows)
+            .for_each(|(parked, _allows)| release(&mut parked.point_tracker));
+        self.best_match_queue = None;
+        self.is_done = false;
+
+        Some(previous)
+    }
+
+    // Whether the stage may fill the pixel when it is empty, given
+    // how far through its reveal schedule and ring growth the stage
+    // is.  Pixels that have been revealed are allowed even outside of
+    // the stage's region, as in reveal().
+    fn stage_allows(
+        &self,
+        stage_index: usize,
+        next_reveal: usize,
+        num_ring_bands: usize,
+        ring_core: &HashSet<PixelLoc>,
+        loc: PixelLoc,
+    ) -> bool {
+        let lookup = &self.stage_lookups[&stage_index];
+        let (was_revealed, is_pending) = match lookup.reveals.get(&loc) {
+            Some(&(first_revealed, last)) => (
+                first_revealed.is_some_and(|reveal| reveal < next_reveal),
+                last >= next_reveal,
+            ),
+            None => (false, false),
+        };
+        if was_revealed {
+            return true;
         }
+
+        let in_region = match &self.stages[stage_index].restricted_region {
+            RestrictedRegion::Allowed(_) => lookup.region.contains(&loc),
+            RestrictedRegion::Forbidden(_) => !lookup.region.contains(&loc),
+        };
+        let in_ring_band = lookup
+            .ring_bands
+            .get(&loc)
+            .is_some_and(|&band| band < num_ring_bands);
+        in_region && !ring_core.contains(&loc) && !is_pending && !in_ring_band
     }
 
-    fn _color_palette_image_data(&self) -> SaveImageData {
-        let mut data = self.stages[self.active_stage.unwrap_or(0)]
-            .palette
-            .iter_points()
-            .map(|p| match p {
-                Some(rgb) => vec![rgb.r(), rgb.g(), rgb.b(), 255],
-                None => vec![0, 0, 0, 0],
+    // Index of the stage that filled each pixel of the layer, in
+    // row-major order over the layer's view, as in layer_view().
+    // Pixels set with set_pixel() before the first stage started, or
+    // not yet filled, are None.
+    pub fn stage_map(&self, layer: u8) -> Vec<Option<usize>> {
+        self.layer_view_pixels(layer)
+            .map(|(index, _loc)| {
+                index.and_then(|index| self.pixel_stages[index])
             })
-            .flat_map(|p| p.into_iter())
-            .collect::<Vec<u8>>();
+            .collect()
+    }
 
-        // TODO: Better method here.  Currently, the smallest size
-        // with enough points that roughly matches the aspect
-        // ratio of layer 0.
-        let aspect_ratio = (self.topology.layers[0].width as f64)
-            / (self.topology.layers[0].height as f64);
+    // Corner, width, and height of the part of the layer shown in the
+    // per-layer outputs.  Fixed-size layers are shown in full.
+    // Unbounded layers are cropped to the bounding box of their
+    // filled pixels, or show their allocated chunks if none are
+    // filled, so their outputs change size as they grow.
+    pub fn layer_view(&self, layer: u8) -> Option<(PixelLoc, u32, u32)> {
+        let size = self.topology.layers().get(layer as usize)?;
+        let bounds = if self.topology.is_unbounded(layer) {
+            self.filled_bounds(layer)
+                .or_else(|| self.topology.allocated_bounds(layer))
+        } else {
+            None
+        };
+        Some(match bounds {
+            Some((min, max)) => {
+                (min, (max.i - min.i + 1) as u32, (max.j - min.j + 1) as u32)
+            }
+            None => (PixelLoc { layer, i: 0, j: 0 }, size.width, size.height),
+        })
+    }
 
-        let area = self.topology.len() as f64;
-        let height = (area / aspect_ratio).sqrt();
-        let width = (height * aspect_ratio).ceil() as u32;
-        let height = height.ceil() as u32;
+    // Index and location of each pixel of the layer's view, in
+    // row-major order.  The index is None for pixels whose chunk
+    // hasn't been allocated, and the location is None for pixels
+    // outside the shape of the layer.
+    fn layer_view_pixels(
+        &self,
+        layer: u8,
+    ) -> impl Iterator<Item = (Option<usize>, Option<PixelLoc>)> + '_ {
+        if self.topology.is_unbounded(layer) {
+            let (corner, width, height) = self.layer_view(layer).unwrap();
+            Either::Left((0..height as i32).flat_map(move |dj| {
+                (0..width as i32).map(move |di| {
+                    let loc = PixelLoc {
+                        layer,
+                        i: corner.i + di,
+                        j: corner.j + dj,
+                    };
+                    (self.topology.get_index(loc), Some(loc))
+                })
+            }))
+        } else {
+            Either::Right(
+                self.topology
+                    .get_layer_bounds(layer)
+                    .into_iter()
+                    .flatten()
+                    .map(move |index| {
+                        (Some(index), self.topology.get_loc(index))
+                    }),
+            )
+        }
+    }
 
-        // Pad data array out with 0 as needed.
-        data.resize((4 * width * height) as usize, 0);
+    // Position of the pixel within the layer's view, in row-major
+    // order, if it is shown there.
+    fn view_position(
+        &self,
+        view: (PixelLoc, u32, u32),
+        loc: PixelLoc,
+    ) -> Option<usize> {
+        let (corner, width, height) = view;
+        let di = loc.i - corner.i;
+        let dj = loc.j - corner.j;
+        let in_view = loc.layer == corner.layer
+            && (0..width as i32).contains(&di)
+            && (0..height as i32).contains(&dj)
+            && self.topology.get_index(loc).is_some();
+        if in_view {
+            Some((dj as usize) * (width as usize) + (di as usize))
+        } else {
+            None
+        }
+    }
 
-        SaveImageData {
-            data,
-            width,
-            height,
+    // The seed point from which the pixel was grown, if known.
+    pub fn get_pixel_origin(&self, loc: PixelLoc) -> Option<PixelLoc> {
+        self.topology
+            .get_index(loc)
+            .and_then(|index| self.pixel_origins[index])
+    }
+
+    // Layers and portals of the image, as of the current stage.
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    // Independent copy of the image as it is now, including the
+    // palettes, frontier, and random number generator, so that one
+    // partially grown image can branch into several continuations.
+    // Each copy continues identically until changed, such as with
+    // reseed() or replace_palette().  Animation outputs and the
+    // progress bar are not copied.  If the colors used are shared
+    // with other images, as in BatchRenderer, the copy gets its own
+    // set of used colors.
+    pub fn clone_state(&self) -> GrowthImage {
+        GrowthImage {
+            topology: self.topology.clone(),
+            pixels: self.pixels.clone(),
+            pixel_stages: self.pixel_stages.clone(),
+            pixel_fill_order: self.pixel_fill_order.clone(),
+            pixel_origins: self.pixel_origins.clone(),
+            num_fill_events: self.num_fill_events,
+            stats: self.stats.clone(),
+            num_filled_pixels: self.num_filled_pixels,
+            blend_base: self.blend_base.clone(),
+            stages: self.stages.clone(),
+            active_stage: self.active_stage,
+            num_stages_started: self.num_stages_started,
+            parked_stages: self.parked_stages.clone(),
+            stage_fill_estimates: self.stage_fill_estimates.clone(),
+            stage_timeline: self.stage_timeline.clone(),
+            unreachable_pixels: self.unreachable_pixels.clone(),
+            region_error: self.region_error,
+            current_stage_iter: self.current_stage_iter,
+            next_reveal: self.next_reveal,
+            ring_bands: self.ring_bands.clone(),
+            ring_core: self.ring_core.clone(),
+            stage_lookups: self.stage_lookups.clone(),
+            best_match_queue: self.best_match_queue.clone(),
+            point_tracker: self.point_tracker.clone(),
+            epsilon: self.epsilon,
+            layer_epsilon: self.layer_epsilon.clone(),
+            layer_stats: self.layer_stats.clone(),
+            target_kernel: Arc::clone(&self.target_kernel),
+            color_map: self.color_map.clone(),
+            seed: self.seed,
+            linear_light_averaging: self.linear_light_averaging,
+            strict_regions: self.strict_regions,
+            background: self.background,
+            alpha_mode: self.alpha_mode,
+            precompute_adjacency: self.precompute_adjacency,
+            kdtree_options: self.kdtree_options,
+            used_colors: self
+                .used_colors
+                .as_ref()
+                .map(|colors| Arc::new(ColorSet::clone(colors))),
+            num_duplicate_colors: self.num_duplicate_colors,
+            rng: self.rng.clone(),
+            is_done: self.is_done,
+            progress: None,
+            animation_outputs: Vec::new(),
+            live_output: None,
+            metadata: self.metadata.clone(),
         }
     }
 
-    fn _write_image_data(&self, filename: PathBuf, data: &SaveImageData) {
-        let file = std::fs::File::create(filename).unwrap();
-        let bufwriter = &mut std::io::BufWriter::new(file);
+    // Allow the active stage's reserved colors to be used from now
+    // on, rather than waiting for the stage's release iteration.
+    pub fn release_reserved_colors(&mut self) {
+        if let Some(stage) = self.active_stage {
+            self.stages[stage].reserved_released = true;
+        }
+    }
 
-        self._write_image_data_to_writer(bufwriter, data);
+    // Restart the random number generator, so that copies made with
+    // clone_state() diverge.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        self.metadata.push((
+            "omnicolor:reseed".to_string(),
+            format!("{} after {} pixels", seed, self.num_filled_pixels),
+        ));
     }
 
-    fn _write_image_data_to_writer(
+    // Replace the colors remaining in a stage's palette with the same
+    // number of colors from a different palette.  Returns false if
+    // there is no such stage.
+    pub fn replace_palette<T>(&mut self, stage_index: usize, palette: T) -> bool
+    where
+        T: Palette,
+    {
+        let stage = match self.stages.get_mut(stage_index) {
+            Some(stage) => stage,
+            None => return false,
+        };
+        let n_colors = stage.palette.num_points() as u32;
+        stage.palette = KDTree::with_options(
+            exclude_colors(
+                palette.generate(n_colors, &mut self.rng),
+                &stage.excluded_colors,
+                stage.exclusion_tolerance,
+            ),
+            self.kdtree_options,
+        );
+        if stage.palette_depletion.is_some() {
+            stage.palette_depletion =
+                Some(PaletteDepletion::new(&stage.palette));
+        }
+        self.best_match_queue = None;
+        self.is_done = false;
+        true
+    }
+
+    fn filled_pixels(&self) -> FilledPixels<'_> {
+        FilledPixels {
+            topology: &self.topology,
+            pixels: &self.pixels,
+            fill_order: &self.pixel_fill_order,
+            linear_light: self.linear_light_averaging,
+            random_seed: self.seed,
+            num_fill_events: self.num_fill_events,
+            animation_frame: self
+                .animation_outputs
+                .first()
+                .map_or(0, |anim| anim.frames_written),
+        }
+    }
+
+    // Post-processing pass to reduce the color differences between
+    // neighboring pixels.  The strength is the number of passes over
+    // the image, and may be fractional.
+    //
+    // If preserve_colors is true, colors are only swapped between
+    // adjacent pixels, when the swap lowers the total squared color
+    // difference with their neighbors.  The image keeps exactly the
+    // same set of colors.  Otherwise, each pass moves every pixel
+    // halfway toward the mean of its neighbors.
+    pub fn smooth(&mut self, preserve_colors: bool, strength: f64) {
+        let filled_indices = self
+            .pixels
+            .iter()
+            .enumerate()
+            .filter(|(_i, p)| p.is_some())
+            .map(|(i, _p)| i)
+            .collect::<Vec<_>>();
+        if filled_indices.is_empty() || strength <= 0.0 {
+            return;
+        }
+
+        if preserve_colors {
+            let num_attempts =
+                (strength * (filled_indices.len() as f64)) as usize;
+            (0..num_attempts).for_each(|_| {
+                let index =
+                    filled_indices[self.rng.gen_range(0..filled_indices.len())];
+                self.try_smoothing_swap(index);
+            });
+        } else {
+            let full_passes = strength.floor() as usize;
+            let partial = strength - strength.floor();
+            (0..full_passes).for_each(|_| self.blend_pass(1.0));
+            if partial > 0.0 {
+                self.blend_pass(partial);
+            }
+        }
+    }
+
+    // Total squared color difference between the color, placed at the
+    // location, and its filled neighbors.  The excluded pixel is
+    // skipped.
+    fn neighbor_energy(
         &self,
-        writer: &mut impl std::io::Write,
-        data: &SaveImageData,
-    ) {
-        let mut encoder = png::Encoder::new(writer, data.width, data.height);
-        encoder.set_color(png::ColorType::RGBA);
-        encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = encoder.write_header().unwrap();
+        loc: PixelLoc,
+        color: &RGB,
+        exclude: PixelLoc,
+    ) -> f64 {
+        self.topology
+            .iter_adjacent(loc)
+            .filter(|&adj| adj != exclude)
+            .flat_map(|adj| self.get_pixel(adj))
+            .map(|adj_color| color.dist2(&adj_color))
+            .sum()
+    }
+
+    fn try_smoothing_swap(&mut self, index: usize) {
+        let loc = match self.topology.get_loc(index) {
+            Some(loc) => loc,
+            None => return,
+        };
+        let neighbors = self
+            .topology
+            .iter_adjacent(loc)
+            .filter(|&adj| self.get_pixel(adj).is_some())
+            .collect::<Vec<_>>();
+        if neighbors.is_empty() {
+            return;
+        }
+        let other = neighbors[self.rng.gen_range(0..neighbors.len())];
+        let other_index = self.topology.get_index(other).unwrap();
+
+        let color = self.pixels[index].unwrap();
+        let other_color = self.pixels[other_index].unwrap();
 
-        writer.write_image_data(&data.data).unwrap();
+        // The difference between the two pixels themselves is the
+        // same before and after, so it is left out.
+        let before = self.neighbor_energy(loc, &color, other)
+            + self.neighbor_energy(other, &other_color, loc);
+        let after = self.neighbor_energy(loc, &other_color, other)
+            + self.neighbor_energy(other, &color, loc);
+        // Everything recorded about the pixels moves with their
+        // colors.
+        if after < before {
+            self.pixels.swap(index, other_index);
+            self.pixel_stages.swap(index, other_index);
+            self.pixel_fill_order.swap(index, other_index);
+            self.pixel_origins.swap(index, other_index);
+            self.stats.swap(index, other_index);
+        }
     }
-}
 
-impl Drop for GrowthImage {
-    fn drop(&mut self) {
-        self.animation_outputs.iter_mut().for_each(|anim| {
-            anim.proc.wait().unwrap();
-        });
+    // Move each filled pixel toward the mean of its filled neighbors,
+    // by the given fraction of half the distance.
+    fn blend_pass(&mut self, fraction: f64) {
+        let weight = 0.5 * fraction.clamp(0.0, 1.0);
+        let new_pixels = (0..self.pixels.len())
+            .map(|index| {
+                let color = self.pixels[index]?;
+                let loc = self.topology.get_loc(index)?;
+                let filled = self.filled_pixels();
+                let mean = filled.mean_color(
+                    filled
+                        .adjacent_colors(loc)
+                        .into_iter()
+                        .map(|adj| (adj, 1.0)),
+                );
+                Some(match mean {
+                    Some(mean) => filled
+                        .mean_color(
+                            vec![(color, 1.0 - weight), (mean, weight)]
+                                .into_iter(),
+                        )
+                        .unwrap(),
+                    None => color,
+                })
+            })
+            .collect();
+        self.pixels = new_pixels;
+    }
+
+    pub fn get_adjacent_color(&self, loc: PixelLoc) -> Option<RGB> {
+        AdjacentMean.target_color(&self.filled_pixels(), loc)
+    }
+
+    // The color that the pixel will try to match, as determined by the
+    // active stage's TargetColorKernel, if it has one, or else by the
+    // image's.
+    pub fn get_target_color(&self, loc: PixelLoc) -> Option<RGB> {
+        self.active_stage
+            .and_then(|index| self.stages[index].target_kernel.as_ref())
+            .unwrap_or(&self.target_kernel)
+            .target_color(&self.filled_pixels(), loc)
+    }
+
+    fn current_stage_finished(&self) -> bool {
+        let active_stage = &self.stages[self.active_stage.unwrap()];
+        let reached_max_stage_iter = match active_stage.max_iter {
+            Some(max_iter) => self.current_stage_iter >= max_iter,
+            None => false,
+        };
+        let empty_palette = active_stage.palette.num_points() == 0
+            && active_stage
+                .seed_palettes
+                .values()
+                .all(|palette| palette.num_points() == 0)
+            && active_stage
+                .fallback_palette
+                .as_ref()
+                .is_none_or(|palette| palette.num_points() == 0)
+            && active_stage
+                .reserved_colors
+                .as_ref()
+                .is_none_or(|palette| palette.num_points() == 0);
+
+        let empty_frontier = self.point_tracker.is_done();
+
+        let condition_met = self.current_stage_iter > 0
+            && self
+                .current_stage_iter
+                .is_multiple_of(active_stage.condition_check_period)
+            && active_stage.advance_conditions.iter().any(|condition| {
+                condition.is_met(&self.topology, &self.pixels)
+            });
+
+        reached_max_stage_iter
+            || empty_palette
+            || empty_frontier
+            || condition_met
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip(self))
+    )]
+    fn start_stage(&mut self, stage_index: usize) {
+        // Advance stage number
+        self.active_stage = Some(stage_index);
+        self.num_stages_started = stage_index + 1;
+        self.current_stage_iter = 0;
+        self.stage_timeline
+            .push(StageTiming::new(stage_index, self.num_fill_events));
+        let active_stage = &self.stages[stage_index];
+
+        // A blended stage grows from an empty buffer, which is
+        // composited over the image once the stage finishes.  Stages
+        // that run concurrently share the image, and can't be
+        // blended.
+        if active_stage.blend.is_some() && !self.is_concurrent(stage_index) {
+            let empty = vec![None; self.pixels.len()];
+            self.blend_base = Some(std::mem::replace(&mut self.pixels, empty));
+        }
+
+        // Recalculate the iterations per frame for each animation.
+        self.animation_outputs.iter_mut().for_each(|anim| {
+            anim.iter_per_frame =
+                (active_stage.animation_iter_per_second / anim.fps) as usize;
+        });
+
+        // Update the geometry with new portals.  Long-term, should
+        // forbidden points go here as well?  Conceptually, they fit
+        // really well with the geometry tracking class, but the
+        // implementation is much cleaner with them being part of the
+        // PointTracker's "used" array.
+        self.topology.set_portals(Arc::clone(&active_stage.portals));
+        if self.precompute_adjacency {
+            self.topology.build_adjacency_table();
+        }
+
+        // Seed points may be moved off of their chunk by the jitter,
+        // so are chosen before the chunks are allocated.
+        let rng = &mut self.rng;
+        let topology = &self.topology;
+        let seed_points = active_stage
+            .selected_seed_points
+            .iter()
+            .map(|&loc| match active_stage.seed_point_jitter {
+                Some(jitter) => {
+                    let mut offset = || jitter.sample(rng).round() as i32;
+                    let moved = PixelLoc {
+                        layer: loc.layer,
+                        i: loc.i + offset(),
+                        j: loc.j + offset(),
+                    };
+                    if topology.is_valid(moved) {
+                        moved
+                    } else {
+                        loc
+                    }
+                }
+                None => loc,
+            })
+            .collect::<Vec<_>>();
+        self.allocate_stage_chunks(stage_index, &seed_points);
+        let active_stage = &self.stages[stage_index];
+
+        // Remake the PointTracker, so that we can clear any forbidden
+        // points from the previous stage, as well as removing any
+        // newly forbidden points from the frontier.
+        let mut point_tracker = PointTracker::new(self.topology.clone());
+
+        match &active_stage.restricted_region {
+            RestrictedRegion::Allowed(points) => {
+                point_tracker.mark_all_as_used();
+                points
+                    .iter()
+                    .for_each(|&loc| point_tracker.mark_as_unused(loc));
+            }
+            RestrictedRegion::Forbidden(points) => {
+                points
+                    .iter()
+                    .for_each(|&loc| point_tracker.mark_as_used(loc));
+            }
+        }
+
+        point_tracker
+            .set_selection_weights(active_stage.selection_weights.clone());
+
+        // Points that are revealed partway through the stage start out
+        // as forbidden.
+        active_stage
+            .reveal_schedule
+            .iter()
+            .filter(|(iter, _points)| *iter > 0)
+            .flat_map(|(_iter, points)| points.iter())
+            .for_each(|&loc| point_tracker.mark_as_used(loc));
+        self.next_reveal = active_stage
+            .reveal_schedule
+            .iter()
+            .take_while(|(iter, _points)| *iter == 0)
+            .count();
+
+        // All filled pixels are either forbidden, or forbidden with a
+        // frontier.
+        let filled_locs = self
+            .pixels
+            .iter()
+            .enumerate()
+            .filter(|(_i, p)| p.is_some())
+            .flat_map(|(i, _p)| self.topology.get_loc(i));
+
+        if active_stage.grow_from_previous {
+            filled_locs.for_each(|loc| point_tracker.fill(loc));
+        } else {
+            filled_locs.for_each(|loc| point_tracker.mark_as_used(loc));
+        };
+
+        // Add in any selected seed points, along with the angular
+        // limits on growth from them.
+        point_tracker.set_growth_cones(active_stage.growth_cones.clone());
+        seed_points
+            .into_iter()
+            .for_each(|loc| point_tracker.add_to_frontier(loc));
+
+        // Randomly pick N seed points from those remaining.
+        // Implementation assumes that N is relatively small, may be
+        // inefficient for large N.
+        point_tracker.add_random_to_frontier(
+            active_stage.num_random_seed_points as usize,
+            &mut self.rng,
+        );
+        point_tracker.set_max_frontier_age(active_stage.frontier_max_age);
+
+        // Set the new point tracker as the one to use
+        self.point_tracker = point_tracker;
+
+        self.ring_bands = Vec::new();
+        self.ring_core = HashSet::new();
+        self.best_match_queue = None;
+        if let Some((r_min, r_max)) = self.stages[stage_index].ring_growth {
+            self.start_ring_growth(r_min as usize, r_max as usize);
+        }
+        if let Some(palette) = self.stages[stage_index].exact_palette.clone() {
+            self.generate_exact_palette(stage_index, palette.as_ref());
+        }
+
+        let stage = &mut self.stages[stage_index];
+        stage.initial_colors = Some(Arc::new(stage.iter_colors().collect()));
+
+        let num_unreachable = self.count_unreachable_pixels();
+        self.unreachable_pixels[stage_index] = Some(num_unreachable);
+        if num_unreachable > 0 {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                stage = stage_index,
+                num_unreachable,
+                "stage has unreachable pixels"
+            );
+            if self.strict_regions && self.region_error.is_none() {
+                self.region_error = Some((stage_index, num_unreachable));
+            }
+        }
+
+        self.stage_fill_estimates[stage_index] = self.stages[stage_index]
+            .limit_num_fills(self.num_fillable_pixels());
+        self.update_progress_length();
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            frontier_size = self.point_tracker.frontier_size(),
+            palette_size = self.stages[stage_index].palette.num_points(),
+            "stage started"
+        );
+    }
+
+    // Allocate the chunks of unbounded layers that hold any point used
+    // by the stage, so that its region, reveals, and seed points can
+    // all be indexed when the stage starts.  Chunks allocated later,
+    // as the growth reaches them, are outside the points listed by
+    // the stage.
+    fn allocate_stage_chunks(
+        &mut self,
+        stage_index: usize,
+        seed_points: &[PixelLoc],
+    ) {
+        if !self.topology.has_unbounded_layers() {
+            return;
+        }
+        let stage = &self.stages[stage_index];
+        let region_points = match &stage.restricted_region {
+            RestrictedRegion::Allowed(points) => points,
+            RestrictedRegion::Forbidden(points) => points,
+        };
+        let points = region_points
+            .iter()
+            .chain(stage.reveal_schedule.iter().flat_map(|(_, p)| p.iter()))
+            .chain(seed_points.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        points.into_iter().for_each(|loc| {
+            self.topology.allocate_chunk(loc);
+        });
+        self.grow_pixel_data();
+    }
+
+    // Allocate the chunks holding the pixel and its neighbors, if it
+    // is on an unbounded layer, so that it can be filled and grown
+    // from.
+    fn allocate_around(&mut self, loc: PixelLoc) {
+        if self.topology.allocate_around(loc) {
+            self.grow_pixel_data();
+        }
+    }
+
+    // Extend the data of each pixel, and each stage's PointTracker,
+    // to cover chunks that were allocated in the topology.  The new
+    // pixels are allowed for stages that list their forbidden
+    // points, and forbidden for stages that list their allowed
+    // points.
+    fn grow_pixel_data(&mut self) {
+        let len = self.topology.len();
+        if len == self.pixels.len() {
+            return;
+        }
+        self.pixels.resize(len, None);
+        self.pixel_stages.resize(len, None);
+        self.pixel_fill_order.resize(len, None);
+        self.pixel_origins.resize(len, None);
+        self.stats.resize(len, None);
+        if let Some(base) = &mut self.blend_base {
+            base.resize(len, None);
+        }
+
+        let stages = &self.stages;
+        let allowed = |stage_index: Option<usize>| {
+            stage_index.is_none_or(|stage_index| {
+                matches!(
+                    stages[stage_index].restricted_region,
+                    RestrictedRegion::Forbidden(_)
+                )
+            })
+        };
+        self.point_tracker
+            .grow(&self.topology, allowed(self.active_stage));
+        if let Some(queue) = &mut self.best_match_queue {
+            queue.grow(len);
+        }
+        let topology = &self.topology;
+        self.parked_stages.iter_mut().for_each(|parked| {
+            parked
+                .point_tracker
+                .grow(topology, allowed(Some(parked.stage_index)));
+            if let Some(queue) = &mut parked.best_match_queue {
+                queue.grow(len);
+            }
+        });
+    }
+
+    // Split the pixels reachable from the seed points into bands of
+    // distance [r_min, r_max], [r_max + 1, 2*r_max - r_min + 1], and so
+    // on.  Only the first band is allowed to start with, growing from
+    // its inner edge.  Pixels closer than r_min are forbidden for the
+    // rest of the stage.
+    fn start_ring_growth(&mut self, r_min: usize, r_max: usize) {
+        let seeds = self
+            .point_tracker
+            .iter_frontier()
+            .copied()
+            .collect::<Vec<_>>();
+        let mut blocked = Region::for_topology(&self.topology);
+        (0..self.topology.len())
+            .flat_map(|index| self.topology.get_loc(index))
+            .filter(|&loc| self.point_tracker.is_blocked(loc))
+            .for_each(|loc| blocked.insert(loc));
+        let distances = self.topology.distance_field_from(&seeds, &blocked);
+
+        let band_width = r_max.saturating_sub(r_min) + 1;
+        let mut bands: Vec<Vec<PixelLoc>> = Vec::new();
+        distances
+            .iter()
+            .enumerate()
+            .flat_map(|(index, dist)| Some((index, (*dist)?)))
+            .for_each(|(index, dist)| {
+                let loc = self.topology.get_loc(index).unwrap();
+                if dist < r_min {
+                    self.point_tracker.mark_as_used(loc);
+                    self.ring_core.insert(loc);
+                    return;
+                }
+
+                let band = (dist - r_min) / band_width;
+                if band > 0 {
+                    if bands.len() < band {
+                        bands.resize(band, Vec::new());
+                    }
+                    bands[band - 1].push(loc);
+                    self.point_tracker.mark_as_used(loc);
+                } else if r_min > 0 && dist == r_min {
+                    self.point_tracker.add_to_frontier(loc);
+                }
+            });
+
+        bands.reverse();
+        self.ring_bands = bands;
+    }
+
+    // Whether each empty pixel may be filled by the active stage,
+    // either now or once revealed, indexed as in get_index().
+    fn fillable_pixels(&self) -> Vec<bool> {
+        let mut fillable =
+            self.pixels
+                .iter()
+                .enumerate()
+                .map(|(index, pixel)| {
+                    pixel.is_none()
+                        && self.topology.get_loc(index).is_some_and(|loc| {
+                            !self.point_tracker.is_blocked(loc)
+                        })
+                })
+                .collect::<Vec<_>>();
+
+        let stage = &self.stages[self.active_stage.unwrap()];
+        stage
+            .reveal_schedule
+            .iter()
+            .skip(self.next_reveal)
+            .flat_map(|(_iter, points)| points.iter())
+            .chain(self.ring_bands.iter().flatten())
+            .flat_map(|&loc| self.topology.get_index(loc))
+            .filter(|&index| self.pixels[index].is_none())
+            .for_each(|index| fillable[index] = true);
+
+        fillable
+    }
+
+    fn num_fillable_pixels(&self) -> usize {
+        self.fillable_pixels().iter().filter(|&&b| b).count()
+    }
+
+    // Number of pixels that the active stage may fill, but that can't
+    // be reached from any of the stage's seed points or its starting
+    // frontier, such as when walls cut off part of the allowed region.
+    // Growth cones are not taken into account.  Pixels of unbounded
+    // layers aren't counted, since their allocated chunks needn't be
+    // connected to each other.
+    fn count_unreachable_pixels(&self) -> usize {
+        let fillable = self.fillable_pixels();
+        let mut blocked = Region::for_topology(&self.topology);
+        fillable
+            .iter()
+            .enumerate()
+            .filter(|(_, &fillable)| !fillable)
+            .flat_map(|(index, _)| self.topology.get_loc(index))
+            .for_each(|loc| blocked.insert(loc));
+        let seeds = self
+            .point_tracker
+            .iter_frontier()
+            .copied()
+            .collect::<Vec<_>>();
+        let distances = self.topology.distance_field_from(&seeds, &blocked);
+
+        fillable
+            .iter()
+            .zip(distances.iter())
+            .enumerate()
+            .filter(|(_, (&fillable, dist))| fillable && dist.is_none())
+            .flat_map(|(index, _)| self.topology.get_loc(index))
+            .filter(|loc| !self.topology.is_unbounded(loc.layer))
+            .count()
+    }
+
+    fn generate_exact_palette(
+        &mut self,
+        stage_index: usize,
+        palette: &dyn Palette,
+    ) {
+        let num_pixels = self.num_fillable_pixels();
+        let stage = &self.stages[stage_index];
+        let num_other_colors = stage
+            .seed_palettes
+            .values()
+            .chain(stage.reserved_colors.iter())
+            .map(|palette| palette.num_points())
+            .sum::<usize>();
+        let n_colors = num_pixels.saturating_sub(num_other_colors);
+
+        let colors = exclude_colors(
+            palette.generate(n_colors as u32, &mut self.rng),
+            &stage.excluded_colors,
+            stage.exclusion_tolerance,
+        );
+        let stage = &mut self.stages[stage_index];
+        stage.palette = KDTree::with_options(colors, self.kdtree_options);
+        if stage.palette_depletion.is_some() {
+            stage.palette_depletion =
+                Some(PaletteDepletion::new(&stage.palette));
+        }
+    }
+
+    // Allow growth into the points, starting from any that are
+    // adjacent to a filled pixel.
+    fn reveal(
+        topology: &Topology,
+        pixels: &[Option<RGB>],
+        point_tracker: &mut PointTracker,
+        points: &[PixelLoc],
+    ) {
+        points
+            .iter()
+            .filter(|&&loc| {
+                topology
+                    .get_index(loc)
+                    .is_some_and(|index| pixels[index].is_none())
+            })
+            .for_each(|&loc| {
+                point_tracker.mark_as_unused(loc);
+                let adjacent_filled = topology
+                    .iter_adjacent_indices(loc)
+                    .any(|index| pixels[index].is_some());
+                if adjacent_filled {
+                    point_tracker.add_to_frontier(loc);
+                }
+            });
+    }
+
+    // Allow growth into any points whose scheduled reveal has been
+    // reached.  If the frontier is empty, the next scheduled points
+    // are revealed early, rather than ending the stage.
+    fn apply_reveals(&mut self) {
+        let stage_index = self.active_stage.unwrap();
+        while let Some((iter, points)) = self.stages[stage_index]
+            .reveal_schedule
+            .get(self.next_reveal)
+        {
+            if *iter > self.current_stage_iter && !self.point_tracker.is_done()
+            {
+                break;
+            }
+
+            Self::reveal(
+                &self.topology,
+                &self.pixels,
+                &mut self.point_tracker,
+                points,
+            );
+            self.next_reveal += 1;
+            self.best_match_queue = None;
+        }
+
+        // With ring growth, the next band is revealed once the
+        // current band is filled.
+        while self.point_tracker.is_done() {
+            match self.ring_bands.pop() {
+                Some(band) => Self::reveal(
+                    &self.topology,
+                    &self.pixels,
+                    &mut self.point_tracker,
+                    &band,
+                ),
+                None => break,
+            }
+        }
+    }
+
+    fn try_fill(&mut self) -> Option<(PixelLoc, RGB)> {
+        loop {
+            if let Some(filled) = self.try_fill_pixel()? {
+                return Some(filled);
+            }
+        }
+    }
+
+    // Fill a single pixel.  Returns None once the image is finished,
+    // and Some(None) if no color was left for the chosen pixel.
+    fn try_fill_pixel(&mut self) -> Option<Option<(PixelLoc, RGB)>> {
+        // Start of the first stage
+        if self.active_stage.is_none() {
+            self.start_stage_group(0);
+        }
+        self.apply_reveals();
+
+        // Advance to the next stage, if needed.
+        while self.region_error.is_none() && self.current_stage_finished() {
+            #[cfg(feature = "tracing")]
+            tracing::info!(
+                stage = self.active_stage.unwrap(),
+                iterations = self.current_stage_iter,
+                frontier_size = self.point_tracker.frontier_size(),
+                "stage finished"
+            );
+            self.finish_stage();
+            if let Some(parked) = self.parked_stages.pop_front() {
+                self.unpark_stage(parked);
+                self.apply_reveals();
+                continue;
+            }
+            let next_stage = self.num_stages_started;
+            if next_stage < self.stages.len() {
+                self.start_stage_group(next_stage);
+                self.apply_reveals();
+            } else {
+                return None;
+            }
+        }
+        if self.region_error.is_some() {
+            return None;
+        }
+
+        // Periodically thin out the frontier, if requested.
+        let active_stage = &self.stages[self.active_stage.unwrap()];
+        if let Some(thinning) = active_stage.frontier_thinning {
+            if self
+                .current_stage_iter
+                .is_multiple_of(thinning.iter_period.max(1))
+            {
+                self.point_tracker
+                    .thin_frontier(thinning.min_distance, &mut self.rng);
+                self.best_match_queue = None;
+            }
+        }
+
+        let next_loc = if active_stage.best_match_first {
+            self.choose_best_match()
+        } else {
+            self.point_tracker.choose_frontier_point(&mut self.rng)
+        };
+        let origin = self.point_tracker.origin(next_loc);
+        let through_portal = self.stages[self.active_stage.unwrap()]
+            .portal_probabilities
+            .get(&next_loc)
+            .copied()
+            .is_none_or(|probability| self.rng.gen::<f32>() < probability);
+        self.allocate_around(next_loc);
+
+        let next_index = self.topology.get_index(next_loc)?;
+
+        let target_color =
+            self.get_target_color(next_loc).unwrap_or_else(|| RGB {
+                vals: [
+                    self.rng.gen::<u8>(),
+                    self.rng.gen::<u8>(),
+                    self.rng.gen::<u8>(),
+                ],
+            });
+
+        // Reserved colors are held back until their release, and
+        // are then used whenever they are at least as close as the
+        // nearest palette color.
+        let epsilon = self.epsilon_at(next_loc);
+        let active_stage = &mut self.stages[self.active_stage.unwrap()];
+        let used_colors = self.used_colors.as_deref();
+        let num_duplicate_colors = &mut self.num_duplicate_colors;
+        let num_fill_events = self.num_fill_events;
+        let current_stage_iter = self.current_stage_iter;
+        if active_stage
+            .reserved_release_iter
+            .is_some_and(|iter| current_stage_iter >= iter)
+            || active_stage.palette.num_points() == 0
+        {
+            active_stage.reserved_released = true;
+        }
+        let reserved_res = match &mut active_stage.reserved_colors {
+            Some(reserved) if active_stage.reserved_released => {
+                let palette_dist2 = active_stage
+                    .palette
+                    .peek_closest(&target_color, epsilon)
+                    .res
+                    .map(|(color, _)| color.dist2(&target_color));
+                // Reserved colors that were already used are discarded,
+                // as for the palette.
+                loop {
+                    let peek = reserved.peek_closest(&target_color, epsilon);
+                    let popped = peek
+                        .res
+                        .filter(|(color, _)| {
+                            palette_dist2.is_none_or(|palette_dist2| {
+                                color.dist2(&target_color) <= palette_dist2
+                            })
+                        })
+                        .and_then(|(_, handle)| {
+                            let color = reserved.commit_pop(handle)?;
+                            Some((color, handle.point_index()))
+                        });
+                    match popped {
+                        Some((color, _))
+                            if used_colors.is_some_and(|used_colors| {
+                                !used_colors.insert(&color)
+                            }) =>
+                        {
+                            #[cfg(feature = "tracing")]
+                            tracing::warn!(
+                                color = ?color.vals,
+                                "skipping duplicate reserved color"
+                            );
+                            *num_duplicate_colors += 1;
+                        }
+                        Some(res) => {
+                            break Some(KdtreeResult {
+                                res: Some(res),
+                                stats: peek.stats,
+                            });
+                        }
+                        None => break None,
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        // Pixels grown from a seed with its own palette use that
+        // palette until it runs out, then fall back to the stage's
+        // palette, and then to the stage's fallback palette.
+        let res = reserved_res.unwrap_or_else(|| {
+            origin
+                .and_then(|seed| active_stage.seed_palettes.get_mut(&seed))
+                .map(|palette| {
+                    Self::pop_palette_color(
+                        palette,
+                        used_colors,
+                        num_duplicate_colors,
+                        &target_color,
+                        epsilon,
+                    )
+                })
+                .filter(|res| res.res.is_some())
+                .unwrap_or_else(|| {
+                    let res = Self::pop_palette_color(
+                        &mut active_stage.palette,
+                        used_colors,
+                        num_duplicate_colors,
+                        &target_color,
+                        epsilon,
+                    );
+                    if let (Some((_, index)), Some(depletion)) =
+                        (res.res, &mut active_stage.palette_depletion)
+                    {
+                        depletion.used_at[index] = Some(num_fill_events);
+                    }
+                    res
+                })
+        });
+        let res = match (res.res, &mut active_stage.fallback_palette) {
+            (None, Some(fallback)) => Self::pop_palette_color(
+                fallback,
+                used_colors,
+                num_duplicate_colors,
+                &target_color,
+                epsilon,
+            ),
+            _ => res,
+        };
+        if self.layer_stats[next_loc.layer as usize] {
+            self.stats[next_index] = Some(res.stats);
+        }
+
+        // Palette searches are too frequent to trace individually, so
+        // only every TRACE_SEARCH_PERIOD-th search is recorded.
+        #[cfg(feature = "tracing")]
+        if self.num_fill_events.is_multiple_of(TRACE_SEARCH_PERIOD) {
+            tracing::trace!(
+                fill_event = self.num_fill_events,
+                nodes_checked = res.stats.nodes_checked,
+                leaf_nodes_checked = res.stats.leaf_nodes_checked,
+                points_checked = res.stats.points_checked,
+                found = res.res.is_some(),
+                "palette search"
+            );
+        }
+
+        // The palettes may run out early, when the colors left in
+        // them had already been used.  If so, the stage is finished,
+        // and the pixel is left for the next stage.  Otherwise, the
+        // pixel's seed palette ran out, and another pixel is chosen.
+        let next_color = match res.res {
+            Some((color, _)) => color,
+            None => {
+                if !self.current_stage_finished() {
+                    self.point_tracker.mark_as_unused(next_loc);
+                }
+                return Some(None);
+            }
+        };
+        self.point_tracker
+            .fill_through_portal(next_loc, through_portal);
+        let jitter = active_stage.color_jitter as i16;
+        let next_color = if let Some(distribution) =
+            active_stage.color_jitter_distribution
+        {
+            let mut vals = next_color.vals;
+            vals.iter_mut().for_each(|val| {
+                let delta = distribution.sample(&mut self.rng).round();
+                *val = (*val as f64 + delta).clamp(0.0, 255.0) as u8;
+            });
+            RGB { vals }
+        } else if jitter > 0 {
+            let mut vals = next_color.vals;
+            vals.iter_mut().for_each(|val| {
+                let delta = self.rng.gen_range(-jitter..=jitter);
+                *val = (*val as i16 + delta).clamp(0, 255) as u8;
+            });
+            RGB { vals }
+        } else {
+            next_color
+        };
+        self.pixels[next_index] = Some(next_color);
+        self.pixel_stages[next_index] = self.active_stage;
+        self.pixel_fill_order[next_index] = Some(self.num_fill_events);
+        self.pixel_origins[next_index] = origin;
+        self.num_fill_events += 1;
+
+        self.current_stage_iter += 1;
+        self.num_filled_pixels += 1;
+
+        if self.best_match_queue.is_some() {
+            self.update_best_match_neighbors(next_loc);
+        }
+
+        // Concurrent stages can't grow into the pixel, and take turns
+        // filling pixels.
+        self.parked_stages.iter_mut().for_each(|parked| {
+            parked.point_tracker.mark_as_used(next_loc);
+        });
+        if let Some(parked) = self.parked_stages.pop_front() {
+            let current = self.unpark_stage(parked);
+            self.parked_stages.push_back(current);
+        }
+
+        Some(Some((next_loc, next_color)))
+    }
+
+    // Whether the stage runs at the same time as another stage.
+    fn is_concurrent(&self, stage_index: usize) -> bool {
+        let with_next = self
+            .stages
+            .get(stage_index + 1)
+            .is_some_and(|next| next.concurrent_with_previous);
+        (stage_index > 0 && self.stages[stage_index].concurrent_with_previous)
+            || with_next
+    }
+
+    // Start the stage, along with any following stages that run
+    // concurrently with it.  The last of these becomes the active
+    // stage.
+    fn start_stage_group(&mut self, stage_index: usize) {
+        self.start_stage(stage_index);
+        while self
+            .stages
+            .get(self.num_stages_started)
+            .is_some_and(|stage| stage.concurrent_with_previous)
+        {
+            let parked = self.park_stage();
+            self.parked_stages.push_back(parked);
+            self.start_stage(self.num_stages_started);
+        }
+    }
+
+    // Copies the state of the active stage.  Only needed when a stage
+    // starts, since start_stage() replaces the PointTracker.
+    fn park_stage(&mut self) -> ParkedStage {
+        ParkedStage {
+            stage_index: self.active_stage.unwrap(),
+            current_stage_iter: self.current_stage_iter,
+            next_reveal: self.next_reveal,
+            ring_bands: std::mem::take(&mut self.ring_bands),
+            ring_core: std::mem::take(&mut self.ring_core),
+            point_tracker: self.point_tracker.clone(),
+            best_match_queue: self.best_match_queue.take(),
+        }
+    }
+
+    // Make the parked stage active, returning the state of the stage
+    // that was previously active.  Swaps rather than copies, since
+    // this happens after every fill.
+    fn unpark_stage(&mut self, parked: ParkedStage) -> ParkedStage {
+        let portals = &self.stages[parked.stage_index].portals;
+        let same_portals = self
+            .active_stage
+            .map(|index| &self.stages[index].portals)
+            .is_some_and(|prev| {
+                Arc::ptr_eq(prev, portals)
+                    || (prev.is_empty() && portals.is_empty())
+            });
+        if !same_portals {
+            self.topology.set_portals(Arc::clone(portals));
+            if self.precompute_adjacency {
+                self.topology.build_adjacency_table();
+            }
+        }
+
+        ParkedStage {
+            stage_index: self.active_stage.replace(parked.stage_index).unwrap(),
+            current_stage_iter: std::mem::replace(
+                &mut self.current_stage_iter,
+                parked.current_stage_iter,
+            ),
+            next_reveal: std::mem::replace(
+                &mut self.next_reveal,
+                parked.next_reveal,
+            ),
+            ring_bands: std::mem::replace(
+                &mut self.ring_bands,
+                parked.ring_bands,
+            ),
+            ring_core: std::mem::replace(&mut self.ring_core, parked.ring_core),
+            point_tracker: std::mem::replace(
+                &mut self.point_tracker,
+                parked.point_tracker,
+            ),
+            best_match_queue: std::mem::replace(
+                &mut self.best_match_queue,
+                parked.best_match_queue,
+            ),
+        }
+    }
+
+    fn epsilon_at(&self, loc: PixelLoc) -> f64 {
+        self.layer_epsilon
+            .get(loc.layer as usize)
+            .copied()
+            .unwrap_or(self.epsilon)
+    }
+
+    // Distance squared from the pixel's target color to the closest
+    // color in the palette it would be filled from.  Pixels without a
+    // target color, such as seed points, are filled first.
+    fn best_match_dist2(&self, loc: PixelLoc) -> f64 {
+        let target = match self.get_target_color(loc) {
+            Some(target) => target,
+            None => return 0.0,
+        };
+        let stage = &self.stages[self.active_stage.unwrap()];
+        let seed_palette = self
+            .point_tracker
+            .origin(loc)
+            .and_then(|seed| stage.seed_palettes.get(&seed));
+        seed_palette
+            .into_iter()
+            .chain(std::iter::once(&stage.palette))
+            .chain(stage.fallback_palette.iter())
+            .find(|palette| palette.num_points() > 0)
+            .and_then(|palette| {
+                palette.get_closest(&target, self.epsilon_at(loc)).res
+            })
+            .map_or(f64::INFINITY, |color| color.dist2(&target))
+    }
+
+    // Frontier point with the closest palette match.  Each candidate
+    // is re-checked against the current palette before it is used,
+    // and pushed back if it has become worse than the next entry.
+    fn choose_best_match(&mut self) -> PixelLoc {
+        let mut queue = self.best_match_queue.take().unwrap_or_default();
+        loop {
+            let (index, dist2) = match queue.pop() {
+                Some(entry) => entry,
+                None => {
+                    queue = BestMatchQueue::new(self.topology.len());
+                    let frontier = self
+                        .point_tracker
+                        .iter_frontier()
+                        .copied()
+                        .filter(|&loc| self.point_tracker.is_in_frontier(loc))
+                        .collect::<Vec<_>>();
+                    frontier.into_iter().for_each(|loc| {
+                        let index = self.topology.get_index(loc).unwrap();
+                        let dist2 = self.best_match_dist2(loc);
+                        queue.push(index, dist2, &mut self.rng);
+                    });
+                    continue;
+                }
+            };
+
+            let loc = self.topology.get_loc(index).unwrap();
+            if !self.point_tracker.is_in_frontier(loc) {
+                continue;
+            }
+            let current_dist2 = self.best_match_dist2(loc);
+            let is_best = current_dist2 <= dist2
+                || queue.peek_dist2().is_none_or(|next| current_dist2 <= next);
+            if is_best {
+                queue.versions[index] += 1;
+                self.best_match_queue = Some(queue);
+                return loc;
+            }
+            queue.push_current(index, current_dist2, &mut self.rng);
+        }
+    }
+
+    // Filling a pixel changes the target color of its neighbors, and
+    // may have added them to the frontier.
+    fn update_best_match_neighbors(&mut self, loc: PixelLoc) {
+        let mut queue = self.best_match_queue.take().unwrap();
+        let neighbors = self
+            .topology
+            .iter_adjacent_indexed(loc)
+            .filter(|&(_index, adjacent)| {
+                self.point_tracker.is_in_frontier(adjacent)
+            })
+            .collect::<Vec<_>>();
+        neighbors.into_iter().for_each(|(index, adjacent)| {
+            let dist2 = self.best_match_dist2(adjacent);
+            queue.push(index, dist2, &mut self.rng);
+        });
+        self.best_match_queue = Some(queue);
+    }
+
+    // Composite the pixels of a blended stage over the image beneath
+    // them.  Where there was nothing beneath, the stage's pixels are
+    // used as-is.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all)
+    )]
+    fn finish_stage(&mut self) {
+        let stage_index = self.active_stage.unwrap();
+        self.stage_fill_estimates[stage_index] = self.current_stage_iter;
+        self.stage_timeline[stage_index]
+            .finish(self.num_fill_events, self.current_stage_iter);
+        self.update_progress_length();
+
+        let base = match self.blend_base.take() {
+            Some(base) => base,
+            None => return,
+        };
+        let (mode, opacity) =
+            self.stages[self.active_stage.unwrap()].blend.unwrap();
+        self.pixels = base
+            .into_iter()
+            .zip(self.pixels.iter())
+            .map(|(base, top)| match (base, top) {
+                (Some(base), Some(top)) => Some(base.blend(top, mode, opacity)),
+                (base, top) => base.or(*top),
+            })
+            .collect();
+        self.num_filled_pixels =
+            self.pixels.iter().filter(|p| p.is_some()).count();
+    }
+
+    // Color of the pixel as it appears in the output, including the
+    // image beneath a blended stage that is in progress.
+    fn displayed_pixel(&self, index: usize) -> Option<RGB> {
+        let top = self.pixels[index];
+        match (&self.blend_base, top) {
+            (Some(base), Some(top)) => {
+                let (mode, opacity) =
+                    self.stages[self.active_stage.unwrap()].blend.unwrap();
+                Some(
+                    base[index]
+                        .map_or(top, |base| base.blend(&top, mode, opacity)),
+                )
+            }
+            (Some(base), None) => base[index],
+            (None, top) => top,
+        }
+    }
+
+    // If enforcing unique colors, discard any that were already used,
+    // either earlier in this stage, by a previous stage, or by another
+    // image sharing the same set of used colors.  Each discarded color
+    // is counted in num_duplicate_colors, and logged as a warning.
+    fn pop_palette_color(
+        palette: &mut KDTree<RGB>,
+        used_colors: Option<&ColorSet>,
+        num_duplicate_colors: &mut usize,
+        target_color: &RGB,
+        epsilon: f64,
+    ) -> KdtreeResult<(RGB, usize)> {
+        let mut pop = || {
+            let peek = palette.peek_closest(target_color, epsilon);
+            let res = peek.res.and_then(|(_, handle)| {
+                let color = palette.commit_pop(handle)?;
+                Some((color, handle.point_index()))
+            });
+            KdtreeResult {
+                res,
+                stats: peek.stats,
+            }
+        };
+
+        let mut res = pop();
+        if let Some(used_colors) = used_colors {
+            while let Some((color, _)) = res.res {
+                if used_colors.insert(&color) {
+                    break;
+                }
+                #[cfg(feature = "tracing")]
+                tracing::warn!(color = ?color.vals, "skipping duplicate color");
+                *num_duplicate_colors += 1;
+                res = pop();
+            }
+        }
+        res
+    }
+
+    // Write the first layer.  An unbounded layer is cropped to the
+    // bounding box of its filled pixels, as in layer_view().
+    pub fn write(&self, filename: PathBuf) {
+        self.write_image(filename, SaveImageType::Generated, 0);
+    }
+
+    pub fn write_image(
+        &self,
+        filename: PathBuf,
+        image_type: SaveImageType,
+        layer: u8,
+    ) {
+        self._image_data(image_type, layer)
+            .write_png_with_text(filename, &self.metadata);
+    }
+
+    // Write the first layer as a Deep Zoom tile pyramid, for panning
+    // and zooming in a browser.  Generating the pyramid here avoids
+    // writing and re-reading a single very large PNG.  See
+    // deepzoom::write_deepzoom for the layout of the directory.
+    pub fn write_deepzoom(&self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        write_deepzoom(
+            &self._image_data(SaveImageType::Generated, 0),
+            dir.as_ref(),
+            DEEPZOOM_TILE_SIZE,
+            DEEPZOOM_OVERLAP,
+        )
+    }
+
+    // Smallest and largest corners of the filled pixels in the layer.
+    pub fn filled_bounds(&self, layer: u8) -> Option<(PixelLoc, PixelLoc)> {
+        self.topology
+            .layer_indices(layer)
+            .filter(|&index| self.pixels[index].is_some())
+            .flat_map(|index| self.topology.get_loc(index))
+            .fold(None, |bounds: Option<(PixelLoc, PixelLoc)>, loc| {
+                Some(match bounds {
+                    None => (loc, loc),
+                    Some((min, max)) => (
+                        PixelLoc {
+                            i: min.i.min(loc.i),
+                            j: min.j.min(loc.j),
+                            ..min
+                        },
+                        PixelLoc {
+                            i: max.i.max(loc.i),
+                            j: max.j.max(loc.j),
+                            ..max
+                        },
+                    ),
+                })
+            })
+    }
+
+    // Text stored in a PNG written by write() or write_image(): the
+    // crate version, seed, epsilon, layer sizes, and the options of
+    // each stage.
+    pub fn read_metadata(
+        path: impl AsRef<Path>,
+    ) -> Result<HashMap<String, String>, Error> {
+        let file = std::fs::File::open(path)?;
+        read_png_text(&mut std::io::BufReader::new(file))
+    }
+
+    // Write the layer as uncompressed pixel data, for use by other
+    // tools without decoding a PNG.
+    pub fn write_raw(
+        &self,
+        filename: PathBuf,
+        format: RawFormat,
+        layer: u8,
+    ) -> Result<(), Error> {
+        let file = std::fs::File::create(filename)?;
+        let mut writer = std::io::BufWriter::new(file);
+        self._image_data(SaveImageType::Generated, layer)
+            .write_raw_to_writer(&mut writer, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Compare the first layer against a reference PNG image, which
+    // must have the same size.  Unfilled pixels are compared as
+    // black.
+    pub fn similarity_to(
+        &self,
+        filename: impl AsRef<Path>,
+        metric: Metric,
+    ) -> Result<f64, Error> {
+        let reference = SaveImageData::read_png(filename.as_ref())?;
+        let generated = self._generated_image_data(0, Background::Transparent);
+        if (reference.width, reference.height)
+            != (generated.width, generated.height)
+        {
+            return Err(Error::ImageSizeMismatch);
+        }
+        Ok(metric.compare(&generated, &reference))
+    }
+
+    // Write a layer as a 32-bit float OpenEXR image, in linear light.
+    // Each pixel is scaled by the hdr_intensity of the stage that
+    // filled it, so values may exceed 1.0.
+    #[cfg(feature = "exr")]
+    pub fn write_exr(&self, filename: PathBuf, layer: u8) -> Result<(), Error> {
+        let (_, width, height) = self.layer_view(layer).unwrap();
+        let indices = self
+            .layer_view_pixels(layer)
+            .map(|(index, _loc)| index)
+            .collect::<Vec<_>>();
+
+        exr::prelude::write_rgba_file(
+            filename,
+            width as usize,
+            height as usize,
+            |i, j| {
+                let index = indices[j * (width as usize) + i];
+                match index.and_then(|index| self.pixels[index]) {
+                    Some(rgb) => {
+                        let intensity = index
+                            .and_then(|index| self.pixel_stages[index])
+                            .map(|stage| self.stages[stage].hdr_intensity)
+                            .unwrap_or(1.0);
+                        let [r, g, b] = rgb.to_linear();
+                        (r * intensity, g * intensity, b * intensity, 1.0f32)
+                    }
+                    None => (0.0, 0.0, 0.0, 0.0),
+                }
+            },
+        )?;
+        Ok(())
+    }
+
+    // Write every layer into a single image, arranged in a labeled
+    // grid.  Useful for inspecting multi-layer images, where the
+    // bridges/underworld layers would otherwise need to be written
+    // out separately.
+    pub fn write_all_layers(&self, filename: PathBuf, layout: LayoutSpec) {
+        let images = (0..self.topology.layers().len())
+            .map(|layer| self._image_data(layout.image_type, layer as u8))
+            .collect::<Vec<_>>();
+        layout.arrange(&images).write_png(filename);
+    }
+
+    fn _write_live_output(&mut self) {
+        let live = match &mut self.live_output {
+            Some(live) => live,
+            None => return,
+        };
+        live.iter_since_write += 1;
+        if live.iter_since_write < live.iter_period && !self.is_done {
+            return;
+        }
+        live.iter_since_write = 0;
+
+        // A failed preview shouldn't end the run, and is retried at
+        // the next write.
+        let filename = live.filename.clone();
+        let _ = self._write_preview(&filename);
+    }
+
+    fn _write_preview(&self, filename: &Path) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        self._image_data(SaveImageType::Generated, 0)
+            .write_png_to_writer_with_text(&mut buf, &self.metadata);
+
+        let mut temp_name = filename.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        std::fs::write(&temp_name, &buf)?;
+        std::fs::rename(&temp_name, filename)?;
+        Ok(())
+    }
+
+    fn _write_to_animations(&mut self) {
+        // Steal the animation vector to mutate it.
+        let mut animations = std::mem::take(&mut self.animation_outputs);
+
+        // Increment the iterations since last frame write, for
+        // animations that are recording.
+        let num_fill_events = self.num_fill_events;
+        let in_window = |anim: &GrowthImageAnimation| {
+            num_fill_events >= anim.start_at_iter
+                && anim.stop_at_iter.is_none_or(|stop| num_fill_events < stop)
+        };
+        animations
+            .iter_mut()
+            .filter(|anim| in_window(anim))
+            .for_each(|anim| anim.iter_since_frame += 1);
+
+        // Write to it, which requires immutable borrow of other parts
+        // of self.
+        animations
+            .iter_mut()
+            .filter(|anim| in_window(anim))
+            .filter(|anim| anim.iter_since_frame >= anim.iter_per_frame)
+            .for_each(|anim| {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!(
+                    "animation_frame",
+                    frame = anim.frames_written,
+                )
+                .entered();
+
+                let overlay = anim.debug_overlay.as_ref().map(|o| o.get());
+                let images = anim
+                    .image_types
+                    .iter()
+                    .map(|&image_type| {
+                        let mut data = match (image_type, anim.background) {
+                            (SaveImageType::Generated, Some(background)) => {
+                                self._generated_image_data(
+                                    anim.layer, background,
+                                )
+                            }
+                            _ => self._image_data(image_type, anim.layer),
+                        };
+                        if let Some(overlay) = &overlay {
+                            if image_type.is_per_layer() {
+                                self._draw_debug_overlay(
+                                    &mut data, anim.layer, overlay,
+                                );
+                            }
+                        }
+                        data
+                    })
+                    .collect::<Vec<_>>();
+                let data = anim.composite.composite(images);
+                let info = FrameInfo {
+                    frame_index: anim.frames_written,
+                    num_filled_pixels: self.num_filled_pixels,
+                    stage: self.active_stage,
+                    width: data.width,
+                    height: data.height,
+                };
+                anim.iter_since_frame = 0;
+                if !anim.output.write_frame(data, info) {
+                    anim.frames_dropped += 1;
+                    return;
+                }
+                if let Some(stage) = self.active_stage {
+                    if anim.chapters.last().map(|c| c.0) != Some(stage) {
+                        anim.chapters.push((stage, anim.frames_written));
+                    }
+                }
+                anim.frames_written += 1;
+            });
+
+        // Put the animation vector back
+        std::mem::swap(&mut animations, &mut self.animation_outputs);
+    }
+
+    pub(crate) fn _image_data(
+        &self,
+        image_type: SaveImageType,
+        layer: u8,
+    ) -> SaveImageData {
+        match image_type {
+            SaveImageType::Generated => {
+                self._generated_image_data(layer, self.background)
+            }
+            SaveImageType::Statistics => self._statistics_image_data(layer),
+            SaveImageType::ColorPalette => self._color_palette_image_data(),
+            SaveImageType::Frontier => self._frontier_image_data(layer),
+            SaveImageType::StageMap => self._stage_map_image_data(layer),
+            SaveImageType::PaletteDepletion => {
+                self._palette_depletion_image_data()
+            }
+        }
+    }
+
+    // Each stage is drawn in its own color, with hues spaced by the
+    // golden angle so that consecutive stages are easy to tell apart.
+    fn _stage_map_image_data(&self, layer: u8) -> SaveImageData {
+        let (_, width, height) = self.layer_view(layer).unwrap();
+        let data = self
+            .stage_map(layer)
+            .iter()
+            .map(|stage| match stage {
+                Some(stage) => {
+                    let rgb: RGB = HSV {
+                        h: ((*stage as f32) * 137.507_76) % 360.0,
+                        s: 0.7,
+                        v: 0.95,
+                    }
+                    .into();
+                    vec![rgb.r(), rgb.g(), rgb.b(), 255]
+                }
+                None => vec![0, 0, 0, 0],
+            })
+            .flat_map(|p| p.into_iter())
+            .collect();
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+
+    // Filled pixels are shown at reduced brightness, with the
+    // frontier highlighted in white.
+    fn _frontier_image_data(&self, layer: u8) -> SaveImageData {
+        let view = self.layer_view(layer).unwrap();
+        let (_, width, height) = view;
+        let mut data = self
+            .layer_view_pixels(layer)
+            .map(|(index, _loc)| index.and_then(|index| self.pixels[index]))
+            .map(|p| match p {
+                Some(rgb) => vec![rgb.r() / 3, rgb.g() / 3, rgb.b() / 3, 255],
+                None => vec![0, 0, 0, 255],
+            })
+            .flat_map(|p| p.into_iter())
+            .collect::<Vec<u8>>();
+
+        self.point_tracker
+            .iter_frontier()
+            .flat_map(|&loc| self.view_position(view, loc))
+            .for_each(|index| {
+                data[4 * index..4 * index + 4]
+                    .copy_from_slice(&[255, 255, 255, 255]);
+            });
+
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+
+    fn _draw_debug_overlay(
+        &self,
+        data: &mut SaveImageData,
+        layer: u8,
+        overlay: &DebugOverlay,
+    ) {
+        if !overlay.any_enabled() {
+            return;
+        }
+
+        let view = self.layer_view(layer).unwrap();
+        let mut mark = |loc: PixelLoc, color: RGB| {
+            if let Some(index) = self.view_position(view, loc) {
+                data.data[4 * index..4 * index + 4].copy_from_slice(&[
+                    color.r(),
+                    color.g(),
+                    color.b(),
+                    255,
+                ]);
+            }
+        };
+
+        if overlay.forbidden.enabled {
+            self.topology
+                .layer_indices(layer)
+                .filter(|&index| self.pixels[index].is_none())
+                .flat_map(|index| self.topology.get_loc(index))
+                .filter(|&loc| self.point_tracker.is_blocked(loc))
+                .for_each(|loc| mark(loc, overlay.forbidden.color));
+        }
+
+        if overlay.frontier.enabled {
+            self.point_tracker
+                .iter_frontier()
+                .for_each(|&loc| mark(loc, overlay.frontier.color));
+        }
+
+        if overlay.portals.enabled {
+            self.topology
+                .iter_portals()
+                .for_each(|(&loc, _)| mark(loc, overlay.portals.color));
+        }
+
+        if overlay.seeds.enabled {
+            let offsets = [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)];
+            self.point_tracker.seeds().into_iter().for_each(|seed| {
+                offsets
+                    .iter()
+                    .map(|(di, dj)| PixelLoc {
+                        layer: seed.layer,
+                        i: seed.i + di,
+                        j: seed.j + dj,
+                    })
+                    .for_each(|loc| mark(loc, overlay.seeds.color));
+            });
+        }
+    }
+
+    fn _generated_image_data(
+        &self,
+        layer: u8,
+        background: Background,
+    ) -> SaveImageData {
+        let (_, width, height) = self.layer_view(layer).unwrap();
+        let data = self
+            .layer_view_pixels(layer)
+            .map(|(index, loc)| {
+                match (index.and_then(|index| self.displayed_pixel(index)), loc)
+                {
+                    (Some(rgb), _) => {
+                        let rgb = match &self.color_map {
+                            Some(color_map) => color_map.map(to_data(rgb)),
+                            None => rgb,
+                        };
+                        vec![rgb.r(), rgb.g(), rgb.b(), 255]
+                    }
+                    (None, Some(loc)) => {
+                        background.rgba(loc, self.alpha_mode).to_vec()
+                    }
+                    // Outside the shape of the layer.
+                    (None, None) => vec![0, 0, 0, 0],
+                }
+            })
+            .flat_map(|p| p.into_iter())
+            .collect();
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+
+    fn _statistics_image_data(&self, layer: u8) -> SaveImageData {
+        let (_, width, height) = self.layer_view(layer).unwrap();
+        let stats = self
+            .layer_view_pixels(layer)
+            .map(|(index, _loc)| index.and_then(|index| self.stats[index]))
+            .collect::<Vec<_>>();
+        let max = stats.iter().filter_map(|s| *s).fold(
+            PerformanceStats::default(),
+            |a, b| PerformanceStats {
+                nodes_checked: a.nodes_checked.max(b.nodes_checked),
+                leaf_nodes_checked: a
+                    .leaf_nodes_checked
+                    .max(b.leaf_nodes_checked),
+                points_checked: a.points_checked.max(b.points_checked),
+            },
+        );
+
+        let data = stats
+            .iter()
+            .map(|s| match s {
+                Some(stats) => vec![
+                    (255.0
+                        * ((stats.nodes_checked as f32).ln()
+                            / (max.nodes_checked as f32).ln()))
+                        as u8,
+                    (255.0
+                        * ((stats.leaf_nodes_checked as f32).ln()
+                            / (max.leaf_nodes_checked as f32).ln()))
+                        as u8,
+                    (255.0
+                        * ((stats.points_checked as f32).ln()
+                            / (max.points_checked as f32).ln()))
+                        as u8,
+                    255,
+                ],
+                None => vec![0, 0, 0, 0],
+            })
+            .flat_map(|p| p.into_iter())
+            .collect();
+
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+
+    // TODO: Better method here.  Currently, the smallest size with
+    // enough points that roughly matches the aspect ratio of layer 0.
+    fn _palette_image_size(&self, num_colors: usize) -> (u32, u32) {
+        let (_, width, height) = self.layer_view(0).unwrap();
+        let aspect_ratio = (width as f64) / (height as f64);
+
+        let area = num_colors as f64;
+        let height = (area / aspect_ratio).sqrt();
+        let width = (height * aspect_ratio).ceil() as u32;
+        let height = height.ceil() as u32;
+        (width, height)
+    }
+
+    // Each palette color at a fixed position, fading out once used.
+    // Blank unless an animation shows the palette depletion, since
+    // the layout must be made before any colors are used.
+    fn _palette_depletion_image_data(&self) -> SaveImageData {
+        let stage = &self.stages[self.active_stage.unwrap_or(0)];
+        let points = stage.palette.iter_points().collect::<Vec<_>>();
+        let (width, height) = self._palette_image_size(points.len());
+        let mut data = vec![0u8; (4 * width * height) as usize];
+
+        if let Some(depletion) = &stage.palette_depletion {
+            let fade_time =
+                ((points.len() as f64) * DEPLETION_FADE_FRACTION).max(1.0);
+            depletion
+                .layout
+                .iter()
+                .zip(data.chunks_exact_mut(4))
+                .for_each(|(&(index, rgb), pixel)| {
+                    let alpha = match (points[index], depletion.used_at[index])
+                    {
+                        (Some(_), _) => 1.0,
+                        (None, Some(used_at)) => {
+                            let age = (self.num_fill_events - used_at) as f64;
+                            (1.0 - age / fade_time).max(0.0)
+                        }
+                        (None, None) => 0.0,
+                    };
+                    pixel.copy_from_slice(&[
+                        rgb.r(),
+                        rgb.g(),
+                        rgb.b(),
+                        (255.0 * alpha).round() as u8,
+                    ]);
+                });
+        }
+
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+
+    fn _color_palette_image_data(&self) -> SaveImageData {
+        let mut data = self.stages[self.active_stage.unwrap_or(0)]
+            .palette
+            .iter_points()
+            .map(|p| match p {
+                Some(rgb) => vec![rgb.r(), rgb.g(), rgb.b(), 255],
+                None => vec![0, 0, 0, 0],
+            })
+            .flat_map(|p| p.into_iter())
+            .collect::<Vec<u8>>();
+
+        let (width, height) = self._palette_image_size(self.topology.len());
+
+        // Pad data array out with 0 as needed.
+        data.resize((4 * width * height) as usize, 0);
+
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+}
+
+impl Drop for GrowthImage {
+    fn drop(&mut self) {
+        // Errors can't be returned from drop().  To check that the
+        // chapter files were written, call finish_animations() first.
+        let _ = self.finish_animations();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::mpsc::{channel, Receiver};
+
+    use crate::bezier_util::BezPathExt;
+    use crate::color_map::Grayscale;
+    use crate::debug_overlay::OverlayKind;
+    use crate::errors::Error;
+    use crate::frame_writer::FrameWritePolicy;
+    use crate::growth_image_builder::GrowthImageBuilder;
+    use crate::kernels::RandomNeighbor;
+    use crate::palettes::{Palette, SphericalPalette, UniformPalette};
+
+    use itertools::Itertools;
+
+    #[test]
+    fn test_unique_colors() -> Result<(), Error> {
+        let red = RGB { vals: [255, 0, 0] };
+        let blue = RGB { vals: [0, 0, 255] };
+        let fill = |enforce_unique| -> Result<GrowthImage, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder
+                .add_layer(10, 10)
+                .seed(0)
+                .enforce_unique_colors(enforce_unique);
+            let single_color = |color| SphericalPalette {
+                central_color: color,
+                color_radius: 0.0,
+            };
+            builder.new_stage().palette(single_color(red)).max_iter(1);
+            builder.new_stage().palette(single_color(red)).max_iter(10);
+            builder.new_stage().palette(single_color(blue)).max_iter(10);
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok(image)
+        };
+        let colors_by_stage = |image: &GrowthImage| {
+            let mut colors = image
+                .pixel_stages
+                .iter()
+                .zip(image.pixels.iter())
+                .flat_map(|(&stage, &color)| Some((stage?, color?.vals)))
+                .collect::<Vec<_>>();
+            colors.sort_by_key(|(stage, _)| *stage);
+            colors.dedup();
+            colors
+        };
+
+        let image = fill(false)?;
+        assert_eq!(image.num_duplicate_colors(), 0);
+        assert_eq!(
+            colors_by_stage(&image),
+            vec![(0, red.vals), (1, red.vals), (2, blue.vals)]
+        );
+        assert_eq!(image.pixels.iter().flatten().count(), 21);
+
+        // The second stage runs out of unused colors right away, which
+        // ends the stage rather than the image.
+        let image = fill(true)?;
+        assert!(image.num_duplicate_colors() > 0);
+        assert_eq!(
+            colors_by_stage(&image),
+            vec![(0, red.vals), (2, blue.vals)]
+        );
+        assert_eq!(image.pixels.iter().flatten().count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_pixel() -> Result<(), Error> {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(8, 8).seed(0);
+        builder.new_stage().seed_points(vec![loc(0, 0)]);
+        let mut image = builder.build()?;
+        image.fill();
+
+        // Setting a pixel adds its empty neighbors to the frontier.
+        let color = RGB { vals: [1, 2, 3] };
+        let in_frontier = |image: &GrowthImage, loc| {
+            image
+                .point_tracker
+                .iter_frontier()
+                .any(|&point| point == loc)
+        };
+        assert!(!in_frontier(&image, loc(6, 6)));
+        assert!(image.set_pixel(loc(7, 7), color).is_none());
+        assert_eq!(
+            image.get_pixel(loc(7, 7)).map(|c| c.vals),
+            Some(color.vals)
+        );
+        assert!(in_frontier(&image, loc(6, 6)));
+
+        image.fill_until_done();
+        assert_eq!(
+            image.get_pixel(loc(7, 7)).map(|c| c.vals),
+            Some(color.vals)
+        );
+        assert_eq!(image.pixels.iter().flatten().count(), 64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear_forbidden_pixel() -> Result<(), Error> {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(8, 8).seed(0);
+        builder
+            .new_stage()
+            .seed_points(vec![loc(0, 0)])
+            .forbidden_points(vec![loc(4, 4)]);
+        let mut image = builder.build()?;
+        image.fill();
+
+        let color = RGB { vals: [1, 2, 3] };
+        image.set_pixel(loc(4, 4), color);
+        image.set_pixel(loc(6, 6), color);
+        assert!(image.clear_pixel(loc(4, 4)).is_some());
+        assert!(image.clear_pixel(loc(6, 6)).is_some());
+        image.fill_until_done();
+
+        // Only the pixel that the stage may fill is filled again.
+        assert!(image.get_pixel(loc(4, 4)).is_none());
+        assert!(image.get_pixel(loc(6, 6)).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_feathered_forbidden_points() -> Result<(), Error> {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let wall = (0..20).map(|j| loc(10, j)).collect::<Vec<_>>();
+        let num_near_wall = |feathered| -> Result<usize, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(20, 20).seed(0);
+            let stage = builder
+                .new_stage()
+                .seed_points(vec![loc(5, 10)])
+                .max_iter(150);
+            if feathered {
+                stage.forbidden_points_feathered(wall.clone(), 4.0);
+            } else {
+                stage.forbidden_points(wall.clone());
+            }
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok((0..20)
+                .filter(|&j| image.get_pixel(loc(9, j)).is_some())
+                .count())
+        };
+        // Growth slows down near a feathered wall, so fewer of the
+        // pixels next to it are filled.
+        assert!(num_near_wall(true)? < num_near_wall(false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed_palettes() -> Result<(), Error> {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let single_color = |vals| -> Box<dyn Palette> {
+            Box::new(SphericalPalette {
+                central_color: RGB { vals },
+                color_radius: 10.0,
+            })
+        };
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder
+            .new_stage()
+            .n_colors(100)
+            .seed_points_with_palettes(vec![
+                (loc(0, 0), single_color([255, 0, 0])),
+                (loc(9, 9), single_color([0, 0, 255])),
+            ])
+            .max_iter(40);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Each pixel is grown from one of the seeds, and takes its
+        // color from that seed's palette.
+        let filled = (0..10)
+            .flat_map(|i| (0..10).map(move |j| loc(i, j)))
+            .filter(|&loc| image.get_pixel(loc).is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(filled.len(), 40);
+        filled.iter().for_each(|&loc| {
+            let color = image.get_pixel(loc).unwrap();
+            match image.get_pixel_origin(loc) {
+                Some(origin)
+                    if origin
+                        == PixelLoc {
+                            layer: 0,
+                            i: 0,
+                            j: 0,
+                        } =>
+                {
+                    assert!(color.r() > 200 && color.b() < 50)
+                }
+                Some(origin)
+                    if origin
+                        == PixelLoc {
+                            layer: 0,
+                            i: 9,
+                            j: 9,
+                        } =>
+                {
+                    assert!(color.b() > 200 && color.r() < 50)
+                }
+                origin => panic!("Unexpected origin {:?}", origin),
+            }
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_smooth_preserves_colors() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder.new_stage().max_iter(50);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Each color keeps the stage and fill order at which it was
+        // placed.
+        let placed_colors = |image: &GrowthImage| {
+            let mut colors = (0..image.pixels.len())
+                .map(|index| {
+                    (
+                        image.pixel_stages[index],
+                        image.pixel_fill_order[index],
+                        image.pixels[index].map(|color| color.vals),
+                    )
+                })
+                .collect::<Vec<_>>();
+            colors.sort();
+            colors
+        };
+        let before = placed_colors(&image);
+        let pixels_before = image.pixels.clone();
+
+        image.smooth(true, 1.0);
+        assert_ne!(image.pixels, pixels_before);
+        assert_eq!(placed_colors(&image), before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fallback_palette() -> Result<(), Error> {
+        let num_filled = |fallback| -> Result<usize, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(10, 10).seed(0);
+            let stage = builder.new_stage().n_colors(50);
+            if fallback {
+                stage.fallback_palette(UniformPalette);
+            }
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok(image.pixels.iter().flatten().count())
+        };
+
+        // Without a fallback, the stage ends once its palette is used
+        // up.
+        assert_eq!(num_filled(false)?, 50);
+        assert_eq!(num_filled(true)?, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gravity_bias() -> Result<(), Error> {
+        let mean_j = |gravity| -> Result<f64, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(30, 30).seed(0);
+            let stage = builder
+                .new_stage()
+                .seed_points(vec![PixelLoc {
+                    layer: 0,
+                    i: 15,
+                    j: 15,
+                }])
+                .max_iter(200);
+            if gravity {
+                stage.gravity_bias(0.0, 4.0);
+            }
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            let filled = (0..image.pixels.len())
+                .filter(|&index| image.pixels[index].is_some())
+                .map(|index| image.topology.get_loc(index).unwrap().j as f64)
+                .collect::<Vec<_>>();
+            Ok(filled.iter().sum::<f64>() / filled.len() as f64)
+        };
+        // Without the bias, growth stays centered on the seed.
+        let unbiased = mean_j(false)?;
+        let biased = mean_j(true)?;
+        assert!((unbiased - 15.0).abs() < 2.0);
+        assert!(biased > unbiased + 2.0);
+        Ok(())
+    }
+
+    struct RecordingSink(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl FrameSink for RecordingSink {
+        fn write_frame(&mut self, rgba: &[u8], _info: &FrameInfo) {
+            self.0.lock().unwrap().push(rgba.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_chapter_file() -> Result<(), Error> {
+        let dir = std::env::temp_dir()
+            .join(format!("omnicolor-test-chapters-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let build = |chapter_file: PathBuf| -> Result<GrowthImage, Error> {
+            let frames = Arc::new(Mutex::new(Vec::new()));
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(10, 10).seed(0);
+            builder
+                .add_output_sink(Box::new(RecordingSink(frames)))
+                .chapter_file(chapter_file);
+            builder
+                .new_stage()
+                .max_iter(50)
+                .animation_iter_per_second(24.0);
+            builder.new_stage().animation_iter_per_second(24.0);
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok(image)
+        };
+
+        let filename = dir.join("chapters.txt");
+        build(filename.clone())?.finish_animations()?;
+        let text = std::fs::read_to_string(&filename)?;
+        assert_eq!(text.matches("[CHAPTER]").count(), 2);
+
+        // The error is returned, rather than panicking when the image
+        // is dropped.
+        let mut image = build(dir.join("missing").join("chapters.txt"))?;
+        assert!(image.finish_animations().is_err());
+        drop(image);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_palette_cache_fingerprint() -> Result<(), Error> {
+        let filename = std::env::temp_dir().join(format!(
+            "omnicolor-test-palette-cache-{}",
+            std::process::id()
+        ));
+        let build = |seed, radius| -> Result<Vec<u8>, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(8, 8).seed(seed);
+            builder
+                .new_stage()
+                .palette(SphericalPalette {
+                    central_color: RGB {
+                        vals: [128, 128, 128],
+                    },
+                    color_radius: radius,
+                })
+                .palette_cache(filename.clone());
+            builder.build()?;
+            Ok(std::fs::read(&filename)?)
+        };
+
+        // Reused when everything matches, regenerated when the
+        // palette or the seed changes.
+        let cached = build(0, 30.0)?;
+        assert_eq!(build(0, 30.0)?, cached);
+        let other_palette = build(0, 60.0)?;
+        assert!(other_palette != cached);
+        let other_seed = build(1, 60.0)?;
+        assert!(other_seed != other_palette);
+
+        std::fs::remove_file(&filename)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_n() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder.new_stage().max_iter(30);
+        builder.new_stage().max_iter(0);
+        builder.new_stage();
+        let mut image = builder.build()?;
+
+        let report = image.fill_n(20);
+        assert_eq!(report.num_filled, 20);
+        assert_eq!(report.stages_started, vec![0]);
+        assert!(!report.is_done);
+
+        // Stage 1 has no iterations, and is skipped over.
+        let report = image.fill_n(20);
+        assert_eq!(report.num_filled, 20);
+        assert_eq!(report.stages_started, vec![1, 2]);
+
+        let report = image.fill_n(1000);
+        assert_eq!(report.num_filled, 60);
+        assert!(report.is_done);
+        assert!(image.is_done());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_at_rate() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder.new_stage().max_iter(30);
+        builder.new_stage();
+        let mut image = builder.build()?;
+
+        let start = std::time::Instant::now();
+        let report = image.fill_at_rate(2000.0);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(45));
+        assert_eq!(report.num_filled, 100);
+        assert_eq!(report.stages_started, vec![0, 1]);
+        assert!(report.is_done);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_state() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder.new_stage().max_iter(200);
+        builder.new_stage();
+        let mut base = builder.build()?;
+        base.fill_n(250);
+
+        // Copies continue identically, unless reseeded.
+        let mut same = base.clone_state();
+        let mut reseeded = base.clone_state();
+        reseeded.reseed(1);
+        base.fill_until_done();
+        same.fill_until_done();
+        reseeded.fill_until_done();
+        assert_eq!(same.pixels, base.pixels);
+        assert_ne!(reseeded.pixels, base.pixels);
+
+        // Pixels from before the branch are shared.
+        let shared = base
+            .pixel_fill_order
+            .iter()
+            .zip(reseeded.pixels.iter().zip(base.pixels.iter()))
+            .filter(|(order, _)| order.is_some_and(|order| order < 250))
+            .all(|(_, (a, b))| a == b);
+        assert!(shared);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_colors() -> Result<(), Error> {
+        let reserved = RGB { vals: [1, 2, 3] };
+        let fill_order = |release_iter| -> Result<Vec<usize>, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(10, 10).seed(0);
+            builder
+                .new_stage()
+                .n_colors(99)
+                .reserve_colors(vec![reserved], release_iter);
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok(image
+                .pixels
+                .iter()
+                .zip(image.pixel_fill_order.iter())
+                .filter(|(color, _)| **color == Some(reserved))
+                .map(|(_, order)| order.unwrap())
+                .collect())
+        };
+
+        // Without a release iteration, the reserved color is only
+        // used after the palette is exhausted.
+        assert_eq!(fill_order(None)?, vec![99]);
+
+        let order = fill_order(Some(50))?;
+        assert_eq!(order.len(), 1);
+        assert!(order[0] >= 50);
+
+        // A reserved color that was already used by an earlier stage
+        // is skipped when unique colors are enforced.
+        let num_reserved = |enforce_unique| -> Result<usize, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder
+                .add_layer(10, 10)
+                .seed(0)
+                .enforce_unique_colors(enforce_unique);
+            builder
+                .new_stage()
+                .palette(SphericalPalette {
+                    central_color: reserved,
+                    color_radius: 0.0,
+                })
+                .n_colors(1);
+            builder
+                .new_stage()
+                .n_colors(50)
+                .reserve_colors(vec![reserved], None);
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok(image
+                .pixels
+                .iter()
+                .filter(|color| **color == Some(reserved))
+                .count())
+        };
+        assert_eq!(num_reserved(false)?, 2);
+        assert_eq!(num_reserved(true)?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_animation_window() -> Result<(), Error> {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder
+            .add_output_sink(Box::new(RecordingSink(Arc::clone(&frames))))
+            .start_at_iter(20)
+            .stop_at_iter(50);
+        builder.new_stage().animation_iter_per_second(24.0);
+        let mut image = builder.build()?;
+
+        image.fill_n(19);
+        assert_eq!(frames.lock().unwrap().len(), 0);
+
+        // One frame for each fill within the window.
+        image.fill_until_done();
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 30);
+        let num_filled = |frame: &[u8]| {
+            frame.chunks_exact(4).filter(|p| p[3] == 255).count()
+        };
+        assert_eq!(num_filled(&frames[0]), 20);
+        assert_eq!(num_filled(&frames[29]), 49);
+        Ok(())
+    }
+
+    #[test]
+    fn test_threaded_frame_writes() -> Result<(), Error> {
+        // The sink waits on the gate before writing its first frame,
+        // so the writer thread stays busy until the fill is done.
+        let render = |policy, blocked: bool| -> Result<_, Error> {
+            struct GatedSink(Arc<Mutex<Vec<Vec<u8>>>>, Option<Receiver<()>>);
+            impl FrameSink for GatedSink {
+                fn write_frame(&mut self, rgba: &[u8], _info: &FrameInfo) {
+                    if let Some(gate) = self.1.take() {
+                        gate.recv().ok();
+                    }
+                    self.0.lock().unwrap().push(rgba.to_vec());
+                }
+            }
+
+            let (release, gate) = channel();
+            let frames = Arc::new(Mutex::new(Vec::new()));
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(10, 10).seed(0);
+            builder
+                .add_output_sink(Box::new(GatedSink(
+                    Arc::clone(&frames),
+                    if blocked { Some(gate) } else { None },
+                )))
+                .write_policy(policy);
+            builder.new_stage().animation_iter_per_second(24.0);
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            let num_dropped = image.num_dropped_frames();
+            release.send(()).ok();
+
+            // Dropping the image waits for the writer thread.
+            drop(image);
+            let frames = frames.lock().unwrap().clone();
+            Ok((frames, num_dropped))
+        };
+
+        // Every frame arrives, in order.
+        let (inline, _) = render(FrameWritePolicy::Inline, false)?;
+        let (frames, num_dropped) = render(FrameWritePolicy::Block(2), false)?;
+        assert_eq!(num_dropped, 0);
+        assert!(frames == inline);
+
+        // With a slow writer, frames are dropped instead of waiting.
+        // At most the frame being written and one queued frame are
+        // kept while the writer is held at the gate.
+        let (frames, num_dropped) =
+            render(FrameWritePolicy::DropFrames(1), true)?;
+        assert!(inline.len() > 2);
+        assert!(frames.len() <= 2);
+        assert!(num_dropped > 0);
+        assert_eq!(frames.len() + num_dropped, inline.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_overlay() -> Result<(), Error> {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let overlay = DebugOverlayHandle::default();
+        overlay.set_enabled(OverlayKind::Forbidden, true);
+        overlay.set_enabled(OverlayKind::Seeds, true);
+        overlay.set_color(OverlayKind::Seeds, RGB { vals: [0, 255, 0] });
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder
+            .add_output_sink(Box::new(RecordingSink(Arc::clone(&frames))))
+            .debug_overlay(overlay.clone());
+        builder
+            .new_stage()
+            .animation_iter_per_second(24.0)
+            .seed_points(vec![PixelLoc {
+                layer: 0,
+                i: 5,
+                j: 5,
+            }])
+            .forbidden_points(
+                (0..10).map(|j| PixelLoc { layer: 0, i: 0, j }).collect(),
+            );
+        let mut image = builder.build()?;
+
+        let pixel = |frame: &[u8], i: usize, j: usize| {
+            let index = 4 * (10 * j + i);
+            frame[index..index + 4].to_vec()
+        };
+
+        image.fill_n(1);
+        {
+            let frames = frames.lock().unwrap();
+            let frame = frames.last().unwrap();
+            assert_eq!(pixel(frame, 0, 3), vec![128, 0, 0, 255]);
+            assert_eq!(pixel(frame, 5, 5), vec![0, 255, 0, 255]);
+            assert_eq!(pixel(frame, 4, 5), vec![0, 255, 0, 255]);
+            assert_eq!(pixel(frame, 8, 8), vec![0, 0, 0, 0]);
+        }
+
+        assert!(!overlay.toggle(OverlayKind::Seeds));
+        image.fill_n(1);
+        {
+            let frames = frames.lock().unwrap();
+            let frame = frames.last().unwrap();
+            let seed_color = image.get_pixel(PixelLoc {
+                layer: 0,
+                i: 5,
+                j: 5,
+            });
+            let seed_color = seed_color.unwrap();
+            assert_eq!(
+                pixel(frame, 5, 5),
+                vec![seed_color.r(), seed_color.g(), seed_color.b(), 255]
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_png_metadata() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(7).epsilon(2.0);
+        builder.new_stage().max_iter(20);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        let mut buf = Vec::new();
+        image
+            ._image_data(SaveImageType::Generated, 0)
+            .write_png_to_writer_with_text(&mut buf, &image.metadata);
+        let metadata = read_png_text(&mut &buf[..])?;
+
+        assert_eq!(metadata["omnicolor:seed"], "7");
+        assert_eq!(metadata["omnicolor:epsilon"], "2");
+        assert_eq!(metadata["omnicolor:layers"], "10x10");
+        assert!(metadata["omnicolor:stages"].contains("max_iter: Some(20)"));
+        assert!(metadata["Software"].starts_with("omnicolor-rust"));
+
+        // The pixel data is unaffected by the text chunks.
+        let decoder = png::Decoder::new(&buf[..]);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels)?;
+        assert_eq!(pixels, image._image_data(SaveImageType::Generated, 0).data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbounded_layer() -> Result<(), Error> {
+        let seed_point = PixelLoc {
+            layer: 0,
+            i: -1000,
+            j: -1000,
+        };
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_unbounded_layer().seed(0);
+        builder
+            .new_stage()
+            .n_colors(10000)
+            .seed_points(vec![seed_point]);
+        let mut image = builder.build()?;
+        let initial_len = image.topology.len();
+
+        // The stage ends once the palette runs out, and the seed
+        // point's chunk isn't connected to those around the origin.
+        image.fill_until_done();
+        assert_eq!(image.unreachable_pixels, vec![Some(0)]);
+        assert_eq!(image.num_filled_pixels, 10000);
+        assert!(image.get_pixel(seed_point).is_some());
+        assert!(image.topology.len() > initial_len);
+
+        // The growth spreads across chunks that were allocated as it
+        // reached them.
+        let (min, max) = image.filled_bounds(0).unwrap();
+        assert!(max.i - min.i > crate::topology::CHUNK_SIZE);
+        let (width, height) =
+            ((max.i - min.i + 1) as u32, (max.j - min.j + 1) as u32);
+        assert_eq!(image.layer_view(0), Some((min, width, height)));
+
+        // Written images are cropped to the filled pixels.
+        let filename = std::env::temp_dir().join(format!(
+            "omnicolor-test-unbounded-{}.png",
+            std::process::id()
+        ));
+        image.write(filename.clone());
+        let written = SaveImageData::read_png(&filename)?;
+        std::fs::remove_file(&filename)?;
+        assert_eq!((written.width, written.height), (width, height));
+        let num_opaque =
+            written.data.chunks_exact(4).filter(|p| p[3] == 255).count();
+        assert_eq!(num_opaque, 10000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_performance_summary() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder.new_stage().max_iter(30);
+        builder.new_stage().max_iter(0);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        assert!(image.performance_summary().is_empty());
+
+        image.fill_until_done();
+        let summary = image.performance_summary();
+        assert_eq!(
+            summary
+                .iter()
+                .map(|s| (s.stage, s.num_pixels))
+                .collect::<Vec<_>>(),
+            vec![(0, 30), (2, 70)]
+        );
+        summary.iter().for_each(|s| {
+            let points = s.points_checked;
+            assert!(points.min > 0);
+            assert!(points.min <= points.p50 && points.p50 <= points.max);
+            assert!((points.min as f64) <= points.mean);
+            assert!(points.mean <= (points.max as f64));
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_stage_timeline() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder.new_stage().max_iter(30);
+        builder.new_stage().max_iter(0);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        assert!(image.stage_timeline().is_empty());
+
+        image.fill_n(10);
+        let timeline = image.stage_timeline();
+        assert_eq!(timeline.len(), 1);
+        assert_eq!(
+            (timeline[0].end_iter, timeline[0].duration()),
+            (None, None)
+        );
+
+        image.fill_until_done();
+        let timeline = image.stage_timeline();
+        assert_eq!(
+            timeline
+                .iter()
+                .map(|t| (t.stage, t.start_iter, t.end_iter, t.num_pixels))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0, Some(30), Some(30)),
+                (1, 30, Some(30), Some(0)),
+                (2, 30, Some(100), Some(70))
+            ]
+        );
+        assert!(timeline.windows(2).all(|pair| {
+            pair[0].end_time.unwrap() <= pair[1].start_time
+                && pair[1].start_time <= pair[1].end_time.unwrap()
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_distributions() -> Result<(), Error> {
+        let render = |with_distributions: bool| -> Result<_, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(16, 16).seed(7);
+            let stage = builder.new_stage().seed_points(vec![PixelLoc {
+                layer: 0,
+                i: 8,
+                j: 8,
+            }]);
+            if with_distributions {
+                stage
+                    .color_jitter_distribution(RandomDistribution::Normal {
+                        mean: 0.0,
+                        std_dev: 4.0,
+                    })
+                    .seed_point_jitter(RandomDistribution::Uniform {
+                        min: -4.0,
+                        max: 4.0,
+                    })
+                    .selection_noise(RandomDistribution::Exponential {
+                        mean: 2.0,
+                    });
+            }
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok(image.pixels.clone())
+        };
+
+        let plain = render(false)?;
+        let varied = render(true)?;
+        assert!(varied.iter().all(|pixel| pixel.is_some()));
+        assert_eq!(varied, render(true)?);
+        assert_ne!(varied, plain);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_colors() -> Result<(), Error> {
+        let black = RGB { vals: [0, 0, 0] };
+        let white = RGB {
+            vals: [255, 255, 255],
+        };
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder
+            .new_stage()
+            .exclude_colors(vec![black, white], 100.0);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Uniform colors within the tolerance are removed rather than
+        // replaced, leaving pixels unfilled.
+        let filled = image.pixels.iter().flatten().collect::<Vec<_>>();
+        assert!(filled.len() < 400);
+        assert!(filled.len() > 300);
+        assert!(filled.iter().all(|color| {
+            [black, white].iter().all(|other| {
+                let dist2 = color
+                    .vals
+                    .iter()
+                    .zip(other.vals.iter())
+                    .map(|(&a, &b)| (a as f32 - b as f32).powi(2))
+                    .sum::<f32>();
+                dist2 > 100.0 * 100.0
+            })
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_layer_epsilon_and_stats() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder
+            .add_layer(20, 20)
+            .add_layer(5, 5)
+            .epsilon(2.0)
+            .layer_epsilon(1, 50.0)
+            .layer_stats(1, false)
+            .seed(0);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        let loc = |layer| PixelLoc { layer, i: 0, j: 0 };
+        assert_eq!(image.epsilon_at(loc(0)), 2.0);
+        assert_eq!(image.epsilon_at(loc(1)), 50.0);
+
+        let index_range =
+            |layer| image.topology.get_layer_bounds(layer).unwrap();
+        assert!(image.stats[index_range(0)].iter().all(|s| s.is_some()));
+        assert!(image.stats[index_range(1)].iter().all(|s| s.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_advance_conditions() -> Result<(), Error> {
+        let whole_layer = Region::from_points(
+            &[(20, 20)],
+            (0..20).cartesian_product(0..20).map(|(i, j)| PixelLoc {
+                layer: 0,
+                i,
+                j,
+            }),
+        );
+        let palette = |r, g, b| SphericalPalette {
+            central_color: RGB { vals: [r, g, b] },
+            color_radius: 10.0,
+        };
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder
+            .new_stage()
+            .palette(palette(20, 20, 20))
+            .advance_when_mean_brightness_below(0.2)
+            .condition_check_period(10);
+        builder
+            .new_stage()
+            .palette(palette(200, 20, 20))
+            .advance_when_region_mean_hue_in(&whole_layer, 330.0..30.0)
+            .advance_when_mean_brightness_above(0.9)
+            .condition_check_period(20);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Each condition is met at its first check.
+        let end_iters = image
+            .stage_timeline()
+            .iter()
+            .map(|timing| timing.end_iter)
+            .collect::<Vec<_>>();
+        assert_eq!(end_iters, vec![Some(10), Some(30), Some(400)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_temporary_wall() -> Result<(), Error> {
+        let wall = (0..10).map(|j| PixelLoc { layer: 0, i: 10, j }).collect();
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 10).seed(0);
+        builder
+            .new_stage()
+            .seed_points(vec![PixelLoc {
+                layer: 0,
+                i: 2,
+                j: 5,
+            }])
+            .temporary_wall(wall, 50);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Nothing beyond the wall is filled until it expires, after
+        // which the entire layer is filled.
+        assert!(image.pixels.iter().all(|pixel| pixel.is_some()));
+        let fill_order = |i, j| {
+            let index = image.topology.get_index(PixelLoc { layer: 0, i, j });
+            image.pixel_fill_order[index.unwrap()].unwrap()
+        };
+        assert!((10..20)
+            .cartesian_product(0..10)
+            .all(|(i, j)| fill_order(i, j) >= 50));
+        Ok(())
+    }
+
+    #[test]
+    fn test_color_map() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0).color_map(Grayscale(2));
+        builder.new_stage();
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Stored values are unchanged, and only the output is mapped.
+        let data = image._image_data(SaveImageType::Generated, 0).data;
+        (0..10).cartesian_product(0..10).for_each(|(i, j)| {
+            let loc = PixelLoc { layer: 0, i, j };
+            let value = image.get_data(loc).unwrap();
+            let expected = Grayscale(2).map(value);
+            let start = 4 * (j * 10 + i) as usize;
+            assert_eq!(data[start..start + 3], expected.vals);
+            assert_eq!(value, to_data(image.get_pixel(loc).unwrap()),);
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbounded_layer_allowed_region() -> Result<(), Error> {
+        let allowed = (-300..-200)
+            .cartesian_product(-5..5)
+            .map(|(i, j)| PixelLoc { layer: 0, i, j })
+            .collect::<Vec<_>>();
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_unbounded_layer().seed(0);
+        builder
+            .new_stage()
+            .allowed_points(allowed)
+            .seed_points(vec![PixelLoc {
+                layer: 0,
+                i: -250,
+                j: 0,
+            }]);
+        let mut image = builder.build()?;
+
+        // Chunks allocated around the edge of the region, as it
+        // fills, stay forbidden to the stage.
+        image.fill_until_done();
+        assert_eq!(image.num_filled_pixels, 1000);
+        assert_eq!(
+            image.filled_bounds(0),
+            Some((
+                PixelLoc {
+                    layer: 0,
+                    i: -300,
+                    j: -5
+                },
+                PixelLoc {
+                    layer: 0,
+                    i: -201,
+                    j: 4
+                }
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_live_output() -> Result<(), Error> {
+        let filename = std::env::temp_dir()
+            .join(format!("omnicolor-live-output-{}.png", std::process::id()));
+        let _ = std::fs::remove_file(&filename);
+
+        let mut builder = GrowthImageBuilder::new();
+        builder
+            .add_layer(10, 10)
+            .seed(0)
+            .live_output(filename.clone(), 30);
+        builder.new_stage();
+        let mut image = builder.build()?;
+
+        image.fill_n(29);
+        assert!(!filename.exists());
+        image.fill_n(1);
+        assert!(filename.exists());
+
+        // The final image is written once finished, even partway
+        // through a period.
+        image.fill_until_done();
+        let decoder = png::Decoder::new(std::fs::File::open(&filename)?);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels)?;
+        assert_eq!(pixels, image._image_data(SaveImageType::Generated, 0).data);
+        assert!(GrowthImage::read_metadata(&filename)?
+            .contains_key("omnicolor:seed"));
+
+        std::fs::remove_file(&filename)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ring_growth() -> Result<(), Error> {
+        let center = PixelLoc {
+            layer: 0,
+            i: 10,
+            j: 10,
+        };
+        let build = |r_min, r_max| {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(21, 21).seed(0);
+            builder
+                .new_stage()
+                .seed_points(vec![center])
+                .ring_growth(r_min, r_max);
+            builder.build()
+        };
+
+        let mut image = build(0, 2)?;
+        image.fill_until_done();
+        let distances = image
+            .topology
+            .distance_field_from(&[center], &Region::new(&[]));
+
+        // Each band is finished before the next band starts.
+        let band_fill_range = |band: usize| {
+            let orders = distances
+                .iter()
+                .zip(image.pixel_fill_order.iter())
+                .filter(|(dist, _)| dist.unwrap() / 3 == band)
+                .map(|(_, order)| order.unwrap());
+            (orders.clone().min().unwrap(), orders.max().unwrap())
+        };
+        let num_bands = distances.iter().map(|d| d.unwrap() / 3).max().unwrap();
+        (0..num_bands).for_each(|band| {
+            assert!(band_fill_range(band).1 < band_fill_range(band + 1).0);
+        });
+
+        // Pixels inside of r_min are never filled.
+        let mut image = build(4, 6)?;
+        image.fill_until_done();
+        distances
+            .iter()
+            .zip(image.pixels.iter())
+            .for_each(|(dist, pixel)| {
+                assert_eq!(pixel.is_some(), dist.unwrap() >= 4);
+            });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_n_colors_exact_for_region() -> Result<(), Error> {
+        let forbidden = (0..20)
+            .flat_map(|i| (0..i).map(move |j| PixelLoc { layer: 0, i, j }))
+            .collect::<Vec<_>>();
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder.new_stage().max_iter(50);
+        builder
+            .new_stage()
+            .forbidden_points(forbidden.clone())
+            .n_colors_exact_for_region();
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Pixels filled in the first stage are already taken, whether
+        // or not they are forbidden in the second stage.
+        let num_allowed = image
+            .pixels
+            .iter()
+            .zip(image.pixel_stages.iter())
+            .enumerate()
+            .filter(|(index, (_, stage))| {
+                **stage == Some(0)
+                    || !forbidden
+                        .contains(&image.topology.get_loc(*index).unwrap())
+            })
+            .count();
+        assert_eq!(image.num_filled_pixels, num_allowed);
+        assert_eq!(image.stages[1].palette.num_points(), 0);
+        assert_eq!(image.current_stage_iter, num_allowed - 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimated_total_fills() -> Result<(), Error> {
+        let allowed = (0..10)
+            .flat_map(|i| (0..5).map(move |j| PixelLoc { layer: 0, i, j }))
+            .collect::<Vec<_>>();
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder.new_stage().allowed_points(allowed).max_iter(30);
+        builder.new_stage().n_colors(100);
+        builder.new_stage().grow_from_previous(true);
+        let mut image = builder.build()?;
+
+        // The first stage is limited by max_iter, and the second by
+        // its palette.  The last fills whatever remains.
+        assert_eq!(image.stage_fill_estimates, vec![30, 100, 270]);
+        assert_eq!(image.estimated_total_fills(), 400);
+
+        image.fill_until_done();
+        assert_eq!(image.estimated_total_fills(), image.num_filled_pixels);
+        assert_eq!(image.stage_fill_estimates, vec![30, 100, 270]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stage_palettes() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder.new_stage().n_colors(60);
+        builder.new_stage().n_colors(200);
+        let mut image = builder.build()?;
+
+        let sorted = |colors: Vec<RGB>| {
+            let mut colors = colors;
+            colors.sort_by_key(|c| c.vals);
+            colors
+        };
+        let initial = (0..2)
+            .map(|i| sorted(image.stage_palette(i).collect()))
+            .collect::<Vec<_>>();
+        assert_eq!(initial[0].len(), 60);
+        assert_eq!(initial[1].len(), 200);
+        assert_eq!(sorted(image.remaining_palette(1).collect()), initial[1]);
+
+        image.fill_until_done();
+
+        // The first stage used its entire palette, and the second
+        // filled the rest of the image.
+        assert_eq!(image.remaining_palette(0).count(), 0);
+        assert_eq!(image.remaining_palette(1).count(), 160);
+        (0..2).for_each(|i| {
+            assert_eq!(sorted(image.stage_palette(i).collect()), initial[i]);
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_regions() -> Result<(), Error> {
+        use crate::scene::RegionSpec;
+
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let region = |builder: &GrowthImageBuilder| {
+            builder.scene_spec().unwrap().stages[0].region.clone()
+        };
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder
+            .new_stage()
+            .also_forbid(vec![loc(0, 0), loc(1, 0)])
+            .also_forbid(vec![loc(2, 0)])
+            .also_allow(vec![loc(1, 0)]);
+        assert_eq!(
+            region(&builder),
+            Some(RegionSpec::Forbidden(vec![loc(0, 0), loc(2, 0)]))
+        );
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder
+            .new_stage()
+            .allowed_points(vec![loc(0, 0), loc(1, 0)])
+            .also_allow(vec![loc(1, 0), loc(2, 0), loc(2, 0)])
+            .also_forbid(vec![loc(0, 0)]);
+        assert_eq!(
+            region(&builder),
+            Some(RegionSpec::Allowed(vec![loc(1, 0), loc(2, 0)]))
+        );
+        let mut image = builder.build()?;
+        image.fill_until_done();
+        assert_eq!(image.num_filled_pixels, 2);
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(10, 10).seed(0);
+        builder
+            .new_stage()
+            .allowed_points(vec![loc(0, 0)])
+            .clear_regions();
+        assert_eq!(region(&builder), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wall_from_path() -> Result<(), Error> {
+        // A curved wall, with no thickness, separating the left edge
+        // from the seed point.
+        let mut path = kurbo::BezPath::new();
+        path.move_to((12.0, -2.0));
+        path.quad_to((-4.0, 10.0), (12.0, 22.0));
+
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder
+            .new_stage()
+            .seed_points(vec![PixelLoc {
+                layer: 0,
+                i: 19,
+                j: 10,
+            }])
+            .wall_from_path(0, &path, 0.0);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        (0..20).for_each(|j| {
+            let loc = PixelLoc { layer: 0, i: 0, j };
+            assert!(image.get_pixel(loc).is_none());
+        });
+        assert!(image.num_filled_pixels > 200);
+
+        // Thicker walls cover more pixels, and are removed from the
+        // allowed points.
+        let thin = path.rasterize_stroke(0, 0.0).len();
+        let thick = path.rasterize_stroke(0, 4.0).len();
+        assert!(thick > 3 * thin);
+
+        let allowed = (0..20)
+            .flat_map(|i| (0..20).map(move |j| PixelLoc { layer: 0, i, j }))
+            .collect::<Vec<_>>();
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder
+            .new_stage()
+            .allowed_points(allowed)
+            .wall_from_path(0, &path, 4.0);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+        path.rasterize_stroke(0, 4.0).into_iter().for_each(|loc| {
+            assert!(image.get_pixel(loc).is_none());
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_match_first() -> Result<(), Error> {
+        let build = || -> Result<GrowthImage, Error> {
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(16, 16).seed(0);
+            builder.new_stage().n_colors(300).best_match_first();
+            builder.build()
+        };
+
+        // Filling is deterministic, even when the frontier is changed
+        // partway through.
+        let mut image = build()?;
+        image.fill_n(100);
+        image.set_pixel(
+            PixelLoc {
+                layer: 0,
+                i: 0,
+                j: 0,
+            },
+            RGB { vals: [0, 0, 0] },
+        );
+        let mut other = image.clone_state();
+        image.fill_until_done();
+        other.fill_until_done();
+        assert_eq!(image.pixels, other.pixels);
+
+        // Every pixel is filled, each with a different color.
+        assert!(image.pixels.iter().all(|p| p.is_some()));
+        assert_eq!(image.remaining_palette(0).count(), 300 - 255);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stage_target_kernel() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(16, 16).seed(0);
+        builder
+            .new_stage()
+            .max_iter(50)
+            .target_kernel(RandomNeighbor);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        image.fill_n(50);
+
+        // During the first stage, each target is an adjacent color,
+        // rather than the mean of the adjacent colors.
+        let frontier = image
+            .point_tracker
+            .iter_frontier()
+            .copied()
+            .collect::<Vec<_>>();
+        let is_adjacent_color = |image: &GrowthImage, loc| {
+            let target = image.get_target_color(loc).unwrap();
+            image.filled_pixels().adjacent_colors(loc).contains(&target)
+        };
+        assert!(frontier.iter().all(|&loc| is_adjacent_color(&image, loc)));
+
+        image.fill();
+        assert_eq!(image.active_stage, Some(1));
+        assert!(!frontier.iter().all(|&loc| is_adjacent_color(&image, loc)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_frontier_max_age() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(32, 32).seed(0);
+        builder.new_stage().frontier_max_age(4);
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        // Points left unselected are skipped, so the stage ends
+        // without filling the entire image.
+        let num_filled = image.pixels.iter().filter(|p| p.is_some()).count();
+        assert!(num_filled > 4);
+        assert!(num_filled < 32 * 32);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_stages() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0);
+        builder
+            .new_stage()
+            .max_iter(100)
+            .seed_points(vec![PixelLoc {
+                layer: 0,
+                i: 0,
+                j: 0,
+            }]);
+        builder
+            .new_stage()
+            .max_iter(100)
+            .seed_points(vec![PixelLoc {
+                layer: 0,
+                i: 19,
+                j: 19,
+            }])
+            .concurrent_with_previous();
+        builder.new_stage();
+        let mut image = builder.build()?;
+
+        // Both stages start together, and take turns filling pixels.
+        let report = image.fill_n(20);
+        assert_eq!(report.stages_started, vec![0, 1]);
+        let count_stage = |image: &GrowthImage, stage| {
+            image
+                .pixel_stages
+                .iter()
+                .filter(|&&s| s == Some(stage))
+                .count()
+        };
+        assert_eq!(count_stage(&image, 0), 10);
+        assert_eq!(count_stage(&image, 1), 10);
+
+        let report = image.fill_n(200);
+        assert_eq!(report.stages_started, vec![2]);
+        assert_eq!(count_stage(&image, 0), 100);
+        assert_eq!(count_stage(&image, 1), 100);
+
+        let report = image.fill_n(1000);
+        assert!(report.is_done);
+        assert_eq!(count_stage(&image, 2), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_voronoi_stages() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(20, 20).seed(0).voronoi_stages(
+            0,
+            4,
+            UniformPalette,
+            90.0,
+        );
+        let mut image = builder.build()?;
+        assert_eq!(image.stages.len(), 4);
+        image.fill_until_done();
+
+        // Each pixel is filled by the stage of the nearest site.
+        let sites = image
+            .stages
+            .iter()
+            .map(|stage| stage.selected_seed_points[0])
+            .collect::<Vec<_>>();
+        let cells = image.topology.voronoi_cells(&sites, &Region::new(&[]));
+        assert!(cells.iter().all(|cell| cell.is_some()));
+        assert_eq!(image.pixel_stages, cells);
+        assert_eq!(image.num_filled_pixels, 400);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_budget() -> Result<(), Error> {
+        let builder = |num_layers: usize, n_colors: Option<u32>| {
+            let mut builder = GrowthImageBuilder::new();
+            for _ in 0..num_layers {
+                builder.add_layer(64, 64);
+            }
+            builder.seed(0);
+            let stage = builder.new_stage();
+            if let Some(n_colors) = n_colors {
+                stage.n_colors(n_colors);
+            }
+            stage.max_iter(0);
+            builder
+        };
+
+        let estimate = builder(1, None).estimate_memory();
+        assert!(estimate.pixels > 0);
+        assert!(estimate.kd_trees > 0);
+        assert_eq!(estimate.adjacency, 0);
+
+        let fewer_colors = builder(1, Some(100)).estimate_memory();
+        assert!(fewer_colors.kd_trees < estimate.kd_trees);
+        assert_eq!(fewer_colors.pixels, estimate.pixels);
+
+        let two_layers = builder(2, None).estimate_memory();
+        assert_eq!(two_layers.pixels, 2 * estimate.pixels);
+
+        let result = builder(1, None)
+            .with_memory_budget(estimate.total() - 1)
+            .build();
+        assert!(matches!(
+            result,
+            Err(Error::MemoryBudgetExceeded { budget, .. })
+                if budget == estimate.total() - 1
+        ));
+
+        builder(1, None)
+            .with_memory_budget(estimate.total())
+            .build()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_elliptical_layer() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_elliptical_layer(20, 10).seed(0);
+        builder.new_stage();
+        let mut image = builder.build()?;
+        let num_valid = image.topology.num_valid();
+        assert!(num_valid < 200);
+        assert_eq!(image.stages[0].palette.num_points(), num_valid);
+
+        image.fill_until_done();
+        let num_filled = image.pixels.iter().filter(|p| p.is_some()).count();
+        assert_eq!(num_filled, num_valid);
+
+        // Corners are outside the ellipse, and left transparent.
+        let data = image._image_data(SaveImageType::Generated, 0).data;
+        assert_eq!(data[0..4].to_vec(), vec![0, 0, 0, 0]);
+        let center = 4 * (5 * 20 + 10);
+        assert_eq!(data[center + 3], 255);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_determinism() -> Result<(), Error> {
+        let mut builder = GrowthImageBuilder::new();
+        builder.add_layer(16, 12).add_layer(6, 6).seed(7);
+        builder
+            .new_stage()
+            .num_random_seed_points(3)
+            .connected_points(vec![(
+                PixelLoc {
+                    layer: 0,
+                    i: 15,
+                    j: 11,
+                },
+                PixelLoc {
+                    layer: 1,
+                    i: 0,
+                    j: 0,
+                },
+            )]);
+        assert_eq!(builder.verify_determinism()?, 7);
+
+        // The builder is left as it was.
+        let mut image = builder.build()?;
+        assert_eq!(image.seed, 7);
+        assert!(!image.precompute_adjacency);
+        image.fill_until_done();
+        Ok(())
+    }
+
+    #[test]
+    fn test_portal_probability() -> Result<(), Error> {
+        // Every point of layer 0 has a portal to layer 1.
+        let build = |probability| {
+            let portals = (0..10)
+                .cartesian_product(0..10)
+                .map(|(i, j)| {
+                    (PixelLoc { layer: 0, i, j }, PixelLoc { layer: 1, i, j })
+                })
+                .collect::<Vec<_>>();
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(10, 10).add_layer(10, 10).seed(0);
+            builder
+                .new_stage()
+                .seed_points(vec![PixelLoc {
+                    layer: 0,
+                    i: 0,
+                    j: 0,
+                }])
+                .one_way_portals(portals)
+                .portal_probability(probability);
+            builder.build()
+        };
+
+        let filled_in_layer = |image: &GrowthImage, layer: u8| {
+            image
+                .pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.is_some())
+                .filter(|(index, _)| {
+                    image.topology.get_loc(*index).unwrap().layer == layer
+                })
+                .count()
+        };
+
+        let mut image = build(0.0)?;
+        image.fill_n(1000);
+        assert_eq!(filled_in_layer(&image, 0), 100);
+        assert_eq!(filled_in_layer(&image, 1), 0);
+
+        let mut image = build(1.0)?;
+        image.fill_until_done();
+        assert_eq!(filled_in_layer(&image, 1), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_background() -> Result<(), Error> {
+        let color = RGB {
+            vals: [200, 100, 50],
+        };
+        let build = |background, alpha_mode| {
+            let mut builder = GrowthImageBuilder::new();
+            builder
+                .add_layer(4, 4)
+                .seed(0)
+                .background(background)
+                .alpha_mode(alpha_mode);
+            builder.new_stage().max_iter(0);
+            builder.build()
+        };
+        let pixel = |image: &GrowthImage, i: usize, j: usize| {
+            let data = image._image_data(SaveImageType::Generated, 0).data;
+            let offset = 4 * (j * 4 + i);
+            data[offset..offset + 4].to_vec()
+        };
+
+        let image = build(Background::Transparent, AlphaMode::Straight)?;
+        assert_eq!(pixel(&image, 0, 0), vec![0, 0, 0, 0]);
+
+        let image = build(Background::Color(color, 255), AlphaMode::Straight)?;
+        assert_eq!(pixel(&image, 0, 0), vec![200, 100, 50, 255]);
+
+        // Only partially transparent backgrounds depend on the alpha
+        // mode.
+        let image = build(Background::Color(color, 51), AlphaMode::Straight)?;
+        assert_eq!(pixel(&image, 0, 0), vec![200, 100, 50, 51]);
+        let image =
+            build(Background::Color(color, 51), AlphaMode::Premultiplied)?;
+        assert_eq!(pixel(&image, 0, 0), vec![40, 20, 10, 51]);
+
+        let image = build(Background::Checkerboard(2), AlphaMode::Straight)?;
+        assert_eq!(pixel(&image, 0, 0), pixel(&image, 1, 1));
+        assert_eq!(pixel(&image, 0, 0), pixel(&image, 2, 2));
+        assert_ne!(pixel(&image, 0, 0), pixel(&image, 2, 0));
+        assert_eq!(pixel(&image, 2, 0)[3], 255);
+
+        // Filled pixels cover the background.
+        let mut image =
+            build(Background::Checkerboard(2), AlphaMode::Straight)?;
+        image.set_pixel(
+            PixelLoc {
+                layer: 0,
+                i: 0,
+                j: 0,
+            },
+            color,
+        );
+        assert_eq!(pixel(&image, 0, 0), vec![200, 100, 50, 255]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreachable_pixels() -> Result<(), Error> {
+        // A wall at i=5 cuts off the 5 columns to its left.
+        let build = |strict| {
+            let wall = (0..10)
+                .map(|j| PixelLoc { layer: 0, i: 5, j })
+                .collect::<Vec<_>>();
+            let mut builder = GrowthImageBuilder::new();
+            builder.add_layer(10, 10).seed(0).strict_regions(strict);
+            builder
+                .new_stage()
+                .seed_points(vec![PixelLoc {
+                    layer: 0,
+                    i: 9,
+                    j: 9,
+                }])
+                .forbidden_points(wall);
+            builder.build()
+        };
+
+        let mut image = build(false)?;
+        assert_eq!(image.num_unreachable_pixels(0), None);
+        let report = image.fill_n(1000);
+        assert_eq!(report.unreachable_pixels, vec![(0, 50)]);
+        assert_eq!(image.num_unreachable_pixels(0), Some(50));
+        assert_eq!(image.num_filled_pixels, 40);
+        assert!(image.check_regions().is_ok());
+
+        let mut image = build(true)?;
+        let report = image.fill_n(1000);
+        assert!(report.is_done);
+        assert_eq!(report.num_filled, 0);
+        assert!(matches!(
+            image.check_regions(),
+            Err(Error::UnreachablePixels {
+                stage: 0,
+                num_pixels: 50
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_formats() -> Result<(), Error> {
+        let data = SaveImageData {
+            data: vec![10, 20, 30, 255, 40, 50, 60, 0],
+            width: 2,
+            height: 1,
+        };
+        let raw = |format| -> Result<Vec<u8>, Error> {
+            let mut output = Vec::new();
+            data.write_raw_to_writer(&mut output, format)?;
+            Ok(output)
+        };
+
+        assert_eq!(raw(RawFormat::RGBA8)?, data.data);
+        assert_eq!(raw(RawFormat::RGB8)?, vec![10, 20, 30, 0, 0, 0]);
+        assert_eq!(
+            raw(RawFormat::PPM)?,
+            b"P6\n2 1\n255\n\x0a\x14\x1e\0\0\0".to_vec()
+        );
+
+        Ok(())
     }
 }
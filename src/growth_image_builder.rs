@@ -1,43 +1,110 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
+use kurbo::BezPath;
 use rand::{Rng, SeedableRng};
 
+use crate::bezier_util::BezPathExt;
+use crate::color::{BlendMode, ColorSet, RGB};
+use crate::color_map::ColorMap;
+use crate::debug_overlay::DebugOverlayHandle;
+use crate::distribution::RandomDistribution;
 use crate::errors::Error;
+use crate::frame_buffer::FrameBuffer;
+use crate::frame_sink::FrameSink;
+use crate::frame_writer::{FrameWritePolicy, FrameWriter};
 use crate::growth_image::{
-    GrowthImage, GrowthImageAnimation, GrowthImageStage, RestrictedRegion,
-    SaveImageType,
+    AlphaMode, AnimationOutput, Background, FrontierThinning, GrowthImage,
+    GrowthImageAnimation, GrowthImageStage, LiveOutput, PaletteDepletion,
+    RestrictedRegion, SaveImageData, SaveImageType,
 };
-use crate::kd_tree::KDTree;
-use crate::palettes::{Palette, UniformPalette};
-use crate::point_tracker::PointTracker;
-use crate::topology::{PixelLoc, RectangularArray, Topology};
+use crate::kd_tree::{KDTree, KDTreeOptions, PerformanceStats};
+use crate::kernels::{AdjacentMean, TargetColorKernel};
+use crate::layout::CompositeLayout;
+use crate::memory::MemoryEstimate;
+use crate::palettes::{exclude_colors, HueRotate, Palette, UniformPalette};
+use crate::point_tracker::{
+    GravityBias, GrowthCone, PointTracker, SelectionWeight,
+};
+use crate::progress::Progress;
+use crate::region::{Mapping, Region};
+use crate::scene::{
+    AnimationSpec, LayerSpec, RegionSpec, SceneSpec, StageSpec,
+};
+use crate::stage_condition::{StageCondition, DEFAULT_CONDITION_CHECK_PERIOD};
+use crate::topology::{LayerShape, PixelLoc, RectangularArray, Topology};
+
+// Defines a consuming variant of a builder method, which takes and
+// returns the builder by value.  Useful when constructing a builder
+// within an expression, or returning one from a function.
+macro_rules! owned_variant {
+    ($with_name:ident, $name:ident $(, $arg:ident : $arg_type:ty)*) => {
+        pub fn $with_name(mut self $(, $arg: $arg_type)*) -> Self {
+            self.$name($($arg),*);
+            self
+        }
+    };
+}
 
 pub struct GrowthImageBuilder {
     topology: Topology,
     epsilon: f64,
+    layer_epsilon: HashMap<u8, f64>,
+    layer_stats: HashMap<u8, bool>,
+    target_kernel: Arc<dyn TargetColorKernel>,
+    color_map: Option<Arc<dyn ColorMap>>,
+    linear_light_averaging: bool,
+    strict_regions: bool,
+    background: Background,
+    alpha_mode: AlphaMode,
+    precompute_adjacency: bool,
+    precompute_locations: bool,
+    kdtree_options: KDTreeOptions,
     stages: Vec<GrowthImageStageBuilder>,
     seed: Option<u64>,
+    memory_budget: Option<usize>,
     show_progress_bar: bool,
+    progress_draw_rate: u64,
+    progress_text_interval: Option<Duration>,
+    enforce_unique_colors: bool,
 
     animation_outputs: Vec<GrowthImageAnimationBuilder>,
+    live_output: Option<(PathBuf, usize)>,
 }
 
 impl GrowthImageBuilder {
     pub fn new() -> Self {
-        let topology = Topology {
-            layers: Vec::new(),
-            portals: HashMap::new(),
-        };
+        let topology = Topology::new(Vec::new(), Arc::new(HashMap::new()));
 
         Self {
             topology,
             epsilon: 1.0,
+            layer_epsilon: HashMap::new(),
+            layer_stats: HashMap::new(),
+            target_kernel: Arc::new(AdjacentMean),
+            color_map: None,
+            linear_light_averaging: false,
+            strict_regions: false,
+            background: Background::Transparent,
+            alpha_mode: AlphaMode::Straight,
+            precompute_adjacency: false,
+            precompute_locations: false,
+            kdtree_options: KDTreeOptions::default(),
             stages: Vec::new(),
             seed: None,
+            memory_budget: None,
             show_progress_bar: false,
+            progress_draw_rate: 10,
+            progress_text_interval: Some(Duration::from_secs(10)),
+            enforce_unique_colors: false,
             animation_outputs: Vec::new(),
+            live_output: None,
         }
     }
 
@@ -46,6 +113,26 @@ impl GrowthImageBuilder {
         self
     }
 
+    // Maximum number of times per second that the progress bar is
+    // redrawn.  Defaults to 10.
+    pub fn progress_draw_rate(&mut self, draw_rate: u64) -> &mut Self {
+        self.progress_draw_rate = draw_rate;
+        self
+    }
+
+    // When stderr isn't a terminal, such as when it is redirected to
+    // a log file, the progress bar is replaced by a line of text
+    // with the percent completed, printed at most once per interval.
+    // Defaults to every 10 seconds.  If None, no progress is shown
+    // without a terminal.
+    pub fn progress_text_interval(
+        &mut self,
+        interval: Option<Duration>,
+    ) -> &mut Self {
+        self.progress_text_interval = interval;
+        self
+    }
+
     pub fn add_output_animation(
         &mut self,
         filename: PathBuf,
@@ -55,10 +142,88 @@ impl GrowthImageBuilder {
         self.animation_outputs.last_mut().unwrap()
     }
 
+    // Rewrite a preview PNG every_n_iters iterations, and once the
+    // image is finished, so that headless runs can be monitored by
+    // refreshing a file.  Much cheaper than an animation, since no
+    // frames are kept.  Each preview is written to a temporary file
+    // alongside the output, then renamed over it, so readers never see
+    // a partially written file.
+    pub fn live_output(
+        &mut self,
+        filename: PathBuf,
+        every_n_iters: usize,
+    ) -> &mut Self {
+        self.live_output = Some((filename, every_n_iters.max(1)));
+        self
+    }
+
+    // Send animation frames to an in-process sink, rather than to
+    // ffmpeg.
+    pub fn add_output_sink(
+        &mut self,
+        sink: Box<dyn FrameSink>,
+    ) -> &mut GrowthImageAnimationBuilder {
+        let new_stage = GrowthImageAnimationBuilder::new_sink(sink);
+        self.animation_outputs.push(new_stage);
+        self.animation_outputs.last_mut().unwrap()
+    }
+
+    // Keep animation frames in the buffer, to be encoded later with
+    // FrameBuffer::encode().
+    pub fn add_output_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> &mut GrowthImageAnimationBuilder {
+        self.add_output_sink(Box::new(buffer.clone()))
+    }
+
     pub fn add_layer(&mut self, width: u32, height: u32) -> &mut Self {
-        self.topology
-            .layers
-            .push(RectangularArray { width, height });
+        self.topology.add_layer(RectangularArray { width, height });
+        self
+    }
+
+    // Add a layer containing only the ellipse inscribed in a
+    // width x height rectangle, for round images.  Pixels outside the
+    // ellipse are never filled, aren't counted when sizing palettes,
+    // and are transparent in the saved image.
+    pub fn add_elliptical_layer(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> &mut Self {
+        self.topology.add_shaped_layer(
+            RectangularArray { width, height },
+            LayerShape::Ellipse,
+        );
+        self
+    }
+
+    // Add a layer that is an equirectangular projection of a sphere,
+    // usually twice as wide as it is tall.  Growth wraps around the
+    // left and right edges and across the poles, so the result can be
+    // used as a 360 degree panorama without a seam.
+    pub fn add_equirectangular_layer(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> &mut Self {
+        self.topology.add_shaped_layer(
+            RectangularArray { width, height },
+            LayerShape::Equirectangular,
+        );
+        self
+    }
+
+    // Add a layer without fixed bounds, which grows outward in any
+    // direction, including to negative coordinates, as pixels near
+    // its edge are filled.  Pixels are stored in chunks, allocated as
+    // the growth reaches them.  By default, a stage's palette is
+    // sized to the pixels allocated when the image is built, so
+    // stages on an unbounded layer should usually set n_colors() or
+    // max_iter().  Generated images of the layer, including from
+    // write(), are cropped to the bounding box of the filled pixels.
+    pub fn add_unbounded_layer(&mut self) -> &mut Self {
+        self.topology.add_unbounded_layer();
         self
     }
 
@@ -68,11 +233,92 @@ impl GrowthImageBuilder {
         self.stages.last_mut().unwrap()
     }
 
+    // Partition the layer into num_cells Voronoi cells around sites
+    // chosen at random, and add a stage for each cell.  Each stage
+    // grows from its site and fills only its own cell, with all of
+    // the cells growing concurrently.  Each cell's palette is the
+    // given palette, with the hue rotated by hue_step degrees more
+    // than for the previous cell.  Distances are measured as in
+    // Topology::shortest_path_len().  Sites are chosen using seed(),
+    // if it has already been set.
+    pub fn voronoi_stages<T>(
+        &mut self,
+        layer: u8,
+        num_cells: usize,
+        palette: T,
+        hue_step: f32,
+    ) -> &mut Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        let valid_locs = self
+            .topology
+            .layer_indices(layer)
+            .flat_map(|index| self.topology.get_loc(index))
+            .collect::<Vec<_>>();
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(
+            self.seed.unwrap_or_else(rand::random),
+        );
+        let sites = rand::seq::index::sample(
+            &mut rng,
+            valid_locs.len(),
+            num_cells.min(valid_locs.len()),
+        )
+        .into_iter()
+        .map(|i| valid_locs[i])
+        .collect::<Vec<_>>();
+
+        let cells = self.topology.voronoi_cells(&sites, &Region::new(&[]));
+        let mut cell_points = vec![Vec::new(); sites.len()];
+        self.topology.layer_indices(layer).for_each(|index| {
+            if let Some(cell) = cells[index] {
+                cell_points[cell].push(self.topology.get_loc(index).unwrap());
+            }
+        });
+
+        let palette: Arc<dyn Palette> = Arc::new(palette);
+        sites.into_iter().zip(cell_points).enumerate().for_each(
+            |(i, (site, points))| {
+                let stage = self
+                    .new_stage()
+                    .palette(HueRotate(
+                        Arc::clone(&palette),
+                        hue_step * (i as f32),
+                    ))
+                    .n_colors_exact_for_region()
+                    .seed_points(vec![site])
+                    .grow_from_previous(false)
+                    .allowed_points(points);
+                if i > 0 {
+                    stage.concurrent_with_previous();
+                }
+            },
+        );
+        self
+    }
+
     pub fn epsilon(&mut self, epsilon: f64) -> &mut Self {
         self.epsilon = epsilon;
         self
     }
 
+    // Epsilon for palette searches at pixels in the layer, in place of
+    // epsilon().  Small bridge or underworld layers that are never
+    // shown can use a coarse epsilon for speed, while the main layer
+    // keeps accurate matches.
+    pub fn layer_epsilon(&mut self, layer: u8, epsilon: f64) -> &mut Self {
+        self.layer_epsilon.insert(layer, epsilon);
+        self
+    }
+
+    // Whether to record the palette search statistics of each pixel in
+    // the layer, as shown by SaveImageType::Statistics and summarized
+    // by GrowthImage::performance_summary().  Defaults to true.
+    pub fn layer_stats(&mut self, layer: u8, collect: bool) -> &mut Self {
+        self.layer_stats.insert(layer, collect);
+        self
+    }
+
     pub fn palette<T>(&mut self, palette: T) -> &mut Self
     where
         T: Palette + Sized + 'static,
@@ -81,39 +327,357 @@ impl GrowthImageBuilder {
         self
     }
 
+    // How the target color of each pixel is determined from the
+    // pixels already filled.  Defaults to the mean of the adjacent
+    // pixels.
+    pub fn target_kernel<T>(&mut self, kernel: T) -> &mut Self
+    where
+        T: TargetColorKernel + 'static,
+    {
+        self.target_kernel = Arc::new(kernel);
+        self
+    }
+
+    // Treat the stored color of each pixel as data, shown in generated
+    // images, animations, and live output through the color map.
+    // Searches of the palette and target color kernels still work on
+    // the stored values.  See ColorMap.
+    pub fn color_map<T>(&mut self, color_map: T) -> &mut Self
+    where
+        T: ColorMap + 'static,
+    {
+        self.color_map = Some(Arc::new(color_map));
+        self
+    }
+
+    // Average target colors in linear light rather than directly on
+    // the sRGB values.  Gives smoother gradients, since averaging
+    // sRGB values darkens the blend.
+    pub fn linear_light_averaging(&mut self, linear: bool) -> &mut Self {
+        self.linear_light_averaging = linear;
+        self
+    }
+
+    // Stop filling if a stage starts with pixels that it may fill, but
+    // that can't be reached from its seed points.  The error is then
+    // available from GrowthImage::check_regions().  Either way, the
+    // number of unreachable pixels is reported in each FillReport.
+    pub fn strict_regions(&mut self, strict: bool) -> &mut Self {
+        self.strict_regions = strict;
+        self
+    }
+
+    // Appearance of unfilled pixels in generated images, including
+    // animations and live output.  Defaults to transparent.
+    pub fn background(&mut self, background: Background) -> &mut Self {
+        self.background = background;
+        self
+    }
+
+    // Whether a partially transparent background color is stored with
+    // straight or premultiplied alpha.  Defaults to straight.
+    pub fn alpha_mode(&mut self, alpha_mode: AlphaMode) -> &mut Self {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+
+    // Precompute the neighbors of every pixel at the start of each
+    // stage, rather than checking bounds and portals on each lookup.
+    // Costs about 36 bytes per pixel, and is mostly useful for
+    // images with many portals or expensive target color kernels.
+    pub fn precompute_adjacency(&mut self, precompute: bool) -> &mut Self {
+        self.precompute_adjacency = precompute;
+        self
+    }
+
+    // Precompute the location of every pixel index, rather than
+    // searching for the layer that contains it on each lookup.
+    // Costs 16 bytes per pixel, and is mostly useful for images with
+    // many layers.
+    pub fn precompute_locations(&mut self, precompute: bool) -> &mut Self {
+        self.precompute_locations = precompute;
+        self
+    }
+
+    // Leaf size and splitting strategy of the KD-trees that hold each
+    // palette.  The fastest settings depend on the shape of the
+    // palette.  Cached palettes keep the settings they were saved
+    // with.
+    pub fn kdtree_options(&mut self, options: KDTreeOptions) -> &mut Self {
+        self.kdtree_options = options;
+        self
+    }
+
+    // Track every color placed across all stages, and skip any
+    // palette color that has already been used.  Separately generated
+    // palettes may contain the same colors, so this is needed to keep
+    // each color unique in multi-stage images.
+    pub fn enforce_unique_colors(&mut self, enforce: bool) -> &mut Self {
+        self.enforce_unique_colors = enforce;
+        self
+    }
+
     pub fn seed(&mut self, seed: u64) -> &mut Self {
         self.seed = Some(seed);
         self
     }
 
+    // Refuse to build an image whose estimate_memory() is larger than
+    // the given number of bytes, returning
+    // Error::MemoryBudgetExceeded rather than running out of memory
+    // partway through.
+    pub fn memory_budget(&mut self, bytes: usize) -> &mut Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    // Approximate memory needed by the image, without building it.
+    // Palettes are counted at their full size, and a region whose
+    // palette is sized by n_colors_exact_for_region() is assumed to
+    // have no pixels filled by earlier stages.  Animations, the
+    // frontier, and the colors used with enforce_unique_colors() are
+    // not included.
+    pub fn estimate_memory(&self) -> MemoryEstimate {
+        let num_pixels = self.topology.len();
+        let per_pixel = |bytes: usize| num_pixels * bytes;
+        let any_blend = self.stages.iter().any(|stage| stage.blend.is_some());
+
+        // Stages that run concurrently each have their own tracker.
+        let max_concurrent = self
+            .stages
+            .iter()
+            .skip(1)
+            .fold((1, 1), |(max, current), stage| {
+                let current = if stage.concurrent_with_previous {
+                    current + 1
+                } else {
+                    1
+                };
+                (max.max(current), current)
+            })
+            .0;
+
+        let (regions, kd_trees) = self
+            .stages
+            .iter()
+            .map(|stage| {
+                stage.estimate_memory(&self.topology, &self.kdtree_options)
+            })
+            .fold((0, 0), |(a, b), (c, d)| (a + c, b + d));
+
+        MemoryEstimate {
+            pixels: per_pixel(
+                std::mem::size_of::<Option<RGB>>()
+                    + 2 * std::mem::size_of::<Option<usize>>()
+                    + std::mem::size_of::<Option<PixelLoc>>(),
+            ) + if any_blend {
+                per_pixel(std::mem::size_of::<Option<RGB>>())
+            } else {
+                0
+            },
+            stats: per_pixel(std::mem::size_of::<Option<PerformanceStats>>()),
+            point_trackers: max_concurrent
+                * per_pixel(
+                    std::mem::size_of::<bool>()
+                        + std::mem::size_of::<Option<PixelLoc>>(),
+                ),
+            regions,
+            kd_trees,
+            // Offsets, and up to 8 neighbors of each pixel.
+            adjacency: if self.precompute_adjacency {
+                per_pixel(9 * std::mem::size_of::<u32>())
+            } else {
+                0
+            },
+            locations: if self.precompute_locations {
+                per_pixel(std::mem::size_of::<Option<PixelLoc>>())
+            } else {
+                0
+            },
+        }
+    }
+
+    // Describe the builder as a SceneSpec, for saving.  Options that
+    // can't be described as data (custom palettes, growth cones,
+    // feathering, reveal schedules, frontier thinning, HDR
+    // intensities, and per-seed palettes) give an error.  The target
+    // color kernel and color map are not included.
+    pub fn scene_spec(&self) -> Result<SceneSpec, Error> {
+        if self.topology.has_unbounded_layers() {
+            return Err(Error::UnsupportedSceneOption("unbounded_layer"));
+        }
+        Ok(SceneSpec {
+            layers: self
+                .topology
+                .layers()
+                .iter()
+                .enumerate()
+                .map(|(layer_i, layer)| LayerSpec {
+                    width: layer.width,
+                    height: layer.height,
+                    shape: self
+                        .topology
+                        .layer_shape(layer_i as u8)
+                        .unwrap_or_default(),
+                    epsilon: self.layer_epsilon.get(&(layer_i as u8)).copied(),
+                    collect_stats: self
+                        .layer_stats
+                        .get(&(layer_i as u8))
+                        .copied()
+                        .unwrap_or(true),
+                })
+                .collect(),
+            stages: self
+                .stages
+                .iter()
+                .map(|stage| stage.scene_spec())
+                .collect::<Result<_, _>>()?,
+            animations: self
+                .animation_outputs
+                .iter()
+                .map(|anim| anim.scene_spec())
+                .collect::<Result<_, _>>()?,
+            epsilon: self.epsilon,
+            seed: self.seed,
+            enforce_unique_colors: self.enforce_unique_colors,
+            linear_light_averaging: self.linear_light_averaging,
+            strict_regions: self.strict_regions,
+            background: self.background,
+            alpha_mode: self.alpha_mode,
+        })
+    }
+
     pub fn build(&self) -> Result<GrowthImage, Error> {
+        let used_colors = if self.enforce_unique_colors {
+            Some(Arc::new(ColorSet::new()))
+        } else {
+            None
+        };
+        self.build_with_used_colors(used_colors)
+    }
+
+    // Fill the image three times with the same seed, and check that
+    // every layer comes out byte-identical: once as configured, once
+    // more to catch randomness from hashing or timing, and once with
+    // the adjacency and location tables toggled, which should only
+    // affect speed.  Animations, live output, and the progress bar
+    // are skipped for these fills.  Without a seed, one is chosen at
+    // random.  Returns the seed used.
+    pub fn verify_determinism(&mut self) -> Result<u64, Error> {
+        let seed = self.seed.unwrap_or_else(rand::random);
+
+        let saved_seed = self.seed.replace(seed);
+        let animation_outputs = std::mem::take(&mut self.animation_outputs);
+        let live_output = self.live_output.take();
+        let show_progress_bar =
+            std::mem::replace(&mut self.show_progress_bar, false);
+
+        let render = |builder: &Self| -> Result<Vec<SaveImageData>, Error> {
+            let mut image = builder.build()?;
+            image.fill_until_done();
+            Ok((0..builder.topology.layers().len() as u8)
+                .map(|layer| image._image_data(SaveImageType::Generated, layer))
+                .collect())
+        };
+        let res = render(self).and_then(|expected| {
+            let repeated = render(self)?;
+            self.precompute_adjacency = !self.precompute_adjacency;
+            self.precompute_locations = !self.precompute_locations;
+            let toggled = render(self);
+            self.precompute_adjacency = !self.precompute_adjacency;
+            self.precompute_locations = !self.precompute_locations;
+
+            vec![repeated, toggled?].into_iter().try_for_each(|layers| {
+                expected.iter().zip(layers.iter()).enumerate().try_for_each(
+                    |(layer, (a, b))| match a
+                        .data
+                        .iter()
+                        .zip(b.data.iter())
+                        .position(|(x, y)| x != y)
+                    {
+                        Some(byte) => {
+                            let pixel = (byte / 4) as u32;
+                            Err(Error::NondeterministicOutput {
+                                seed,
+                                first_difference: PixelLoc {
+                                    layer: layer as u8,
+                                    i: (pixel % a.width) as i32,
+                                    j: (pixel / a.width) as i32,
+                                },
+                            })
+                        }
+                        None => Ok(()),
+                    },
+                )
+            })
+        });
+
+        self.seed = saved_seed;
+        self.animation_outputs = animation_outputs;
+        self.live_output = live_output;
+        self.show_progress_bar = show_progress_bar;
+        res.map(|()| seed)
+    }
+
+    // Build, tracking used colors in the given set.  Used by
+    // BatchRenderer to share one set between several images.
+    pub(crate) fn build_with_used_colors(
+        &self,
+        used_colors: Option<Arc<ColorSet>>,
+    ) -> Result<GrowthImage, Error> {
         if self.stages.len() == 0 {
             return Err(Error::NoStagesDefined);
         }
-        if self.topology.len() == 0 {
+        if self.topology.is_empty() {
             return Err(Error::NoLayersDefined);
         }
+        if let Some(budget) = self.memory_budget {
+            let estimate = self.estimate_memory();
+            if estimate.total() > budget {
+                return Err(Error::MemoryBudgetExceeded { estimate, budget });
+            }
+        }
 
-        let mut rng = match self.seed {
-            Some(seed) => rand_chacha::ChaCha8Rng::seed_from_u64(seed),
-            None => rand_chacha::ChaCha8Rng::from_entropy(),
-        };
+        // Without a user-specified seed, pick one at random, so that
+        // it can be recorded in the image metadata.
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
 
-        let pixels = vec![None; self.topology.len()];
-        let stats = vec![None; self.topology.len()];
-        let stages = self
+        let mut topology = self.topology.clone();
+        if self.precompute_locations {
+            topology.build_location_table();
+        }
+
+        let pixels = vec![None; topology.len()];
+        let pixel_stages = vec![None; topology.len()];
+        let pixel_fill_order = vec![None; topology.len()];
+        let pixel_origins = vec![None; topology.len()];
+        let stats = vec![None; topology.len()];
+        let mut stages = self
             .stages
             .iter()
-            .map(|s| s.build(&self.topology, &mut rng))
-            .collect();
+            .map(|s| s.build(&topology, &self.kdtree_options, seed, &mut rng))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let show_depletion = self.animation_outputs.iter().any(|anim| {
+            anim.image_types.contains(&SaveImageType::PaletteDepletion)
+        });
+        if show_depletion {
+            stages.iter_mut().for_each(|stage| {
+                stage.palette_depletion =
+                    Some(PaletteDepletion::new(&stage.palette));
+            });
+        }
 
-        let progress_bar = if self.show_progress_bar {
-            let bar = ProgressBar::new(self.topology.len() as u64);
-            bar.set_style(ProgressStyle::default_bar().template(
-                "[{pos}/{len}] {wide_bar} [{elapsed_precise}, ETA: {eta_precise}]",
-            ));
-            bar.set_draw_rate(10);
-            Some(bar)
+        let stage_fill_estimates =
+            GrowthImage::estimate_stage_fills(&topology, &stages);
+
+        let progress = if self.show_progress_bar {
+            Progress::new(
+                stage_fill_estimates.iter().sum::<usize>() as u64,
+                self.progress_draw_rate,
+                self.progress_text_interval,
+            )
         } else {
             None
         };
@@ -128,28 +692,261 @@ impl GrowthImageBuilder {
         // wrangle the lifetimes, should be able to have the portal
         // HashMap live in the stage and be borrowed from there.
         Ok(GrowthImage {
-            topology: self.topology.clone(),
+            topology: topology.clone(),
             pixels,
+            pixel_stages,
+            pixel_fill_order,
+            pixel_origins,
+            num_fill_events: 0,
             stats,
             epsilon: self.epsilon,
+            layer_epsilon: (0..topology.layers().len() as u8)
+                .map(|layer| {
+                    self.layer_epsilon
+                        .get(&layer)
+                        .copied()
+                        .unwrap_or(self.epsilon)
+                })
+                .collect(),
+            layer_stats: (0..topology.layers().len() as u8)
+                .map(|layer| {
+                    self.layer_stats.get(&layer).copied().unwrap_or(true)
+                })
+                .collect(),
+            target_kernel: Arc::clone(&self.target_kernel),
+            color_map: self.color_map.clone(),
+            seed,
+            linear_light_averaging: self.linear_light_averaging,
+            strict_regions: self.strict_regions,
+            background: self.background,
+            alpha_mode: self.alpha_mode,
+            precompute_adjacency: self.precompute_adjacency,
+            kdtree_options: self.kdtree_options,
+            used_colors,
+            num_duplicate_colors: 0,
             stages,
             active_stage: None,
+            num_stages_started: 0,
+            parked_stages: VecDeque::new(),
+            stage_fill_estimates,
+            stage_timeline: Vec::new(),
+            unreachable_pixels: vec![None; self.stages.len()],
+            region_error: None,
             current_stage_iter: 0,
-            point_tracker: PointTracker::new(self.topology.clone()),
+            next_reveal: 0,
+            ring_bands: Vec::new(),
+            ring_core: HashSet::new(),
+            stage_lookups: HashMap::new(),
+            best_match_queue: None,
+            point_tracker: PointTracker::new(topology),
             is_done: false,
             num_filled_pixels: 0,
+            blend_base: None,
             rng,
-            progress_bar,
+            progress,
             animation_outputs,
+            live_output: self.live_output.as_ref().map(|(filename, period)| {
+                LiveOutput {
+                    filename: filename.clone(),
+                    iter_period: *period,
+                    iter_since_write: 0,
+                }
+            }),
+            metadata: self.image_metadata(seed),
         })
     }
+
+    // Stages whose options can't be described as data are listed
+    // with the reason.
+    fn image_metadata(&self, seed: u64) -> Vec<(String, String)> {
+        let layers = self
+            .topology
+            .layers()
+            .iter()
+            .enumerate()
+            .map(|(layer_i, layer)| {
+                match self.topology.layer_shape(layer_i as u8) {
+                    _ if self.topology.is_unbounded(layer_i as u8) => {
+                        "unbounded".to_string()
+                    }
+                    Some(LayerShape::Ellipse) => {
+                        format!("{}x{} ellipse", layer.width, layer.height)
+                    }
+                    Some(LayerShape::Equirectangular) => format!(
+                        "{}x{} equirectangular",
+                        layer.width, layer.height
+                    ),
+                    _ => format!("{}x{}", layer.width, layer.height),
+                }
+            })
+            .join(", ");
+        let stages = self
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| match stage.scene_spec() {
+                Ok(spec) => format!("{}: {:?}", i, spec),
+                Err(err) => format!("{}: {}", i, err),
+            })
+            .join("\n");
+
+        vec![
+            (
+                "Software".to_string(),
+                format!("omnicolor-rust {}", env!("CARGO_PKG_VERSION")),
+            ),
+            ("omnicolor:seed".to_string(), seed.to_string()),
+            ("omnicolor:epsilon".to_string(), self.epsilon.to_string()),
+            (
+                "omnicolor:enforce_unique_colors".to_string(),
+                self.enforce_unique_colors.to_string(),
+            ),
+            ("omnicolor:layers".to_string(), layers),
+            ("omnicolor:stages".to_string(), stages),
+        ]
+    }
+}
+
+impl GrowthImageBuilder {
+    owned_variant!(with_progress_bar, show_progress_bar);
+    owned_variant!(with_progress_draw_rate, progress_draw_rate, draw_rate: u64);
+    owned_variant!(
+        with_progress_text_interval,
+        progress_text_interval,
+        interval: Option<Duration>
+    );
+    owned_variant!(
+        with_live_output,
+        live_output,
+        filename: PathBuf,
+        every_n_iters: usize
+    );
+    owned_variant!(with_layer, add_layer, width: u32, height: u32);
+    owned_variant!(
+        with_elliptical_layer,
+        add_elliptical_layer,
+        width: u32,
+        height: u32
+    );
+    owned_variant!(
+        with_equirectangular_layer,
+        add_equirectangular_layer,
+        width: u32,
+        height: u32
+    );
+    owned_variant!(with_unbounded_layer, add_unbounded_layer);
+    owned_variant!(with_epsilon, epsilon, epsilon: f64);
+    owned_variant!(
+        with_layer_epsilon,
+        layer_epsilon,
+        layer: u8,
+        epsilon: f64
+    );
+    owned_variant!(with_layer_stats, layer_stats, layer: u8, collect: bool);
+    owned_variant!(
+        with_enforce_unique_colors,
+        enforce_unique_colors,
+        enforce: bool
+    );
+    owned_variant!(
+        with_linear_light_averaging,
+        linear_light_averaging,
+        linear: bool
+    );
+    owned_variant!(with_strict_regions, strict_regions, strict: bool);
+    owned_variant!(with_background, background, background: Background);
+    owned_variant!(with_alpha_mode, alpha_mode, alpha_mode: AlphaMode);
+    owned_variant!(
+        with_precompute_adjacency,
+        precompute_adjacency,
+        precompute: bool
+    );
+    owned_variant!(
+        with_precompute_locations,
+        precompute_locations,
+        precompute: bool
+    );
+    owned_variant!(
+        with_kdtree_options,
+        kdtree_options,
+        options: KDTreeOptions
+    );
+    owned_variant!(with_seed, seed, seed: u64);
+    owned_variant!(with_memory_budget, memory_budget, bytes: usize);
+
+    pub fn with_palette<T>(mut self, palette: T) -> Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.palette(palette);
+        self
+    }
+
+    pub fn with_voronoi_stages<T>(
+        mut self,
+        layer: u8,
+        num_cells: usize,
+        palette: T,
+        hue_step: f32,
+    ) -> Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.voronoi_stages(layer, num_cells, palette, hue_step);
+        self
+    }
+
+    pub fn with_target_kernel<T>(mut self, kernel: T) -> Self
+    where
+        T: TargetColorKernel + 'static,
+    {
+        self.target_kernel(kernel);
+        self
+    }
+
+    pub fn with_color_map<T>(mut self, color_map: T) -> Self
+    where
+        T: ColorMap + 'static,
+    {
+        self.color_map(color_map);
+        self
+    }
+
+    pub fn with_stage(mut self, mut stage: GrowthImageStageBuilder) -> Self {
+        stage.is_first_stage = self.stages.is_empty();
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn with_output_animation(
+        mut self,
+        animation: GrowthImageAnimationBuilder,
+    ) -> Self {
+        self.animation_outputs.push(animation);
+        self
+    }
+}
+
+impl Default for GrowthImageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub struct GrowthImageStageBuilder {
-    palette: Box<dyn Palette>,
+    palette: Arc<dyn Palette>,
     n_colors: Option<u32>,
+    n_colors_exact: bool,
+    fallback_palette: Option<Box<dyn Palette>>,
+    palette_cache: Option<PathBuf>,
+    reserved_colors: Vec<RGB>,
+    reserved_release_iter: Option<usize>,
+    excluded_colors: Vec<RGB>,
+    exclusion_tolerance: f32,
 
     max_iter: Option<usize>,
+    advance_conditions: Vec<StageCondition>,
+    condition_check_period: usize,
 
     // For these four, track whether the user explicitly requested
     // specific options for the seed points.  To minimize
@@ -159,11 +956,32 @@ pub struct GrowthImageStageBuilder {
     // points.
     num_random_seed_points: Option<u32>,
     selected_seed_points: Option<Vec<PixelLoc>>,
+    growth_cones: Vec<(PixelLoc, GrowthCone)>,
+    seed_palettes: Vec<(PixelLoc, Box<dyn Palette>)>,
     grow_from_previous: Option<bool>,
     is_first_stage: bool,
 
     restricted_region: RestrictedRegion,
+    feather_radius: Option<f64>,
+    reveal_schedule: RevealSchedule,
+    temporary_walls: Vec<(usize, Vec<PixelLoc>)>,
+    ring_growth: Option<(u32, u32)>,
+    best_match_first: bool,
+    concurrent_with_previous: bool,
+    target_kernel: Option<Arc<dyn TargetColorKernel>>,
     connected_points: Vec<(PixelLoc, PixelLoc)>,
+    one_way_portals: Vec<(PixelLoc, PixelLoc)>,
+    portal_probability: f32,
+    portal_probabilities: Vec<(PixelLoc, f32)>,
+    frontier_thinning: Option<FrontierThinning>,
+    frontier_max_age: Option<usize>,
+    gravity_bias: Option<(f64, f64)>,
+    color_jitter: u8,
+    color_jitter_distribution: Option<RandomDistribution>,
+    seed_point_jitter: Option<RandomDistribution>,
+    selection_noise: Option<RandomDistribution>,
+    blend: Option<(BlendMode, f32)>,
+    hdr_intensity: f32,
 
     animation_iter_per_second: f64,
 }
@@ -171,15 +989,47 @@ pub struct GrowthImageStageBuilder {
 impl GrowthImageStageBuilder {
     fn new(stage_i: usize) -> Self {
         Self {
-            palette: Box::new(UniformPalette),
+            palette: Arc::new(UniformPalette),
             n_colors: None,
+            n_colors_exact: false,
+            fallback_palette: None,
+            palette_cache: None,
+            reserved_colors: Vec::new(),
+            reserved_release_iter: None,
+            excluded_colors: Vec::new(),
+            exclusion_tolerance: 0.0,
             max_iter: None,
+            advance_conditions: Vec::new(),
+            condition_check_period: DEFAULT_CONDITION_CHECK_PERIOD,
             num_random_seed_points: None,
             selected_seed_points: None,
+            growth_cones: Vec::new(),
+            seed_palettes: Vec::new(),
             grow_from_previous: None,
             is_first_stage: stage_i == 0,
-            restricted_region: RestrictedRegion::Forbidden(Vec::new()),
+            restricted_region: RestrictedRegion::Forbidden(
+                Arc::new(Vec::new()),
+            ),
+            feather_radius: None,
+            reveal_schedule: RevealSchedule::Points(Vec::new()),
+            temporary_walls: Vec::new(),
+            ring_growth: None,
+            best_match_first: false,
+            concurrent_with_previous: false,
+            target_kernel: None,
             connected_points: Vec::new(),
+            one_way_portals: Vec::new(),
+            portal_probability: 1.0,
+            portal_probabilities: Vec::new(),
+            frontier_thinning: None,
+            frontier_max_age: None,
+            gravity_bias: None,
+            color_jitter: 0,
+            color_jitter_distribution: None,
+            seed_point_jitter: None,
+            selection_noise: None,
+            blend: None,
+            hdr_intensity: 1.0,
             animation_iter_per_second: 240000.0,
         }
     }
@@ -188,7 +1038,7 @@ impl GrowthImageStageBuilder {
     where
         T: Palette + Sized + 'static,
     {
-        self.palette = Box::new(palette);
+        self.palette = Arc::new(palette);
         self
     }
 
@@ -197,11 +1047,130 @@ impl GrowthImageStageBuilder {
         self
     }
 
+    // Generate the palette when the stage starts, with exactly as
+    // many colors as there are empty pixels that the stage may fill,
+    // including any that are revealed partway through the stage.
+    // Colors in per-seed palettes and reserved colors count toward
+    // the total.  If every pixel is reachable from the seed points,
+    // the stage ends with no unfilled pixels and no leftover colors.
+    // Overrides n_colors(), and the palette isn't cached.
+    pub fn n_colors_exact_for_region(&mut self) -> &mut Self {
+        self.n_colors_exact = true;
+        self
+    }
+
+    // Colors to use once the main palette runs out, so that the stage
+    // continues until max_iter is reached or the region is filled,
+    // rather than leaving a partially filled region.  The fallback
+    // palette has enough colors to fill the entire image.
+    pub fn fallback_palette<T>(&mut self, palette: T) -> &mut Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.fallback_palette = Some(Box::new(palette));
+        self
+    }
+
+    // File in which to save the stage's palette after it is generated
+    // and sorted into a KD-tree, and from which to load it on later
+    // runs.  The file records the palette's description, the image
+    // seed, and the number of colors, and is regenerated if any of
+    // them differ.  Palettes without a description are only told
+    // apart by the seed and number of colors.  Loading a cached
+    // palette skips random number generation for it, so the rest of
+    // the image may differ from an uncached run with the same seed.
+    pub fn palette_cache(&mut self, filename: PathBuf) -> &mut Self {
+        self.palette_cache = Some(filename);
+        self
+    }
+
+    // Colors to hold back from the stage until the given iteration of
+    // the stage, so that specific colors are still available late in
+    // the stage rather than being used early as approximate matches.
+    // Once released, a reserved color is used whenever it is at least
+    // as close to the target as the nearest palette color.  Without a
+    // release iteration, the colors are released by
+    // GrowthImage::release_reserved_colors().  Either way, they are
+    // released once the palette runs out.  These are in addition to
+    // the palette's colors.
+    pub fn reserve_colors(
+        &mut self,
+        colors: Vec<RGB>,
+        release_iter: Option<usize>,
+    ) -> &mut Self {
+        self.reserved_colors = colors;
+        self.reserved_release_iter = release_iter;
+        self
+    }
+
+    // Remove palette colors within the tolerance, as a distance in
+    // RGB space, of any of the colors.  Applies to the palette,
+    // fallback palette, and per-seed palettes after they are
+    // generated, so the stage has fewer colors than n_colors() if any
+    // are removed.  Reserved colors are kept.
+    pub fn exclude_colors(
+        &mut self,
+        colors: Vec<RGB>,
+        tolerance: f32,
+    ) -> &mut Self {
+        self.excluded_colors = colors;
+        self.exclusion_tolerance = tolerance;
+        self
+    }
+
     pub fn max_iter(&mut self, max_iter: usize) -> &mut Self {
         self.max_iter = Some(max_iter);
         self
     }
 
+    // End the stage once the mean brightness of the filled pixels,
+    // from 0.0 to 1.0, drops below the threshold.  As with the other
+    // advance_when_* conditions, this is checked every
+    // condition_check_period() iterations, and the stage ends once
+    // any of its conditions are met.
+    pub fn advance_when_mean_brightness_below(
+        &mut self,
+        threshold: f32,
+    ) -> &mut Self {
+        self.advance_conditions
+            .push(StageCondition::MeanBrightnessBelow(threshold));
+        self
+    }
+
+    pub fn advance_when_mean_brightness_above(
+        &mut self,
+        threshold: f32,
+    ) -> &mut Self {
+        self.advance_conditions
+            .push(StageCondition::MeanBrightnessAbove(threshold));
+        self
+    }
+
+    // End the stage once the mean hue of the filled pixels in the
+    // region, in degrees, is within the range.  A range such as
+    // 330.0..30.0 wraps around through 0.  Gray pixels have no hue,
+    // and are ignored.
+    pub fn advance_when_region_mean_hue_in(
+        &mut self,
+        region: &Region,
+        hue_range: Range<f32>,
+    ) -> &mut Self {
+        self.advance_conditions
+            .push(StageCondition::RegionMeanHueIn {
+                region: Arc::new(region.points()),
+                hue_range,
+            });
+        self
+    }
+
+    // Number of iterations between checks of the advance_when_*
+    // conditions, each of which looks at every filled pixel.
+    // Defaults to 100.
+    pub fn condition_check_period(&mut self, iter_period: usize) -> &mut Self {
+        self.condition_check_period = iter_period;
+        self
+    }
+
     pub fn num_random_seed_points(
         &mut self,
         num_seed_points: u32,
@@ -215,6 +1184,31 @@ impl GrowthImageStageBuilder {
         self
     }
 
+    // Seed points whose growth is limited to a cone, as seen from the
+    // seed point.  These are used in addition to any seed points
+    // specified with seed_points().
+    pub fn growth_cones(
+        &mut self,
+        growth_cones: Vec<(PixelLoc, GrowthCone)>,
+    ) -> &mut Self {
+        self.growth_cones = growth_cones;
+        self
+    }
+
+    // Seed points that each draw from their own palette, so that the
+    // regions grown from each seed are colored differently.  Each
+    // palette provides an equal share of the stage's colors.  Once a
+    // seed's palette runs out, growth from it continues with the
+    // stage's palette.  These are used in addition to any seed points
+    // specified with seed_points().
+    pub fn seed_points_with_palettes(
+        &mut self,
+        seeds: Vec<(PixelLoc, Box<dyn Palette>)>,
+    ) -> &mut Self {
+        self.seed_palettes = seeds;
+        self
+    }
+
     pub fn grow_from_previous(
         &mut self,
         grow_from_previous: bool,
@@ -227,7 +1221,9 @@ impl GrowthImageStageBuilder {
         &mut self,
         allowed_points: Vec<PixelLoc>,
     ) -> &mut Self {
-        self.restricted_region = RestrictedRegion::Allowed(allowed_points);
+        self.restricted_region =
+            RestrictedRegion::Allowed(Arc::new(allowed_points));
+        self.feather_radius = None;
         self
     }
 
@@ -235,88 +1231,1086 @@ impl GrowthImageStageBuilder {
         &mut self,
         forbidden_points: Vec<PixelLoc>,
     ) -> &mut Self {
-        self.restricted_region = RestrictedRegion::Forbidden(forbidden_points);
+        self.restricted_region =
+            RestrictedRegion::Forbidden(Arc::new(forbidden_points));
+        self.feather_radius = None;
         self
     }
 
-    pub fn connected_points(
+    // Forbid the points given, and reduce the probability of growth
+    // near them.  Pixels closer than the radius to a forbidden point
+    // are selected from the frontier with probability proportional to
+    // their distance, softening the edges of walls.
+    pub fn forbidden_points_feathered(
         &mut self,
-        connected_points: Vec<(PixelLoc, PixelLoc)>,
+        forbidden_points: Vec<PixelLoc>,
+        radius: f64,
     ) -> &mut Self {
-        self.connected_points = connected_points;
+        self.forbidden_points(forbidden_points);
+        self.feather_radius = Some(radius);
         self
     }
 
-    pub fn animation_iter_per_second(
+    // Points that are forbidden at the start of the stage, and become
+    // allowed once the stage has run for the given number of
+    // iterations.
+    pub fn reveal_points(
         &mut self,
-        iter_per_second: f64,
+        schedule: Vec<(usize, Vec<PixelLoc>)>,
     ) -> &mut Self {
-        self.animation_iter_per_second = iter_per_second;
+        self.reveal_schedule = RevealSchedule::Points(schedule);
         self
     }
 
-    fn build(
-        &self,
-        topology: &Topology,
-        rng: &mut impl Rng,
-    ) -> GrowthImageStage {
-        let num_random_seed_points = match self.num_random_seed_points {
-            Some(n) => n,
-            None => {
-                if self.selected_seed_points.is_some() {
-                    0
-                } else if self.is_first_stage
-                    || self.grow_from_previous == Some(false)
-                {
-                    1
-                } else {
-                    0
-                }
-            }
+    // Reveal a growing circle around the center point, with the
+    // radius increasing by one pixel every iter_per_radius
+    // iterations.  Pixels outside the circle are forbidden until
+    // revealed.
+    pub fn reveal_circle(
+        &mut self,
+        center: PixelLoc,
+        iter_per_radius: usize,
+    ) -> &mut Self {
+        self.reveal_schedule = RevealSchedule::Circle {
+            center,
+            iter_per_radius,
         };
+        self
+    }
+
+    // Block growth through the points until the stage has run for the
+    // given number of iterations, after which colors can flood through
+    // them.  Walls are in addition to any reveal schedule, and may be
+    // called repeatedly for walls that expire at different times.  As
+    // with reveal_points(), walls expire early if growth has nowhere
+    // else to go.  Points forbidden to the stage stay forbidden.
+    pub fn temporary_wall(
+        &mut self,
+        points: Vec<PixelLoc>,
+        expires_after_iters: usize,
+    ) -> &mut Self {
+        self.temporary_walls.push((expires_after_iters, points));
+        self
+    }
+
+    // Restrict growth to pixels whose distance from the seed points,
+    // moving between adjacent pixels, is within [r_min, r_max].  Once
+    // that band is filled, the next band of the same width outside it
+    // is allowed, and so on, giving concentric rings.  Pixels closer
+    // than r_min are forbidden.  With grow_from_previous(), distances
+    // are measured from the edge of the previously filled pixels.
+    pub fn ring_growth(&mut self, r_min: u32, r_max: u32) -> &mut Self {
+        self.ring_growth = Some((r_min, r_max));
+        self
+    }
+
+    // Rather than choosing frontier points at random, fill whichever
+    // frontier point has the closest match available in the palette.
+    // Gives much smoother images, at the cost of a palette search for
+    // each neighbor of every filled pixel.  Selection weights, such as
+    // from gravity_bias(), are ignored.
+    pub fn best_match_first(&mut self) -> &mut Self {
+        self.best_match_first = true;
+        self
+    }
+
+    // Run this stage at the same time as the previous stage, with the
+    // stages taking turns to fill one pixel each.  Each stage keeps
+    // its own frontier, palette, and region, and a stage that finishes
+    // early leaves the remaining stages to continue.  The next
+    // non-concurrent stage starts once all of them have finished.
+    // Has no effect on the first stage.  Stages that run concurrently
+    // share the image, so blend() is ignored for them.
+    pub fn concurrent_with_previous(&mut self) -> &mut Self {
+        self.concurrent_with_previous = true;
+        self
+    }
+
+    // Target color kernel to use during this stage, in place of the
+    // image's target_kernel().
+    pub fn target_kernel<T>(&mut self, kernel: T) -> &mut Self
+    where
+        T: TargetColorKernel + 'static,
+    {
+        self.target_kernel = Some(Arc::new(kernel));
+        self
+    }
+
+    pub fn allowed_region(&mut self, region: &Region) -> &mut Self {
+        self.allowed_points(region.points())
+    }
+
+    pub fn forbidden_region(&mut self, region: &Region) -> &mut Self {
+        self.forbidden_points(region.points())
+    }
+
+    // Forbid the points, on top of any other forbidden points, or
+    // remove them from the allowed points.  Along with also_allow(),
+    // regions can be built up from several sources, with each call
+    // taking precedence over earlier calls for the points it names.
+    pub fn also_forbid(&mut self, forbid: Vec<PixelLoc>) -> &mut Self {
+        match &mut self.restricted_region {
+            RestrictedRegion::Forbidden(points) => {
+                Arc::make_mut(points).extend(forbid);
+            }
+            RestrictedRegion::Allowed(points) => {
+                let forbid = forbid.into_iter().collect::<HashSet<_>>();
+                Arc::make_mut(points).retain(|loc| !forbid.contains(loc));
+            }
+        }
+        self
+    }
+
+    // Allow the points, on top of any other allowed points, or remove
+    // them from the forbidden points.  A stage without
+    // allowed_points() already allows every point that isn't
+    // forbidden, so this doesn't restrict the stage to these points.
+    pub fn also_allow(&mut self, allow: Vec<PixelLoc>) -> &mut Self {
+        match &mut self.restricted_region {
+            RestrictedRegion::Allowed(points) => {
+                let existing = points.iter().copied().collect::<HashSet<_>>();
+                let new_points = allow
+                    .into_iter()
+                    .filter(|loc| !existing.contains(loc))
+                    .unique();
+                Arc::make_mut(points).extend(new_points);
+            }
+            RestrictedRegion::Forbidden(points) => {
+                let allow = allow.into_iter().collect::<HashSet<_>>();
+                Arc::make_mut(points).retain(|loc| !allow.contains(loc));
+            }
+        }
+        self
+    }
+
+    // Remove any allowed or forbidden points, along with feathering,
+    // so that every point is allowed.
+    pub fn clear_regions(&mut self) -> &mut Self {
+        self.restricted_region =
+            RestrictedRegion::Forbidden(Arc::new(Vec::new()));
+        self.feather_radius = None;
+        self
+    }
+
+    // Forbid the pixels within half the thickness of the path, as in
+    // also_forbid().  Can be called several times to add several
+    // walls.
+    pub fn wall_from_path(
+        &mut self,
+        layer: u8,
+        path: &BezPath,
+        thickness: f64,
+    ) -> &mut Self {
+        self.also_forbid(path.rasterize_stroke(layer, thickness))
+    }
+
+    pub fn connected_points(
+        &mut self,
+        connected_points: Vec<(PixelLoc, PixelLoc)>,
+    ) -> &mut Self {
+        self.connected_points = connected_points;
+        self
+    }
+
+    // Portals that lead from the first point to the second, but not
+    // back.  A point with a two-way portal from connected_points()
+    // uses that portal instead.  If a point has several one-way
+    // portals, the first is used.
+    pub fn one_way_portals(
+        &mut self,
+        portals: Vec<(PixelLoc, PixelLoc)>,
+    ) -> &mut Self {
+        self.one_way_portals = portals;
+        self
+    }
 
-        let selected_seed_points = match self.selected_seed_points.as_ref() {
-            Some(points) => points.clone(),
-            None => Vec::new(),
+    // Connect regions of different sizes, such as a small bridge
+    // layer and a long edge, with portals from every point of each
+    // region to a point of the other, as matched by the mapping.
+    // Several points may lead to the same point.  For
+    // Mapping::Custom, which only maps from region_a, each point of
+    // region_b leads back to the first point of region_a that maps
+    // onto it.  Adds to the one_way_portals().
+    pub fn connect_mapped(
+        &mut self,
+        region_a: &Region,
+        region_b: &Region,
+        mapping: Mapping,
+    ) -> &mut Self {
+        let forward = region_a.map_onto(region_b, &mapping);
+        let backward = match mapping {
+            Mapping::Custom(_) => {
+                forward.iter().map(|&(a, b)| (b, a)).collect()
+            }
+            _ => region_b.map_onto(region_a, &mapping),
         };
+        self.one_way_portals.extend(forward);
+        self.one_way_portals.extend(backward);
+        self
+    }
 
-        let portals = self
+    // Probability that filling a point grows through the portal that
+    // leads from it, rather than only to its neighbors within the
+    // layer.  Applies to every portal of the stage, unless overridden
+    // by portal_probabilities().  Low probabilities make growth
+    // between layers sparse and tendril-like, rather than flooding
+    // across a wide band of portals at once.
+    pub fn portal_probability(&mut self, probability: f32) -> &mut Self {
+        self.portal_probability = probability;
+        self
+    }
+
+    // Probability of growing through the portal from each of the
+    // points, as in portal_probability().
+    pub fn portal_probabilities(
+        &mut self,
+        probabilities: Vec<(PixelLoc, f32)>,
+    ) -> &mut Self {
+        self.portal_probabilities = probabilities;
+        self
+    }
+
+    // Every iter_period iterations, thin the frontier so that no two
+    // frontier points are within min_distance of each other.  The
+    // removed points are not forbidden, and may still be filled
+    // later.  Gives a lacier structure for large frontiers.
+    pub fn frontier_thinning(
+        &mut self,
+        min_distance: f64,
+        iter_period: usize,
+    ) -> &mut Self {
+        self.frontier_thinning = Some(FrontierThinning {
+            min_distance,
+            iter_period,
+        });
+        self
+    }
+
+    // Drop frontier points that go unselected for more than max_age
+    // iterations of the stage.  The dropped points are skipped for
+    // the rest of the stage, leaving ragged gaps in the growth, and
+    // the size of the frontier stays bounded.
+    pub fn frontier_max_age(&mut self, max_age: usize) -> &mut Self {
+        self.frontier_max_age = Some(max_age);
+        self
+    }
+
+    // Favor frontier points further along the given vector, in pixels
+    // (i, j), so that growth drips or piles up in that direction.
+    // The length of the vector gives the strength of the bias.  At a
+    // strength of 1.0, points at the far side of the layer are
+    // selected about a third as often as points at the near side.
+    // Combines with feathered forbidden points.
+    pub fn gravity_bias(&mut self, di: f64, dj: f64) -> &mut Self {
+        self.gravity_bias = Some((di, dj));
+        self
+    }
+
+    // Perturb each channel of the placed colors by up to max_delta, for
+    // a noisier texture.  The palette color is still consumed, but the
+    // pixel may end up with a color that is used elsewhere, even when
+    // enforcing unique colors.
+    pub fn color_jitter(&mut self, max_delta: u8) -> &mut Self {
+        self.color_jitter = max_delta;
+        self
+    }
+
+    // As color_jitter(), but with each channel's offset drawn from
+    // the distribution, rounded to the nearest integer.  Overrides
+    // color_jitter().
+    pub fn color_jitter_distribution(
+        &mut self,
+        distribution: RandomDistribution,
+    ) -> &mut Self {
+        self.color_jitter_distribution = Some(distribution);
+        self
+    }
+
+    // Move each of the seed_points() when the stage starts, by an
+    // offset in i and in j drawn from the distribution, in pixels.
+    // Seed points that would move off of the layer are left in
+    // place.
+    pub fn seed_point_jitter(
+        &mut self,
+        distribution: RandomDistribution,
+    ) -> &mut Self {
+        self.seed_point_jitter = Some(distribution);
+        self
+    }
+
+    // Give each pixel a random weight when choosing the next frontier
+    // point to fill, of exp(x) for x drawn from the distribution.  A
+    // wider distribution gives more irregular growth.  Combines with
+    // gravity_bias() and feathered forbidden points.
+    pub fn selection_noise(
+        &mut self,
+        distribution: RandomDistribution,
+    ) -> &mut Self {
+        self.selection_noise = Some(distribution);
+        self
+    }
+
+    // Grow the stage in its own buffer, starting from an empty image,
+    // and composite it over the image with the blend mode and opacity
+    // once the stage finishes.  The stage's pixels don't grow from or
+    // avoid the pixels beneath them.  Unless other seed points are
+    // given, the stage starts from one random seed point.
+    pub fn blend(&mut self, mode: BlendMode, opacity: f32) -> &mut Self {
+        self.blend = Some((mode, opacity));
+        self
+    }
+
+    // Multiplier applied to the linear-light colors of this stage when
+    // writing float outputs.  Values above 1.0 allow a stage to exceed
+    // the dynamic range of the 8-bit outputs.
+    pub fn hdr_intensity(&mut self, intensity: f32) -> &mut Self {
+        self.hdr_intensity = intensity;
+        self
+    }
+
+    pub fn animation_iter_per_second(
+        &mut self,
+        iter_per_second: f64,
+    ) -> &mut Self {
+        self.animation_iter_per_second = iter_per_second;
+        self
+    }
+
+    fn scene_spec(&self) -> Result<StageSpec, Error> {
+        let unsupported = |option| Err(Error::UnsupportedSceneOption(option));
+        if !self.growth_cones.is_empty() {
+            return unsupported("growth_cones");
+        }
+        if !self.seed_palettes.is_empty() {
+            return unsupported("seed_points_with_palettes");
+        }
+        if self.feather_radius.is_some() {
+            return unsupported("forbidden_points_feathered");
+        }
+        match &self.reveal_schedule {
+            RevealSchedule::Points(points) if points.is_empty() => {}
+            _ => return unsupported("reveal_points"),
+        }
+        if !self.temporary_walls.is_empty() {
+            return unsupported("temporary_wall");
+        }
+        if self.frontier_thinning.is_some() {
+            return unsupported("frontier_thinning");
+        }
+        if self.target_kernel.is_some() {
+            return unsupported("target_kernel");
+        }
+        if self.hdr_intensity != 1.0 {
+            return unsupported("hdr_intensity");
+        }
+        if !self.advance_conditions.is_empty() {
+            return unsupported("advance_when");
+        }
+
+        let palette = match self.palette.spec() {
+            Some(palette) => palette,
+            None => return unsupported("palette"),
+        };
+        let fallback_palette = match &self.fallback_palette {
+            Some(fallback) => match fallback.spec() {
+                Some(fallback) => Some(fallback),
+                None => return unsupported("fallback_palette"),
+            },
+            None => None,
+        };
+
+        let region = match &self.restricted_region {
+            RestrictedRegion::Allowed(points) => {
+                Some(RegionSpec::Allowed(points.to_vec()))
+            }
+            RestrictedRegion::Forbidden(points) if points.is_empty() => None,
+            RestrictedRegion::Forbidden(points) => {
+                Some(RegionSpec::Forbidden(points.to_vec()))
+            }
+        };
+
+        let default_iter_per_second =
+            GrowthImageStageBuilder::new(0).animation_iter_per_second;
+
+        Ok(StageSpec {
+            palette,
+            n_colors: self.n_colors,
+            n_colors_exact: self.n_colors_exact,
+            fallback_palette,
+            palette_cache: self.palette_cache.clone(),
+            reserved_colors: self.reserved_colors.clone(),
+            reserved_release_iter: self.reserved_release_iter,
+            excluded_colors: self.excluded_colors.clone(),
+            exclusion_tolerance: self.exclusion_tolerance,
+            max_iter: self.max_iter,
+            num_random_seed_points: self.num_random_seed_points,
+            seed_points: self.selected_seed_points.clone(),
+            grow_from_previous: self.grow_from_previous,
+            region,
+            ring_growth: self.ring_growth,
+            best_match_first: self.best_match_first,
+            concurrent_with_previous: self.concurrent_with_previous,
+            frontier_max_age: self.frontier_max_age,
+            gravity_bias: self.gravity_bias,
+            color_jitter: self.color_jitter,
+            color_jitter_distribution: self.color_jitter_distribution,
+            seed_point_jitter: self.seed_point_jitter,
+            selection_noise: self.selection_noise,
+            blend: self.blend,
+            connected_points: self.connected_points.clone(),
+            one_way_portals: self.one_way_portals.clone(),
+            portal_probability: if self.portal_probability == 1.0 {
+                None
+            } else {
+                Some(self.portal_probability)
+            },
+            portal_probabilities: self.portal_probabilities.clone(),
+            animation_iter_per_second: if self.animation_iter_per_second
+                == default_iter_per_second
+            {
+                None
+            } else {
+                Some(self.animation_iter_per_second)
+            },
+            #[cfg(feature = "expressions")]
+            expressions: Default::default(),
+        })
+    }
+
+    fn build(
+        &self,
+        topology: &Topology,
+        kdtree_options: &KDTreeOptions,
+        seed: u64,
+        rng: &mut impl Rng,
+    ) -> Result<GrowthImageStage, Error> {
+        let num_random_seed_points = match self.num_random_seed_points {
+            Some(n) => n,
+            None => {
+                if self.selected_seed_points.is_some()
+                    || !self.growth_cones.is_empty()
+                    || !self.seed_palettes.is_empty()
+                {
+                    0
+                } else if self.is_first_stage
+                    || self.grow_from_previous == Some(false)
+                    || self.blend.is_some()
+                {
+                    1
+                } else {
+                    0
+                }
+            }
+        };
+
+        let selected_seed_points = self
+            .selected_seed_points
+            .iter()
+            .flatten()
+            .copied()
+            .chain(self.growth_cones.iter().map(|&(loc, _cone)| loc))
+            .chain(self.seed_palettes.iter().map(|(loc, _palette)| *loc))
+            .collect();
+        let growth_cones =
+            Arc::new(self.growth_cones.iter().copied().collect());
+
+        let mut portals: HashMap<PixelLoc, PixelLoc> = self
             .connected_points
             .iter()
             .filter(|(a, b)| topology.is_valid(*a) && topology.is_valid(*b))
             .flat_map(|&(a, b)| vec![(a, b), (b, a)].into_iter())
             .collect();
+        self.one_way_portals
+            .iter()
+            .filter(|(a, b)| topology.is_valid(*a) && topology.is_valid(*b))
+            .for_each(|&(a, b)| {
+                portals.entry(a).or_insert(b);
+            });
+        let mut portal_probabilities: HashMap<PixelLoc, f32> = portals
+            .keys()
+            .map(|&loc| (loc, self.portal_probability))
+            .collect();
+        self.portal_probabilities
+            .iter()
+            .filter(|(loc, _)| portals.contains_key(loc))
+            .for_each(|&(loc, probability)| {
+                portal_probabilities.insert(loc, probability);
+            });
+        portal_probabilities.retain(|_, probability| *probability < 1.0);
+        let portals = Arc::new(portals);
+        let portal_probabilities = Arc::new(portal_probabilities);
+
+        let reveal_schedule = Arc::new(self.build_reveal_schedule(topology));
+
+        let mut selection_weights: Vec<Arc<dyn SelectionWeight>> = Vec::new();
+        if let (RestrictedRegion::Forbidden(points), Some(radius)) =
+            (&self.restricted_region, self.feather_radius)
+        {
+            selection_weights
+                .push(Arc::new(feathered_weights(topology, points, radius)));
+        }
+        if let Some((di, dj)) = self.gravity_bias {
+            selection_weights
+                .push(Arc::new(GravityBias::new(topology, di, dj)));
+        }
+        if let Some(distribution) = self.selection_noise {
+            let weights = (0..topology.len())
+                .map(|_| distribution.sample(rng).exp() as f32)
+                .collect::<Vec<_>>();
+            selection_weights.push(Arc::new(weights));
+        }
+
+        let n_colors = self.n_colors.unwrap_or(topology.num_valid() as u32);
+        let palette = if self.n_colors_exact {
+            KDTree::with_options(Vec::new(), *kdtree_options)
+        } else {
+            self.build_palette(n_colors, kdtree_options, seed, rng)?
+        };
+        // Applied after loading from the palette cache, so that the
+        // cache holds the palette as generated.
+        let palette = if self.excluded_colors.is_empty() {
+            palette
+        } else {
+            KDTree::with_options(
+                self.exclude(
+                    palette.iter_points().flatten().copied().collect(),
+                ),
+                *kdtree_options,
+            )
+        };
+
+        let seed_n_colors = if self.seed_palettes.is_empty() {
+            0
+        } else {
+            let num_seeds = self.seed_palettes.len() as u32;
+            n_colors.div_ceil(num_seeds)
+        };
+        let seed_palettes = self
+            .seed_palettes
+            .iter()
+            .map(|(loc, palette)| {
+                let colors = self.exclude(palette.generate(seed_n_colors, rng));
+                (*loc, KDTree::with_options(colors, *kdtree_options))
+            })
+            .collect();
+
+        let fallback_palette = self.fallback_palette.as_ref().map(|palette| {
+            let colors = palette.generate(topology.num_valid() as u32, rng);
+            KDTree::with_options(self.exclude(colors), *kdtree_options)
+        });
 
-        let n_colors = self.n_colors.unwrap_or(topology.len() as u32);
-        let palette = KDTree::new(self.palette.generate(n_colors, rng));
+        let reserved_colors = if self.reserved_colors.is_empty() {
+            None
+        } else {
+            Some(KDTree::with_options(
+                self.reserved_colors.clone(),
+                *kdtree_options,
+            ))
+        };
 
-        GrowthImageStage {
+        Ok(GrowthImageStage {
             palette: palette,
+            exact_palette: if self.n_colors_exact {
+                Some(Arc::clone(&self.palette))
+            } else {
+                None
+            },
+            reserved_colors,
+            reserved_release_iter: self.reserved_release_iter,
+            reserved_released: false,
+            excluded_colors: self.excluded_colors.clone(),
+            exclusion_tolerance: self.exclusion_tolerance,
+            initial_colors: None,
+            seed_palettes,
+            fallback_palette,
             max_iter: self.max_iter,
+            advance_conditions: self.advance_conditions.clone(),
+            condition_check_period: self.condition_check_period.max(1),
             grow_from_previous: self.grow_from_previous.unwrap_or(true),
             selected_seed_points,
+            growth_cones,
             num_random_seed_points,
             restricted_region: self.restricted_region.clone(),
+            selection_weights,
+            reveal_schedule,
             portals,
+            portal_probabilities,
             animation_iter_per_second: self.animation_iter_per_second,
+            frontier_thinning: self.frontier_thinning,
+            frontier_max_age: self.frontier_max_age,
+            ring_growth: self.ring_growth,
+            best_match_first: self.best_match_first,
+            concurrent_with_previous: self.concurrent_with_previous,
+            target_kernel: self.target_kernel.clone(),
+            color_jitter: self.color_jitter,
+            color_jitter_distribution: self.color_jitter_distribution,
+            seed_point_jitter: self.seed_point_jitter,
+            blend: self.blend,
+            palette_depletion: None,
+            hdr_intensity: self.hdr_intensity,
+        })
+    }
+
+    // Approximate bytes used by the stage's regions, and by its
+    // palettes.
+    fn estimate_memory(
+        &self,
+        topology: &Topology,
+        kdtree_options: &KDTreeOptions,
+    ) -> (usize, usize) {
+        let num_pixels = topology.len();
+        let num_valid = topology.num_valid();
+        let region_points = match &self.restricted_region {
+            RestrictedRegion::Allowed(points) => points.len(),
+            RestrictedRegion::Forbidden(points) => points.len(),
+        };
+        let feathered_weights = if self.feather_radius.is_some() {
+            num_pixels * std::mem::size_of::<f32>()
+        } else {
+            0
+        };
+        let regions =
+            region_points * std::mem::size_of::<PixelLoc>() + feathered_weights;
+
+        let n_colors = match (&self.restricted_region, self.n_colors_exact) {
+            (RestrictedRegion::Allowed(points), true) => points.len(),
+            (_, true) => num_valid,
+            (_, false) => self.n_colors.map_or(num_valid, |n| n as usize),
+        };
+        let seed_n_colors = if self.seed_palettes.is_empty() {
+            0
+        } else {
+            let num_seeds = self.seed_palettes.len();
+            num_seeds * n_colors.div_ceil(num_seeds)
+        };
+        let fallback_n_colors = if self.fallback_palette.is_some() {
+            num_valid
+        } else {
+            0
+        };
+        let kd_trees = [
+            n_colors,
+            seed_n_colors,
+            fallback_n_colors,
+            self.reserved_colors.len(),
+        ]
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| KDTree::<RGB>::estimate_memory(n, kdtree_options))
+        .sum();
+
+        (regions, kd_trees)
+    }
+
+    // The reveal schedule, along with the expiration of each temporary
+    // wall.  Wall points that the stage may never fill are left out,
+    // so that they aren't opened when the wall expires.
+    fn build_reveal_schedule(
+        &self,
+        topology: &Topology,
+    ) -> Vec<(usize, Vec<PixelLoc>)> {
+        let mut schedule = self.reveal_schedule.build(topology);
+        if self.temporary_walls.is_empty() {
+            return schedule;
         }
+
+        let restricted = match &self.restricted_region {
+            RestrictedRegion::Allowed(points) => points,
+            RestrictedRegion::Forbidden(points) => points,
+        }
+        .iter()
+        .copied()
+        .collect::<HashSet<_>>();
+        let is_allowed = |loc: &PixelLoc| match &self.restricted_region {
+            RestrictedRegion::Allowed(_) => restricted.contains(loc),
+            RestrictedRegion::Forbidden(_) => !restricted.contains(loc),
+        };
+        self.temporary_walls.iter().for_each(|(iter, points)| {
+            let points = points
+                .iter()
+                .filter(|loc| is_allowed(loc))
+                .copied()
+                .collect();
+            schedule.push((*iter, points));
+        });
+        schedule.sort_by_key(|(iter, _points)| *iter);
+        schedule
+    }
+
+    fn exclude(&self, colors: Vec<RGB>) -> Vec<RGB> {
+        exclude_colors(colors, &self.excluded_colors, self.exclusion_tolerance)
+    }
+
+    fn build_palette(
+        &self,
+        n_colors: u32,
+        kdtree_options: &KDTreeOptions,
+        seed: u64,
+        rng: &mut impl Rng,
+    ) -> Result<KDTree<RGB>, Error> {
+        let mut generate = || {
+            KDTree::with_options(
+                self.palette.generate(n_colors, rng),
+                *kdtree_options,
+            )
+        };
+        let filename = match &self.palette_cache {
+            Some(filename) => filename,
+            None => return Ok(generate()),
+        };
+
+        // Written ahead of the KD-tree, as its length in bytes
+        // followed by the text.
+        let fingerprint = format!(
+            "palette={:?} seed={} n_colors={}",
+            self.palette.spec(),
+            seed,
+            n_colors
+        );
+
+        if filename.exists() {
+            let mut file = BufReader::new(File::open(filename)?);
+            let mut len = [0u8; 8];
+            let matches = file.read_exact(&mut len).is_ok()
+                && u64::from_le_bytes(len) == fingerprint.len() as u64
+                && {
+                    let mut cached = vec![0u8; fingerprint.len()];
+                    file.read_exact(&mut cached).is_ok()
+                        && cached == fingerprint.as_bytes()
+                };
+            if matches {
+                let palette = KDTree::deserialize(file)?;
+                if palette.num_points() == n_colors as usize {
+                    return Ok(palette);
+                }
+            }
+        }
+
+        let palette = generate();
+        let mut file = BufWriter::new(File::create(filename)?);
+        file.write_all(&(fingerprint.len() as u64).to_le_bytes())?;
+        file.write_all(fingerprint.as_bytes())?;
+        palette.serialize(&mut file)?;
+        file.flush()?;
+        Ok(palette)
+    }
+}
+
+// Selection weights that increase linearly from 0 at a forbidden
+// point to 1 at the given radius.
+fn feathered_weights(
+    topology: &Topology,
+    points: &[PixelLoc],
+    radius: f64,
+) -> Vec<f32> {
+    let mut weights = vec![1.0f32; topology.len()];
+    let int_radius = radius.ceil() as i32;
+
+    points.iter().for_each(|center| {
+        (-int_radius..=int_radius)
+            .flat_map(|di| (-int_radius..=int_radius).map(move |dj| (di, dj)))
+            .for_each(|(di, dj)| {
+                let loc = PixelLoc {
+                    layer: center.layer,
+                    i: center.i + di,
+                    j: center.j + dj,
+                };
+                if let Some(index) = topology.get_index(loc) {
+                    let dist = ((di * di + dj * dj) as f64).sqrt();
+                    let weight = (dist / radius).min(1.0) as f32;
+                    weights[index] = weights[index].min(weight);
+                }
+            });
+    });
+
+    weights
+}
+
+#[derive(Clone)]
+enum RevealSchedule {
+    Points(Vec<(usize, Vec<PixelLoc>)>),
+    Circle {
+        center: PixelLoc,
+        iter_per_radius: usize,
+    },
+}
+
+impl RevealSchedule {
+    // Convert to a list of points to reveal, sorted by the iteration
+    // at which they should be revealed.
+    fn build(&self, topology: &Topology) -> Vec<(usize, Vec<PixelLoc>)> {
+        let mut schedule = match self {
+            RevealSchedule::Points(schedule) => schedule.clone(),
+            RevealSchedule::Circle {
+                center,
+                iter_per_radius,
+            } => {
+                let mut by_radius: HashMap<usize, Vec<PixelLoc>> =
+                    HashMap::new();
+                topology
+                    .layer_indices(center.layer)
+                    .flat_map(|index| topology.get_loc(index))
+                    .for_each(|loc| {
+                        let di = (loc.i - center.i) as f64;
+                        let dj = (loc.j - center.j) as f64;
+                        let radius = (di * di + dj * dj).sqrt().ceil();
+                        by_radius.entry(radius as usize).or_default().push(loc);
+                    });
+                by_radius
+                    .into_iter()
+                    .map(|(radius, points)| (radius * iter_per_radius, points))
+                    .collect()
+            }
+        };
+        schedule.sort_by_key(|(iter, _points)| *iter);
+        schedule
+    }
+}
+
+impl GrowthImageStageBuilder {
+    owned_variant!(with_n_colors, n_colors, n_colors: u32);
+    owned_variant!(with_n_colors_exact_for_region, n_colors_exact_for_region);
+    owned_variant!(with_palette_cache, palette_cache, filename: PathBuf);
+    owned_variant!(
+        with_reserve_colors,
+        reserve_colors,
+        colors: Vec<RGB>,
+        release_iter: Option<usize>
+    );
+    owned_variant!(
+        with_exclude_colors,
+        exclude_colors,
+        colors: Vec<RGB>,
+        tolerance: f32
+    );
+    owned_variant!(with_max_iter, max_iter, max_iter: usize);
+    owned_variant!(
+        with_advance_when_mean_brightness_below,
+        advance_when_mean_brightness_below,
+        threshold: f32
+    );
+    owned_variant!(
+        with_advance_when_mean_brightness_above,
+        advance_when_mean_brightness_above,
+        threshold: f32
+    );
+    owned_variant!(
+        with_advance_when_region_mean_hue_in,
+        advance_when_region_mean_hue_in,
+        region: &Region,
+        hue_range: Range<f32>
+    );
+    owned_variant!(
+        with_condition_check_period,
+        condition_check_period,
+        iter_period: usize
+    );
+    owned_variant!(
+        with_num_random_seed_points,
+        num_random_seed_points,
+        num_seed_points: u32
+    );
+    owned_variant!(with_seed_points, seed_points, seed_points: Vec<PixelLoc>);
+    owned_variant!(
+        with_growth_cones,
+        growth_cones,
+        growth_cones: Vec<(PixelLoc, GrowthCone)>
+    );
+    owned_variant!(
+        with_seed_points_with_palettes,
+        seed_points_with_palettes,
+        seeds: Vec<(PixelLoc, Box<dyn Palette>)>
+    );
+    owned_variant!(
+        with_grow_from_previous,
+        grow_from_previous,
+        grow_from_previous: bool
+    );
+    owned_variant!(
+        with_allowed_points,
+        allowed_points,
+        allowed_points: Vec<PixelLoc>
+    );
+    owned_variant!(
+        with_forbidden_points,
+        forbidden_points,
+        forbidden_points: Vec<PixelLoc>
+    );
+    owned_variant!(
+        with_forbidden_points_feathered,
+        forbidden_points_feathered,
+        forbidden_points: Vec<PixelLoc>,
+        radius: f64
+    );
+    owned_variant!(with_allowed_region, allowed_region, region: &Region);
+    owned_variant!(with_forbidden_region, forbidden_region, region: &Region);
+    owned_variant!(with_also_forbid, also_forbid, forbid: Vec<PixelLoc>);
+    owned_variant!(with_also_allow, also_allow, allow: Vec<PixelLoc>);
+    owned_variant!(with_clear_regions, clear_regions);
+    owned_variant!(
+        with_wall_from_path,
+        wall_from_path,
+        layer: u8,
+        path: &BezPath,
+        thickness: f64
+    );
+    owned_variant!(
+        with_reveal_points,
+        reveal_points,
+        schedule: Vec<(usize, Vec<PixelLoc>)>
+    );
+    owned_variant!(
+        with_reveal_circle,
+        reveal_circle,
+        center: PixelLoc,
+        iter_per_radius: usize
+    );
+    owned_variant!(
+        with_temporary_wall,
+        temporary_wall,
+        points: Vec<PixelLoc>,
+        expires_after_iters: usize
+    );
+    owned_variant!(
+        with_connected_points,
+        connected_points,
+        connected_points: Vec<(PixelLoc, PixelLoc)>
+    );
+    owned_variant!(
+        with_one_way_portals,
+        one_way_portals,
+        portals: Vec<(PixelLoc, PixelLoc)>
+    );
+    owned_variant!(
+        with_connect_mapped,
+        connect_mapped,
+        region_a: &Region,
+        region_b: &Region,
+        mapping: Mapping
+    );
+    owned_variant!(
+        with_portal_probability,
+        portal_probability,
+        probability: f32
+    );
+    owned_variant!(
+        with_portal_probabilities,
+        portal_probabilities,
+        probabilities: Vec<(PixelLoc, f32)>
+    );
+    owned_variant!(with_ring_growth, ring_growth, r_min: u32, r_max: u32);
+    owned_variant!(with_best_match_first, best_match_first);
+    owned_variant!(with_concurrent_with_previous, concurrent_with_previous);
+    owned_variant!(
+        with_frontier_thinning,
+        frontier_thinning,
+        min_distance: f64,
+        iter_period: usize
+    );
+    owned_variant!(with_frontier_max_age, frontier_max_age, max_age: usize);
+    owned_variant!(with_gravity_bias, gravity_bias, di: f64, dj: f64);
+    owned_variant!(with_color_jitter, color_jitter, max_delta: u8);
+    owned_variant!(
+        with_color_jitter_distribution,
+        color_jitter_distribution,
+        distribution: RandomDistribution
+    );
+    owned_variant!(
+        with_seed_point_jitter,
+        seed_point_jitter,
+        distribution: RandomDistribution
+    );
+    owned_variant!(
+        with_selection_noise,
+        selection_noise,
+        distribution: RandomDistribution
+    );
+    owned_variant!(with_blend, blend, mode: BlendMode, opacity: f32);
+    owned_variant!(with_hdr_intensity, hdr_intensity, intensity: f32);
+    owned_variant!(
+        with_animation_iter_per_second,
+        animation_iter_per_second,
+        iter_per_second: f64
+    );
+
+    pub fn with_palette<T>(mut self, palette: T) -> Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.palette(palette);
+        self
+    }
+
+    pub fn with_fallback_palette<T>(mut self, palette: T) -> Self
+    where
+        T: Palette + Sized + 'static,
+    {
+        self.fallback_palette(palette);
+        self
+    }
+
+    pub fn with_target_kernel<T>(mut self, kernel: T) -> Self
+    where
+        T: TargetColorKernel + 'static,
+    {
+        self.target_kernel(kernel);
+        self
+    }
+}
+
+// A standalone stage, to be added with GrowthImageBuilder::with_stage.
+impl Default for GrowthImageStageBuilder {
+    fn default() -> Self {
+        Self::new(0)
     }
 }
 
+enum AnimationTarget {
+    File(PathBuf),
+    Sink(Arc<Mutex<Box<dyn FrameSink>>>),
+}
+
 pub struct GrowthImageAnimationBuilder {
-    output_file: PathBuf,
+    target: AnimationTarget,
     fps: f64,
     layer: u8,
-    image_type: SaveImageType,
+    image_types: Vec<SaveImageType>,
+    composite: CompositeLayout,
+    chapter_file: Option<PathBuf>,
+    debug_overlay: Option<DebugOverlayHandle>,
+    background: Option<Background>,
+    start_at_iter: usize,
+    stop_at_iter: Option<usize>,
+    write_policy: Option<FrameWritePolicy>,
 }
 
+// Frames that may wait for an ffmpeg output's writer thread, by
+// default.
+const DEFAULT_FRAME_QUEUE: usize = 4;
+
 impl GrowthImageAnimationBuilder {
-    fn new(output_file: PathBuf) -> Self {
+    pub fn new(output_file: PathBuf) -> Self {
+        Self::with_target(AnimationTarget::File(output_file))
+    }
+
+    // Send frames to the sink, rather than to an ffmpeg process.
+    pub fn new_sink(sink: Box<dyn FrameSink>) -> Self {
+        Self::with_target(AnimationTarget::Sink(Arc::new(Mutex::new(sink))))
+    }
+
+    fn with_target(target: AnimationTarget) -> Self {
         Self {
-            output_file,
+            target,
             fps: 24.0,
             layer: 0,
-            image_type: SaveImageType::Generated,
+            image_types: vec![SaveImageType::Generated],
+            composite: CompositeLayout::SideBySide,
+            chapter_file: None,
+            debug_overlay: None,
+            background: None,
+            start_at_iter: 0,
+            stop_at_iter: None,
+            write_policy: None,
         }
     }
 
@@ -331,33 +2325,189 @@ impl GrowthImageAnimationBuilder {
     }
 
     pub fn image_type(&mut self, image_type: SaveImageType) -> &mut Self {
-        self.image_type = image_type;
+        self.image_types = vec![image_type];
+        self
+    }
+
+    // Show several image types in each frame, combined according to
+    // composite_layout().  This uses a single ffmpeg process, rather
+    // than one per image type.
+    pub fn image_types(
+        &mut self,
+        image_types: Vec<SaveImageType>,
+    ) -> &mut Self {
+        self.image_types = image_types;
         self
     }
 
+    pub fn composite_layout(
+        &mut self,
+        composite: CompositeLayout,
+    ) -> &mut Self {
+        self.composite = composite;
+        self
+    }
+
+    // Once the image is finished, write the first frame of each
+    // stage to a sidecar file, as chapters in the FFMETADATA format.
+    pub fn chapter_file(&mut self, filename: PathBuf) -> &mut Self {
+        self.chapter_file = Some(filename);
+        self
+    }
+
+    // Draw debug markers over each frame.  The handle may be kept to
+    // toggle the overlays while the image is being generated.  As a
+    // runtime aid, the overlay isn't included in the scene_spec().
+    pub fn debug_overlay(&mut self, overlay: DebugOverlayHandle) -> &mut Self {
+        self.debug_overlay = Some(overlay);
+        self
+    }
+
+    // Background of generated frames, in place of the image's
+    // background().  Since ffmpeg output has no alpha, a transparent
+    // background is shown as black.
+    pub fn background(&mut self, background: Background) -> &mut Self {
+        self.background = Some(background);
+        self
+    }
+
+    // Only write frames once this many pixels have been filled,
+    // counting every fill event of the image.  Together with
+    // stop_at_iter(), captures a window of the growth at a high frame
+    // rate, without encoding frames for the entire image.
+    pub fn start_at_iter(&mut self, iter: usize) -> &mut Self {
+        self.start_at_iter = iter;
+        self
+    }
+
+    // Stop writing frames once this many pixels have been filled.
+    pub fn stop_at_iter(&mut self, iter: usize) -> &mut Self {
+        self.stop_at_iter = Some(iter);
+        self
+    }
+
+    // How frames are handed to the output.  By default, frames for
+    // ffmpeg are written on a separate thread, with
+    // FrameWritePolicy::Block, while frames for a sink are written
+    // inline, so that a sink has every frame as soon as fill()
+    // returns.  As a runtime aid, the policy isn't included in the
+    // scene_spec().
+    pub fn write_policy(&mut self, policy: FrameWritePolicy) -> &mut Self {
+        self.write_policy = Some(policy);
+        self
+    }
+
+    owned_variant!(with_fps, fps, fps: f64);
+    owned_variant!(with_layer, layer, layer: u8);
+    owned_variant!(with_image_type, image_type, image_type: SaveImageType);
+    owned_variant!(
+        with_image_types,
+        image_types,
+        image_types: Vec<SaveImageType>
+    );
+    owned_variant!(
+        with_composite_layout,
+        composite_layout,
+        composite: CompositeLayout
+    );
+    owned_variant!(with_chapter_file, chapter_file, filename: PathBuf);
+    owned_variant!(
+        with_debug_overlay,
+        debug_overlay,
+        overlay: DebugOverlayHandle
+    );
+    owned_variant!(with_background, background, background: Background);
+    owned_variant!(with_start_at_iter, start_at_iter, iter: usize);
+    owned_variant!(with_stop_at_iter, stop_at_iter, iter: usize);
+    owned_variant!(
+        with_write_policy,
+        write_policy,
+        policy: FrameWritePolicy
+    );
+
+    fn scene_spec(&self) -> Result<AnimationSpec, Error> {
+        let filename = match &self.target {
+            AnimationTarget::File(filename) => filename.clone(),
+            AnimationTarget::Sink(_) => {
+                return Err(Error::UnsupportedSceneOption("output_sink"));
+            }
+        };
+        Ok(AnimationSpec {
+            filename,
+            fps: self.fps,
+            layer: self.layer,
+            image_types: self.image_types.clone(),
+            composite: self.composite,
+            chapter_file: self.chapter_file.clone(),
+            background: self.background,
+            start_at_iter: self.start_at_iter,
+            stop_at_iter: self.stop_at_iter,
+        })
+    }
+
     fn build(&self) -> Result<GrowthImageAnimation, Error> {
-        let proc = std::process::Command::new("ffmpeg")
-            .args(&["-f", "image2pipe", "-i", "-"])
-            .args(&["-hide_banner", "-loglevel", "error"])
-            .args(&["-framerate", &self.fps.to_string()])
-            .args(&["-vcodec", "libx264"])
-            .args(&["-pix_fmt", "yuv420p"])
-            // crf for libx264 is on scale from 0 to 51.  0 is lossless.
-            .args(&["-crf", "23"])
-            .args(&["-preset", "fast"])
-            .arg("-y")
-            .arg(&self.output_file)
-            // Images will be sent to ffmpeg by stdin
-            .stdin(std::process::Stdio::piped())
-            .spawn()?;
+        let (output, default_policy) = match &self.target {
+            AnimationTarget::File(output_file) => (
+                AnimationOutput::Ffmpeg(spawn_ffmpeg(output_file, self.fps)?),
+                FrameWritePolicy::Block(DEFAULT_FRAME_QUEUE),
+            ),
+            AnimationTarget::Sink(sink) => (
+                AnimationOutput::Sink(Arc::clone(sink)),
+                FrameWritePolicy::Inline,
+            ),
+        };
+        let output = match self.write_policy.unwrap_or(default_policy) {
+            FrameWritePolicy::Inline => output,
+            FrameWritePolicy::Block(capacity) => AnimationOutput::Threaded(
+                FrameWriter::spawn(output, capacity, false),
+            ),
+            FrameWritePolicy::DropFrames(capacity) => {
+                AnimationOutput::Threaded(FrameWriter::spawn(
+                    output, capacity, true,
+                ))
+            }
+        };
 
         Ok(GrowthImageAnimation {
-            proc,
+            output,
+            frames_written: 0,
+            frames_dropped: 0,
             fps: self.fps,
-            image_type: self.image_type,
+            image_types: self.image_types.clone(),
+            composite: self.composite,
             layer: self.layer,
             iter_per_frame: 0,
             iter_since_frame: 0,
+            chapters: Vec::new(),
+            chapter_file: self.chapter_file.clone(),
+            debug_overlay: self.debug_overlay.clone(),
+            background: self.background,
+            start_at_iter: self.start_at_iter,
+            stop_at_iter: self.stop_at_iter,
         })
     }
 }
+
+// Start an ffmpeg process that encodes PNG images written to its
+// stdin as an H.264 video.
+pub(crate) fn spawn_ffmpeg(
+    output_file: &Path,
+    fps: f64,
+) -> Result<std::process::Child, Error> {
+    let proc = std::process::Command::new("ffmpeg")
+        .args(&["-f", "image2pipe", "-i", "-"])
+        .args(&["-hide_banner", "-loglevel", "error"])
+        .args(&["-framerate", &fps.to_string()])
+        .args(&["-vcodec", "libx264"])
+        .args(&["-pix_fmt", "yuv420p"])
+        // crf for libx264 is on scale from 0 to 51.  0 is
+        // lossless.
+        .args(&["-crf", "23"])
+        .args(&["-preset", "fast"])
+        .arg("-y")
+        .arg(output_file)
+        // Images will be sent to ffmpeg by stdin
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+    Ok(proc)
+}
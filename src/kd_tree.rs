@@ -1,5 +1,52 @@
+use std::io::{Read, Write};
+
+use itertools::Itertools;
+
+use crate::errors::Error;
+
 const MAX_LEAF_SIZE: usize = 50;
 
+// Number of candidate split positions per dimension evaluated by
+// SplitStrategy::SurfaceArea.
+const SURFACE_AREA_CANDIDATES: usize = 8;
+
+// Maximum number of points used by SplitStrategy::SurfaceArea to
+// evaluate the candidate splits of each node.
+const SURFACE_AREA_SAMPLE_SIZE: usize = 1024;
+
+// How to choose the dimension and position at which each internal
+// node of a KDTree is split.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SplitStrategy {
+    // Cycle through the dimensions, splitting at the median.
+    CycleDimensions,
+    // Split the dimension with the largest range of values, at the
+    // median.  Better for palettes that are narrow along some axes,
+    // such as a SphericalPalette far from the center of the RGB cube.
+    MaxSpread,
+    // Split where the sum of each side's bounding box surface area,
+    // weighted by its number of points, is smallest.  Slowest to
+    // build.
+    SurfaceArea,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KDTreeOptions {
+    pub max_leaf_size: usize,
+    pub split_strategy: SplitStrategy,
+}
+
+impl Default for KDTreeOptions {
+    fn default() -> Self {
+        Self {
+            max_leaf_size: MAX_LEAF_SIZE,
+            split_strategy: SplitStrategy::CycleDimensions,
+        }
+    }
+}
+
 pub trait Point: Copy + std::fmt::Debug {
     type Dtype: PartialOrd + Copy + Into<f64> + std::fmt::Debug;
     const NUM_DIMENSIONS: u8;
@@ -12,7 +59,25 @@ pub trait Point: Copy + std::fmt::Debug {
     fn dist2(&self, other: &Self) -> f64;
 }
 
-#[derive(Debug)]
+// Points that can be saved as part of a KD-tree.  Each point and
+// each coordinate value should have a fixed-size binary encoding.
+pub trait SerializablePoint: Point {
+    fn write_point(&self, writer: &mut impl Write) -> std::io::Result<()>;
+    fn read_point(reader: &mut impl Read) -> std::io::Result<Self>;
+
+    fn write_val(
+        val: Self::Dtype,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()>;
+    fn read_val(reader: &mut impl Read) -> std::io::Result<Self::Dtype>;
+}
+
+// Identifies a file written by KDTree::serialize, followed by the
+// format version.
+const KDTREE_MAGIC: &[u8; 4] = b"OCKD";
+const KDTREE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
 enum NodeData<T: Point> {
     Internal {
         left: usize,
@@ -26,13 +91,14 @@ enum NodeData<T: Point> {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Node<T: Point> {
     num_points: u32,
     parent: Option<usize>,
     data: NodeData<T>,
 }
 
+#[derive(Clone)]
 pub struct KDTree<T: Point> {
     points: Vec<Option<T>>,
     nodes: Vec<Node<T>>,
@@ -62,8 +128,23 @@ impl Default for PerformanceStats {
     }
 }
 
+// Refers to a point found by peek_closest(), which may be removed
+// with commit_pop().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PopHandle {
+    point_index: usize,
+    leaf_node_index: usize,
+}
+
+impl PopHandle {
+    // Position of the point in the order given by iter_points().
+    pub fn point_index(&self) -> usize {
+        self.point_index
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
-pub struct KdtreeResult<T: Point> {
+pub struct KdtreeResult<T> {
     pub res: Option<T>,
     pub stats: PerformanceStats,
 }
@@ -72,20 +153,86 @@ impl<T> KDTree<T>
 where
     T: Point,
 {
-    pub fn new(mut points: Vec<T>) -> Self {
+    #[allow(dead_code)]
+    pub fn new(points: Vec<T>) -> Self {
+        Self::with_options(points, KDTreeOptions::default())
+    }
+
+    // Panics if any point has a NaN coordinate.  Use
+    // try_with_options for points that aren't known to be valid.
+    pub fn with_options(points: Vec<T>, options: KDTreeOptions) -> Self {
+        Self::try_with_options(points, options)
+            .expect("KDTree points may not have NaN coordinates")
+    }
+
+    #[allow(dead_code)]
+    pub fn try_new(points: Vec<T>) -> Result<Self, Error> {
+        Self::try_with_options(points, KDTreeOptions::default())
+    }
+
+    pub fn try_with_options(
+        mut points: Vec<T>,
+        options: KDTreeOptions,
+    ) -> Result<Self, Error> {
+        // NaN doesn't compare equal to itself, and would break the
+        // ordering used to split nodes.
+        let has_nan = points.iter().any(|p| {
+            (0..T::NUM_DIMENSIONS).any(|dim| {
+                let val = p.get_val(dim);
+                val.partial_cmp(&val).is_none()
+            })
+        });
+        if has_nan {
+            return Err(Error::NaNCoordinate);
+        }
+
         let mut nodes = Vec::new();
 
-        Self::generate_nodes(&mut nodes, &mut points, 0, 0, None);
+        Self::generate_nodes(&mut nodes, &mut points, 0, 0, None, &options);
 
         let points = points.iter().map(|p| Some(*p)).collect();
 
-        KDTree { points, nodes }
+        Ok(KDTree { points, nodes })
     }
 
     pub fn num_points(&self) -> usize {
         self.nodes[0].num_points as usize
     }
 
+    // Approximate number of bytes used by a tree of num_points
+    // points, without building it.  Assumes that each split is at the
+    // median, as with SplitStrategy::CycleDimensions.
+    pub(crate) fn estimate_memory(
+        num_points: usize,
+        options: &KDTreeOptions,
+    ) -> usize {
+        // Median splits give at most two distinct subtree sizes at each
+        // depth, so the nodes can be counted one depth at a time.
+        let max_leaf_size = options.max_leaf_size.max(1);
+        let mut num_nodes = 0;
+        let mut depth: Vec<(usize, usize)> = vec![(num_points, 1)];
+        while !depth.is_empty() {
+            num_nodes += depth.iter().map(|(_, count)| count).sum::<usize>();
+            let mut next_depth: Vec<(usize, usize)> = Vec::new();
+            depth
+                .iter()
+                .filter(|(size, _)| *size > max_leaf_size)
+                .flat_map(|&(size, count)| {
+                    vec![(size / 2, count), (size - size / 2, count)]
+                })
+                .for_each(|(size, count)| {
+                    match next_depth.iter_mut().find(|(s, _)| *s == size) {
+                        Some((_, total)) => *total += count,
+                        None => next_depth.push((size, count)),
+                    }
+                });
+            depth = next_depth;
+        }
+
+        num_points * std::mem::size_of::<Option<T>>()
+            + num_nodes * std::mem::size_of::<Node<T>>()
+    }
+
     pub fn iter_points(&self) -> impl Iterator<Item = &Option<T>> {
         self.points.iter()
     }
@@ -96,9 +243,9 @@ where
         point_index_offset: usize,
         dimension: u8,
         parent_index: Option<usize>,
+        options: &KDTreeOptions,
     ) {
-        // If few enough points, make a leaf node.
-        if points.len() <= MAX_LEAF_SIZE {
+        let make_leaf = |nodes: &mut Vec<Node<T>>| {
             let node = Node {
                 num_points: points.len() as u32,
                 parent: parent_index,
@@ -108,13 +255,41 @@ where
                 },
             };
             nodes.push(node);
+        };
+
+        // If few enough points, make a leaf node.
+        if points.len() <= options.max_leaf_size.max(1) {
+            make_leaf(nodes);
             return;
         }
 
-        let median_point_index = points.len() / 2;
+        let (dimension, median_point_index) = match options.split_strategy {
+            SplitStrategy::CycleDimensions => (dimension, points.len() / 2),
+            SplitStrategy::MaxSpread => {
+                (Self::max_spread_dimension(points), points.len() / 2)
+            }
+            SplitStrategy::SurfaceArea => Self::surface_area_split(points),
+        };
+
+        // Splitting along a dimension in which every point has the
+        // same value doesn't separate anything, and searches would
+        // need to check both sides.  Instead, use the next dimension
+        // that does have a spread.  If the points are all identical,
+        // no split can help, so they all go in one leaf.
+        let dimension = match (0..T::NUM_DIMENSIONS)
+            .map(|offset| (dimension + offset) % T::NUM_DIMENSIONS)
+            .find(|&dim| Self::has_spread(points, dim))
+        {
+            Some(dim) => dim,
+            None => {
+                make_leaf(nodes);
+                return;
+            }
+        };
+
         // Can't use select_nth_unstable_by_key because that requires
-        // Ord, which f32/f64 don't implement.  The .unwrap() could
-        // panic if passed NaN values.
+        // Ord, which f32/f64 don't implement.  The .unwrap() can't
+        // panic, since NaN values are rejected on construction.
         points.select_nth_unstable_by(median_point_index, |a, b| {
             a.get_val(dimension)
                 .partial_cmp(&b.get_val(dimension))
@@ -144,6 +319,7 @@ where
             point_index_offset,
             next_dimension,
             Some(this_node_index),
+            options,
         );
 
         // Now, the index of the right subtree is known and can be
@@ -162,9 +338,110 @@ where
             point_index_offset + median_point_index,
             next_dimension,
             Some(this_node_index),
+            options,
         );
     }
 
+    fn has_spread(points: &[T], dim: u8) -> bool {
+        let first = points[0].get_val(dim);
+        points.iter().any(|p| p.get_val(dim) != first)
+    }
+
+    fn max_spread_dimension(points: &[T]) -> u8 {
+        (0..T::NUM_DIMENSIONS)
+            .map(|dim| {
+                let (min, max) = points.iter().fold(
+                    (f64::INFINITY, f64::NEG_INFINITY),
+                    |(min, max), p| {
+                        let val: f64 = p.get_val(dim).into();
+                        (min.min(val), max.max(val))
+                    },
+                );
+                (dim, max - min)
+            })
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(dim, _)| dim)
+            .unwrap()
+    }
+
+    // Dimension and index at which to split.  Each side's cost is the
+    // surface area of its bounding box, multiplied by its number of
+    // points.  Candidate splits are evaluated on an evenly spaced
+    // sample of the points, to keep construction time reasonable.
+    fn surface_area_split(points: &[T]) -> (u8, usize) {
+        let n = points.len();
+        let ndim = T::NUM_DIMENSIONS as usize;
+        let box_cost = |extents: &[f64]| {
+            if ndim == 1 {
+                return extents[0];
+            }
+            (0..ndim)
+                .flat_map(|i| ((i + 1)..ndim).map(move |j| (i, j)))
+                .map(|(i, j)| extents[i] * extents[j])
+                .sum::<f64>()
+        };
+
+        // Bounding box extents of the first `count` points, for each
+        // of the counts in increasing order.
+        let extents_at = |points: &mut dyn Iterator<Item = &T>,
+                          counts: &[usize]| {
+            let mut bounds = vec![(f64::INFINITY, f64::NEG_INFINITY); ndim];
+            let mut output = Vec::new();
+            points.enumerate().for_each(|(i, p)| {
+                bounds.iter_mut().enumerate().for_each(|(dim, (min, max))| {
+                    let val: f64 = p.get_val(dim as u8).into();
+                    *min = min.min(val);
+                    *max = max.max(val);
+                });
+                if counts.contains(&(i + 1)) {
+                    output.push(
+                        bounds
+                            .iter()
+                            .map(|(min, max)| max - min)
+                            .collect::<Vec<_>>(),
+                    );
+                }
+            });
+            output
+        };
+
+        let mut sample = points
+            .iter()
+            .step_by((n / SURFACE_AREA_SAMPLE_SIZE).max(1))
+            .copied()
+            .collect::<Vec<_>>();
+        let m = sample.len();
+        let splits = (1..SURFACE_AREA_CANDIDATES)
+            .map(|k| (k * m / SURFACE_AREA_CANDIDATES).clamp(1, m - 1))
+            .dedup()
+            .collect::<Vec<_>>();
+        let left_counts = splits.clone();
+        let right_counts = splits.iter().rev().map(|s| m - s).collect_vec();
+
+        let mut best = (f64::INFINITY, 0, m / 2);
+        (0..T::NUM_DIMENSIONS).for_each(|dim| {
+            sample.sort_unstable_by(|a, b| {
+                a.get_val(dim).partial_cmp(&b.get_val(dim)).unwrap()
+            });
+            let left = extents_at(&mut sample.iter(), &left_counts);
+            let mut right = extents_at(&mut sample.iter().rev(), &right_counts);
+            right.reverse();
+
+            splits.iter().zip(left.iter().zip(right.iter())).for_each(
+                |(&split, (left, right))| {
+                    let cost = box_cost(left) * (split as f64)
+                        + box_cost(right) * ((m - split) as f64);
+                    if cost < best.0 {
+                        best = (cost, dim, split);
+                    }
+                },
+            );
+        });
+
+        let split = (best.2 * n / m).clamp(1, n - 1);
+        (best.1, split)
+    }
+
     #[allow(dead_code)]
     pub fn get_closest(&self, target: &T, epsilon: f64) -> KdtreeResult<T> {
         let mut stats = PerformanceStats::default();
@@ -175,29 +452,60 @@ where
         KdtreeResult { res, stats }
     }
 
-    pub fn pop_closest(&mut self, target: &T, epsilon: f64) -> KdtreeResult<T> {
+    // Find the closest point without removing it.  The returned
+    // handle can be passed to commit_pop() to remove the point later,
+    // allowing several candidates to be evaluated before committing
+    // to any of them.
+    pub fn peek_closest(
+        &self,
+        target: &T,
+        epsilon: f64,
+    ) -> KdtreeResult<(T, PopHandle)> {
         let mut stats = PerformanceStats::default();
-        let res = self.get_closest_node(target, 0, &mut stats, epsilon);
-
-        let res = match res {
-            None => None,
-            Some(res) => {
-                let output = self.points[res.point_index];
-
-                self.points[res.point_index] = None;
-                let mut node_index = Some(res.leaf_node_index);
-                while node_index != None {
-                    let node = &mut self.nodes[node_index.unwrap()];
-                    node.num_points -= 1;
-                    node_index = node.parent;
-                }
-
-                output
-            }
-        };
+        let res = self
+            .get_closest_node(target, 0, &mut stats, epsilon)
+            .and_then(|res| {
+                self.points[res.point_index].map(|p| {
+                    (
+                        p,
+                        PopHandle {
+                            point_index: res.point_index,
+                            leaf_node_index: res.leaf_node_index,
+                        },
+                    )
+                })
+            });
         KdtreeResult { res, stats }
     }
 
+    // Remove the point referred to by a handle from peek_closest().
+    // Returns None if the point has already been removed, such as by
+    // committing another handle to the same point.
+    pub fn commit_pop(&mut self, handle: PopHandle) -> Option<T> {
+        let output = self.points[handle.point_index].take()?;
+
+        let mut node_index = Some(handle.leaf_node_index);
+        while let Some(index) = node_index {
+            let node = &mut self.nodes[index];
+            node.num_points -= 1;
+            node_index = node.parent;
+        }
+
+        Some(output)
+    }
+
+    #[allow(dead_code)]
+    pub fn pop_closest(&mut self, target: &T, epsilon: f64) -> KdtreeResult<T> {
+        let peek = self.peek_closest(target, epsilon);
+        let res = peek
+            .res
+            .and_then(|(_point, handle)| self.commit_pop(handle));
+        KdtreeResult {
+            res,
+            stats: peek.stats,
+        }
+    }
+
     fn get_closest_node(
         &self,
         target: &T,
@@ -282,6 +590,159 @@ where
     }
 }
 
+impl<T> KDTree<T>
+where
+    T: SerializablePoint,
+{
+    // Write the tree, including which points have been removed, so
+    // that it can be loaded without rebuilding.  All integers are
+    // little-endian.
+    pub fn serialize(&self, mut writer: impl Write) -> Result<(), Error> {
+        let writer = &mut writer;
+        writer.write_all(KDTREE_MAGIC)?;
+        writer.write_all(&KDTREE_FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.points.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.nodes.len() as u64).to_le_bytes())?;
+
+        for point in &self.points {
+            match point {
+                Some(point) => {
+                    writer.write_all(&[1])?;
+                    point.write_point(writer)?;
+                }
+                None => writer.write_all(&[0])?,
+            }
+        }
+
+        for node in &self.nodes {
+            writer.write_all(&node.num_points.to_le_bytes())?;
+            let parent = node.parent.map_or(u64::MAX, |p| p as u64);
+            writer.write_all(&parent.to_le_bytes())?;
+            match &node.data {
+                NodeData::Internal {
+                    left,
+                    right,
+                    dimension,
+                    median_val,
+                } => {
+                    writer.write_all(&[0])?;
+                    writer.write_all(&(*left as u64).to_le_bytes())?;
+                    writer.write_all(&(*right as u64).to_le_bytes())?;
+                    writer.write_all(&[*dimension])?;
+                    T::write_val(*median_val, writer)?;
+                }
+                NodeData::Leaf { i_initial, i_final } => {
+                    writer.write_all(&[1])?;
+                    writer.write_all(&(*i_initial as u64).to_le_bytes())?;
+                    writer.write_all(&(*i_final as u64).to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Read a tree written by serialize().  Gives InvalidKDTreeFile if
+    // the file isn't a KD-tree, or if any of its indices are out of
+    // range.
+    pub fn deserialize(mut reader: impl Read) -> Result<Self, Error> {
+        let reader = &mut reader;
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != KDTREE_MAGIC || read_u32(reader)? != KDTREE_FORMAT_VERSION
+        {
+            return Err(Error::InvalidKDTreeFile);
+        }
+        let num_points = read_u64(reader)? as usize;
+        let num_nodes = read_u64(reader)? as usize;
+        if num_nodes == 0 {
+            return Err(Error::InvalidKDTreeFile);
+        }
+
+        let points = (0..num_points)
+            .map(|_| match read_u8(reader)? {
+                0 => Ok(None),
+                1 => Ok(Some(T::read_point(reader)?)),
+                _ => Err(Error::InvalidKDTreeFile),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let read_index = |reader: &mut _, max: usize| {
+            let index = read_u64(reader)? as usize;
+            if index < max {
+                Ok(index)
+            } else {
+                Err(Error::InvalidKDTreeFile)
+            }
+        };
+
+        let nodes = (0..num_nodes)
+            .map(|_| {
+                let node_points = read_u32(reader)?;
+                let parent = match read_u64(reader)? {
+                    u64::MAX => None,
+                    parent if (parent as usize) < num_nodes => {
+                        Some(parent as usize)
+                    }
+                    _ => return Err(Error::InvalidKDTreeFile),
+                };
+                let data = match read_u8(reader)? {
+                    0 => {
+                        let left = read_index(reader, num_nodes)?;
+                        let right = read_index(reader, num_nodes)?;
+                        let dimension = read_u8(reader)?;
+                        if dimension >= T::NUM_DIMENSIONS {
+                            return Err(Error::InvalidKDTreeFile);
+                        }
+                        let median_val = T::read_val(reader)?;
+                        NodeData::Internal {
+                            left,
+                            right,
+                            dimension,
+                            median_val,
+                        }
+                    }
+                    1 => {
+                        let i_initial = read_index(reader, points.len() + 1)?;
+                        let i_final = read_index(reader, points.len() + 1)?;
+                        if i_initial > i_final {
+                            return Err(Error::InvalidKDTreeFile);
+                        }
+                        NodeData::Leaf { i_initial, i_final }
+                    }
+                    _ => return Err(Error::InvalidKDTreeFile),
+                };
+                Ok(Node {
+                    num_points: node_points,
+                    parent,
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(KDTree { points, nodes })
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> std::io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -308,6 +769,30 @@ mod test {
         }
     }
 
+    impl SerializablePoint for TestPoint {
+        fn write_point(&self, writer: &mut impl Write) -> std::io::Result<()> {
+            Self::write_val(self.x, writer)?;
+            Self::write_val(self.y, writer)
+        }
+
+        fn read_point(reader: &mut impl Read) -> std::io::Result<Self> {
+            Ok(TestPoint {
+                x: Self::read_val(reader)?,
+                y: Self::read_val(reader)?,
+            })
+        }
+
+        fn write_val(val: f32, writer: &mut impl Write) -> std::io::Result<()> {
+            writer.write_all(&val.to_le_bytes())
+        }
+
+        fn read_val(reader: &mut impl Read) -> std::io::Result<f32> {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(f32::from_le_bytes(buf))
+        }
+    }
+
     #[test]
     fn test_make_kdtree() {
         let points = vec![
@@ -444,6 +929,39 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_peek_and_commit() {
+        let points = (0..10000)
+            .map(|i| TestPoint {
+                x: (i / 100) as f32,
+                y: (i % 100) as f32,
+            })
+            .collect::<Vec<_>>();
+        let mut tree = KDTree::new(points);
+
+        let target = TestPoint { x: 1.2, y: 1.2 };
+
+        // Peeking doesn't change the tree.
+        let (first, first_handle) =
+            tree.peek_closest(&target, 0.0).res.unwrap();
+        let (second, second_handle) =
+            tree.peek_closest(&target, 0.0).res.unwrap();
+        assert_eq!(first, TestPoint { x: 1.0, y: 1.0 });
+        assert_eq!(first, second);
+        assert_eq!(first_handle, second_handle);
+        assert_eq!(tree.num_points(), 10000);
+
+        // Committing removes the point, and stale handles are
+        // rejected.
+        assert_eq!(tree.commit_pop(first_handle), Some(first));
+        assert_eq!(tree.num_points(), 9999);
+        assert_eq!(tree.commit_pop(second_handle), None);
+        assert_eq!(tree.num_points(), 9999);
+
+        let (next, _) = tree.peek_closest(&target, 0.0).res.unwrap();
+        assert_ne!(next, first);
+    }
+
     #[test]
     fn test_epsilon() {
         // This test relies on too many implementation details, maybe
@@ -467,4 +985,187 @@ mod test {
         assert_eq!(res.res, Some(TestPoint { x: -1.0, y: -2.0 }));
         assert_eq!(res.stats.leaf_nodes_checked, 1);
     }
+
+    #[test]
+    fn test_serialize() -> Result<(), Error> {
+        let points = (0..10000)
+            .map(|i| TestPoint {
+                x: (i / 100) as f32,
+                y: (i % 100) as f32,
+            })
+            .collect::<Vec<_>>();
+        let mut tree = KDTree::new(points);
+        let target = TestPoint { x: 1.2, y: 1.2 };
+        tree.pop_closest(&target, 0.0);
+
+        let mut buffer = Vec::new();
+        tree.serialize(&mut buffer)?;
+        let mut loaded = KDTree::<TestPoint>::deserialize(buffer.as_slice())?;
+
+        // Removed points stay removed, and searches give the same
+        // results.
+        assert_eq!(loaded.num_points(), 9999);
+        assert_eq!(loaded.nodes.len(), tree.nodes.len());
+        (0..10).for_each(|_| {
+            assert_eq!(
+                loaded.pop_closest(&target, 0.0).res,
+                tree.pop_closest(&target, 0.0).res
+            );
+        });
+
+        // Truncated or unrecognized files are rejected.
+        assert!(KDTree::<TestPoint>::deserialize(&buffer[..100]).is_err());
+        buffer[0] = b'X';
+        assert!(matches!(
+            KDTree::<TestPoint>::deserialize(buffer.as_slice()),
+            Err(Error::InvalidKDTreeFile)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_strategies() {
+        let points = (0..5000)
+            .map(|i| TestPoint {
+                x: ((i * 37) % 101) as f32,
+                y: 0.1 * ((i * 53) % 97) as f32,
+            })
+            .collect::<Vec<_>>();
+        let targets = (0..50)
+            .map(|i| TestPoint {
+                x: 2.1 * (i as f32),
+                y: 0.2 * (i as f32),
+            })
+            .collect::<Vec<_>>();
+
+        let strategies = vec![
+            SplitStrategy::CycleDimensions,
+            SplitStrategy::MaxSpread,
+            SplitStrategy::SurfaceArea,
+        ];
+        strategies.into_iter().for_each(|split_strategy| {
+            let options = KDTreeOptions {
+                max_leaf_size: 7,
+                split_strategy,
+            };
+            let tree = KDTree::with_options(points.clone(), options);
+            assert_eq!(tree.num_points(), points.len());
+
+            targets.iter().for_each(|target| {
+                let expected = points
+                    .iter()
+                    .map(|p| p.dist2(target))
+                    .fold(f64::INFINITY, f64::min);
+                let found = tree.get_closest(target, 0.0).res.unwrap();
+                assert_eq!(found.dist2(target), expected);
+            });
+        });
+    }
+
+    #[test]
+    fn test_nan_rejected() {
+        let points = vec![
+            TestPoint { x: 0.0, y: 1.0 },
+            TestPoint {
+                x: 1.0,
+                y: f32::NAN,
+            },
+        ];
+        assert!(matches!(KDTree::try_new(points), Err(Error::NaNCoordinate)));
+    }
+
+    #[test]
+    fn test_duplicate_points() {
+        let brute_force = |points: &[TestPoint], target: &TestPoint| {
+            points
+                .iter()
+                .map(|p| p.dist2(target))
+                .fold(f64::INFINITY, f64::min)
+        };
+
+        // All identical, all identical along one dimension, and
+        // mostly identical with a few outliers.
+        let n = 20 * MAX_LEAF_SIZE;
+        let cases = vec![
+            (0..n).map(|_| TestPoint { x: 1.0, y: 2.0 }).collect_vec(),
+            (0..n)
+                .map(|i| TestPoint {
+                    x: 3.0,
+                    y: (i % 7) as f32,
+                })
+                .collect_vec(),
+            (0..n)
+                .map(|i| TestPoint {
+                    x: if i % 97 == 0 { i as f32 } else { 0.0 },
+                    y: 0.0,
+                })
+                .collect_vec(),
+        ];
+        let strategies = vec![
+            SplitStrategy::CycleDimensions,
+            SplitStrategy::MaxSpread,
+            SplitStrategy::SurfaceArea,
+        ];
+
+        cases.iter().for_each(|points| {
+            strategies.iter().for_each(|&split_strategy| {
+                let options = KDTreeOptions {
+                    split_strategy,
+                    ..Default::default()
+                };
+                let mut tree =
+                    KDTree::try_with_options(points.clone(), options).unwrap();
+
+                // No node splits along x when all points share the
+                // same x value.
+                if points.iter().all(|p| p.x == points[0].x) {
+                    assert!(tree.nodes.iter().all(|node| !matches!(
+                        node.data,
+                        NodeData::Internal { dimension: 0, .. }
+                    )));
+                }
+
+                let mut remaining = points.clone();
+                let target = TestPoint { x: 2.5, y: 1.5 };
+                while let Some(p) = tree.pop_closest(&target, 0.0).res {
+                    assert_eq!(
+                        p.dist2(&target),
+                        brute_force(&remaining, &target)
+                    );
+                    let index = remaining.iter().position(|&q| q == p).unwrap();
+                    remaining.swap_remove(index);
+                }
+                assert!(remaining.is_empty());
+            });
+        });
+
+        // Identical points are kept in a single leaf.
+        let tree = KDTree::new(cases[0].clone());
+        assert_eq!(tree.nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_estimate_memory() {
+        let options = KDTreeOptions {
+            max_leaf_size: 4,
+            ..Default::default()
+        };
+        [0, 1, 4, 5, 100, 1001].iter().for_each(|&n| {
+            let points = (0..n)
+                .map(|i| TestPoint {
+                    x: (i * 37 % 101) as f32,
+                    y: (i * 53 % 103) as f32,
+                })
+                .collect_vec();
+            let tree = KDTree::with_options(points, options);
+            let actual = tree.points.len()
+                * std::mem::size_of::<Option<TestPoint>>()
+                + tree.nodes.len() * std::mem::size_of::<Node<TestPoint>>();
+            assert_eq!(
+                KDTree::<TestPoint>::estimate_memory(n, &options),
+                actual
+            );
+        });
+    }
 }
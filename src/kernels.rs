@@ -0,0 +1,641 @@
+use std::path::Path;
+
+use crate::color::RGB;
+use crate::errors::Error;
+use crate::growth_image::SaveImageData;
+use crate::topology::{PixelLoc, Topology};
+
+// Read-only view of the pixels filled so far, passed to a
+// TargetColorKernel.
+pub struct FilledPixels<'a> {
+    pub(crate) topology: &'a Topology,
+    pub(crate) pixels: &'a [Option<RGB>],
+    pub(crate) fill_order: &'a [Option<usize>],
+    pub(crate) linear_light: bool,
+    // Mixed into random_value(), so that it depends on the image's
+    // seed and on the number of pixels filled so far.
+    pub(crate) random_seed: u64,
+    pub(crate) num_fill_events: usize,
+    // Number of frames written to the first output animation.
+    pub(crate) animation_frame: usize,
+}
+
+// SplitMix64 finalizer.  Used rather than std's hasher, whose output
+// may change between Rust versions.
+fn mix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl<'a> FilledPixels<'a> {
+    pub fn get(&self, loc: PixelLoc) -> Option<RGB> {
+        self.topology
+            .get_index(loc)
+            .and_then(|index| self.pixels[index])
+    }
+
+    // Relative order in which the pixel was filled.  Larger values
+    // were filled more recently.
+    pub fn fill_order(&self, loc: PixelLoc) -> Option<usize> {
+        self.topology
+            .get_index(loc)
+            .and_then(|index| self.fill_order[index])
+    }
+
+    // All pixels adjacent to the location, including those reached
+    // through portals.
+    pub fn adjacent(
+        &self,
+        loc: PixelLoc,
+    ) -> impl Iterator<Item = PixelLoc> + '_ {
+        self.topology.iter_adjacent(loc)
+    }
+
+    // Pseudo-random value for the location.  Repeated calls give the
+    // same value until another pixel is filled, so that kernels can
+    // make random choices without a random number generator, and
+    // still be reproducible for a given seed.
+    pub fn random_value(&self, loc: PixelLoc) -> u64 {
+        let loc_bits = ((loc.layer as u64) << 56)
+            ^ ((loc.i as u32 as u64) << 28)
+            ^ (loc.j as u32 as u64);
+        mix64(
+            self.random_seed
+                ^ mix64(loc_bits ^ mix64(self.num_fill_events as u64)),
+        )
+    }
+
+    // Number of pixels filled so far, including any later cleared.
+    pub fn num_fill_events(&self) -> usize {
+        self.num_fill_events
+    }
+
+    // Number of frames written so far to the image's first output
+    // animation, or 0 if it has none.
+    pub fn animation_frame(&self) -> usize {
+        self.animation_frame
+    }
+
+    pub fn layer_size(&self, layer: u8) -> Option<(u32, u32)> {
+        self.topology
+            .layers()
+            .get(layer as usize)
+            .map(|size| (size.width, size.height))
+    }
+
+    pub fn adjacent_colors(&self, loc: PixelLoc) -> Vec<RGB> {
+        self.topology
+            .iter_adjacent_indices(loc)
+            .flat_map(|index| self.pixels[index])
+            .collect()
+    }
+
+    // Weighted mean of the colors.  If the image averages in linear
+    // light, the colors are converted from sRGB before averaging and
+    // back afterward.  Otherwise, the sRGB values are averaged
+    // directly, which darkens blends of dissimilar colors.
+    pub fn mean_color(
+        &self,
+        colors: impl Iterator<Item = (RGB, f64)>,
+    ) -> Option<RGB> {
+        let linear_light = self.linear_light;
+        let (weight, sums) = colors.fold(
+            (0.0, [0.0; 3]),
+            |(weight, mut sums), (color, color_weight)| {
+                let vals = if linear_light {
+                    let [r, g, b] = color.to_linear();
+                    [r as f64, g as f64, b as f64]
+                } else {
+                    [color.r() as f64, color.g() as f64, color.b() as f64]
+                };
+                sums.iter_mut()
+                    .zip(vals.iter())
+                    .for_each(|(sum, &val)| *sum += color_weight * val);
+                (weight + color_weight, sums)
+            },
+        );
+
+        if weight <= 0.0 {
+            None
+        } else if linear_light {
+            Some(RGB::from_linear([
+                (sums[0] / weight) as f32,
+                (sums[1] / weight) as f32,
+                (sums[2] / weight) as f32,
+            ]))
+        } else {
+            Some(RGB {
+                vals: [
+                    (sums[0] / weight) as u8,
+                    (sums[1] / weight) as u8,
+                    (sums[2] / weight) as u8,
+                ],
+            })
+        }
+    }
+}
+
+// Determines the color that a pixel should try to match, based on the
+// pixels that have already been filled.  Returning None indicates
+// that no target could be determined, and a random color is used
+// instead.
+pub trait TargetColorKernel: Send + Sync {
+    fn target_color(&self, filled: &FilledPixels, loc: PixelLoc)
+        -> Option<RGB>;
+}
+
+// Mean of the adjacent filled pixels.  This is the default.
+#[derive(Debug, Copy, Clone)]
+pub struct AdjacentMean;
+
+impl TargetColorKernel for AdjacentMean {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        filled.mean_color(
+            filled
+                .adjacent_colors(loc)
+                .into_iter()
+                .map(|color| (color, 1.0)),
+        )
+    }
+}
+
+// Per-channel median of the adjacent filled pixels.  Less sensitive
+// to a single outlier, so colors bleed less across thin walls.
+#[derive(Debug, Copy, Clone)]
+pub struct AdjacentMedian;
+
+impl TargetColorKernel for AdjacentMedian {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        let colors = filled.adjacent_colors(loc);
+        if colors.is_empty() {
+            return None;
+        }
+
+        let mut vals = [0u8; 3];
+        vals.iter_mut().enumerate().for_each(|(channel, val)| {
+            let mut channel_vals = colors
+                .iter()
+                .map(|color| color.vals[channel])
+                .collect::<Vec<_>>();
+            channel_vals.sort_unstable();
+            *val = channel_vals[channel_vals.len() / 2];
+        });
+
+        Some(RGB { vals })
+    }
+}
+
+// Gaussian-weighted mean of all filled pixels within the radius, in
+// the same layer.  Gives smoother gradients than the adjacent pixels
+// alone, at the cost of a slower fill.
+#[derive(Debug, Copy, Clone)]
+pub struct GaussianMean {
+    pub radius: f64,
+}
+
+impl TargetColorKernel for GaussianMean {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        let range = self.radius.ceil() as i32;
+        let radius2 = self.radius * self.radius;
+        let sigma2 = (radius2 / 4.0).max(f64::EPSILON);
+
+        let colors = (-range..=range)
+            .flat_map(|dj| (-range..=range).map(move |di| (di, dj)))
+            .filter_map(|(di, dj)| {
+                let dist2 = (di * di + dj * dj) as f64;
+                if dist2 > radius2 {
+                    return None;
+                }
+                let color = filled.get(PixelLoc {
+                    layer: loc.layer,
+                    i: loc.i + di,
+                    j: loc.j + dj,
+                })?;
+                Some((color, (-dist2 / (2.0 * sigma2)).exp()))
+            });
+
+        filled.mean_color(colors)
+    }
+}
+
+// The most recently filled adjacent pixel.  Produces long streaks of
+// similar color that follow the order of growth.
+#[derive(Debug, Copy, Clone)]
+pub struct MostRecentNeighbor;
+
+impl TargetColorKernel for MostRecentNeighbor {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        filled
+            .adjacent(loc)
+            .filter_map(|adj| Some((filled.fill_order(adj)?, filled.get(adj)?)))
+            .max_by_key(|&(order, _)| order)
+            .map(|(_, color)| color)
+    }
+}
+
+// One of the adjacent filled pixels, chosen at random.  Averaging
+// several neighbors pulls the target toward gray, while copying a
+// single neighbor keeps more of the variation along the growth
+// front.
+#[derive(Debug, Copy, Clone)]
+pub struct RandomNeighbor;
+
+impl TargetColorKernel for RandomNeighbor {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        let colors = filled.adjacent_colors(loc);
+        if colors.is_empty() {
+            return None;
+        }
+        let index = filled.random_value(loc) % (colors.len() as u64);
+        Some(colors[index as usize])
+    }
+}
+
+// Target colors taken from a reference photo, so that the growth
+// reproduces the photo while still using each palette color once.
+// The weight blends between the photo (1.0) and the mean of the
+// adjacent pixels (0.0).  Reference images are scaled to the size of
+// their layer.  Layers without a reference image use the adjacent
+// pixels alone.
+pub struct PhotoReference {
+    layers: Vec<Option<SaveImageData>>,
+    weight: f64,
+}
+
+impl PhotoReference {
+    pub fn new(weight: f64) -> Self {
+        Self {
+            layers: Vec::new(),
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn load_layer(
+        &mut self,
+        layer: u8,
+        filename: impl AsRef<Path>,
+    ) -> Result<&mut Self, Error> {
+        let image = SaveImageData::read_png(filename.as_ref())?;
+        let layer = layer as usize;
+        if self.layers.len() <= layer {
+            self.layers.resize_with(layer + 1, || None);
+        }
+        self.layers[layer] = Some(image);
+        Ok(self)
+    }
+
+    pub fn with_layer(
+        mut self,
+        layer: u8,
+        filename: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        self.load_layer(layer, filename)?;
+        Ok(self)
+    }
+
+    fn photo_color(&self, filled: &FilledPixels, loc: PixelLoc) -> Option<RGB> {
+        let image = self.layers.get(loc.layer as usize)?.as_ref()?;
+        sample_image(image, filled, loc)
+    }
+}
+
+impl TargetColorKernel for PhotoReference {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        let photo = self.photo_color(filled, loc);
+        blend_with_adjacent(filled, loc, photo, self.weight)
+    }
+}
+
+// Color of the image at the location, with nearest-neighbor scaling
+// from the layer to the image.
+fn sample_image(
+    image: &SaveImageData,
+    filled: &FilledPixels,
+    loc: PixelLoc,
+) -> Option<RGB> {
+    let (width, height) = filled.layer_size(loc.layer)?;
+    if loc.i < 0 || loc.j < 0 || width == 0 || height == 0 {
+        return None;
+    }
+
+    let x = (loc.i as u64) * (image.width as u64) / (width as u64);
+    let y = (loc.j as u64) * (image.height as u64) / (height as u64);
+    if x >= image.width as u64 || y >= image.height as u64 {
+        return None;
+    }
+    let index = (4 * (y * (image.width as u64) + x)) as usize;
+    let p = &image.data[index..index + 4];
+    Some(RGB {
+        vals: [p[0], p[1], p[2]],
+    })
+}
+
+// Weighted mean of the photo color and the mean of the adjacent
+// pixels, or whichever of the two is available.
+fn blend_with_adjacent(
+    filled: &FilledPixels,
+    loc: PixelLoc,
+    photo: Option<RGB>,
+    weight: f64,
+) -> Option<RGB> {
+    let adjacent = AdjacentMean.target_color(filled, loc);
+    match (photo, adjacent) {
+        (Some(photo), Some(adjacent)) => filled.mean_color(
+            vec![(photo, weight), (adjacent, 1.0 - weight)].into_iter(),
+        ),
+        (photo, adjacent) => photo.or(adjacent),
+    }
+}
+
+// When a PhotoSequence moves on to its next reference frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameTiming {
+    // After the given number of pixels are filled.
+    FillEvents(usize),
+
+    // After the given number of frames are written to the image's
+    // first output animation, so that the reference moves in step
+    // with the video being made.
+    AnimationFrames(usize),
+}
+
+// Target colors taken from a sequence of reference frames, such as
+// the frames of a video, so that the growth follows a moving source.
+// Otherwise the same as PhotoReference, for a single layer.  After
+// the last frame, the sequence either holds on the last frame or
+// loops back to the first.
+pub struct PhotoSequence {
+    layer: u8,
+    frames: Vec<SaveImageData>,
+    weight: f64,
+    timing: FrameTiming,
+    looping: bool,
+}
+
+impl PhotoSequence {
+    pub fn new(layer: u8, weight: f64, timing: FrameTiming) -> Self {
+        Self {
+            layer,
+            frames: Vec::new(),
+            weight: weight.clamp(0.0, 1.0),
+            timing,
+            looping: false,
+        }
+    }
+
+    pub fn looping(&mut self, looping: bool) -> &mut Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping(looping);
+        self
+    }
+
+    // Add a frame to the end of the sequence.
+    pub fn load_frame(
+        &mut self,
+        filename: impl AsRef<Path>,
+    ) -> Result<&mut Self, Error> {
+        self.frames
+            .push(SaveImageData::read_png(filename.as_ref())?);
+        Ok(self)
+    }
+
+    // Add each PNG file in the directory, in order of their names.
+    pub fn load_dir(
+        &mut self,
+        dir: impl AsRef<Path>,
+    ) -> Result<&mut Self, Error> {
+        let mut filenames = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        filenames.retain(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        });
+        filenames.sort();
+        for filename in filenames.iter() {
+            self.load_frame(filename)?;
+        }
+        Ok(self)
+    }
+
+    pub fn with_dir(mut self, dir: impl AsRef<Path>) -> Result<Self, Error> {
+        self.load_dir(dir)?;
+        Ok(self)
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn current_frame(&self, filled: &FilledPixels) -> Option<&SaveImageData> {
+        let step = match self.timing {
+            FrameTiming::FillEvents(n) => filled.num_fill_events / n.max(1),
+            FrameTiming::AnimationFrames(n) => {
+                filled.animation_frame / n.max(1)
+            }
+        };
+        let num_frames = self.frames.len();
+        if num_frames == 0 {
+            None
+        } else if self.looping {
+            self.frames.get(step % num_frames)
+        } else {
+            self.frames.get(step.min(num_frames - 1))
+        }
+    }
+}
+
+impl TargetColorKernel for PhotoSequence {
+    fn target_color(
+        &self,
+        filled: &FilledPixels,
+        loc: PixelLoc,
+    ) -> Option<RGB> {
+        let photo = if loc.layer == self.layer {
+            self.current_frame(filled)
+                .and_then(|image| sample_image(image, filled, loc))
+        } else {
+            None
+        };
+        blend_with_adjacent(filled, loc, photo, self.weight)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::topology::RectangularArray;
+
+    #[test]
+    fn test_kernels() {
+        let topology = Topology::new(
+            vec![RectangularArray {
+                width: 3,
+                height: 1,
+            }],
+            Arc::new(HashMap::new()),
+        );
+        let pixels = vec![
+            Some(RGB { vals: [0, 0, 0] }),
+            None,
+            Some(RGB {
+                vals: [200, 100, 50],
+            }),
+        ];
+        let fill_order = vec![Some(1), None, Some(0)];
+        let mut filled = FilledPixels {
+            topology: &topology,
+            pixels: &pixels,
+            fill_order: &fill_order,
+            linear_light: false,
+            random_seed: 0,
+            num_fill_events: 0,
+            animation_frame: 0,
+        };
+        let center = PixelLoc {
+            layer: 0,
+            i: 1,
+            j: 0,
+        };
+
+        assert_eq!(
+            AdjacentMean.target_color(&filled, center),
+            Some(RGB {
+                vals: [100, 50, 25]
+            })
+        );
+        assert_eq!(
+            MostRecentNeighbor.target_color(&filled, center),
+            Some(RGB { vals: [0, 0, 0] })
+        );
+        assert_eq!(
+            AdjacentMedian.target_color(&filled, center),
+            Some(RGB {
+                vals: [200, 100, 50]
+            })
+        );
+        assert_eq!(
+            AdjacentMean.target_color(
+                &filled,
+                PixelLoc {
+                    layer: 0,
+                    i: 5,
+                    j: 5
+                }
+            ),
+            None
+        );
+
+        // A random neighbor is chosen for each fill event, and stays
+        // the same until the next fill event.
+        let choices = (0..20)
+            .map(|num_fill_events| {
+                filled.num_fill_events = num_fill_events;
+                let color = RandomNeighbor.target_color(&filled, center);
+                assert_eq!(color, RandomNeighbor.target_color(&filled, center));
+                color.unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(choices.contains(&pixels[0].unwrap()));
+        assert!(choices.contains(&pixels[2].unwrap()));
+        assert!(choices
+            .iter()
+            .all(|c| *c == pixels[0].unwrap() || *c == pixels[2].unwrap()));
+    }
+    #[test]
+    fn test_photo_sequence() {
+        let topology = Topology::new(
+            vec![RectangularArray {
+                width: 2,
+                height: 2,
+            }],
+            Arc::new(HashMap::new()),
+        );
+        let pixels = vec![None; 4];
+        let fill_order = vec![None; 4];
+        let mut filled = FilledPixels {
+            topology: &topology,
+            pixels: &pixels,
+            fill_order: &fill_order,
+            linear_light: false,
+            random_seed: 0,
+            num_fill_events: 0,
+            animation_frame: 0,
+        };
+        let loc = PixelLoc {
+            layer: 0,
+            i: 1,
+            j: 1,
+        };
+
+        let frame = |vals: [u8; 3]| SaveImageData {
+            data: vec![vals[0], vals[1], vals[2], 255],
+            width: 1,
+            height: 1,
+        };
+        let red = RGB { vals: [255, 0, 0] };
+        let blue = RGB { vals: [0, 0, 255] };
+
+        let mut sequence =
+            PhotoSequence::new(0, 1.0, FrameTiming::FillEvents(5));
+        assert_eq!(sequence.target_color(&filled, loc), None);
+        sequence.frames = vec![frame(red.vals), frame(blue.vals)];
+
+        let at_fill =
+            |sequence: &PhotoSequence, filled: &mut FilledPixels, n| {
+                filled.num_fill_events = n;
+                sequence.target_color(filled, loc)
+            };
+        assert_eq!(at_fill(&sequence, &mut filled, 4), Some(red));
+        assert_eq!(at_fill(&sequence, &mut filled, 5), Some(blue));
+        assert_eq!(at_fill(&sequence, &mut filled, 10), Some(blue));
+        sequence.looping(true);
+        assert_eq!(at_fill(&sequence, &mut filled, 10), Some(red));
+
+        let mut sequence =
+            PhotoSequence::new(0, 1.0, FrameTiming::AnimationFrames(2));
+        sequence.frames = vec![frame(red.vals), frame(blue.vals)];
+        filled.animation_frame = 1;
+        assert_eq!(sequence.target_color(&filled, loc), Some(red));
+        filled.animation_frame = 2;
+        assert_eq!(sequence.target_color(&filled, loc), Some(blue));
+
+        // Other layers aren't affected.
+        let other_layer = PixelLoc { layer: 1, ..loc };
+        assert_eq!(sequence.target_color(&filled, other_layer), None);
+    }
+}
@@ -0,0 +1,304 @@
+use std::path::PathBuf;
+
+use crate::growth_image::{GrowthImage, SaveImageData, SaveImageType};
+
+#[derive(Debug, Copy, Clone)]
+pub struct LayoutSpec {
+    pub image_type: SaveImageType,
+
+    // Number of columns in the grid.  If None, picks the number of
+    // columns that gives a roughly square grid.
+    pub columns: Option<u32>,
+
+    // Spacing between cells, and around the border, in pixels.
+    pub padding: u32,
+
+    // Size of each label pixel.  A scale of 0 disables labels.
+    pub label_scale: u32,
+
+    pub background: [u8; 4],
+    pub label_color: [u8; 4],
+}
+
+impl Default for LayoutSpec {
+    fn default() -> Self {
+        Self {
+            image_type: SaveImageType::Generated,
+            columns: None,
+            padding: 10,
+            label_scale: 3,
+            background: [32, 32, 32, 255],
+            label_color: [255, 255, 255, 255],
+        }
+    }
+}
+
+// 3x5 bitmap glyphs for the digits 0-9, one row per u8, using the
+// lowest three bits.
+const DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+// Same format, for the letters A-Z.  Lowercase letters are drawn as
+// uppercase.
+const LETTER_GLYPHS: [[u8; 5]; 26] = [
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+    [0b011, 0b100, 0b101, 0b101, 0b011],
+    [0b101, 0b101, 0b111, 0b101, 0b101],
+    [0b111, 0b010, 0b010, 0b010, 0b111],
+    [0b001, 0b001, 0b001, 0b101, 0b010],
+    [0b101, 0b101, 0b110, 0b101, 0b101],
+    [0b100, 0b100, 0b100, 0b100, 0b111],
+    [0b101, 0b111, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b101, 0b101, 0b101],
+    [0b010, 0b101, 0b101, 0b101, 0b010],
+    [0b110, 0b101, 0b110, 0b100, 0b100],
+    [0b010, 0b101, 0b101, 0b110, 0b011],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110],
+    [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b101, 0b101, 0b101, 0b101, 0b111],
+    [0b101, 0b101, 0b101, 0b101, 0b010],
+    [0b101, 0b101, 0b111, 0b111, 0b101],
+    [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010],
+    [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+
+fn glyph(c: char) -> Option<[u8; 5]> {
+    match c {
+        '0'..='9' => Some(DIGIT_GLYPHS[c.to_digit(10)? as usize]),
+        'a'..='z' => Some(LETTER_GLYPHS[(c as u8 - b'a') as usize]),
+        'A'..='Z' => Some(LETTER_GLYPHS[(c as u8 - b'A') as usize]),
+        '.' => Some([0b000, 0b000, 0b000, 0b000, 0b010]),
+        ',' => Some([0b000, 0b000, 0b000, 0b010, 0b100]),
+        '-' => Some([0b000, 0b000, 0b111, 0b000, 0b000]),
+        '=' => Some([0b000, 0b111, 0b000, 0b111, 0b000]),
+        ':' => Some([0b000, 0b010, 0b000, 0b010, 0b000]),
+        ' ' => Some([0; 5]),
+        _ => None,
+    }
+}
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+impl LayoutSpec {
+    fn label_height(&self) -> u32 {
+        if self.label_scale > 0 {
+            GLYPH_HEIGHT * self.label_scale + self.padding
+        } else {
+            0
+        }
+    }
+
+    // Arrange the images in a grid, labeled with their index.
+    pub(crate) fn arrange(&self, images: &[SaveImageData]) -> SaveImageData {
+        let labels = (0..images.len())
+            .map(|index| index.to_string())
+            .collect::<Vec<_>>();
+        self.arrange_labeled(images, &labels)
+    }
+
+    // Arrange the images in a grid, with each cell large enough to
+    // hold the largest image.  Images are drawn at their native
+    // resolution, so the aspect ratio of each is preserved.  Labels
+    // may contain digits, letters, and simple punctuation, and are
+    // clipped at the edge of the cell.
+    pub(crate) fn arrange_labeled(
+        &self,
+        images: &[SaveImageData],
+        labels: &[String],
+    ) -> SaveImageData {
+        let num_images = images.len() as u32;
+        let columns = self
+            .columns
+            .unwrap_or_else(|| (num_images as f64).sqrt().ceil() as u32)
+            .max(1);
+        let rows = num_images.div_ceil(columns).max(1);
+
+        let cell_width = images.iter().map(|im| im.width).max().unwrap_or(0);
+        let cell_height = images.iter().map(|im| im.height).max().unwrap_or(0)
+            + self.label_height();
+
+        let width = columns * cell_width + (columns + 1) * self.padding;
+        let height = rows * cell_height + (rows + 1) * self.padding;
+
+        let mut output = SaveImageData {
+            data: self
+                .background
+                .iter()
+                .cycle()
+                .take((4 * width * height) as usize)
+                .copied()
+                .collect(),
+            width,
+            height,
+        };
+
+        images.iter().enumerate().for_each(|(index, image)| {
+            let col = (index as u32) % columns;
+            let row = (index as u32) / columns;
+            let x0 = self.padding + col * (cell_width + self.padding);
+            let y0 = self.padding + row * (cell_height + self.padding);
+
+            if self.label_scale > 0 {
+                if let Some(label) = labels.get(index) {
+                    self.draw_label(&mut output, x0, y0, cell_width, label);
+                }
+            }
+
+            // Center each image horizontally within its cell.
+            let x_offset = x0 + (cell_width - image.width) / 2;
+            let y_offset = y0 + self.label_height();
+            (0..image.height).for_each(|j| {
+                let src_start = (4 * j * image.width) as usize;
+                let src_end = src_start + (4 * image.width) as usize;
+                let dest_start =
+                    (4 * ((y_offset + j) * width + x_offset)) as usize;
+                let dest_end = dest_start + (4 * image.width) as usize;
+                output.data[dest_start..dest_end]
+                    .copy_from_slice(&image.data[src_start..src_end]);
+            });
+        });
+
+        output
+    }
+
+    fn draw_label(
+        &self,
+        output: &mut SaveImageData,
+        x0: u32,
+        y0: u32,
+        max_width: u32,
+        label: &str,
+    ) {
+        let scale = self.label_scale;
+        label.chars().filter_map(glyph).enumerate().for_each(
+            |(char_i, glyph)| {
+                let char_x0 = x0 + (char_i as u32) * (GLYPH_WIDTH + 1) * scale;
+                (0..GLYPH_HEIGHT * scale)
+                    .flat_map(|dy| {
+                        (0..GLYPH_WIDTH * scale).map(move |dx| (dx, dy))
+                    })
+                    .filter(|&(dx, dy)| {
+                        let row = glyph[(dy / scale) as usize];
+                        let bit = GLYPH_WIDTH - 1 - dx / scale;
+                        (row >> bit) & 1 != 0
+                    })
+                    .for_each(|(dx, dy)| {
+                        let x = char_x0 + dx;
+                        let y = y0 + dy;
+                        if x < x0 + max_width
+                            && x < output.width
+                            && y < output.height
+                        {
+                            let index = (4 * (y * output.width + x)) as usize;
+                            output.data[index..index + 4]
+                                .copy_from_slice(&self.label_color);
+                        }
+                    });
+            },
+        );
+    }
+
+    // Render each image, and write them as a single labeled grid.
+    // Only the first layer of each image is used.
+    pub fn write_contact_sheet(
+        &self,
+        filename: PathBuf,
+        cells: &[(&GrowthImage, String)],
+    ) {
+        let images = cells
+            .iter()
+            .map(|(image, _label)| image._image_data(self.image_type, 0))
+            .collect::<Vec<_>>();
+        let labels = cells
+            .iter()
+            .map(|(_image, label)| label.clone())
+            .collect::<Vec<_>>();
+        self.arrange_labeled(&images, &labels).write_png(filename);
+    }
+}
+
+// How multiple image types are combined into a single animation
+// frame.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompositeLayout {
+    // Each image placed next to each other, left to right.
+    SideBySide,
+
+    // The first image at full size, with the others shrunk by the
+    // given factor and placed along the bottom-right corner.
+    PictureInPicture { scale_divisor: u32 },
+}
+
+impl CompositeLayout {
+    pub(crate) fn composite(
+        &self,
+        mut images: Vec<SaveImageData>,
+    ) -> SaveImageData {
+        if images.len() == 1 {
+            return images.pop().unwrap();
+        }
+
+        match *self {
+            CompositeLayout::SideBySide => LayoutSpec {
+                columns: Some(images.len() as u32),
+                padding: 0,
+                label_scale: 0,
+                ..Default::default()
+            }
+            .arrange(&images),
+
+            CompositeLayout::PictureInPicture { scale_divisor } => {
+                let scale_divisor = scale_divisor.max(1);
+                let mut images = images.into_iter();
+                let mut output = images.next().unwrap();
+
+                let mut x_end = output.width;
+                images.for_each(|image| {
+                    let width = (image.width / scale_divisor).min(x_end);
+                    let height =
+                        (image.height / scale_divisor).min(output.height);
+                    let x0 = x_end - width;
+                    let y0 = output.height - height;
+
+                    // Nearest-neighbor downsampling.
+                    (0..height)
+                        .flat_map(|j| (0..width).map(move |i| (i, j)))
+                        .for_each(|(i, j)| {
+                            let src = (4
+                                * (j * scale_divisor * image.width
+                                    + i * scale_divisor))
+                                as usize;
+                            let dest = (4
+                                * ((y0 + j) * output.width + (x0 + i)))
+                                as usize;
+                            output.data[dest..dest + 4]
+                                .copy_from_slice(&image.data[src..src + 4]);
+                        });
+
+                    x_end = x0;
+                });
+
+                output
+            }
+        }
+    }
+}
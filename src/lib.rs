@@ -5,17 +5,68 @@ mod errors;
 
 pub mod bezier_util;
 
-mod color;
+mod batch;
+pub mod color;
+mod color_map;
+pub mod compose;
+mod debug_overlay;
+mod deepzoom;
+#[cfg(feature = "distributed")]
+mod distributed;
+mod distribution;
+#[cfg(feature = "expressions")]
+pub mod expression;
+mod frame_buffer;
+mod frame_sink;
+mod frame_writer;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 mod growth_image;
 mod growth_image_builder;
 mod kd_tree;
+pub mod kernels;
+mod layout;
+mod memory;
 pub mod palettes;
+mod performance;
+mod png_text;
 mod point_tracker;
+mod progress;
+mod region;
+pub mod scene;
+#[cfg(feature = "minifb")]
+mod seed_editor;
+mod similarity;
+mod stage_condition;
 mod topology;
 
-pub use color::RGB;
+pub use batch::BatchRenderer;
+pub use color::{BlendMode, RGB};
+pub use color_map::{ColorMap, Gradient, Grayscale};
+pub use debug_overlay::{
+    DebugOverlay, DebugOverlayHandle, OverlayKind, OverlayStyle,
+};
+#[cfg(feature = "distributed")]
+pub use distributed::{Coordinator, PalettePartition, TileJob, Worker};
+pub use distribution::RandomDistribution;
 pub use errors::Error;
-pub use growth_image::SaveImageType;
-pub use growth_image_builder::GrowthImageBuilder;
+pub use frame_buffer::FrameBuffer;
+pub use frame_sink::{FrameInfo, FrameSink};
+pub use frame_writer::FrameWritePolicy;
+pub use growth_image::{
+    AlphaMode, Background, FillReport, GrowthImage, RawFormat, SaveImageType,
+};
+pub use growth_image_builder::{
+    GrowthImageAnimationBuilder, GrowthImageBuilder, GrowthImageStageBuilder,
+};
+pub use kd_tree::{KDTreeOptions, SplitStrategy};
+pub use layout::{CompositeLayout, LayoutSpec};
+pub use memory::MemoryEstimate;
 pub use palettes::*;
-pub use topology::PixelLoc;
+pub use performance::{StagePerformance, StageTiming, StatSummary};
+pub use point_tracker::GrowthCone;
+pub use region::{Mapping, Region};
+#[cfg(feature = "minifb")]
+pub use seed_editor::{SeedEditor, SeedLayout};
+pub use similarity::Metric;
+pub use topology::{LayerShape, PixelLoc, RectangularArray, Topology};
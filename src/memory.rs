@@ -0,0 +1,41 @@
+// Approximate number of bytes used by each part of a GrowthImage,
+// as returned by GrowthImageBuilder::estimate_memory().
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MemoryEstimate {
+    // Color, stage, fill order, and origin of each pixel.
+    pub pixels: usize,
+
+    // Search statistics of each pixel.
+    pub stats: usize,
+
+    // Used pixels and seed origins, for each stage that is active at
+    // once.  The frontier itself is not included, since its size
+    // depends on how the image grows.
+    pub point_trackers: usize,
+
+    // Allowed or forbidden points and selection weights of each
+    // stage.
+    pub regions: usize,
+
+    // Palettes of each stage, stored as KD-trees.
+    pub kd_trees: usize,
+
+    // Table of neighbors of each pixel, with precompute_adjacency().
+    pub adjacency: usize,
+
+    // Table of the location of each pixel, with
+    // precompute_locations().
+    pub locations: usize,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> usize {
+        self.pixels
+            + self.stats
+            + self.point_trackers
+            + self.regions
+            + self.kd_trees
+            + self.adjacency
+            + self.locations
+    }
+}
@@ -1,15 +1,204 @@
-use rand::{Rng, RngCore};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::color::RGB;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{Rng, RngCore, SeedableRng};
 
-pub trait Palette {
+use crate::color::{HSL, RGB};
+use crate::errors::Error;
+use crate::growth_image::SaveImageData;
+use crate::scene::PaletteSpec;
+
+pub trait Palette: Send + Sync {
     fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB>;
+
+    // Description of the palette, for saving as part of a SceneSpec.
+    // Palettes without one can't be saved.
+    fn spec(&self) -> Option<PaletteSpec> {
+        None
+    }
+}
+
+// Palettes that can produce any one of their colors without
+// generating the rest, such as for splitting a very large palette
+// between the tiles of a distributed render.  The colors of a palette
+// are determined by n_colors and the seed, and need not match those
+// from generate().  Returns None past the end of the palette, for
+// palettes that hold fewer colors than requested.
+pub trait IndexedPalette: Palette {
+    fn color_at(&self, index: u64, n_colors: u64, seed: u64) -> Option<RGB>;
+}
+
+impl<T: Palette + ?Sized> Palette for Box<T> {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        (**self).generate(n_colors, rng)
+    }
+
+    fn spec(&self) -> Option<PaletteSpec> {
+        (**self).spec()
+    }
+}
+
+impl<T: Palette + ?Sized> Palette for Arc<T> {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        (**self).generate(n_colors, rng)
+    }
+
+    fn spec(&self) -> Option<PaletteSpec> {
+        (**self).spec()
+    }
+}
+
+// Adapters that transform the colors of another palette, so that
+// variations of a palette can be used for different stages.  They
+// can be nested, e.g. HueRotate(Desaturate(UniformPalette, 0.5),
+// 90.0).  None of them can be saved as part of a SceneSpec.
+
+// Colors of another palette, with the hue rotated by the angle given
+// in degrees.
+#[derive(Copy, Clone)]
+pub struct HueRotate<P>(pub P, pub f32);
+
+impl<P: Palette> Palette for HueRotate<P> {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        let HueRotate(palette, degrees) = self;
+        palette
+            .generate(n_colors, rng)
+            .into_iter()
+            .map(|color| color.rotate_hue(*degrees))
+            .collect()
+    }
+}
+
+impl<P: IndexedPalette> IndexedPalette for HueRotate<P> {
+    fn color_at(&self, index: u64, n_colors: u64, seed: u64) -> Option<RGB> {
+        let HueRotate(palette, degrees) = self;
+        palette
+            .color_at(index, n_colors, seed)
+            .map(|color| color.rotate_hue(*degrees))
+    }
+}
+
+// Colors of another palette, with the HSL saturation reduced by the
+// fraction given, in the range [0, 1].  An amount of 1.0 gives
+// grayscale.
+#[derive(Copy, Clone)]
+pub struct Desaturate<P>(pub P, pub f32);
+
+impl<P> Desaturate<P> {
+    fn apply(&self, color: RGB) -> RGB {
+        let scale = 1.0 - self.1.clamp(0.0, 1.0);
+        let hsl = color.to_hsl();
+        HSL {
+            s: hsl.s * scale,
+            ..hsl
+        }
+        .into()
+    }
+}
+
+impl<P: Palette> Palette for Desaturate<P> {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        self.0
+            .generate(n_colors, rng)
+            .into_iter()
+            .map(|color| self.apply(color))
+            .collect()
+    }
+}
+
+impl<P: IndexedPalette> IndexedPalette for Desaturate<P> {
+    fn color_at(&self, index: u64, n_colors: u64, seed: u64) -> Option<RGB> {
+        self.0
+            .color_at(index, n_colors, seed)
+            .map(|color| self.apply(color))
+    }
+}
+
+// Colors of another palette, with each channel raised to the power
+// given, as a fraction of 255.  Values above 1.0 darken the palette,
+// and values below 1.0 lighten it.
+#[derive(Copy, Clone)]
+pub struct Gamma<P>(pub P, pub f32);
+
+impl<P> Gamma<P> {
+    fn apply(&self, color: RGB) -> RGB {
+        let mut vals = color.vals;
+        vals.iter_mut().for_each(|val| {
+            let unit = (*val as f32) / 255.0;
+            *val = (255.0 * unit.powf(self.1)).round() as u8;
+        });
+        RGB { vals }
+    }
+}
+
+impl<P: Palette> Palette for Gamma<P> {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        self.0
+            .generate(n_colors, rng)
+            .into_iter()
+            .map(|color| self.apply(color))
+            .collect()
+    }
+}
+
+impl<P: IndexedPalette> IndexedPalette for Gamma<P> {
+    fn color_at(&self, index: u64, n_colors: u64, seed: u64) -> Option<RGB> {
+        self.0
+            .color_at(index, n_colors, seed)
+            .map(|color| self.apply(color))
+    }
+}
+
+// Colors of another palette, omitting any within the given distance
+// in RGB space of the excluded colors, such as to avoid pure black
+// and white.  Fewer colors than requested are generated if any are
+// omitted.
+#[derive(Clone)]
+pub struct ExcludeColors<P>(pub P, pub Vec<RGB>, pub f32);
+
+impl<P: Palette> Palette for ExcludeColors<P> {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        let ExcludeColors(palette, excluded, tolerance) = self;
+        exclude_colors(palette.generate(n_colors, rng), excluded, *tolerance)
+    }
+}
+
+pub(crate) fn exclude_colors(
+    colors: Vec<RGB>,
+    excluded: &[RGB],
+    tolerance: f32,
+) -> Vec<RGB> {
+    if excluded.is_empty() {
+        return colors;
+    }
+    let max_dist2 = tolerance * tolerance;
+    colors
+        .into_iter()
+        .filter(|color| {
+            excluded.iter().all(|other| {
+                let dist2 = color
+                    .vals
+                    .iter()
+                    .zip(other.vals.iter())
+                    .map(|(&a, &b)| (a as f32 - b as f32).powi(2))
+                    .sum::<f32>();
+                dist2 > max_dist2
+            })
+        })
+        .collect()
 }
 
 #[derive(Copy, Clone)]
 pub struct UniformPalette;
 
 impl Palette for UniformPalette {
+    fn spec(&self) -> Option<PaletteSpec> {
+        Some(PaletteSpec::Uniform)
+    }
+
     fn generate(&self, n_colors: u32, _: &mut dyn RngCore) -> Vec<RGB> {
         let mut output = Vec::new();
         output.reserve(n_colors as usize);
@@ -32,6 +221,26 @@ impl Palette for UniformPalette {
     }
 }
 
+// As generate(), but in double precision, since a palette split
+// between tiles may have more colors than f32 can index exactly.
+impl IndexedPalette for UniformPalette {
+    fn color_at(&self, index: u64, n_colors: u64, _seed: u64) -> Option<RGB> {
+        if index >= n_colors {
+            return None;
+        }
+        let dim_size = (n_colors as f64).powf(1.0 / 3.0);
+        let val = (index as f64) / dim_size;
+        let r = 255.0 * (val % 1.0);
+        let val = val.floor() / dim_size;
+        let g = 255.0 * (val % 1.0);
+        let val = val.floor() / dim_size;
+        let b = 255.0 * val;
+        Some(RGB {
+            vals: [r as u8, g as u8, b as u8],
+        })
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct SphericalPalette {
     pub central_color: RGB,
@@ -39,33 +248,539 @@ pub struct SphericalPalette {
 }
 
 impl Palette for SphericalPalette {
+    fn spec(&self) -> Option<PaletteSpec> {
+        Some(PaletteSpec::Spherical {
+            central_color: self.central_color,
+            color_radius: self.color_radius,
+        })
+    }
+
     fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
         let mut output = Vec::new();
         output.reserve(n_colors as usize);
 
         for _i in 0..n_colors {
-            let r = self.color_radius * rng.gen::<f32>().powf(1.0 / 3.0);
-            let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
-            let costheta = 1.0 - 2.0 * rng.gen::<f32>();
-            let sintheta = (1.0 - costheta * costheta).sqrt();
-
-            let dx = r * sintheta * phi.cos();
-            let dy = r * sintheta * phi.sin();
-            let dz = r * costheta;
-
-            let color = RGB {
-                vals: [
-                    ((self.central_color.r() as f32) + dx).clamp(0.0, 255.0)
-                        as u8,
-                    ((self.central_color.g() as f32) + dy).clamp(0.0, 255.0)
-                        as u8,
-                    ((self.central_color.b() as f32) + dz).clamp(0.0, 255.0)
-                        as u8,
-                ],
-            };
-            output.push(color);
+            output.push(self.sample(rng));
         }
 
         output
     }
 }
+
+impl SphericalPalette {
+    // Uniformly distributed within the sphere, using three random
+    // values.
+    fn sample(&self, rng: &mut dyn RngCore) -> RGB {
+        let r = self.color_radius * rng.gen::<f32>().powf(1.0 / 3.0);
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+        let costheta = 1.0 - 2.0 * rng.gen::<f32>();
+        let sintheta = (1.0 - costheta * costheta).sqrt();
+
+        let dx = r * sintheta * phi.cos();
+        let dy = r * sintheta * phi.sin();
+        let dz = r * costheta;
+
+        RGB {
+            vals: [
+                ((self.central_color.r() as f32) + dx).clamp(0.0, 255.0) as u8,
+                ((self.central_color.g() as f32) + dy).clamp(0.0, 255.0) as u8,
+                ((self.central_color.b() as f32) + dz).clamp(0.0, 255.0) as u8,
+            ],
+        }
+    }
+}
+
+// Each color is sampled from its own position in the random stream,
+// so any one of them can be found directly.
+impl IndexedPalette for SphericalPalette {
+    fn color_at(&self, index: u64, n_colors: u64, seed: u64) -> Option<RGB> {
+        if index >= n_colors {
+            return None;
+        }
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        rng.set_word_pos(3 * index as u128);
+        Some(self.sample(&mut rng))
+    }
+}
+
+// Colors spaced evenly along a 3-D Hilbert curve through the RGB
+// cube.  Nearby colors along the curve are nearby in color space, so
+// growth tends to follow smooth bands of color.  The starting point
+// along the curve is chosen randomly.
+#[derive(Copy, Clone)]
+pub struct HilbertPalette;
+
+// Bits per color channel.
+const HILBERT_BITS: u32 = 8;
+
+impl Palette for HilbertPalette {
+    fn spec(&self) -> Option<PaletteSpec> {
+        Some(PaletteSpec::Hilbert)
+    }
+
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        let curve_length = 1u64 << (3 * HILBERT_BITS);
+        let n_colors = (n_colors as u64).min(curve_length);
+        if n_colors == 0 {
+            return Vec::new();
+        }
+
+        let step = (curve_length as f64) / (n_colors as f64);
+        let start = step * rng.gen::<f64>();
+
+        (0..n_colors)
+            .map(|i| hilbert_color(start + step * (i as f64)))
+            .collect()
+    }
+}
+
+impl IndexedPalette for HilbertPalette {
+    fn color_at(&self, index: u64, n_colors: u64, seed: u64) -> Option<RGB> {
+        let curve_length = 1u64 << (3 * HILBERT_BITS);
+        let n_colors = n_colors.min(curve_length);
+        if index >= n_colors {
+            return None;
+        }
+        let step = (curve_length as f64) / (n_colors as f64);
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        let start = step * rng.gen::<f64>();
+        Some(hilbert_color(start + step * (index as f64)))
+    }
+}
+
+// Color at the position along the curve, clamped to its end.
+fn hilbert_color(pos: f64) -> RGB {
+    let curve_length = 1u64 << (3 * HILBERT_BITS);
+    let index = (pos as u64).min(curve_length - 1);
+    let [r, g, b] = hilbert_point(index, HILBERT_BITS);
+    RGB {
+        vals: [r as u8, g as u8, b as u8],
+    }
+}
+
+// Position of the index along a 3-D Hilbert curve, with the given
+// number of bits per axis.  Uses Skilling's algorithm ("Programming
+// the Hilbert curve", 2004), which operates on the index after
+// distributing its bits across the three axes.
+fn hilbert_point(index: u64, bits: u32) -> [u32; 3] {
+    let mut x = [0u32; 3];
+    (0..bits).for_each(|level| {
+        (0..3).for_each(|axis| {
+            let bit = 3 * (bits - 1 - level) + (2 - axis as u32);
+            x[axis] |= (((index >> bit) & 1) as u32) << (bits - 1 - level);
+        });
+    });
+
+    // Gray decode
+    let t = x[2] >> 1;
+    x[2] ^= x[1];
+    x[1] ^= x[0];
+    x[0] ^= t;
+
+    // Undo excess work
+    let mut q = 2;
+    while q != (1 << bits) {
+        let p = q - 1;
+        (0..3).rev().for_each(|axis| {
+            if x[axis] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[axis]) & p;
+                x[0] ^= t;
+                x[axis] ^= t;
+            }
+        });
+        q <<= 1;
+    }
+
+    x
+}
+
+// Colors drawn from an image.  If fewer colors are requested than the
+// image has pixels, the image's colors are clustered down to the
+// requested number, which may give fewer colors if the image has few
+// distinct colors.  Otherwise, the pixel colors are repeated.
+#[derive(Clone)]
+pub struct ImagePalette {
+    pub colors: Vec<RGB>,
+    pub method: ClusterMethod,
+}
+
+impl ImagePalette {
+    // Transparent pixels are skipped.
+    pub fn from_png(
+        filename: impl AsRef<Path>,
+        method: ClusterMethod,
+    ) -> Result<Self, Error> {
+        let image = SaveImageData::read_png(filename.as_ref())?;
+        let colors = image
+            .data
+            .chunks_exact(4)
+            .filter(|p| p[3] > 0)
+            .map(|p| RGB {
+                vals: [p[0], p[1], p[2]],
+            })
+            .collect();
+        Ok(Self { colors, method })
+    }
+}
+
+impl Palette for ImagePalette {
+    fn generate(&self, n_colors: u32, rng: &mut dyn RngCore) -> Vec<RGB> {
+        let n_colors = n_colors as usize;
+        if n_colors >= self.colors.len() {
+            self.colors.iter().copied().cycle().take(n_colors).collect()
+        } else {
+            cluster(&self.colors, n_colors, self.method, rng)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClusterMethod {
+    // Lloyd's algorithm, starting from a k-means++ initialization.
+    // Gives the closest fit, but each iteration compares every
+    // distinct color to every cluster, so is best suited to small k.
+    KMeans,
+
+    // Repeatedly split the box of colors with the largest range at
+    // its median.  Much faster for large k, and deterministic.
+    MedianCut,
+}
+
+// Upper limit on the iterations of Lloyd's algorithm, which usually
+// converges well before this.
+const KMEANS_MAX_ITERATIONS: usize = 50;
+
+// Reduce a set of colors to at most k representative colors.  Each
+// distinct color is weighted by the number of times it occurs, so
+// common colors receive more clusters.  The result depends only on
+// the colors and the state of the rng, so a seeded rng gives the same
+// clusters on every run.
+pub fn cluster(
+    colors: &[RGB],
+    k: usize,
+    method: ClusterMethod,
+    rng: &mut dyn RngCore,
+) -> Vec<RGB> {
+    let mut counts = HashMap::new();
+    colors
+        .iter()
+        .for_each(|&color| *counts.entry(color).or_insert(0usize) += 1);
+    let mut weighted = counts.into_iter().collect::<Vec<_>>();
+    weighted.sort_by_key(|(color, _count)| color.vals);
+
+    if k == 0 {
+        Vec::new()
+    } else if weighted.len() <= k {
+        weighted.into_iter().map(|(color, _count)| color).collect()
+    } else {
+        match method {
+            ClusterMethod::KMeans => kmeans(&weighted, k, rng),
+            ClusterMethod::MedianCut => median_cut(weighted, k),
+        }
+    }
+}
+
+fn as_f32(color: &RGB) -> [f32; 3] {
+    let [r, g, b] = color.vals;
+    [r as f32, g as f32, b as f32]
+}
+
+fn dist2(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// Weighted mean of the colors, rounded to the nearest color.
+fn weighted_mean(colors: &[(RGB, usize)]) -> RGB {
+    let total = colors.iter().map(|(_, count)| *count as f64).sum::<f64>();
+    let mut vals = [0u8; 3];
+    (0..3).for_each(|channel| {
+        let sum = colors
+            .iter()
+            .map(|(color, count)| {
+                (color.vals[channel] as f64) * (*count as f64)
+            })
+            .sum::<f64>();
+        vals[channel] = (sum / total).round() as u8;
+    });
+    RGB { vals }
+}
+
+fn kmeans(
+    weighted: &[(RGB, usize)],
+    k: usize,
+    rng: &mut dyn RngCore,
+) -> Vec<RGB> {
+    let points = weighted
+        .iter()
+        .map(|(color, _)| as_f32(color))
+        .collect::<Vec<_>>();
+    let weights = weighted
+        .iter()
+        .map(|(_, count)| *count as f32)
+        .collect::<Vec<_>>();
+
+    // k-means++ initialization.  Each center is chosen with
+    // probability proportional to the weighted squared distance from
+    // the nearest existing center.
+    let first = WeightedIndex::new(&weights).unwrap().sample(rng);
+    let mut centers = vec![points[first]];
+    let mut nearest_dist2 = points
+        .iter()
+        .map(|point| dist2(point, &centers[0]))
+        .collect::<Vec<_>>();
+    while centers.len() < k {
+        let probs = nearest_dist2
+            .iter()
+            .zip(weights.iter())
+            .map(|(d2, w)| d2 * w)
+            .collect::<Vec<_>>();
+        let next = match WeightedIndex::new(&probs) {
+            Ok(dist) => dist.sample(rng),
+            // Every point is at an existing center.
+            Err(_) => break,
+        };
+        centers.push(points[next]);
+        nearest_dist2
+            .iter_mut()
+            .zip(points.iter())
+            .for_each(|(d2, point)| *d2 = d2.min(dist2(point, &points[next])));
+    }
+
+    let nearest_center = |centers: &[[f32; 3]], point: &[f32; 3]| {
+        (0..centers.len())
+            .min_by(|&a, &b| {
+                dist2(point, &centers[a])
+                    .partial_cmp(&dist2(point, &centers[b]))
+                    .unwrap()
+            })
+            .unwrap()
+    };
+
+    let mut assignments = vec![usize::MAX; points.len()];
+    for _ in 0..KMEANS_MAX_ITERATIONS {
+        let mut changed = false;
+        points.iter().zip(assignments.iter_mut()).for_each(
+            |(point, assigned)| {
+                let nearest = nearest_center(&centers, point);
+                changed |= nearest != *assigned;
+                *assigned = nearest;
+            },
+        );
+        if !changed {
+            break;
+        }
+
+        // Clusters that lost all of their colors keep their previous
+        // center.
+        let mut sums = vec![([0.0f64; 3], 0.0f64); centers.len()];
+        points
+            .iter()
+            .zip(weights.iter())
+            .zip(assignments.iter())
+            .for_each(|((point, &weight), &assigned)| {
+                let (sum, total) = &mut sums[assigned];
+                (0..3).for_each(|c| sum[c] += (point[c] * weight) as f64);
+                *total += weight as f64;
+            });
+        centers
+            .iter_mut()
+            .zip(sums.iter())
+            .filter(|(_, (_, total))| *total > 0.0)
+            .for_each(|(center, (sum, total))| {
+                (0..3).for_each(|c| center[c] = (sum[c] / total) as f32);
+            });
+    }
+
+    centers
+        .iter()
+        .map(|center| RGB {
+            vals: [
+                center[0].round() as u8,
+                center[1].round() as u8,
+                center[2].round() as u8,
+            ],
+        })
+        .collect()
+}
+
+// Channel with the largest range of values, along with that range.
+fn widest_channel(colors: &[(RGB, usize)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let vals = colors.iter().map(|(color, _)| color.vals[channel]);
+            let range = vals.clone().max().unwrap() - vals.min().unwrap();
+            (channel, range)
+        })
+        .max_by_key(|&(channel, range)| (range, Reverse(channel)))
+        .unwrap()
+}
+
+fn median_cut(weighted: Vec<(RGB, usize)>, k: usize) -> Vec<RGB> {
+    // Boxes that can be split, keyed by their widest range.  Ties are
+    // broken by the order in which the boxes were made, so that the
+    // result is deterministic.
+    let mut boxes = vec![weighted];
+    let mut heap = BinaryHeap::new();
+    heap.push((widest_channel(&boxes[0]).1, Reverse(0)));
+
+    while boxes.len() < k {
+        let index = match heap.pop() {
+            Some((0, _)) => break,
+            Some((_, Reverse(index))) => index,
+            None => break,
+        };
+
+        let colors = &mut boxes[index];
+        let (channel, _) = widest_channel(colors);
+        colors.sort_by_key(|(color, _)| color.vals[channel]);
+
+        // Split at the weighted median, leaving at least one color on
+        // each side.
+        let total = colors.iter().map(|(_, count)| count).sum::<usize>();
+        let mut cumulative = 0;
+        let split = colors
+            .iter()
+            .position(|(_, count)| {
+                cumulative += count;
+                2 * cumulative >= total
+            })
+            .unwrap()
+            + 1;
+        let split = split.min(colors.len() - 1);
+        let upper = colors.split_off(split);
+
+        let new_index = boxes.len();
+        boxes.push(upper);
+        [index, new_index].iter().for_each(|&i| {
+            heap.push((widest_channel(&boxes[i]).1, Reverse(i)));
+        });
+    }
+
+    boxes.iter().map(|colors| weighted_mean(colors)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_hilbert_curve() {
+        let bits = 4;
+        let points = (0..(1u64 << (3 * bits)))
+            .map(|index| hilbert_point(index, bits))
+            .collect::<Vec<_>>();
+
+        // Each point is visited exactly once.
+        let unique = points.iter().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), points.len());
+        assert!(points.iter().flatten().all(|&x| x < (1 << bits)));
+
+        // Consecutive points are adjacent.
+        points.windows(2).for_each(|pair| {
+            let dist = pair[0]
+                .iter()
+                .zip(pair[1].iter())
+                .map(|(&a, &b)| (a as i64 - b as i64).abs())
+                .sum::<i64>();
+            assert_eq!(dist, 1);
+        });
+    }
+
+    #[test]
+    fn test_adapters() {
+        let color = |r, g, b| RGB { vals: [r, g, b] };
+        let base = ImagePalette {
+            colors: vec![color(255, 0, 0), color(0, 0, 255), color(64, 64, 64)],
+            method: ClusterMethod::MedianCut,
+        };
+        let generate = |palette: &dyn Palette| {
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+            palette.generate(3, &mut rng)
+        };
+
+        assert_eq!(
+            generate(&HueRotate(base.clone(), 120.0)),
+            vec![color(0, 255, 0), color(255, 0, 0), color(64, 64, 64)]
+        );
+        assert_eq!(
+            generate(&Desaturate(base.clone(), 1.0)),
+            vec![
+                color(128, 128, 128),
+                color(128, 128, 128),
+                color(64, 64, 64)
+            ]
+        );
+        assert_eq!(generate(&Desaturate(base.clone(), 0.0)), base.colors);
+        assert_eq!(
+            generate(&Gamma(base.clone(), 2.0)),
+            vec![color(255, 0, 0), color(0, 0, 255), color(16, 16, 16)]
+        );
+        assert_eq!(
+            generate(&ExcludeColors(
+                base.clone(),
+                vec![color(0, 0, 0), color(0, 0, 250)],
+                120.0
+            )),
+            vec![color(255, 0, 0)]
+        );
+
+        // Adapters can be nested, and applied to shared palettes.
+        let shared: Arc<dyn Palette> = Arc::new(base);
+        assert_eq!(
+            generate(&HueRotate(Gamma(shared, 2.0), 120.0)),
+            vec![color(0, 255, 0), color(255, 0, 0), color(16, 16, 16)]
+        );
+    }
+
+    #[test]
+    fn test_cluster() {
+        let color = |r, g, b| RGB { vals: [r, g, b] };
+        let dark = (0..10).map(|i| color(i, i, 0));
+        let light = (0..10).map(|i| color(200 + i, 200, 200 - i));
+        let colors = dark.chain(light).collect::<Vec<_>>();
+
+        for &method in [ClusterMethod::KMeans, ClusterMethod::MedianCut].iter()
+        {
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+            let mut clusters = cluster(&colors, 2, method, &mut rng);
+            clusters.sort_by_key(|color| color.vals);
+            assert_eq!(
+                clusters,
+                vec![color(5, 5, 0), color(205, 200, 196)],
+                "{:?}",
+                method
+            );
+
+            // Repeated colors are only counted once toward k.
+            let repeated = vec![color(1, 2, 3); 5];
+            assert_eq!(
+                cluster(&repeated, 3, method, &mut rng),
+                vec![color(1, 2, 3)]
+            );
+
+            // Same seed, same clusters.
+            let mut rng_a = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+            let mut rng_b = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+            assert_eq!(
+                cluster(&colors, 7, method, &mut rng_a),
+                cluster(&colors, 7, method, &mut rng_b)
+            );
+        }
+
+        // Median cut gives more clusters to the more common colors.
+        let skewed = (0..90)
+            .map(|i| color(0, 0, i))
+            .chain((0..10).map(|i| color(255, 255, 200 + i)))
+            .collect::<Vec<_>>();
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        let clusters = cluster(&skewed, 10, ClusterMethod::MedianCut, &mut rng);
+        assert_eq!(clusters.len(), 10);
+        assert!(clusters.iter().filter(|color| color.vals[0] == 0).count() > 5);
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+
+use crate::kd_tree::PerformanceStats;
+
+// Distribution of one search statistic over the pixels of a stage.
+// Percentiles use the nearest-rank method, so each is a value that
+// occurred for some pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatSummary {
+    pub min: u32,
+    pub mean: f64,
+    pub max: u32,
+    pub p50: u32,
+    pub p90: u32,
+    pub p99: u32,
+}
+
+impl StatSummary {
+    // Returns None if there are no values.
+    pub(crate) fn new(mut values: Vec<u32>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let n = values.len();
+        let percentile = |p: usize| values[(p * n).div_ceil(100).max(1) - 1];
+        Some(Self {
+            min: values[0],
+            mean: values.iter().map(|&v| v as f64).sum::<f64>() / (n as f64),
+            max: values[n - 1],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+// Search statistics of the pixels filled by one stage, as returned
+// by GrowthImage::performance_summary().  Pixels placed with
+// set_pixel() have no statistics, and are not included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StagePerformance {
+    pub stage: usize,
+    pub num_pixels: usize,
+    pub nodes_checked: StatSummary,
+    pub leaf_nodes_checked: StatSummary,
+    pub points_checked: StatSummary,
+}
+
+impl StagePerformance {
+    // Returns None if the stage has no statistics.
+    pub(crate) fn new(
+        stage: usize,
+        stats: &[PerformanceStats],
+    ) -> Option<Self> {
+        let summary = |f: fn(&PerformanceStats) -> u32| {
+            StatSummary::new(stats.iter().map(f).collect())
+        };
+        Some(Self {
+            stage,
+            num_pixels: stats.len(),
+            nodes_checked: summary(|s| s.nodes_checked)?,
+            leaf_nodes_checked: summary(|s| s.leaf_nodes_checked)?,
+            points_checked: summary(|s| s.points_checked)?,
+        })
+    }
+}
+
+// When a stage ran, as returned by GrowthImage::stage_timeline().
+// Iterations count the fill events of the whole image, as used by
+// start_at_iter() of an animation, so stages that run concurrently
+// overlap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StageTiming {
+    pub stage: usize,
+    pub start_iter: usize,
+    pub start_time: Instant,
+
+    // None while the stage is running, or if the image stopped
+    // before the stage finished.
+    pub end_iter: Option<usize>,
+    pub end_time: Option<Instant>,
+    pub num_pixels: Option<usize>,
+}
+
+impl StageTiming {
+    pub(crate) fn new(stage: usize, start_iter: usize) -> Self {
+        Self {
+            stage,
+            start_iter,
+            start_time: Instant::now(),
+            end_iter: None,
+            end_time: None,
+            num_pixels: None,
+        }
+    }
+
+    pub(crate) fn finish(&mut self, end_iter: usize, num_pixels: usize) {
+        self.end_iter = Some(end_iter);
+        self.end_time = Some(Instant::now());
+        self.num_pixels = Some(num_pixels);
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.end_time.map(|end| end - self.start_time)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stat_summary() {
+        assert_eq!(StatSummary::new(Vec::new()), None);
+
+        let summary = StatSummary::new((1..=100).rev().collect()).unwrap();
+        assert_eq!((summary.min, summary.max), (1, 100));
+        assert_eq!(summary.mean, 50.5);
+        assert_eq!((summary.p50, summary.p90, summary.p99), (50, 90, 99));
+
+        let summary = StatSummary::new(vec![7]).unwrap();
+        assert_eq!((summary.min, summary.p50, summary.p99), (7, 7, 7));
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::errors::Error;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+// Contents of an uncompressed iTXt chunk, which holds UTF-8 text.
+// Layout is the keyword, a null separator, the compression flag and
+// method, then a null-terminated language tag and translated
+// keyword, both left empty, and finally the text.
+pub(crate) fn itxt_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(keyword.as_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(text.as_bytes());
+    data
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn split_at_null(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = bytes.iter().position(|&b| b == 0)?;
+    Some((&bytes[..pos], &bytes[pos + 1..]))
+}
+
+fn parse_text_chunk(
+    chunk_type: &[u8],
+    data: &[u8],
+) -> Option<(String, String)> {
+    match chunk_type {
+        b"tEXt" => {
+            let (keyword, text) = split_at_null(data)?;
+            Some((latin1(keyword), latin1(text)))
+        }
+        b"iTXt" => {
+            let (keyword, rest) = split_at_null(data)?;
+            // Compressed text isn't written by this crate, and is
+            // skipped.
+            if rest.first().copied()? != 0 {
+                return None;
+            }
+            let (_language, rest) = split_at_null(rest.get(2..)?)?;
+            let (_translated, text) = split_at_null(rest)?;
+            Some((latin1(keyword), String::from_utf8_lossy(text).into_owned()))
+        }
+        _ => None,
+    }
+}
+
+// Read the uncompressed tEXt and iTXt chunks of a PNG file.  Chunks
+// are read directly, since the png crate doesn't decode text chunks.
+pub(crate) fn read_png_text(
+    reader: &mut impl Read,
+) -> Result<HashMap<String, String>, Error> {
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature)?;
+    if &signature != PNG_SIGNATURE {
+        return Err(Error::UnsupportedImageFormat);
+    }
+
+    let mut text = HashMap::new();
+    loop {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let length =
+            u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = &header[4..8];
+
+        // Chunk data, followed by the CRC.
+        let mut data = vec![0u8; length as usize + 4];
+        reader.read_exact(&mut data)?;
+        data.truncate(length as usize);
+
+        if chunk_type == b"IEND" {
+            break;
+        }
+        if let Some((keyword, value)) = parse_text_chunk(chunk_type, &data) {
+            text.insert(keyword, value);
+        }
+    }
+
+    Ok(text)
+}
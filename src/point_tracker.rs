@@ -1,37 +1,241 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 
-use rand::distributions::Distribution;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
 use rand::Rng;
 
 use crate::topology::{PixelLoc, Topology};
 
+// Restricts growth from a seed point to within an angular range,
+// as seen from the seed point.  Angles are in radians, measured from
+// the +i axis towards the +j axis.
+#[derive(Debug, Copy, Clone)]
+pub struct GrowthCone {
+    pub direction: f64,
+    pub half_angle: f64,
+}
+
+impl GrowthCone {
+    pub fn contains(&self, seed: PixelLoc, loc: PixelLoc) -> bool {
+        let di = (loc.i - seed.i) as f64;
+        let dj = (loc.j - seed.j) as f64;
+        if di == 0.0 && dj == 0.0 {
+            return true;
+        }
+
+        let pi = std::f64::consts::PI;
+        let delta = (dj.atan2(di) - self.direction).rem_euclid(2.0 * pi);
+        let delta = if delta > pi { 2.0 * pi - delta } else { delta };
+        delta <= self.half_angle
+    }
+}
+
+// Relative probability, in the range [0, 1], that a frontier point is
+// selected.  When several weights are used, they are multiplied
+// together.
+pub(crate) trait SelectionWeight: Send + Sync {
+    fn weight(&self, loc: PixelLoc, index: usize) -> f32;
+}
+
+// A fixed weight for each pixel, by index.  Pixels in chunks of an
+// unbounded layer that were allocated after the weights were made
+// are unweighted.
+impl SelectionWeight for Vec<f32> {
+    fn weight(&self, _loc: PixelLoc, index: usize) -> f32 {
+        self.get(index).copied().unwrap_or(1.0)
+    }
+}
+
+// Favors frontier points further along the gravity vector, with
+// weights from exp(-strength) at the far side of the layer to 1 at the
+// near side, where the strength is the length of the vector.
+pub(crate) struct GravityBias {
+    direction: (f64, f64),
+    strength: f64,
+
+    // Range of positions along the gravity direction, for each layer.
+    layer_extents: Vec<(f64, f64)>,
+}
+
+impl GravityBias {
+    pub(crate) fn new(topology: &Topology, di: f64, dj: f64) -> Self {
+        let strength = (di * di + dj * dj).sqrt();
+        let direction = if strength > 0.0 {
+            (di / strength, dj / strength)
+        } else {
+            (0.0, 0.0)
+        };
+        let layer_extents = topology
+            .layers()
+            .iter()
+            .map(|layer| {
+                let (w, h) = (layer.width as f64, layer.height as f64);
+                vec![(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)]
+                    .into_iter()
+                    .map(|(i, j)| i * direction.0 + j * direction.1)
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), x| {
+                        (a.min(x), b.max(x))
+                    })
+            })
+            .collect();
+        Self {
+            direction,
+            strength,
+            layer_extents,
+        }
+    }
+}
+
+impl SelectionWeight for GravityBias {
+    fn weight(&self, loc: PixelLoc, _index: usize) -> f32 {
+        let (min, max) = self.layer_extents[loc.layer as usize];
+        if max <= min {
+            return 1.0;
+        }
+        let pos = (loc.i as f64) * self.direction.0
+            + (loc.j as f64) * self.direction.1;
+        let frac = (pos - min) / (max - min);
+        (self.strength * (frac - 1.0)).exp() as f32
+    }
+}
+
+#[derive(Clone)]
 pub struct PointTracker {
     frontier: Vec<PixelLoc>,
     frontier_map: HashMap<PixelLoc, usize>,
     used: Vec<bool>,
     topology: Topology,
+
+    // The seed point from which each pixel was reached, used to
+    // apply per-seed growth cones.
+    origin: Vec<Option<PixelLoc>>,
+    growth_cones: Arc<HashMap<PixelLoc, GrowthCone>>,
+
+    // Frontier points that were removed by thin_frontier().  These
+    // are still candidates to be filled, and are returned to the
+    // frontier at the next thinning, or once the frontier is empty.
+    reserve: Vec<PixelLoc>,
+    reserve_map: HashMap<PixelLoc, usize>,
+
+    // Weights giving the relative probability that a frontier point
+    // is selected.  If empty, all frontier points are equally likely.
+    selection_weights: Vec<Arc<dyn SelectionWeight>>,
+
+    // If set, frontier points are dropped once they have gone
+    // unselected for too long.
+    frontier_age: Option<FrontierAge>,
 }
 
+#[derive(Clone)]
+struct FrontierAge {
+    max_age: usize,
+    num_fills: usize,
+
+    // Points in the order they were added to the frontier, along with
+    // the fill at which they were added.  Entries for points that
+    // were since filled or removed are skipped when they expire.
+    queue: VecDeque<(PixelLoc, usize)>,
+    added_at: HashMap<PixelLoc, usize>,
+}
+
+impl FrontierAge {
+    fn push(&mut self, loc: PixelLoc) {
+        self.queue.push_back((loc, self.num_fills));
+        self.added_at.insert(loc, self.num_fills);
+    }
+}
+
+// Number of times a weighted selection may be rejected before
+// falling back to an exact weighted choice over the entire frontier.
+// Prevents stalling when only low-weight points remain in the
+// frontier.
+const MAX_SELECTION_ATTEMPTS: usize = 100;
+
 impl PointTracker {
     pub fn new(topology: Topology) -> Self {
+        // Pixels outside the shape of their layer may never be added to
+        // the frontier.
+        let used = if topology.num_valid() == topology.len() {
+            vec![false; topology.len()]
+        } else {
+            (0..topology.len())
+                .map(|index| topology.get_loc(index).is_none())
+                .collect()
+        };
         Self {
-            used: vec![false; topology.len()],
+            used,
+            origin: vec![None; topology.len()],
             topology,
             frontier: Vec::new(),
             frontier_map: HashMap::new(),
+            growth_cones: Arc::new(HashMap::new()),
+            reserve: Vec::new(),
+            reserve_map: HashMap::new(),
+            selection_weights: Vec::new(),
+            frontier_age: None,
         }
     }
 
+    // Take on chunks that were allocated in the topology since this
+    // tracker was made, keeping the tracker's own portals.  The new
+    // pixels start out as candidates for the frontier if allowed, or
+    // as forbidden otherwise.
+    pub(crate) fn grow(&mut self, topology: &Topology, allowed: bool) {
+        self.topology.copy_chunks_from(topology);
+        let len = self.topology.len();
+        self.used.resize(len, !allowed);
+        self.origin.resize(len, None);
+    }
+
+    pub(crate) fn set_selection_weights(
+        &mut self,
+        weights: Vec<Arc<dyn SelectionWeight>>,
+    ) {
+        self.selection_weights = weights;
+    }
+
+    // Drop frontier points that go unselected for more than max_age
+    // fills, leaving them permanently skipped.  Ages are counted from
+    // this call, so points already in the frontier start at zero.
+    pub(crate) fn set_max_frontier_age(&mut self, max_age: Option<usize>) {
+        self.frontier_age = max_age.map(|max_age| {
+            let mut frontier_age = FrontierAge {
+                max_age,
+                num_fills: 0,
+                queue: VecDeque::new(),
+                added_at: HashMap::new(),
+            };
+            self.frontier
+                .iter()
+                .chain(self.reserve.iter())
+                .for_each(|&loc| frontier_age.push(loc));
+            frontier_age
+        });
+    }
+
+    pub fn set_growth_cones(
+        &mut self,
+        growth_cones: Arc<HashMap<PixelLoc, GrowthCone>>,
+    ) {
+        self.growth_cones = growth_cones;
+    }
+
     pub fn add_to_frontier(&mut self, loc: PixelLoc) {
         let index = self.topology.get_index(loc);
         if let Some(index) = index {
-            PointTracker::_add_to_frontier(
+            let added = PointTracker::_add_to_frontier(
                 &mut self.frontier,
                 &mut self.frontier_map,
                 &mut self.used,
+                &mut self.origin,
                 index,
                 loc,
+                Some(loc),
             );
+            if added {
+                self.push_frontier_age(loc);
+            }
         }
     }
 
@@ -66,13 +270,18 @@ impl PointTracker {
             .collect::<Vec<_>>()
             .iter()
             .for_each(|&(i_arr, loc)| {
-                PointTracker::_add_to_frontier(
+                let added = PointTracker::_add_to_frontier(
                     &mut self.frontier,
                     &mut self.frontier_map,
                     &mut self.used,
+                    &mut self.origin,
                     i_arr,
                     loc,
-                )
+                    Some(loc),
+                );
+                if added {
+                    self.push_frontier_age(loc);
+                }
             });
     }
 
@@ -80,13 +289,25 @@ impl PointTracker {
         frontier: &mut Vec<PixelLoc>,
         frontier_map: &mut HashMap<PixelLoc, usize>,
         used: &mut Vec<bool>,
+        origin: &mut Vec<Option<PixelLoc>>,
         index: usize,
         loc: PixelLoc,
-    ) {
+        origin_seed: Option<PixelLoc>,
+    ) -> bool {
         if !used[index] {
             frontier_map.insert(loc, frontier.len());
             frontier.push(loc);
             used[index] = true;
+            origin[index] = origin_seed;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn push_frontier_age(&mut self, loc: PixelLoc) {
+        if let Some(frontier_age) = &mut self.frontier_age {
+            frontier_age.push(loc);
         }
     }
 
@@ -98,58 +319,542 @@ impl PointTracker {
         self._mark_used_unused_state(loc, false);
     }
 
+    // Either way, the point is no longer a candidate to be filled.  A
+    // point that is marked as unused may be added to the frontier
+    // again later.
     fn _mark_used_unused_state(&mut self, loc: PixelLoc, val: bool) {
         let index = self.topology.get_index(loc);
         if let Some(index) = index {
             self.used[index] = val;
+            self.remove_from_frontier(loc);
         }
     }
 
     pub fn mark_all_as_used(&mut self) {
         self.used.iter_mut().for_each(|x| *x = true);
+        self.frontier.clear();
+        self.frontier_map.clear();
+        self.reserve.clear();
+        self.reserve_map.clear();
     }
 
     pub fn is_done(&self) -> bool {
         return self.frontier.len() == 0;
     }
 
+    // Whether the point is in the frontier, excluding any points held
+    // in reserve by thin_frontier().
+    pub fn is_in_frontier(&self, loc: PixelLoc) -> bool {
+        self.frontier_map.contains_key(&loc)
+    }
+
     pub fn frontier_size(&self) -> usize {
         self.frontier.len()
     }
 
-    pub fn get_frontier_point(&self, index: usize) -> PixelLoc {
-        self.frontier[index]
+    // Whether the point is outside the frontier and cannot be added
+    // to it, either because it is forbidden or because it has already
+    // been filled.
+    pub fn is_blocked(&self, loc: PixelLoc) -> bool {
+        self.topology.get_index(loc).is_some_and(|index| {
+            self.used[index]
+                && !self.frontier_map.contains_key(&loc)
+                && !self.reserve_map.contains_key(&loc)
+        })
+    }
+
+    // Seed points from which the current points were grown.
+    pub fn seeds(&self) -> HashSet<PixelLoc> {
+        self.origin.iter().flatten().copied().collect()
+    }
+
+    // The seed point from which a frontier point was reached.
+    pub fn origin(&self, loc: PixelLoc) -> Option<PixelLoc> {
+        self.topology
+            .get_index(loc)
+            .and_then(|index| self.origin[index])
+    }
+
+    // Randomly select a point from the frontier.  If selection weights
+    // are set, points are chosen in proportion to their weight.  Uses
+    // rejection sampling, which is fast when most weights are close
+    // to 1, and falls back to an exact weighted choice if too many
+    // points are rejected.  If every weight is zero, all points are
+    // equally likely.
+    pub fn choose_frontier_point(&self, rng: &mut impl Rng) -> PixelLoc {
+        if self.selection_weights.is_empty() {
+            return self.random_frontier_point(rng);
+        }
+
+        for _ in 0..MAX_SELECTION_ATTEMPTS {
+            let loc = self.random_frontier_point(rng);
+            let weight = self.selection_weight(loc);
+            if weight >= 1.0 || rng.gen::<f32>() < weight {
+                return loc;
+            }
+        }
+
+        let weights = self
+            .frontier
+            .iter()
+            .map(|&loc| self.selection_weight(loc).max(0.0))
+            .collect::<Vec<_>>();
+        match WeightedIndex::new(&weights) {
+            Ok(dist) => self.frontier[dist.sample(rng)],
+            Err(_) => self.random_frontier_point(rng),
+        }
+    }
+
+    fn selection_weight(&self, loc: PixelLoc) -> f32 {
+        self.topology.get_index(loc).map_or(1.0, |index| {
+            self.selection_weights
+                .iter()
+                .map(|weights| weights.weight(loc, index))
+                .product()
+        })
+    }
+
+    fn random_frontier_point(&self, rng: &mut impl Rng) -> PixelLoc {
+        self.frontier[rng.gen_range(0..self.frontier_size())]
+    }
+
+    // All points that are candidates to be filled, including any held
+    // in reserve by thin_frontier().
+    pub fn iter_frontier(&self) -> impl Iterator<Item = &PixelLoc> {
+        self.frontier.iter().chain(self.reserve.iter())
     }
 
     pub fn fill(&mut self, loc: PixelLoc) {
+        self.fill_through_portal(loc, true);
+    }
+
+    // Fill the point, but without growing through the portal that
+    // leads from it.  The portal's destination may still be reached
+    // in other ways.
+    pub(crate) fn fill_through_portal(&mut self, loc: PixelLoc, portal: bool) {
         let topology = &self.topology;
         let mut frontier = &mut self.frontier;
         let mut frontier_map = &mut self.frontier_map;
         let mut used = &mut self.used;
+        let mut origin = &mut self.origin;
+        let frontier_age = &mut self.frontier_age;
+        let growth_cones = &self.growth_cones;
+
+        let loc_index = topology.get_index(loc);
+        let origin_seed = loc_index.and_then(|i| origin[i]);
+
+        // Filled points may not be added to the frontier again.
+        if let Some(index) = loc_index {
+            used[index] = true;
+        }
+        let growth_cone = origin_seed
+            .and_then(|seed| growth_cones.get(&seed).map(|c| (seed, c)));
+
+        // Unless the destination is also adjacent within the layer.
+        let skipped_portal = if portal {
+            None
+        } else {
+            topology.portal_from(loc).filter(|dest| {
+                dest.layer != loc.layer
+                    || (dest.i - loc.i).abs() > 1
+                    || (dest.j - loc.j).abs() > 1
+            })
+        };
 
-        topology.iter_adjacent(loc).for_each(|adjacent| {
-            let index = topology.get_index(adjacent);
-            if let Some(index) = index {
-                PointTracker::_add_to_frontier(
+        topology
+            .iter_adjacent_indexed(loc)
+            .filter(|&(_index, adjacent)| Some(adjacent) != skipped_portal)
+            .filter(|&(_index, adjacent)| match growth_cone {
+                Some((seed, cone)) => cone.contains(seed, adjacent),
+                None => true,
+            })
+            .for_each(|(index, adjacent)| {
+                let added = PointTracker::_add_to_frontier(
                     &mut frontier,
                     &mut frontier_map,
                     &mut used,
+                    &mut origin,
                     index,
                     adjacent,
+                    origin_seed,
                 );
+                if added {
+                    if let Some(frontier_age) = frontier_age.as_mut() {
+                        frontier_age.push(adjacent);
+                    }
+                }
+            });
+
+        self.remove_from_frontier(loc);
+        self.expire_frontier();
+
+        if self.frontier.is_empty() {
+            self.restore_reserve();
+        }
+    }
+
+    // Advance the age of the frontier by one fill, and remove any
+    // points older than the maximum age.  These remain marked as
+    // used, so they are never added to the frontier again.
+    fn expire_frontier(&mut self) {
+        let mut frontier_age = match self.frontier_age.take() {
+            Some(frontier_age) => frontier_age,
+            None => return,
+        };
+
+        frontier_age.num_fills += 1;
+        while let Some(&(loc, added)) = frontier_age.queue.front() {
+            if frontier_age.num_fills - added <= frontier_age.max_age {
+                break;
+            }
+            frontier_age.queue.pop_front();
+            if frontier_age.added_at.get(&loc) == Some(&added) {
+                frontier_age.added_at.remove(&loc);
+                self.remove_from_frontier(loc);
+            }
+        }
+
+        self.frontier_age = Some(frontier_age);
+    }
+
+    // Thin the frontier such that no two frontier points on the same
+    // layer are within min_distance of each other.  Points are
+    // considered in random order, and any that are too close to an
+    // already-kept point are held in reserve.
+    pub fn thin_frontier(&mut self, min_distance: f64, rng: &mut impl Rng) {
+        self.restore_reserve();
+        if min_distance <= 0.0 {
+            return;
+        }
+
+        let mut candidates = std::mem::take(&mut self.frontier);
+        candidates.shuffle(rng);
+        self.frontier_map.clear();
+
+        // Spatial hash of kept points, with cells of size
+        // min_distance, so only the adjacent cells need to be checked.
+        let cell_of = |loc: &PixelLoc| {
+            (
+                loc.layer,
+                (loc.i as f64 / min_distance).floor() as i64,
+                (loc.j as f64 / min_distance).floor() as i64,
+            )
+        };
+        let mut grid: HashMap<(u8, i64, i64), Vec<PixelLoc>> = HashMap::new();
+        let min_dist2 = min_distance * min_distance;
+
+        candidates.into_iter().for_each(|loc| {
+            let (layer, ci, cj) = cell_of(&loc);
+            let too_close = (-1..=1)
+                .flat_map(|di| (-1..=1).map(move |dj| (di, dj)))
+                .flat_map(|(di, dj)| grid.get(&(layer, ci + di, cj + dj)))
+                .flatten()
+                .any(|other| {
+                    let di = (other.i - loc.i) as f64;
+                    let dj = (other.j - loc.j) as f64;
+                    di * di + dj * dj < min_dist2
+                });
+
+            if too_close {
+                self.reserve_map.insert(loc, self.reserve.len());
+                self.reserve.push(loc);
+            } else {
+                grid.entry((layer, ci, cj)).or_default().push(loc);
+                self.frontier_map.insert(loc, self.frontier.len());
+                self.frontier.push(loc);
             }
         });
+    }
 
-        self.remove_from_frontier(loc);
+    fn restore_reserve(&mut self) {
+        self.reserve_map.clear();
+        let reserve = std::mem::take(&mut self.reserve);
+        reserve.into_iter().for_each(|loc| {
+            self.frontier_map.insert(loc, self.frontier.len());
+            self.frontier.push(loc);
+        });
     }
 
     fn remove_from_frontier(&mut self, loc: PixelLoc) {
-        let index = self.frontier_map.get(&loc).map(|i| *i);
-        if let Some(index) = index {
-            let last_point = *self.frontier.last().unwrap();
-            self.frontier_map.insert(last_point, index);
-            self.frontier.swap_remove(index);
-            self.frontier_map.remove(&loc);
+        Self::swap_remove_point(
+            &mut self.frontier,
+            &mut self.frontier_map,
+            loc,
+        );
+        Self::swap_remove_point(&mut self.reserve, &mut self.reserve_map, loc);
+    }
+
+    fn swap_remove_point(
+        points: &mut Vec<PixelLoc>,
+        map: &mut HashMap<PixelLoc, usize>,
+        loc: PixelLoc,
+    ) {
+        if let Some(index) = map.remove(&loc) {
+            let last_point = *points.last().unwrap();
+            points.swap_remove(index);
+            if last_point != loc {
+                map.insert(last_point, index);
+            }
+        }
+    }
+
+    // Panics if the internal bookkeeping is inconsistent.
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub(crate) fn check_invariants(&self) {
+        let check_map =
+            |points: &[PixelLoc], map: &HashMap<PixelLoc, usize>| {
+                assert_eq!(points.len(), map.len());
+                points.iter().enumerate().for_each(|(i, loc)| {
+                    assert_eq!(
+                        map.get(loc),
+                        Some(&i),
+                        "{:?} out of place",
+                        loc
+                    );
+                });
+            };
+        check_map(&self.frontier, &self.frontier_map);
+        check_map(&self.reserve, &self.reserve_map);
+
+        let mut seen = HashSet::new();
+        self.iter_frontier().for_each(|&loc| {
+            assert!(seen.insert(loc), "{:?} is in the frontier twice", loc);
+            let index = self.topology.get_index(loc);
+            assert!(index.is_some(), "{:?} is out of bounds", loc);
+            assert!(self.used[index.unwrap()], "{:?} is not used", loc);
+        });
+    }
+}
+
+// Operations on a PointTracker, for randomized testing.
+#[cfg(any(test, feature = "fuzzing"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum TrackerOp {
+    AddToFrontier(PixelLoc),
+    Fill(PixelLoc),
+    FillChosen,
+    MarkAsUsed(PixelLoc),
+    MarkAsUnused(PixelLoc),
+    ThinFrontier(f64),
+}
+
+// Applies the operations to a PointTracker on a single layer, checking
+// the invariants after each one.  Pixels are treated the same way
+// that GrowthImage does: filled by fill(), and cleared by
+// mark_as_unused().  Panics on any inconsistency.
+#[cfg(any(test, feature = "fuzzing"))]
+pub(crate) fn run_tracker_ops(width: u32, height: u32, ops: &[TrackerOp]) {
+    use rand::SeedableRng;
+
+    use crate::topology::RectangularArray;
+
+    let topology = Topology::new(
+        vec![RectangularArray { width, height }],
+        Arc::new(HashMap::new()),
+    );
+    let mut tracker = PointTracker::new(topology.clone());
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut filled = HashSet::new();
+
+    let fill = |tracker: &mut PointTracker,
+                filled: &mut HashSet<PixelLoc>,
+                loc: PixelLoc| {
+        tracker.fill(loc);
+        if topology.is_valid(loc) {
+            filled.insert(loc);
+        }
+
+        // Filling a second time has no effect.
+        let frontier = tracker.iter_frontier().copied().collect::<Vec<_>>();
+        tracker.fill(loc);
+        assert!(tracker.iter_frontier().eq(frontier.iter()));
+    };
+
+    ops.iter().for_each(|&op| {
+        match op {
+            TrackerOp::AddToFrontier(loc) => tracker.add_to_frontier(loc),
+            TrackerOp::Fill(loc) => fill(&mut tracker, &mut filled, loc),
+            TrackerOp::FillChosen => {
+                if !tracker.is_done() {
+                    let loc = tracker.choose_frontier_point(&mut rng);
+                    fill(&mut tracker, &mut filled, loc);
+                }
+            }
+            TrackerOp::MarkAsUsed(loc) => {
+                tracker.mark_as_used(loc);
+                assert!(tracker.iter_frontier().all(|&p| p != loc));
+            }
+            TrackerOp::MarkAsUnused(loc) => {
+                tracker.mark_as_unused(loc);
+                filled.remove(&loc);
+            }
+            TrackerOp::ThinFrontier(min_distance) => {
+                let before = tracker.iter_frontier().count();
+                tracker.thin_frontier(min_distance, &mut rng);
+                assert_eq!(tracker.iter_frontier().count(), before);
+            }
+        }
+
+        tracker.check_invariants();
+        assert!(
+            tracker.iter_frontier().all(|loc| !filled.contains(loc)),
+            "Filled point in frontier after {:?}",
+            op
+        );
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    fn loc(i: i32, j: i32) -> PixelLoc {
+        PixelLoc { layer: 0, i, j }
+    }
+
+    #[test]
+    fn test_fill_adds_neighbors() {
+        let topology = Topology::new(
+            vec![crate::topology::RectangularArray {
+                width: 3,
+                height: 3,
+            }],
+            Arc::new(HashMap::new()),
+        );
+        let mut tracker = PointTracker::new(topology);
+
+        tracker.add_to_frontier(loc(0, 0));
+        assert_eq!(tracker.frontier_size(), 1);
+        assert_eq!(tracker.origin(loc(0, 0)), Some(loc(0, 0)));
+
+        tracker.fill(loc(0, 0));
+        let mut frontier = tracker.iter_frontier().copied().collect::<Vec<_>>();
+        frontier.sort_by_key(|p| (p.i, p.j));
+        assert_eq!(frontier, vec![loc(0, 1), loc(1, 0), loc(1, 1)]);
+        assert_eq!(tracker.origin(loc(1, 1)), Some(loc(0, 0)));
+
+        tracker.mark_as_used(loc(1, 1));
+        assert_eq!(tracker.frontier_size(), 2);
+        tracker.check_invariants();
+
+        tracker.fill(loc(1, 0));
+        assert!(tracker.iter_frontier().all(|&p| p != loc(0, 0)));
+        assert!(tracker.iter_frontier().all(|&p| p != loc(1, 1)));
+        tracker.check_invariants();
+    }
+
+    #[test]
+    fn test_weighted_selection() {
+        use rand::SeedableRng;
+
+        let topology = Topology::new(
+            vec![crate::topology::RectangularArray {
+                width: 10,
+                height: 10,
+            }],
+            Arc::new(HashMap::new()),
+        );
+        let mut tracker = PointTracker::new(topology.clone());
+        (0..10).for_each(|i| tracker.add_to_frontier(loc(i, 0)));
+
+        // Only one point may be selected.
+        let mut weights = vec![0.0; topology.len()];
+        weights[topology.get_index(loc(3, 0)).unwrap()] = 0.5;
+        tracker.set_selection_weights(vec![Arc::new(weights)]);
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        (0..50).for_each(|_| {
+            assert_eq!(tracker.choose_frontier_point(&mut rng), loc(3, 0));
+        });
+
+        // No point may be selected, so all are equally likely.
+        tracker.set_selection_weights(vec![Arc::new(vec![0.0; 100])]);
+        let chosen = (0..200)
+            .map(|_| tracker.choose_frontier_point(&mut rng))
+            .collect::<HashSet<_>>();
+        assert_eq!(chosen.len(), 10);
+    }
+
+    #[test]
+    fn test_frontier_max_age() {
+        let topology = Topology::new(
+            vec![crate::topology::RectangularArray {
+                width: 10,
+                height: 1,
+            }],
+            Arc::new(HashMap::new()),
+        );
+        let mut tracker = PointTracker::new(topology);
+        tracker.add_to_frontier(loc(0, 0));
+        tracker.add_to_frontier(loc(9, 0));
+        tracker.set_max_frontier_age(Some(2));
+
+        tracker.fill(loc(0, 0));
+        tracker.fill(loc(1, 0));
+        assert!(tracker.is_in_frontier(loc(9, 0)));
+
+        // Unselected for three fills.
+        tracker.fill(loc(2, 0));
+        assert!(!tracker.is_in_frontier(loc(9, 0)));
+        assert!(tracker.is_blocked(loc(9, 0)));
+        assert!(tracker.is_in_frontier(loc(3, 0)));
+        tracker.check_invariants();
+
+        // Expired points are not added again by their neighbors.
+        tracker.fill(loc(8, 0));
+        assert!(!tracker.is_in_frontier(loc(9, 0)));
+        tracker.check_invariants();
+
+        // Ages are reset when the limit is removed.
+        tracker.set_max_frontier_age(None);
+        (0..5).for_each(|_| tracker.fill(loc(9, 0)));
+        assert!(tracker.is_in_frontier(loc(3, 0)));
+    }
+
+    #[test]
+    fn test_thin_frontier() {
+        let ops = (0..10)
+            .map(|i| TrackerOp::AddToFrontier(loc(i, i)))
+            .chain(std::iter::once(TrackerOp::ThinFrontier(5.0)))
+            .chain((0..10).map(|i| TrackerOp::Fill(loc(i, i))))
+            .chain(std::iter::repeat(TrackerOp::FillChosen).take(200))
+            .collect::<Vec<_>>();
+        run_tracker_ops(10, 10, &ops);
+    }
+
+    #[test]
+    fn test_fill_from_previous_stage() {
+        // Filling adjacent points one after another, as done at the
+        // start of a stage, must not return earlier points to the
+        // frontier.
+        let ops = (0..5)
+            .map(|i| TrackerOp::Fill(loc(i, 0)))
+            .collect::<Vec<_>>();
+        run_tracker_ops(5, 5, &ops);
+    }
+
+    fn arb_op(width: i32, height: i32) -> impl Strategy<Value = TrackerOp> {
+        let arb_loc =
+            (-2..width + 2, -2..height + 2).prop_map(|(i, j)| loc(i, j));
+        prop_oneof![
+            arb_loc.clone().prop_map(TrackerOp::AddToFrontier),
+            arb_loc.clone().prop_map(TrackerOp::Fill),
+            Just(TrackerOp::FillChosen),
+            Just(TrackerOp::FillChosen),
+            arb_loc.clone().prop_map(TrackerOp::MarkAsUsed),
+            arb_loc.prop_map(TrackerOp::MarkAsUnused),
+            (0.0..4.0).prop_map(TrackerOp::ThinFrontier),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_tracker_consistent(
+            ops in proptest::collection::vec(arb_op(8, 6), 0..200)
+        ) {
+            run_tracker_ops(8, 6, &ops);
         }
     }
 }
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+// Progress of the fill, either as a progress bar, or as plain lines
+// of text when stderr isn't a terminal.  Redrawing a bar into a log
+// file leaves it unreadable.
+pub(crate) enum Progress {
+    Bar(ProgressBar),
+    Text(ProgressText),
+}
+
+pub(crate) struct ProgressText {
+    pos: u64,
+    len: u64,
+    interval: Duration,
+    start: Instant,
+    last_print: Instant,
+}
+
+impl Progress {
+    // Falls back to plain text if stderr isn't a terminal, unless
+    // text_interval is None, in which case nothing is shown.
+    pub(crate) fn new(
+        len: u64,
+        draw_rate: u64,
+        text_interval: Option<Duration>,
+    ) -> Option<Self> {
+        let bar = ProgressBar::new(len);
+        if bar.is_hidden() {
+            return text_interval
+                .map(|interval| Self::Text(ProgressText::new(len, interval)));
+        }
+
+        bar.set_style(ProgressStyle::default_bar().template(
+            "[{pos}/{len}] {wide_bar} [{elapsed_precise}, ETA: {eta_precise}]",
+        ));
+        bar.set_draw_rate(draw_rate);
+        Some(Self::Bar(bar))
+    }
+
+    pub(crate) fn set_length(&mut self, len: u64) {
+        match self {
+            Self::Bar(bar) => bar.set_length(len),
+            Self::Text(text) => text.len = len,
+        }
+    }
+
+    pub(crate) fn inc(&mut self, delta: u64) {
+        match self {
+            Self::Bar(bar) => bar.inc(delta),
+            Self::Text(text) => {
+                text.pos += delta;
+                if text.last_print.elapsed() >= text.interval {
+                    text.print();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        match self {
+            Self::Bar(bar) => bar.finish(),
+            Self::Text(text) => text.print(),
+        }
+    }
+}
+
+impl ProgressText {
+    fn new(len: u64, interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            pos: 0,
+            len,
+            interval,
+            start: now,
+            last_print: now,
+        }
+    }
+
+    fn print(&mut self) {
+        self.last_print = Instant::now();
+        eprintln!(
+            "{}",
+            format_progress_line(self.pos, self.len, self.start.elapsed())
+        );
+    }
+}
+
+fn format_progress_line(pos: u64, len: u64, elapsed: Duration) -> String {
+    let percent = if len == 0 {
+        100.0
+    } else {
+        100.0 * (pos as f64) / (len as f64)
+    };
+    let secs = elapsed.as_secs();
+    format!(
+        "{:5.1}% [{}/{}] elapsed {:02}:{:02}:{:02}",
+        percent,
+        pos,
+        len,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_progress_line() {
+        assert_eq!(
+            format_progress_line(250, 1000, Duration::from_secs(3723)),
+            " 25.0% [250/1000] elapsed 01:02:03"
+        );
+        assert_eq!(
+            format_progress_line(0, 0, Duration::from_secs(0)),
+            "100.0% [0/0] elapsed 00:00:00"
+        );
+    }
+}
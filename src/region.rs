@@ -0,0 +1,785 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use itertools::Either;
+
+use crate::errors::Error;
+use crate::growth_image::SaveImageData;
+use crate::topology::{PixelLoc, RectangularArray, Topology};
+
+const REGION_MAGIC: &[u8; 4] = b"OCRG";
+const REGION_VERSION: u8 = 2;
+
+// How the points of one region are matched to the points of another,
+// when connecting regions of different sizes with portals.
+pub enum Mapping {
+    // Stretch the bounding box of each region onto the other, so that
+    // a short edge connects proportionally along a long edge.
+    Scale,
+
+    // Repeat the smaller region along the larger one, wrapping around
+    // the bounding box of the smaller region.
+    Tile,
+
+    // Arbitrary mapping from points of the first region to points of
+    // the second.  Points that map to None, or to a point outside of
+    // the second region, are left unconnected.
+    Custom(Box<dyn Fn(PixelLoc) -> Option<PixelLoc>>),
+}
+
+// Bounding box of a set of points, ignoring their layers.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    i0: i32,
+    j0: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Bounds {
+    fn of(points: &[PixelLoc]) -> Option<Self> {
+        let i0 = points.iter().map(|loc| loc.i).min()?;
+        let j0 = points.iter().map(|loc| loc.j).min()?;
+        let i1 = points.iter().map(|loc| loc.i).max()?;
+        let j1 = points.iter().map(|loc| loc.j).max()?;
+        Some(Self {
+            i0,
+            j0,
+            width: i1 - i0 + 1,
+            height: j1 - j0 + 1,
+        })
+    }
+}
+
+// Position along a side of length `to` corresponding to `offset`
+// along a side of length `from`, matching the endpoints.
+fn scale_offset(offset: i32, from: i32, to: i32) -> i32 {
+    if from <= 1 {
+        (to - 1) / 2
+    } else {
+        ((offset as f64) * ((to - 1) as f64) / ((from - 1) as f64)).round()
+            as i32
+    }
+}
+
+fn boundary_points(image: &SaveImageData, threshold: f32) -> Vec<(i32, i32)> {
+    let width = image.width as i32;
+    let height = image.height as i32;
+    let color = |i: i32, j: i32| {
+        let index = 4 * (j * width + i) as usize;
+        let p = &image.data[index..index + 4];
+        if p[3] > 0 {
+            Some([p[0] as f32, p[1] as f32, p[2] as f32])
+        } else {
+            None
+        }
+    };
+    let threshold2 = threshold * threshold;
+    let differs =
+        |a: (i32, i32), b: (i32, i32)| match (color(a.0, a.1), color(b.0, b.1))
+        {
+            (Some(a), Some(b)) => {
+                let dist2 = a
+                    .iter()
+                    .zip(b.iter())
+                    .map(|(x, y)| (x - y) * (x - y))
+                    .sum::<f32>();
+                dist2 > threshold2
+            }
+            _ => false,
+        };
+
+    let mut on_boundary = vec![false; (width * height) as usize];
+    (0..height)
+        .flat_map(|j| (0..width).map(move |i| (i, j)))
+        .for_each(|(i, j)| {
+            [(i + 1, j), (i, j + 1)]
+                .iter()
+                .filter(|&&(ni, nj)| ni < width && nj < height)
+                .filter(|&&neighbor| differs((i, j), neighbor))
+                .for_each(|&(ni, nj)| {
+                    on_boundary[(j * width + i) as usize] = true;
+                    on_boundary[(nj * width + ni) as usize] = true;
+                });
+        });
+
+    (0..height)
+        .flat_map(|j| (0..width).map(move |i| (i, j)))
+        .filter(|&(i, j)| on_boundary[(j * width + i) as usize])
+        .collect()
+}
+
+// A set of pixels, stored as a bitset over every layer of an image.
+// Regions can be saved to disk, so that expensive geometric
+// preprocessing (distance maps, SVG rasterization) can be reused
+// between runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    layers: Vec<RectangularArray>,
+    bits: Vec<u8>,
+
+    // Points of each unbounded layer, which can't be covered by a
+    // bitset.  Stored as (j, i), so that they are in row-major order.
+    unbounded: BTreeMap<u8, BTreeSet<(i32, i32)>>,
+}
+
+impl Region {
+    pub fn new(layer_sizes: &[(u32, u32)]) -> Self {
+        let layers = layer_sizes
+            .iter()
+            .map(|&(width, height)| RectangularArray { width, height })
+            .collect::<Vec<_>>();
+        let num_pixels = layers.iter().map(|layer| layer.len()).sum::<usize>();
+        Self {
+            layers,
+            bits: vec![0; num_pixels.div_ceil(8)],
+            unbounded: BTreeMap::new(),
+        }
+    }
+
+    // Empty region with the layers of the topology, including any
+    // unbounded layers, whose points may have any coordinates.
+    pub fn for_topology(topology: &Topology) -> Self {
+        let layer_sizes = topology
+            .layers()
+            .iter()
+            .map(|layer| (layer.width, layer.height))
+            .collect::<Vec<_>>();
+        let mut region = Self::new(&layer_sizes);
+        region.unbounded = (0..layer_sizes.len() as u8)
+            .filter(|&layer| topology.is_unbounded(layer))
+            .map(|layer| (layer, BTreeSet::new()))
+            .collect();
+        region
+    }
+
+    pub fn from_points(
+        layer_sizes: &[(u32, u32)],
+        points: impl IntoIterator<Item = PixelLoc>,
+    ) -> Self {
+        let mut region = Self::new(layer_sizes);
+        points.into_iter().for_each(|loc| region.insert(loc));
+        region
+    }
+
+    fn get_index(&self, loc: PixelLoc) -> Option<usize> {
+        let layer = self.layers.get(loc.layer as usize)?;
+        let offset = self.layers[0..(loc.layer as usize)]
+            .iter()
+            .map(|prev_layer| prev_layer.len())
+            .sum::<usize>();
+        layer.get_index(loc).map(|index| index + offset)
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.bits[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    // Add a point to the region.  Points outside of the layers are
+    // ignored.
+    pub fn insert(&mut self, loc: PixelLoc) {
+        if let Some(points) = self.unbounded.get_mut(&loc.layer) {
+            points.insert((loc.j, loc.i));
+        } else if let Some(index) = self.get_index(loc) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn remove(&mut self, loc: PixelLoc) {
+        if let Some(points) = self.unbounded.get_mut(&loc.layer) {
+            points.remove(&(loc.j, loc.i));
+        } else if let Some(index) = self.get_index(loc) {
+            self.bits[index / 8] &= !(1 << (index % 8));
+        }
+    }
+
+    pub fn contains(&self, loc: PixelLoc) -> bool {
+        if let Some(points) = self.unbounded.get(&loc.layer) {
+            return points.contains(&(loc.j, loc.i));
+        }
+        self.get_index(loc).is_some_and(|index| self.is_set(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|b| b.count_ones() as usize)
+            .sum::<usize>()
+            + self
+                .unbounded
+                .values()
+                .map(|points| points.len())
+                .sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&b| b == 0)
+            && self.unbounded.values().all(|points| points.is_empty())
+    }
+
+    pub fn points(&self) -> Vec<PixelLoc> {
+        self.layers
+            .iter()
+            .enumerate()
+            .scan(0, |offset, (layer_i, layer)| {
+                let layer_offset = *offset;
+                *offset += layer.len();
+                Some((layer_offset, layer_i as u8, layer))
+            })
+            .flat_map(|(offset, layer_i, layer)| {
+                match self.unbounded.get(&layer_i) {
+                    Some(points) => {
+                        Either::Left(points.iter().map(move |&(j, i)| {
+                            PixelLoc {
+                                layer: layer_i,
+                                i,
+                                j,
+                            }
+                        }))
+                    }
+                    None => Either::Right(
+                        (0..layer.len())
+                            .filter(move |index| self.is_set(offset + index))
+                            .flat_map(move |index| {
+                                layer.get_loc(layer_i, index)
+                            }),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    // Morphological operations, measuring distance as the number of
+    // steps between adjacent pixels of the topology, including
+    // through portals.  The topology should have the same layers as
+    // the region.  For example, a band 10 pixels wide around a logo
+    // is the pixels of logo.dilate(&topology, 10) that aren't in the
+    // logo.
+
+    // The region, along with every pixel within n steps of it.
+    pub fn dilate(&self, topology: &Topology, n: usize) -> Self {
+        let mut output = self.clone();
+        self.within_steps(topology, &self.points(), n)
+            .into_iter()
+            .for_each(|loc| output.insert(loc));
+        output
+    }
+
+    // The pixels of the region that are more than n steps from every
+    // pixel outside of it.  Only pixels of the topology count as
+    // outside, so the edges of a layer don't erode the region.
+    pub fn erode(&self, topology: &Topology, n: usize) -> Self {
+        let outside = (0..topology.len())
+            .flat_map(|index| topology.get_loc(index))
+            .filter(|&loc| !self.contains(loc))
+            .collect::<Vec<_>>();
+        let mut output = self.clone();
+        self.within_steps(topology, &outside, n)
+            .into_iter()
+            .for_each(|loc| output.remove(loc));
+        output
+    }
+
+    // Erosion followed by dilation, removing features of the region
+    // narrower than about 2n pixels.
+    pub fn open(&self, topology: &Topology, n: usize) -> Self {
+        self.erode(topology, n).dilate(topology, n)
+    }
+
+    // Dilation followed by erosion, filling in gaps of the region
+    // narrower than about 2n pixels.
+    pub fn close(&self, topology: &Topology, n: usize) -> Self {
+        self.dilate(topology, n).erode(topology, n)
+    }
+
+    fn within_steps(
+        &self,
+        topology: &Topology,
+        seeds: &[PixelLoc],
+        n: usize,
+    ) -> Vec<PixelLoc> {
+        topology
+            .distance_field_from(seeds, &Region::new(&[]))
+            .into_iter()
+            .enumerate()
+            .filter(|(_, dist)| dist.is_some_and(|dist| dist <= n))
+            .flat_map(|(index, _)| topology.get_loc(index))
+            .collect()
+    }
+
+    // Pixels on the boundaries between color regions of a previously
+    // generated image, for use as walls or seed points of a follow-up
+    // render.  A pixel is on a boundary if its color differs from a
+    // horizontally or vertically adjacent pixel by more than the
+    // threshold, as a Euclidean distance in RGB.  Both sides of each
+    // boundary are included, so the boundaries can't be crossed
+    // diagonally.  Transparent pixels are skipped.  The image must be
+    // the same size as the given layer.
+    pub fn from_image_boundaries(
+        layer_sizes: &[(u32, u32)],
+        layer: u8,
+        filename: impl AsRef<Path>,
+        threshold: f32,
+    ) -> Result<Self, Error> {
+        let image = SaveImageData::read_png(filename.as_ref())?;
+        if layer_sizes.get(layer as usize) != Some(&(image.width, image.height))
+        {
+            return Err(Error::ImageSizeMismatch);
+        }
+        Ok(Self::from_points(
+            layer_sizes,
+            boundary_points(&image, threshold)
+                .into_iter()
+                .map(|(i, j)| PixelLoc { layer, i, j }),
+        ))
+    }
+
+    // Pair each point of this region with a point of the target
+    // region, according to the mapping.  With Mapping::Scale and
+    // Mapping::Tile, a position that falls outside of the target
+    // region is moved to the nearest point of the target region, so
+    // that every point is paired.
+    pub fn map_onto(
+        &self,
+        target: &Region,
+        mapping: &Mapping,
+    ) -> Vec<(PixelLoc, PixelLoc)> {
+        let source_points = self.points();
+        let target_points = target.points();
+        let (source_bounds, target_bounds) =
+            match (Bounds::of(&source_points), Bounds::of(&target_points)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Vec::new(),
+            };
+
+        let by_position = target_points
+            .iter()
+            .map(|&loc| ((loc.i, loc.j), loc))
+            .collect::<HashMap<_, _>>();
+        let nearest = |i: i32, j: i32| -> PixelLoc {
+            by_position.get(&(i, j)).copied().unwrap_or_else(|| {
+                *target_points
+                    .iter()
+                    .min_by_key(|loc| {
+                        let di = (loc.i - i) as i64;
+                        let dj = (loc.j - j) as i64;
+                        di * di + dj * dj
+                    })
+                    .unwrap()
+            })
+        };
+
+        source_points
+            .iter()
+            .flat_map(|&loc| {
+                let di = loc.i - source_bounds.i0;
+                let dj = loc.j - source_bounds.j0;
+                let mapped = match mapping {
+                    Mapping::Scale => Some(nearest(
+                        target_bounds.i0
+                            + scale_offset(
+                                di,
+                                source_bounds.width,
+                                target_bounds.width,
+                            ),
+                        target_bounds.j0
+                            + scale_offset(
+                                dj,
+                                source_bounds.height,
+                                target_bounds.height,
+                            ),
+                    )),
+                    Mapping::Tile => Some(nearest(
+                        target_bounds.i0 + di.rem_euclid(target_bounds.width),
+                        target_bounds.j0 + dj.rem_euclid(target_bounds.height),
+                    )),
+                    Mapping::Custom(func) => {
+                        func(loc).filter(|&mapped| target.contains(mapped))
+                    }
+                };
+                mapped.map(|mapped| (loc, mapped))
+            })
+            .collect()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = std::fs::File::create(path)?;
+        self.write_to(&mut std::io::BufWriter::new(file), true)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = std::fs::File::open(path)?;
+        Self::read_from(&mut std::io::BufReader::new(file))
+    }
+
+    // File format, all integers little-endian:
+    //   4 bytes   magic number, "OCRG"
+    //   u8        version
+    //   u8        1 if the bitset is run-length encoded, 0 otherwise
+    //   u32       number of layers
+    //   u32, u32  width and height of each layer
+    //   u32       number of unbounded layers
+    //   for each unbounded layer:
+    //     u8        layer
+    //     u32       number of points
+    //     i32, i32  i and j of each point
+    //   ...       bitset, either raw or as (count, byte) pairs
+    // Version 1 files, which have no unbounded layers, omit the
+    // unbounded layer section.
+    pub fn write_to(
+        &self,
+        writer: &mut impl Write,
+        compress: bool,
+    ) -> Result<(), Error> {
+        writer.write_all(REGION_MAGIC)?;
+        writer.write_all(&[REGION_VERSION, compress as u8])?;
+        writer.write_all(&(self.layers.len() as u32).to_le_bytes())?;
+        for layer in self.layers.iter() {
+            writer.write_all(&layer.width.to_le_bytes())?;
+            writer.write_all(&layer.height.to_le_bytes())?;
+        }
+        writer.write_all(&(self.unbounded.len() as u32).to_le_bytes())?;
+        for (&layer, points) in self.unbounded.iter() {
+            writer.write_all(&[layer])?;
+            writer.write_all(&(points.len() as u32).to_le_bytes())?;
+            for &(j, i) in points.iter() {
+                writer.write_all(&i.to_le_bytes())?;
+                writer.write_all(&j.to_le_bytes())?;
+            }
+        }
+
+        if compress {
+            let mut remaining = &self.bits[..];
+            while let Some(&byte) = remaining.first() {
+                let count = remaining
+                    .iter()
+                    .take(255)
+                    .take_while(|&&b| b == byte)
+                    .count();
+                writer.write_all(&[count as u8, byte])?;
+                remaining = &remaining[count..];
+            }
+        } else {
+            writer.write_all(&self.bits)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self, Error> {
+        let mut header = [0u8; 6];
+        reader.read_exact(&mut header)?;
+        let version = header[4];
+        if &header[0..4] != REGION_MAGIC
+            || !(1..=REGION_VERSION).contains(&version)
+        {
+            return Err(Error::InvalidRegionFile);
+        }
+        let compressed = header[5] != 0;
+
+        let read_u32 = |reader: &mut dyn Read| -> Result<u32, Error> {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+
+        let num_layers = read_u32(reader)?;
+        let layer_sizes = (0..num_layers)
+            .map(|_| Ok((read_u32(reader)?, read_u32(reader)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut region = Self::new(&layer_sizes);
+        if version >= 2 {
+            let num_unbounded = read_u32(reader)?;
+            for _ in 0..num_unbounded {
+                let mut layer = [0u8; 1];
+                reader.read_exact(&mut layer)?;
+                if layer[0] as usize >= layer_sizes.len() {
+                    return Err(Error::InvalidRegionFile);
+                }
+                let num_points = read_u32(reader)?;
+                let points = (0..num_points)
+                    .map(|_| {
+                        let i = read_u32(reader)? as i32;
+                        let j = read_u32(reader)? as i32;
+                        Ok((j, i))
+                    })
+                    .collect::<Result<BTreeSet<_>, Error>>()?;
+                region.unbounded.insert(layer[0], points);
+            }
+        }
+
+        if compressed {
+            let mut pos = 0;
+            while pos < region.bits.len() {
+                let mut pair = [0u8; 2];
+                reader.read_exact(&mut pair)?;
+                let count = pair[0] as usize;
+                if count == 0 || pos + count > region.bits.len() {
+                    return Err(Error::InvalidRegionFile);
+                }
+                region.bits[pos..pos + count]
+                    .iter_mut()
+                    .for_each(|b| *b = pair[1]);
+                pos += count;
+            }
+        } else {
+            reader.read_exact(&mut region.bits)?;
+        }
+
+        Ok(region)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_region_roundtrip() -> Result<(), Error> {
+        let layer_sizes = [(20, 10), (5, 5)];
+        let points = (0..20)
+            .map(|i| PixelLoc { layer: 0, i, j: 3 })
+            .chain((0..5).map(|j| PixelLoc { layer: 1, i: 2, j }))
+            .collect::<Vec<_>>();
+        let region = Region::from_points(&layer_sizes, points.clone());
+
+        assert_eq!(region.len(), 25);
+        assert!(region.contains(PixelLoc {
+            layer: 1,
+            i: 2,
+            j: 4
+        }));
+        assert!(!region.contains(PixelLoc {
+            layer: 0,
+            i: 2,
+            j: 4
+        }));
+        assert_eq!(region.points(), points);
+
+        for &compress in [false, true].iter() {
+            let mut buf = Vec::new();
+            region.write_to(&mut buf, compress)?;
+            let loaded = Region::read_from(&mut &buf[..])?;
+            assert_eq!(loaded, region);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbounded_region() -> Result<(), Error> {
+        let mut topology = Topology::new(
+            vec![RectangularArray {
+                width: 4,
+                height: 4,
+            }],
+            Default::default(),
+        );
+        topology.add_unbounded_layer();
+        let points = vec![
+            PixelLoc {
+                layer: 0,
+                i: 1,
+                j: 2,
+            },
+            PixelLoc {
+                layer: 1,
+                i: 5000,
+                j: -7000,
+            },
+            PixelLoc {
+                layer: 1,
+                i: -3,
+                j: -7000,
+            },
+            PixelLoc {
+                layer: 1,
+                i: 2,
+                j: 9,
+            },
+        ];
+        let mut region = Region::for_topology(&topology);
+        points.iter().for_each(|&loc| region.insert(loc));
+
+        assert_eq!(region.len(), 4);
+        assert!(region.contains(points[1]));
+        assert!(!region.contains(PixelLoc {
+            layer: 1,
+            i: 0,
+            j: 0
+        }));
+        let mut sorted = points.clone();
+        sorted.swap(1, 2);
+        assert_eq!(region.points(), sorted);
+
+        let mut buf = Vec::new();
+        region.write_to(&mut buf, true)?;
+        assert_eq!(Region::read_from(&mut &buf[..])?, region);
+
+        region.remove(points[1]);
+        assert_eq!(region.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_region_version_1() -> Result<(), Error> {
+        let region = Region::from_points(
+            &[(3, 3)],
+            vec![PixelLoc {
+                layer: 0,
+                i: 1,
+                j: 1,
+            }],
+        );
+        let mut buf = Vec::new();
+        region.write_to(&mut buf, false)?;
+
+        // Version 1 had no count of unbounded layers after the layer
+        // sizes.
+        buf[4] = 1;
+        buf.drain(18..22);
+        assert_eq!(Region::read_from(&mut &buf[..])?, region);
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_onto() {
+        let layer_sizes = [(10, 10), (3, 1)];
+        let edge = Region::from_points(
+            &layer_sizes,
+            (0..10).map(|i| PixelLoc { layer: 0, i, j: 0 }),
+        );
+        let bridge = Region::from_points(
+            &layer_sizes,
+            (0..3).map(|i| PixelLoc { layer: 1, i, j: 0 }),
+        );
+        let as_i = |pairs: Vec<(PixelLoc, PixelLoc)>| {
+            pairs.iter().map(|(a, b)| (a.i, b.i)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            as_i(bridge.map_onto(&edge, &Mapping::Scale)),
+            vec![(0, 0), (1, 5), (2, 9)]
+        );
+        let backward = edge.map_onto(&bridge, &Mapping::Scale);
+        assert!(backward.iter().all(|(_, b)| b.layer == 1));
+        assert_eq!(
+            as_i(backward),
+            vec![
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (3, 1),
+                (4, 1),
+                (5, 1),
+                (6, 1),
+                (7, 2),
+                (8, 2),
+                (9, 2)
+            ]
+        );
+
+        assert_eq!(
+            as_i(edge.map_onto(&bridge, &Mapping::Tile))
+                .into_iter()
+                .map(|(_, b)| b)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 0, 1, 2, 0, 1, 2, 0]
+        );
+
+        // Points mapped outside of the target are left unconnected.
+        let custom = Mapping::Custom(Box::new(|loc| {
+            Some(PixelLoc {
+                layer: 0,
+                i: 5 * loc.i,
+                j: 0,
+            })
+        }));
+        assert_eq!(as_i(bridge.map_onto(&edge, &custom)), vec![(0, 0), (1, 5)]);
+    }
+
+    #[test]
+    fn test_boundary_points() {
+        // Left half red, right half blue.  The top-left pixel is a
+        // slightly different red, under the threshold, and the
+        // bottom row of the boundary is transparent on one side.
+        let mut image = SaveImageData {
+            data: (0..4)
+                .flat_map(|_j| 0..6)
+                .flat_map(|i| {
+                    if i < 3 {
+                        vec![255, 0, 0, 255]
+                    } else {
+                        vec![0, 0, 255, 255]
+                    }
+                })
+                .collect(),
+            width: 6,
+            height: 4,
+        };
+        image.data[0..4].copy_from_slice(&[250, 0, 0, 255]);
+        image.data[4 * 20..4 * 21].copy_from_slice(&[0, 0, 0, 0]);
+
+        let expected = (0..3)
+            .flat_map(|j| vec![(2, j), (3, j)])
+            .collect::<Vec<_>>();
+        assert_eq!(boundary_points(&image, 10.0), expected);
+    }
+
+    #[test]
+    fn test_morphology() {
+        let layer_sizes = [(9, 9)];
+        let topology = Topology::new(
+            vec![RectangularArray {
+                width: 9,
+                height: 9,
+            }],
+            Default::default(),
+        );
+        let center = PixelLoc {
+            layer: 0,
+            i: 4,
+            j: 4,
+        };
+        let point = Region::from_points(&layer_sizes, vec![center]);
+
+        // Pixels are adjacent to all eight of their neighbors.
+        let square = point.dilate(&topology, 2);
+        assert_eq!(square.len(), 25);
+        assert!(square.contains(PixelLoc {
+            layer: 0,
+            i: 2,
+            j: 6
+        }));
+        assert!(!square.contains(PixelLoc {
+            layer: 0,
+            i: 1,
+            j: 4
+        }));
+        assert_eq!(point.dilate(&topology, 0), point);
+
+        assert_eq!(square.erode(&topology, 2), point);
+        assert_eq!(square.open(&topology, 2), square);
+        assert!(point.open(&topology, 1).is_empty());
+
+        // The layer edges don't erode the region.
+        let full = Region::from_points(
+            &layer_sizes,
+            (0..9)
+                .flat_map(|j| (0..9).map(move |i| PixelLoc { layer: 0, i, j })),
+        );
+        assert_eq!(full.erode(&topology, 3), full);
+
+        // Closing fills a one-pixel hole.
+        let mut holed = full.clone();
+        holed.remove(center);
+        assert_eq!(holed.close(&topology, 1), full);
+    }
+
+    #[test]
+    fn test_region_invalid_file() {
+        let buf = b"not a region file";
+        assert!(Region::read_from(&mut &buf[..]).is_err());
+    }
+}
@@ -0,0 +1,817 @@
+#[cfg(feature = "expressions")]
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use itertools::Itertools;
+
+use crate::color::{BlendMode, RGB};
+use crate::distribution::RandomDistribution;
+use crate::errors::Error;
+#[cfg(feature = "expressions")]
+use crate::expression::Expression;
+use crate::growth_image::{AlphaMode, Background, SaveImageType};
+use crate::growth_image_builder::{
+    GrowthImageAnimationBuilder, GrowthImageBuilder, GrowthImageStageBuilder,
+};
+use crate::layout::CompositeLayout;
+use crate::palettes::{
+    HilbertPalette, Palette, SphericalPalette, UniformPalette,
+};
+#[cfg(feature = "expressions")]
+use crate::topology::RectangularArray;
+use crate::topology::{LayerShape, PixelLoc};
+
+// Plain-data description of a GrowthImageBuilder, so that build
+// configurations can be saved, shared, and regenerated.  With the
+// "serde" feature, these can be serialized to any serde format.
+//
+// Only the options that can be described as data are included.
+// Custom palettes and target color kernels have no description, and
+// must be added to the builder after conversion.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SceneSpec {
+    pub layers: Vec<LayerSpec>,
+    pub stages: Vec<StageSpec>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub animations: Vec<AnimationSpec>,
+    #[cfg_attr(feature = "serde", serde(default = "default_epsilon"))]
+    pub epsilon: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub enforce_unique_colors: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub linear_light_averaging: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub strict_regions: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub background: Background,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub alpha_mode: AlphaMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerSpec {
+    pub width: u32,
+    pub height: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shape: LayerShape,
+    // Overrides the scene's epsilon for pixels in the layer.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub epsilon: Option<f64>,
+    #[cfg_attr(feature = "serde", serde(default = "default_collect_stats"))]
+    pub collect_stats: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PaletteSpec {
+    Uniform,
+    Spherical {
+        central_color: RGB,
+        color_radius: f32,
+    },
+    Hilbert,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegionSpec {
+    Allowed(Vec<PixelLoc>),
+    Forbidden(Vec<PixelLoc>),
+}
+
+// Unset options use the same defaults as GrowthImageStageBuilder.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StageSpec {
+    pub palette: PaletteSpec,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub n_colors: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub n_colors_exact: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fallback_palette: Option<PaletteSpec>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub palette_cache: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reserved_colors: Vec<RGB>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reserved_release_iter: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub excluded_colors: Vec<RGB>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub exclusion_tolerance: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_iter: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub num_random_seed_points: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed_points: Option<Vec<PixelLoc>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub grow_from_previous: Option<bool>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub region: Option<RegionSpec>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ring_growth: Option<(u32, u32)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub best_match_first: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub concurrent_with_previous: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frontier_max_age: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gravity_bias: Option<(f64, f64)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub color_jitter: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub color_jitter_distribution: Option<RandomDistribution>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed_point_jitter: Option<RandomDistribution>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub selection_noise: Option<RandomDistribution>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub blend: Option<(BlendMode, f32)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub connected_points: Vec<(PixelLoc, PixelLoc)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub one_way_portals: Vec<(PixelLoc, PixelLoc)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub portal_probability: Option<f32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub portal_probabilities: Vec<(PixelLoc, f32)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub animation_iter_per_second: Option<f64>,
+
+    // Options given as an Expression, keyed by the name of the
+    // option, such as max_iter = "0.3 * allowed_pixels".  Evaluated
+    // by SceneSpec::evaluated(), replacing the value of the option.
+    // Expressions may use the variables stage, num_stages, width and
+    // height (of the first layer), num_pixels, and allowed_pixels
+    // (within the stage's region).  Requires the "expressions"
+    // feature.
+    #[cfg(feature = "expressions")]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub expressions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnimationSpec {
+    pub filename: PathBuf,
+    pub fps: f64,
+    pub layer: u8,
+    pub image_types: Vec<SaveImageType>,
+    pub composite: CompositeLayout,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chapter_file: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub background: Option<Background>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub start_at_iter: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stop_at_iter: Option<usize>,
+}
+
+#[cfg(feature = "serde")]
+fn default_epsilon() -> f64 {
+    1.0
+}
+
+#[cfg(feature = "serde")]
+fn default_collect_stats() -> bool {
+    true
+}
+
+impl Default for StageSpec {
+    fn default() -> Self {
+        Self {
+            palette: PaletteSpec::Uniform,
+            n_colors: None,
+            n_colors_exact: false,
+            fallback_palette: None,
+            palette_cache: None,
+            reserved_colors: Vec::new(),
+            reserved_release_iter: None,
+            excluded_colors: Vec::new(),
+            exclusion_tolerance: 0.0,
+            max_iter: None,
+            num_random_seed_points: None,
+            seed_points: None,
+            grow_from_previous: None,
+            region: None,
+            ring_growth: None,
+            best_match_first: false,
+            concurrent_with_previous: false,
+            frontier_max_age: None,
+            gravity_bias: None,
+            color_jitter: 0,
+            color_jitter_distribution: None,
+            seed_point_jitter: None,
+            selection_noise: None,
+            blend: None,
+            connected_points: Vec::new(),
+            one_way_portals: Vec::new(),
+            portal_probability: None,
+            portal_probabilities: Vec::new(),
+            animation_iter_per_second: None,
+            #[cfg(feature = "expressions")]
+            expressions: BTreeMap::new(),
+        }
+    }
+}
+
+impl PaletteSpec {
+    pub fn to_palette(&self) -> Box<dyn Palette> {
+        match *self {
+            PaletteSpec::Uniform => Box::new(UniformPalette),
+            PaletteSpec::Spherical {
+                central_color,
+                color_radius,
+            } => Box::new(SphericalPalette {
+                central_color,
+                color_radius,
+            }),
+            PaletteSpec::Hilbert => Box::new(HilbertPalette),
+        }
+    }
+}
+
+#[cfg(feature = "expressions")]
+impl LayerSpec {
+    fn num_pixels(&self) -> usize {
+        let size = RectangularArray {
+            width: self.width,
+            height: self.height,
+        };
+        (0..size.len())
+            .flat_map(|index| size.get_loc(0, index))
+            .filter(|&loc| self.shape.contains(&size, loc))
+            .count()
+    }
+}
+
+impl SceneSpec {
+    // With the "expressions" feature, the expressions of each stage
+    // are evaluated first, giving an error if any are invalid.
+    pub fn try_to_builder(&self) -> Result<GrowthImageBuilder, Error> {
+        #[cfg(feature = "expressions")]
+        let builder = self.evaluated()?._to_builder();
+        #[cfg(not(feature = "expressions"))]
+        let builder = self._to_builder();
+        Ok(builder)
+    }
+
+    // Copy of the scene, with the expressions of each stage evaluated
+    // and replaced by their values.  Downscale before evaluating, so
+    // that the expressions use the size of the preview.
+    #[cfg(feature = "expressions")]
+    pub fn evaluated(&self) -> Result<SceneSpec, Error> {
+        let num_pixels = self
+            .layers
+            .iter()
+            .map(|layer| layer.num_pixels())
+            .sum::<usize>() as f64;
+        let (width, height) = self.layers.first().map_or((0.0, 0.0), |layer| {
+            (layer.width as f64, layer.height as f64)
+        });
+
+        let stages = self
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(stage_i, stage)| {
+                let allowed_pixels = match &stage.region {
+                    Some(RegionSpec::Allowed(points)) => points.len() as f64,
+                    Some(RegionSpec::Forbidden(points)) => {
+                        (num_pixels - points.len() as f64).max(0.0)
+                    }
+                    None => num_pixels,
+                };
+                let variables = vec![
+                    ("stage", stage_i as f64),
+                    ("num_stages", self.stages.len() as f64),
+                    ("width", width),
+                    ("height", height),
+                    ("num_pixels", num_pixels),
+                    ("allowed_pixels", allowed_pixels),
+                ]
+                .into_iter()
+                .collect();
+                stage.evaluated(&variables)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(SceneSpec {
+            stages,
+            ..self.clone()
+        })
+    }
+
+    fn _to_builder(&self) -> GrowthImageBuilder {
+        let mut builder = GrowthImageBuilder::new();
+        builder
+            .epsilon(self.epsilon)
+            .enforce_unique_colors(self.enforce_unique_colors)
+            .linear_light_averaging(self.linear_light_averaging)
+            .strict_regions(self.strict_regions)
+            .background(self.background)
+            .alpha_mode(self.alpha_mode);
+        if let Some(seed) = self.seed {
+            builder.seed(seed);
+        }
+
+        self.layers.iter().enumerate().for_each(|(layer_i, layer)| {
+            match layer.shape {
+                LayerShape::Rectangle => {
+                    builder.add_layer(layer.width, layer.height);
+                }
+                LayerShape::Ellipse => {
+                    builder.add_elliptical_layer(layer.width, layer.height);
+                }
+                LayerShape::Equirectangular => {
+                    builder
+                        .add_equirectangular_layer(layer.width, layer.height);
+                }
+            }
+            if let Some(epsilon) = layer.epsilon {
+                builder.layer_epsilon(layer_i as u8, epsilon);
+            }
+            if !layer.collect_stats {
+                builder.layer_stats(layer_i as u8, false);
+            }
+        });
+
+        self.stages
+            .iter()
+            .for_each(|stage| stage.apply(builder.new_stage()));
+
+        self.animations.iter().for_each(|anim| {
+            anim.apply(builder.add_output_animation(anim.filename.clone()));
+        });
+
+        builder
+    }
+
+    // Smaller copy of the scene, for quick previews.  Each dimension
+    // is divided by the factor, with locations scaled to match, and
+    // color and iteration counts are divided by the square of the
+    // factor.  Animations and palette caches are left out.
+    pub fn downscaled(&self, factor: u32) -> SceneSpec {
+        let factor = factor.max(1);
+        let area = (factor * factor) as usize;
+        let scale_loc = |loc: &PixelLoc| PixelLoc {
+            layer: loc.layer,
+            i: loc.i.div_euclid(factor as i32),
+            j: loc.j.div_euclid(factor as i32),
+        };
+        let scale_locs =
+            |locs: &[PixelLoc]| locs.iter().map(scale_loc).unique().collect();
+
+        let stages = self
+            .stages
+            .iter()
+            .map(|stage| StageSpec {
+                n_colors: stage
+                    .n_colors
+                    .map(|n| (n as usize).div_ceil(area) as u32),
+                palette_cache: None,
+                max_iter: stage.max_iter.map(|n| n.div_ceil(area)),
+                frontier_max_age: stage
+                    .frontier_max_age
+                    .map(|n| n.div_ceil(area)),
+                seed_points: stage.seed_points.as_deref().map(scale_locs),
+                seed_point_jitter: stage
+                    .seed_point_jitter
+                    .map(|jitter| jitter.scaled(1.0 / factor as f64)),
+                region: stage.region.as_ref().map(|region| match region {
+                    RegionSpec::Allowed(points) => {
+                        RegionSpec::Allowed(scale_locs(points))
+                    }
+                    RegionSpec::Forbidden(points) => {
+                        RegionSpec::Forbidden(scale_locs(points))
+                    }
+                }),
+                ring_growth: stage
+                    .ring_growth
+                    .map(|(r_min, r_max)| (r_min / factor, r_max / factor)),
+                connected_points: stage
+                    .connected_points
+                    .iter()
+                    .map(|(a, b)| (scale_loc(a), scale_loc(b)))
+                    .filter(|(a, b)| a != b)
+                    .unique()
+                    .collect(),
+                one_way_portals: stage
+                    .one_way_portals
+                    .iter()
+                    .map(|(a, b)| (scale_loc(a), scale_loc(b)))
+                    .filter(|(a, b)| a != b)
+                    .unique()
+                    .collect(),
+                portal_probabilities: stage
+                    .portal_probabilities
+                    .iter()
+                    .map(|(loc, probability)| (scale_loc(loc), *probability))
+                    .unique_by(|(loc, _)| *loc)
+                    .collect(),
+                animation_iter_per_second: stage
+                    .animation_iter_per_second
+                    .map(|rate| rate / (area as f64)),
+                ..stage.clone()
+            })
+            .collect();
+
+        SceneSpec {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| LayerSpec {
+                    width: layer.width.div_ceil(factor).max(1),
+                    height: layer.height.div_ceil(factor).max(1),
+                    ..*layer
+                })
+                .collect(),
+            stages,
+            animations: Vec::new(),
+            background: match self.background {
+                Background::Checkerboard(size) => {
+                    Background::Checkerboard((size / factor).max(1))
+                }
+                background => background,
+            },
+            ..self.clone()
+        }
+    }
+}
+
+impl StageSpec {
+    #[cfg(feature = "expressions")]
+    fn evaluated(
+        &self,
+        variables: &HashMap<&str, f64>,
+    ) -> Result<StageSpec, Error> {
+        let mut stage = StageSpec {
+            expressions: BTreeMap::new(),
+            ..self.clone()
+        };
+        for (name, text) in self.expressions.iter() {
+            let val = Expression::parse(text)?.eval(variables)?;
+            let count = val.max(0.0).round() as usize;
+            let channel = val.clamp(0.0, 255.0).round() as u8;
+            match (name.as_str(), &mut stage.palette) {
+                ("max_iter", _) => stage.max_iter = Some(count),
+                ("n_colors", _) => stage.n_colors = Some(count as u32),
+                ("num_random_seed_points", _) => {
+                    stage.num_random_seed_points = Some(count as u32)
+                }
+                ("reserved_release_iter", _) => {
+                    stage.reserved_release_iter = Some(count)
+                }
+                ("frontier_max_age", _) => stage.frontier_max_age = Some(count),
+                ("portal_probability", _) => {
+                    stage.portal_probability = Some(val as f32)
+                }
+                ("animation_iter_per_second", _) => {
+                    stage.animation_iter_per_second = Some(val)
+                }
+                (
+                    "color_radius",
+                    PaletteSpec::Spherical { color_radius, .. },
+                ) => *color_radius = val as f32,
+                ("central_r", PaletteSpec::Spherical { central_color, .. }) => {
+                    central_color.vals[0] = channel
+                }
+                ("central_g", PaletteSpec::Spherical { central_color, .. }) => {
+                    central_color.vals[1] = channel
+                }
+                ("central_b", PaletteSpec::Spherical { central_color, .. }) => {
+                    central_color.vals[2] = channel
+                }
+                _ => {
+                    return Err(Error::InvalidExpression(format!(
+                        "no option '{}' for {:?} palette",
+                        name, stage.palette
+                    )))
+                }
+            }
+        }
+        Ok(stage)
+    }
+
+    fn apply(&self, stage: &mut GrowthImageStageBuilder) {
+        stage.palette(self.palette.to_palette());
+        if let Some(fallback) = &self.fallback_palette {
+            stage.fallback_palette(fallback.to_palette());
+        }
+
+        if let Some(filename) = &self.palette_cache {
+            stage.palette_cache(filename.clone());
+        }
+
+        if !self.reserved_colors.is_empty() {
+            stage.reserve_colors(
+                self.reserved_colors.clone(),
+                self.reserved_release_iter,
+            );
+        }
+        if !self.excluded_colors.is_empty() {
+            stage.exclude_colors(
+                self.excluded_colors.clone(),
+                self.exclusion_tolerance,
+            );
+        }
+
+        if let Some(n_colors) = self.n_colors {
+            stage.n_colors(n_colors);
+        }
+        if self.n_colors_exact {
+            stage.n_colors_exact_for_region();
+        }
+        if let Some(max_iter) = self.max_iter {
+            stage.max_iter(max_iter);
+        }
+        if let Some(num) = self.num_random_seed_points {
+            stage.num_random_seed_points(num);
+        }
+        if let Some(seed_points) = &self.seed_points {
+            stage.seed_points(seed_points.clone());
+        }
+        if let Some(grow_from_previous) = self.grow_from_previous {
+            stage.grow_from_previous(grow_from_previous);
+        }
+        match &self.region {
+            Some(RegionSpec::Allowed(points)) => {
+                stage.allowed_points(points.clone());
+            }
+            Some(RegionSpec::Forbidden(points)) => {
+                stage.forbidden_points(points.clone());
+            }
+            None => {}
+        }
+        if let Some((r_min, r_max)) = self.ring_growth {
+            stage.ring_growth(r_min, r_max);
+        }
+        if self.best_match_first {
+            stage.best_match_first();
+        }
+        if self.concurrent_with_previous {
+            stage.concurrent_with_previous();
+        }
+        if let Some(max_age) = self.frontier_max_age {
+            stage.frontier_max_age(max_age);
+        }
+        if let Some((di, dj)) = self.gravity_bias {
+            stage.gravity_bias(di, dj);
+        }
+        if self.color_jitter > 0 {
+            stage.color_jitter(self.color_jitter);
+        }
+        if let Some(distribution) = self.color_jitter_distribution {
+            stage.color_jitter_distribution(distribution);
+        }
+        if let Some(distribution) = self.seed_point_jitter {
+            stage.seed_point_jitter(distribution);
+        }
+        if let Some(distribution) = self.selection_noise {
+            stage.selection_noise(distribution);
+        }
+        if let Some((mode, opacity)) = self.blend {
+            stage.blend(mode, opacity);
+        }
+        if !self.connected_points.is_empty() {
+            stage.connected_points(self.connected_points.clone());
+        }
+        if !self.one_way_portals.is_empty() {
+            stage.one_way_portals(self.one_way_portals.clone());
+        }
+        if let Some(probability) = self.portal_probability {
+            stage.portal_probability(probability);
+        }
+        if !self.portal_probabilities.is_empty() {
+            stage.portal_probabilities(self.portal_probabilities.clone());
+        }
+        if let Some(iter_per_second) = self.animation_iter_per_second {
+            stage.animation_iter_per_second(iter_per_second);
+        }
+    }
+}
+
+impl AnimationSpec {
+    fn apply(&self, anim: &mut GrowthImageAnimationBuilder) {
+        anim.fps(self.fps)
+            .layer(self.layer)
+            .image_types(self.image_types.clone())
+            .composite_layout(self.composite);
+        if let Some(chapter_file) = &self.chapter_file {
+            anim.chapter_file(chapter_file.clone());
+        }
+        if let Some(background) = self.background {
+            anim.background(background);
+        }
+        anim.start_at_iter(self.start_at_iter);
+        if let Some(stop_at_iter) = self.stop_at_iter {
+            anim.stop_at_iter(stop_at_iter);
+        }
+    }
+}
+
+impl TryFrom<&SceneSpec> for GrowthImageBuilder {
+    type Error = Error;
+
+    fn try_from(spec: &SceneSpec) -> Result<Self, Error> {
+        spec.try_to_builder()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::errors::Error;
+
+    #[test]
+    fn test_scene_roundtrip() -> Result<(), Error> {
+        let spec = SceneSpec {
+            layers: vec![LayerSpec {
+                width: 30,
+                height: 20,
+                shape: LayerShape::Ellipse,
+                epsilon: Some(4.0),
+                collect_stats: false,
+            }],
+            stages: vec![
+                StageSpec {
+                    palette: PaletteSpec::Spherical {
+                        central_color: RGB { vals: [10, 20, 30] },
+                        color_radius: 25.0,
+                    },
+                    max_iter: Some(100),
+                    seed_points: Some(vec![PixelLoc {
+                        layer: 0,
+                        i: 5,
+                        j: 5,
+                    }]),
+                    ..Default::default()
+                },
+                StageSpec {
+                    region: Some(RegionSpec::Forbidden(vec![PixelLoc {
+                        layer: 0,
+                        i: 0,
+                        j: 0,
+                    }])),
+                    ..Default::default()
+                },
+            ],
+            animations: Vec::new(),
+            epsilon: 2.5,
+            seed: Some(7),
+            enforce_unique_colors: true,
+            linear_light_averaging: false,
+            strict_regions: false,
+            background: Background::Transparent,
+            alpha_mode: AlphaMode::Straight,
+        };
+
+        let builder = spec.try_to_builder()?;
+        assert_eq!(builder.scene_spec()?, spec);
+
+        let mut image = builder.build()?;
+        image.fill_until_done();
+
+        Ok(())
+    }
+
+    #[cfg(feature = "expressions")]
+    #[test]
+    fn test_expressions() -> Result<(), Error> {
+        let expressions = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .collect()
+        };
+        let stage = |pairs: &[(&str, &str)]| StageSpec {
+            palette: PaletteSpec::Spherical {
+                central_color: RGB { vals: [0, 0, 0] },
+                color_radius: 10.0,
+            },
+            region: Some(RegionSpec::Forbidden(vec![PixelLoc {
+                layer: 0,
+                i: 0,
+                j: 0,
+            }])),
+            expressions: expressions(pairs),
+            ..Default::default()
+        };
+        let mut spec = SceneSpec {
+            layers: vec![LayerSpec {
+                width: 20,
+                height: 10,
+                shape: LayerShape::Rectangle,
+                epsilon: None,
+                collect_stats: true,
+            }],
+            stages: vec![
+                stage(&[("max_iter", "0.3 * allowed_pixels")]),
+                stage(&[
+                    ("central_r", "100 * (stage + 1)"),
+                    ("color_radius", "width + height"),
+                ]),
+            ],
+            animations: Vec::new(),
+            epsilon: 1.0,
+            seed: Some(0),
+            enforce_unique_colors: false,
+            linear_light_averaging: false,
+            strict_regions: false,
+            background: Background::Transparent,
+            alpha_mode: AlphaMode::Straight,
+        };
+
+        let evaluated = spec.evaluated()?;
+        assert_eq!(evaluated.stages[0].max_iter, Some(60));
+        assert_eq!(
+            evaluated.stages[1].palette,
+            PaletteSpec::Spherical {
+                central_color: RGB { vals: [200, 0, 0] },
+                color_radius: 30.0,
+            }
+        );
+        assert!(evaluated.stages.iter().all(|s| s.expressions.is_empty()));
+
+        // Expressions use the size of the downscaled preview.
+        let preview = spec.downscaled(2).evaluated()?;
+        assert_eq!(preview.stages[0].max_iter, Some(15));
+
+        spec.stages[0].expressions = expressions(&[("max_iter", "2 +")]);
+        assert!(matches!(
+            spec.try_to_builder(),
+            Err(Error::InvalidExpression(_))
+        ));
+        spec.stages[0].expressions = expressions(&[("epsilon", "2")]);
+        assert!(matches!(
+            spec.try_to_builder(),
+            Err(Error::InvalidExpression(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_downscaled() {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let spec = SceneSpec {
+            layers: vec![LayerSpec {
+                width: 100,
+                height: 50,
+                shape: LayerShape::Rectangle,
+                epsilon: None,
+                collect_stats: true,
+            }],
+            stages: vec![StageSpec {
+                n_colors: Some(1000),
+                max_iter: Some(999),
+                seed_points: Some(vec![loc(10, 10), loc(11, 11)]),
+                region: Some(RegionSpec::Forbidden(vec![
+                    loc(0, 0),
+                    loc(1, 0),
+                    loc(99, 49),
+                ])),
+                ..Default::default()
+            }],
+            animations: Vec::new(),
+            epsilon: 1.0,
+            seed: None,
+            enforce_unique_colors: false,
+            linear_light_averaging: false,
+            strict_regions: false,
+            background: Background::Transparent,
+            alpha_mode: AlphaMode::Straight,
+        };
+
+        let preview = spec.downscaled(4);
+        assert_eq!(
+            preview.layers,
+            vec![LayerSpec {
+                width: 25,
+                height: 13,
+                shape: LayerShape::Rectangle,
+                epsilon: None,
+                collect_stats: true,
+            }]
+        );
+        let stage = &preview.stages[0];
+        assert_eq!(stage.n_colors, Some(63));
+        assert_eq!(stage.max_iter, Some(63));
+        assert_eq!(stage.seed_points, Some(vec![loc(2, 2)]));
+        assert_eq!(
+            stage.region,
+            Some(RegionSpec::Forbidden(vec![loc(0, 0), loc(24, 12)]))
+        );
+        assert_eq!(spec.downscaled(1), spec);
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use itertools::Itertools;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+
+use crate::errors::Error;
+use crate::growth_image_builder::GrowthImageStageBuilder;
+use crate::topology::PixelLoc;
+
+// Interactive placement of seed points and walls, before growth
+// starts.  Shows an empty canvas for one layer.  Left-click places a
+// seed point, left-drag draws a wall, and right-click or backspace
+// removes the most recent seed point or wall.  Press enter or close
+// the window when done.
+pub struct SeedEditor {
+    layer: u8,
+    width: u32,
+    height: u32,
+    scale: u32,
+}
+
+// Seed points and walls from a SeedEditor.
+#[derive(Debug, Clone, Default)]
+pub struct SeedLayout {
+    pub seed_points: Vec<PixelLoc>,
+    pub walls: Vec<PixelLoc>,
+}
+
+enum EditorItem {
+    Seed(PixelLoc),
+    Wall(Vec<PixelLoc>),
+}
+
+const BACKGROUND_COLOR: u32 = 0x202020;
+const WALL_COLOR: u32 = 0xc0c0c0;
+const SEED_COLOR: u32 = 0xff4040;
+
+// Seed points are drawn at least this large, in screen pixels, so
+// that they are visible when the canvas isn't scaled up.
+const MIN_SEED_MARKER: u32 = 5;
+
+// Default size of the longer side of the window, in screen pixels.
+const DEFAULT_WINDOW_SIZE: u32 = 800;
+
+impl SeedEditor {
+    pub fn new(width: u32, height: u32) -> Self {
+        let scale = DEFAULT_WINDOW_SIZE / width.max(height).max(1);
+        Self {
+            layer: 0,
+            width,
+            height,
+            scale: scale.max(1),
+        }
+    }
+
+    pub fn layer(&mut self, layer: u8) -> &mut Self {
+        self.layer = layer;
+        self
+    }
+
+    // Number of screen pixels per image pixel.
+    pub fn scale(&mut self, scale: u32) -> &mut Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    pub fn run(&self) -> Result<SeedLayout, Error> {
+        let screen_width = (self.width * self.scale) as usize;
+        let screen_height = (self.height * self.scale) as usize;
+        let mut window = Window::new(
+            "Click to place seeds, drag to draw walls, enter when done",
+            screen_width,
+            screen_height,
+            WindowOptions::default(),
+        )?;
+        window.limit_update_rate(Some(Duration::from_micros(16600)));
+
+        let mut items = Vec::new();
+        let mut stroke: Option<Vec<PixelLoc>> = None;
+        let mut right_was_down = false;
+
+        while window.is_open() && !window.is_key_down(Key::Enter) {
+            let mouse = window
+                .get_mouse_pos(MouseMode::Clamp)
+                .map(|(x, y)| self.screen_to_loc(x, y));
+            let left_down = window.get_mouse_down(MouseButton::Left);
+
+            match (left_down, &mut stroke, mouse) {
+                (true, None, Some(loc)) => {
+                    stroke = Some(vec![loc]);
+                }
+                (true, Some(points), Some(loc)) => {
+                    let prev = *points.last().unwrap();
+                    if prev != loc {
+                        points.extend(
+                            prev.line_to(loc)
+                                .into_iter()
+                                .filter(|&p| p != prev),
+                        );
+                    }
+                }
+                (false, Some(_), _) => {
+                    let points = stroke.take().unwrap();
+                    items.push(if points.len() == 1 {
+                        EditorItem::Seed(points[0])
+                    } else {
+                        EditorItem::Wall(points)
+                    });
+                }
+                _ => {}
+            }
+
+            let right_down = window.get_mouse_down(MouseButton::Right);
+            if (right_down && !right_was_down)
+                || window.is_key_pressed(Key::Backspace, KeyRepeat::Yes)
+            {
+                items.pop();
+            }
+            right_was_down = right_down;
+
+            let buffer = self.render(&items, stroke.as_deref());
+            window.update_with_buffer(&buffer, screen_width, screen_height)?;
+        }
+
+        // A drag in progress when the window closes still counts.
+        if let Some(points) = stroke {
+            items.push(EditorItem::Wall(points));
+        }
+
+        Ok(SeedLayout {
+            seed_points: items
+                .iter()
+                .filter_map(|item| match item {
+                    EditorItem::Seed(loc) => Some(*loc),
+                    EditorItem::Wall(_) => None,
+                })
+                .collect(),
+            walls: items
+                .iter()
+                .filter_map(|item| match item {
+                    EditorItem::Wall(points) => Some(points.iter().copied()),
+                    EditorItem::Seed(_) => None,
+                })
+                .flatten()
+                .unique()
+                .collect(),
+        })
+    }
+
+    fn screen_to_loc(&self, x: f32, y: f32) -> PixelLoc {
+        let i =
+            (x.max(0.0) as u32 / self.scale).min(self.width.saturating_sub(1));
+        let j =
+            (y.max(0.0) as u32 / self.scale).min(self.height.saturating_sub(1));
+        PixelLoc {
+            layer: self.layer,
+            i: i as i32,
+            j: j as i32,
+        }
+    }
+
+    fn render(
+        &self,
+        items: &[EditorItem],
+        stroke: Option<&[PixelLoc]>,
+    ) -> Vec<u32> {
+        let screen_width = self.width * self.scale;
+        let screen_height = self.height * self.scale;
+        let mut buffer =
+            vec![BACKGROUND_COLOR; (screen_width * screen_height) as usize];
+
+        let mut fill_rect = |x0: i64, y0: i64, size: i64, color: u32| {
+            let xmin = x0.max(0);
+            let ymin = y0.max(0);
+            let xmax = (x0 + size).min(screen_width as i64);
+            let ymax = (y0 + size).min(screen_height as i64);
+            (ymin..ymax)
+                .cartesian_product(xmin..xmax)
+                .for_each(|(y, x)| {
+                    buffer[(y * screen_width as i64 + x) as usize] = color;
+                });
+        };
+
+        let scale = self.scale as i64;
+        let walls = items
+            .iter()
+            .filter_map(|item| match item {
+                EditorItem::Wall(points) => Some(points.as_slice()),
+                EditorItem::Seed(_) => None,
+            })
+            .chain(stroke)
+            .flatten();
+        walls.for_each(|loc| {
+            fill_rect(
+                (loc.i as i64) * scale,
+                (loc.j as i64) * scale,
+                scale,
+                WALL_COLOR,
+            );
+        });
+
+        let marker = self.scale.max(MIN_SEED_MARKER) as i64;
+        items.iter().for_each(|item| {
+            if let EditorItem::Seed(loc) = item {
+                let center_x = (loc.i as i64) * scale + scale / 2;
+                let center_y = (loc.j as i64) * scale + scale / 2;
+                fill_rect(
+                    center_x - marker / 2,
+                    center_y - marker / 2,
+                    marker,
+                    SEED_COLOR,
+                );
+            }
+        });
+
+        buffer
+    }
+}
+
+impl SeedLayout {
+    // Use the seed points and walls in the stage.  Walls are added as
+    // forbidden points, replacing any previous allowed or forbidden
+    // region.
+    pub fn apply(&self, stage: &mut GrowthImageStageBuilder) {
+        if !self.seed_points.is_empty() {
+            stage.seed_points(self.seed_points.clone());
+        }
+        if !self.walls.is_empty() {
+            stage.forbidden_points(self.walls.clone());
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::growth_image::SaveImageData;
+
+// Metrics for comparing a generated image against a reference image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Metric {
+    // Mean squared error over the RGB channels, in the range
+    // [0, 65025].  Lower is more similar.
+    MSE,
+
+    // Mean structural similarity of the luminance, over sliding
+    // windows.  In the range [-1, 1], where 1 is identical.
+    SSIM,
+}
+
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STEP: u32 = 4;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+// Pixels with zero alpha are treated as black.
+fn rgb(data: &SaveImageData) -> impl Iterator<Item = [f64; 3]> + '_ {
+    data.data.chunks_exact(4).map(|p| {
+        if p[3] == 0 {
+            [0.0; 3]
+        } else {
+            [p[0] as f64, p[1] as f64, p[2] as f64]
+        }
+    })
+}
+
+fn luminance(data: &SaveImageData) -> Vec<f64> {
+    rgb(data)
+        .map(|[r, g, b]| 0.299 * r + 0.587 * g + 0.114 * b)
+        .collect()
+}
+
+impl Metric {
+    // Both images must be the same size.
+    pub(crate) fn compare(&self, a: &SaveImageData, b: &SaveImageData) -> f64 {
+        match self {
+            Metric::MSE => Self::mse(a, b),
+            Metric::SSIM => Self::ssim(a, b),
+        }
+    }
+
+    fn mse(a: &SaveImageData, b: &SaveImageData) -> f64 {
+        let num_pixels = (a.width * a.height) as f64;
+        if num_pixels == 0.0 {
+            return 0.0;
+        }
+
+        let sum = rgb(a)
+            .zip(rgb(b))
+            .map(|(pa, pb)| {
+                pa.iter()
+                    .zip(pb.iter())
+                    .map(|(va, vb)| (va - vb) * (va - vb))
+                    .sum::<f64>()
+            })
+            .sum::<f64>();
+        sum / (3.0 * num_pixels)
+    }
+
+    fn ssim(a: &SaveImageData, b: &SaveImageData) -> f64 {
+        let lum_a = luminance(a);
+        let lum_b = luminance(b);
+
+        // Images smaller than a window are compared as a single
+        // window.
+        let window_width = SSIM_WINDOW.min(a.width);
+        let window_height = SSIM_WINDOW.min(a.height);
+        if window_width == 0 || window_height == 0 {
+            return 1.0;
+        }
+
+        let window_starts = |size: u32, window: u32| {
+            (0..=(size - window)).step_by(SSIM_STEP as usize)
+        };
+
+        let (count, total) = window_starts(a.height, window_height)
+            .flat_map(|y0| {
+                window_starts(a.width, window_width).map(move |x0| (x0, y0))
+            })
+            .map(|(x0, y0)| {
+                let indices = (y0..y0 + window_height).flat_map(|y| {
+                    (x0..x0 + window_width)
+                        .map(move |x| (y * a.width + x) as usize)
+                });
+
+                let n = (window_width * window_height) as f64;
+                let (sum_a, sum_b, sum_aa, sum_bb, sum_ab) = indices.fold(
+                    (0.0, 0.0, 0.0, 0.0, 0.0),
+                    |(sa, sb, saa, sbb, sab), index| {
+                        let va = lum_a[index];
+                        let vb = lum_b[index];
+                        (
+                            sa + va,
+                            sb + vb,
+                            saa + va * va,
+                            sbb + vb * vb,
+                            sab + va * vb,
+                        )
+                    },
+                );
+
+                let mean_a = sum_a / n;
+                let mean_b = sum_b / n;
+                let var_a = sum_aa / n - mean_a * mean_a;
+                let var_b = sum_bb / n - mean_b * mean_b;
+                let covar = sum_ab / n - mean_a * mean_b;
+
+                ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covar + SSIM_C2))
+                    / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1)
+                        * (var_a + var_b + SSIM_C2))
+            })
+            .fold((0usize, 0.0), |(count, total), val| {
+                (count + 1, total + val)
+            });
+
+        total / (count as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gradient(width: u32, height: u32, offset: u8) -> SaveImageData {
+        let data = (0..height)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .flat_map(|(i, j)| {
+                let val = ((i * 7 + j * 3) as u8).wrapping_add(offset);
+                vec![val, val, val, 255]
+            })
+            .collect();
+        SaveImageData {
+            data,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_identical_images() {
+        let image = gradient(20, 12, 0);
+        assert_eq!(Metric::MSE.compare(&image, &image), 0.0);
+        assert!((Metric::SSIM.compare(&image, &image) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_different_images() {
+        let a = gradient(20, 12, 0);
+        let b = gradient(20, 12, 10);
+        assert_eq!(Metric::MSE.compare(&a, &b), 100.0);
+        assert!(Metric::SSIM.compare(&a, &b) < 1.0);
+    }
+}
@@ -0,0 +1,93 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::color::RGB;
+use crate::topology::{PixelLoc, Topology};
+
+pub(crate) const DEFAULT_CONDITION_CHECK_PERIOD: usize = 100;
+
+// Condition on the pixels filled so far, for ending a stage early.
+// Checked periodically while the stage fills, so that later stages
+// can react to what has already been painted.
+#[derive(Clone)]
+pub(crate) enum StageCondition {
+    // Mean brightness of the filled pixels, as the mean of the red,
+    // green, and blue channels from 0.0 to 1.0, is below the
+    // threshold.
+    MeanBrightnessBelow(f32),
+
+    // As MeanBrightnessBelow, but above the threshold.
+    MeanBrightnessAbove(f32),
+
+    // Mean hue of the filled pixels in the region is within the
+    // range, in degrees.  A range whose start is after its end wraps
+    // around through 0, such as 330.0..30.0 for reds.  Hues are
+    // averaged as angles, weighted by saturation, so gray pixels
+    // don't count.
+    RegionMeanHueIn {
+        region: Arc<Vec<PixelLoc>>,
+        hue_range: Range<f32>,
+    },
+}
+
+impl StageCondition {
+    pub(crate) fn is_met(
+        &self,
+        topology: &Topology,
+        pixels: &[Option<RGB>],
+    ) -> bool {
+        match self {
+            StageCondition::MeanBrightnessBelow(threshold) => {
+                mean_brightness(pixels).is_some_and(|mean| mean < *threshold)
+            }
+            StageCondition::MeanBrightnessAbove(threshold) => {
+                mean_brightness(pixels).is_some_and(|mean| mean > *threshold)
+            }
+            StageCondition::RegionMeanHueIn { region, hue_range } => {
+                let colors = region
+                    .iter()
+                    .flat_map(|&loc| topology.get_index(loc))
+                    .flat_map(|index| pixels[index]);
+                mean_hue(colors).is_some_and(|hue| {
+                    if hue_range.start <= hue_range.end {
+                        hue_range.start <= hue && hue < hue_range.end
+                    } else {
+                        hue >= hue_range.start || hue < hue_range.end
+                    }
+                })
+            }
+        }
+    }
+}
+
+fn mean_brightness(pixels: &[Option<RGB>]) -> Option<f32> {
+    let (sum, count) =
+        pixels
+            .iter()
+            .flatten()
+            .fold((0u64, 0u64), |(sum, count), color| {
+                let total =
+                    color.vals.iter().map(|&val| val as u64).sum::<u64>();
+                (sum + total, count + 1)
+            });
+    if count == 0 {
+        None
+    } else {
+        Some((sum as f64 / (3.0 * 255.0 * count as f64)) as f32)
+    }
+}
+
+// Mean hue in degrees, in the range [0, 360).
+fn mean_hue(colors: impl Iterator<Item = RGB>) -> Option<f32> {
+    let (x, y) = colors.fold((0.0f64, 0.0f64), |(x, y), color| {
+        let hsv = color.to_hsv();
+        let angle = (hsv.h as f64).to_radians();
+        let weight = hsv.s as f64;
+        (x + weight * angle.cos(), y + weight * angle.sin())
+    });
+    if x == 0.0 && y == 0.0 {
+        None
+    } else {
+        Some(y.atan2(x).to_degrees().rem_euclid(360.0) as f32)
+    }
+}
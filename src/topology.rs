@@ -1,12 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::ops::Range;
+use std::sync::Arc;
 
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 
 #[allow(unused_imports)]
 use crate::errors::Error;
+use crate::region::Region;
+
+// Side length of the square chunks in which unbounded layers are
+// stored.
+pub(crate) const CHUNK_SIZE: i32 = 64;
+const CHUNK_AREA: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PixelLoc {
     pub layer: u8,
     pub i: i32,
@@ -70,18 +78,332 @@ impl PixelLoc {
 
 #[derive(Clone)]
 pub struct Topology {
-    pub layers: Vec<RectangularArray>,
-    pub portals: HashMap<PixelLoc, PixelLoc>,
+    layers: Vec<RectangularArray>,
+    shapes: Vec<LayerShape>,
+    portals: Arc<HashMap<PixelLoc, PixelLoc>>,
+
+    // Cumulative number of pixels before each layer, with the total
+    // number of pixels as the last element.  Updated whenever a layer
+    // is added, since get_index() and get_loc() are on the hot path
+    // of every fill.
+    layer_offsets: Vec<usize>,
+
+    // Number of pixels within the shape of each layer.
+    num_valid: usize,
+
+    // Optional precomputed neighbors of each pixel, including
+    // portals.  Must be rebuilt whenever the layers or portals
+    // change.
+    adjacency: Option<Arc<AdjacencyTable>>,
+
+    // Optional precomputed location of each index, with None for
+    // pixels outside the shape of their layer.  Must be rebuilt
+    // whenever a layer is added.
+    locations: Option<Arc<Vec<Option<PixelLoc>>>>,
+
+    // Chunks of each unbounded layer, indexed by layer, with None for
+    // fixed-size layers.
+    unbounded: Arc<Vec<Option<UnboundedLayer>>>,
+
+    // Layer and position of each allocated chunk, in the order they
+    // were allocated.  The pixels of the n-th chunk have indices
+    // starting at num_fixed() + n*CHUNK_AREA, after every pixel of
+    // the fixed-size layers, in row-major order within the chunk.
+    chunk_order: Arc<Vec<(u8, (i32, i32))>>,
+}
+
+// Sparse storage for a layer without fixed bounds, which may extend
+// in any direction, including to negative coordinates.  Pixels are
+// allocated in square chunks as growth reaches them, so only the
+// area around the filled pixels is stored.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UnboundedLayer {
+    // Position in chunk_order of each allocated chunk, keyed by the
+    // chunk's position in units of CHUNK_SIZE.
+    chunks: HashMap<(i32, i32), usize>,
+}
+
+// Position of the chunk containing the pixel, in units of CHUNK_SIZE.
+fn chunk_of(loc: PixelLoc) -> (i32, i32) {
+    (loc.i.div_euclid(CHUNK_SIZE), loc.j.div_euclid(CHUNK_SIZE))
+}
+
+// The 8 pixels surrounding a location, whether or not they exist.
+fn surrounding(loc: PixelLoc) -> impl Iterator<Item = PixelLoc> {
+    (-1..=1)
+        .cartesian_product(-1..=1)
+        .filter(|&(di, dj)| (di != 0) || (dj != 0))
+        .map(move |(di, dj)| PixelLoc {
+            layer: loc.layer,
+            i: loc.i + di,
+            j: loc.j + dj,
+        })
+}
+
+// Neighbor indices of every pixel, stored as a flat array.  The
+// neighbors of pixel i are neighbors[offsets[i]..offsets[i+1]].
+struct AdjacencyTable {
+    offsets: Vec<u32>,
+    neighbors: Vec<u32>,
 }
 
 // Currently, most of these just delegate to RectangularArray, but
 // they'll be more differentiated once there are multiple layers to
 // the image.
 impl Topology {
+    pub fn new(
+        layers: Vec<RectangularArray>,
+        portals: Arc<HashMap<PixelLoc, PixelLoc>>,
+    ) -> Self {
+        let mut topology = Self {
+            layers: Vec::new(),
+            shapes: Vec::new(),
+            portals,
+            layer_offsets: vec![0],
+            num_valid: 0,
+            adjacency: None,
+            locations: None,
+            unbounded: Arc::new(Vec::new()),
+            chunk_order: Arc::new(Vec::new()),
+        };
+        layers
+            .into_iter()
+            .for_each(|layer| topology.add_layer(layer));
+        topology
+    }
+
+    pub fn add_layer(&mut self, layer: RectangularArray) {
+        self.add_shaped_layer(layer, LayerShape::Rectangle);
+    }
+
+    // Pixels of the layer outside of the shape are invalid.  They are
+    // still given an index, so that each layer can be stored as a
+    // rectangular image, but get_loc() returns None for them.
+    pub fn add_shaped_layer(
+        &mut self,
+        layer: RectangularArray,
+        shape: LayerShape,
+    ) {
+        let layer_i = self.layers.len() as u8;
+        self.num_valid += (0..layer.len())
+            .flat_map(|index| layer.get_loc(layer_i, index))
+            .filter(|&loc| shape.contains(&layer, loc))
+            .count();
+        self.layer_offsets.push(self.num_fixed() + layer.len());
+        self.layers.push(layer);
+        self.shapes.push(shape);
+        Arc::make_mut(&mut self.unbounded).push(None);
+        self.adjacency = None;
+        self.locations = None;
+    }
+
+    // Add a layer without fixed bounds, whose coordinates may be
+    // negative.  Its pixels are stored in chunks of CHUNK_SIZE, which
+    // are allocated with allocate_chunk() or allocate_around() as
+    // growth reaches them.  Starts with the four chunks around the
+    // origin, along with those of any portals leading to or from it.
+    // The layer is listed in layers() with a size of 0x0.
+    pub fn add_unbounded_layer(&mut self) {
+        let layer_i = self.layers.len() as u8;
+        self.layer_offsets.push(self.num_fixed());
+        self.layers.push(RectangularArray {
+            width: 0,
+            height: 0,
+        });
+        self.shapes.push(LayerShape::Rectangle);
+        Arc::make_mut(&mut self.unbounded)
+            .push(Some(UnboundedLayer::default()));
+        self.adjacency = None;
+        self.locations = None;
+
+        self.allocate_around(PixelLoc {
+            layer: layer_i,
+            i: 0,
+            j: 0,
+        });
+        self.allocate_portal_chunks();
+    }
+
+    pub fn is_unbounded(&self, layer: u8) -> bool {
+        matches!(self.unbounded.get(layer as usize), Some(Some(_)))
+    }
+
+    // Allocate the chunk containing the pixel, if it is on an
+    // unbounded layer and not yet allocated.  Returns whether a chunk
+    // was added.  Indices of existing pixels are unchanged, and the
+    // new pixels are given the next CHUNK_AREA indices.
+    pub fn allocate_chunk(&mut self, loc: PixelLoc) -> bool {
+        let chunk = chunk_of(loc);
+        match self.unbounded.get(loc.layer as usize) {
+            Some(Some(layer)) if !layer.chunks.contains_key(&chunk) => {}
+            _ => return false,
+        }
+        let slot = self.chunk_order.len();
+        Arc::make_mut(&mut self.unbounded)[loc.layer as usize]
+            .as_mut()
+            .unwrap()
+            .chunks
+            .insert(chunk, slot);
+        Arc::make_mut(&mut self.chunk_order).push((loc.layer, chunk));
+        self.num_valid += CHUNK_AREA;
+        true
+    }
+
+    // Allocate the chunks holding the pixel and each of its
+    // neighbors, so that they can all be indexed.  Returns whether
+    // any chunk was added.
+    pub fn allocate_around(&mut self, loc: PixelLoc) -> bool {
+        if !self.is_unbounded(loc.layer) {
+            return false;
+        }
+        // With chunks at least 2 pixels across, the corners of the
+        // neighborhood cover every chunk that it touches.
+        [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter().fold(
+            false,
+            |added, &(di, dj)| {
+                let corner = PixelLoc {
+                    layer: loc.layer,
+                    i: loc.i + di,
+                    j: loc.j + dj,
+                };
+                self.allocate_chunk(corner) || added
+            },
+        )
+    }
+
+    // Take on the chunks allocated in another copy of the topology,
+    // which must have the same layers, while keeping this copy's
+    // portals.
+    pub(crate) fn copy_chunks_from(&mut self, other: &Topology) {
+        self.unbounded = Arc::clone(&other.unbounded);
+        self.chunk_order = Arc::clone(&other.chunk_order);
+        self.num_valid = other.num_valid;
+    }
+
+    pub fn has_unbounded_layers(&self) -> bool {
+        self.unbounded.iter().any(|layer| layer.is_some())
+    }
+
+    // Portals can only lead to pixels that have an index, so both
+    // ends of each portal on an unbounded layer are allocated along
+    // with the portal.
+    fn allocate_portal_chunks(&mut self) {
+        let portals = Arc::clone(&self.portals);
+        portals
+            .iter()
+            .flat_map(|(&a, &b)| vec![a, b].into_iter())
+            .for_each(|loc| {
+                self.allocate_chunk(loc);
+            });
+    }
+
+    // Number of indices used by the fixed-size layers, which come
+    // before the chunks of any unbounded layer.
+    fn num_fixed(&self) -> usize {
+        *self.layer_offsets.last().unwrap()
+    }
+
+    // Index of a pixel on an unbounded layer, if its chunk has been
+    // allocated.
+    fn get_chunk_index(&self, loc: PixelLoc) -> Option<usize> {
+        let layer = self.unbounded.get(loc.layer as usize)?.as_ref()?;
+        let slot = *layer.chunks.get(&chunk_of(loc))?;
+        let within = loc.j.rem_euclid(CHUNK_SIZE) * CHUNK_SIZE
+            + loc.i.rem_euclid(CHUNK_SIZE);
+        Some(self.num_fixed() + slot * CHUNK_AREA + within as usize)
+    }
+
+    fn get_chunk_loc(&self, index: usize) -> Option<PixelLoc> {
+        let offset = index.checked_sub(self.num_fixed())?;
+        let &(layer, (ci, cj)) = self.chunk_order.get(offset / CHUNK_AREA)?;
+        let within = (offset % CHUNK_AREA) as i32;
+        Some(PixelLoc {
+            layer,
+            i: ci * CHUNK_SIZE + within % CHUNK_SIZE,
+            j: cj * CHUNK_SIZE + within / CHUNK_SIZE,
+        })
+    }
+
+    pub fn layer_shape(&self, layer: u8) -> Option<LayerShape> {
+        self.shapes.get(layer as usize).copied()
+    }
+
+    pub fn set_portals(&mut self, portals: Arc<HashMap<PixelLoc, PixelLoc>>) {
+        self.portals = portals;
+        self.adjacency = None;
+        if self.has_unbounded_layers() {
+            self.allocate_portal_chunks();
+        }
+    }
+
+    pub fn iter_portals(&self) -> impl Iterator<Item = (&PixelLoc, &PixelLoc)> {
+        self.portals.iter()
+    }
+
+    // Destination of the portal leading from the point, if any.
+    pub fn portal_from(&self, loc: PixelLoc) -> Option<PixelLoc> {
+        if self.portals.is_empty() {
+            None
+        } else {
+            self.portals.get(&loc).copied()
+        }
+    }
+
+    // Precompute the neighbors of every pixel.  Uses 4 bytes per
+    // neighbor, about 36 bytes per pixel, but avoids recomputing
+    // bounds checks and portal lookups on every call to
+    // iter_adjacent_indexed().  Images too large to index with u32
+    // are left without a table.  Only the fixed-size layers are
+    // included, since the neighbors of a chunk of an unbounded layer
+    // change as the chunks around it are allocated.
+    pub fn build_adjacency_table(&mut self) {
+        self.adjacency = None;
+        let num_fixed = self.num_fixed();
+        let mut offsets = Vec::with_capacity(num_fixed + 1);
+        let mut neighbors = Vec::with_capacity(8 * num_fixed);
+        offsets.push(0);
+        for index in 0..num_fixed {
+            if let Some(loc) = self.get_loc(index) {
+                neighbors.extend(
+                    self.iter_adjacent(loc)
+                        .flat_map(|adj| self.get_index(adj))
+                        .map(|adj_index| adj_index as u32),
+                );
+            }
+            if neighbors.len() > u32::MAX as usize {
+                return;
+            }
+            offsets.push(neighbors.len() as u32);
+        }
+        self.adjacency = Some(Arc::new(AdjacencyTable { offsets, neighbors }));
+    }
+
+    // Precompute the location of every index.  Uses 16 bytes per
+    // pixel, but replaces the search over layers and the division in
+    // get_loc() with a single lookup.  In the "Topology" benchmarks,
+    // which look up the location and then the index of every pixel,
+    // the table was about 1.3x faster with 1 or 8 layers of 256x256,
+    // and 2x faster with 64 layers.  Chunks allocated after the table
+    // is built are looked up without it.
+    pub fn build_location_table(&mut self) {
+        self.locations = None;
+        let locations =
+            (0..self.len()).map(|index| self.get_loc(index)).collect();
+        self.locations = Some(Arc::new(locations));
+    }
+
+    // Every location of an unbounded layer is valid, even if its
+    // chunk has yet to be allocated.
     pub fn is_valid(&self, loc: PixelLoc) -> bool {
+        if self.is_unbounded(loc.layer) {
+            return true;
+        }
+        let layer_i = loc.layer as usize;
         self.layers
-            .get(loc.layer as usize)
-            .map(|layer| layer.is_valid(loc))
+            .get(layer_i)
+            .map(|layer| {
+                layer.is_valid(loc) && self.shapes[layer_i].contains(layer, loc)
+            })
             .unwrap_or(false)
     }
 
@@ -90,74 +412,310 @@ impl Topology {
     // for that layer).
     pub fn get_index(&self, loc: PixelLoc) -> Option<usize> {
         // Allow for a flat array of pixels to store all layers
+        let layer = loc.layer as usize;
         self.layers
-            .get(loc.layer as usize)
-            .map(|layer| {
-                layer.get_index(loc).map(|in_layer_index| {
-                    let offset = self.layers[0..(loc.layer as usize)]
-                        .iter()
-                        .map(|prev_layer| prev_layer.len())
-                        .sum::<usize>();
-                    in_layer_index + offset
+            .get(layer)
+            .filter(|layer_size| self.shapes[layer].contains(layer_size, loc))
+            .and_then(|layer_size| {
+                layer_size.get_index(loc).map(|in_layer_index| {
+                    in_layer_index + self.layer_offsets[layer]
                 })
             })
-            .flatten()
+            .or_else(|| self.get_chunk_index(loc))
     }
 
     pub fn iter_adjacent(
         &self,
         loc: PixelLoc,
     ) -> impl Iterator<Item = PixelLoc> + '_ {
+        let layer_i = loc.layer as usize;
         let within_layer = self
             .layers
-            .get(loc.layer as usize)
-            .map(|layer| layer.iter_adjacent(loc))
+            .get(layer_i)
+            .map(move |layer| match self.shapes[layer_i] {
+                _ if self.is_unbounded(loc.layer) => {
+                    Either::Left(Either::Left(surrounding(loc)))
+                }
+                LayerShape::Equirectangular => Either::Left(Either::Right(
+                    layer.iter_adjacent_equirectangular(loc),
+                )),
+                shape => Either::Right(
+                    layer
+                        .iter_adjacent(loc)
+                        .filter(move |&adj| shape.contains(layer, adj)),
+                ),
+            })
             .into_iter()
             .flatten();
-        let by_portal = self.portals.get(&loc).into_iter().map(|x| *x);
-        by_portal.chain(within_layer)
+        // Most images have no portals, so skip the hash lookup
+        // entirely in that case.
+        let by_portal = if self.portals.is_empty() {
+            None
+        } else {
+            self.portals.get(&loc).copied()
+        };
+        by_portal.into_iter().chain(within_layer)
+    }
+
+    // Index and location of each adjacent pixel.  Uses the adjacency
+    // table, if one has been built.
+    pub fn iter_adjacent_indexed(
+        &self,
+        loc: PixelLoc,
+    ) -> impl Iterator<Item = (usize, PixelLoc)> + '_ {
+        match (&self.adjacency, self.get_index(loc)) {
+            (Some(table), Some(index)) if index + 1 < table.offsets.len() => {
+                let start = table.offsets[index] as usize;
+                let end = table.offsets[index + 1] as usize;
+                Either::Left(table.neighbors[start..end].iter().map(
+                    move |&adj_index| {
+                        let adj_index = adj_index as usize;
+                        (adj_index, self.get_loc(adj_index).unwrap())
+                    },
+                ))
+            }
+            _ => Either::Right(self.iter_adjacent(loc).flat_map(move |adj| {
+                self.get_index(adj).map(|adj_index| (adj_index, adj))
+            })),
+        }
     }
 
+    // Index of each adjacent pixel.  Uses the adjacency table, if one
+    // has been built.
+    pub fn iter_adjacent_indices(
+        &self,
+        loc: PixelLoc,
+    ) -> impl Iterator<Item = usize> + '_ {
+        match (&self.adjacency, self.get_index(loc)) {
+            (Some(table), Some(index)) if index + 1 < table.offsets.len() => {
+                let start = table.offsets[index] as usize;
+                let end = table.offsets[index + 1] as usize;
+                Either::Left(
+                    table.neighbors[start..end]
+                        .iter()
+                        .map(|&adj_index| adj_index as usize),
+                )
+            }
+            _ => Either::Right(
+                self.iter_adjacent(loc)
+                    .flat_map(move |adj| self.get_index(adj)),
+            ),
+        }
+    }
+
+    // Range of indices of a fixed-size layer.  Empty for unbounded
+    // layers, whose pixels are listed by layer_indices().
     pub fn get_layer_bounds(&self, layer: u8) -> Option<Range<usize>> {
         let layer = layer as usize;
         if layer < self.layers.len() {
-            let offset = self.layers[0..layer]
-                .iter()
-                .map(|prev_layer| prev_layer.len())
-                .sum::<usize>();
-            let len = self.layers[layer].len();
-            Some(offset..(offset + len))
+            Some(self.layer_offsets[layer]..self.layer_offsets[layer + 1])
         } else {
             None
         }
     }
 
-    pub fn get_loc(&self, index: usize) -> Option<PixelLoc> {
-        self.layers
+    // Every index of the layer, including those of pixels outside the
+    // shape of a fixed-size layer.  The indices of an unbounded layer
+    // are in the order that its chunks were allocated.
+    pub fn layer_indices(&self, layer: u8) -> impl Iterator<Item = usize> + '_ {
+        let num_fixed = self.num_fixed();
+        let chunks = self
+            .chunk_order
             .iter()
             .enumerate()
-            .scan(0, |cumsum, (layer_i, layer)| {
-                let min_index = *cumsum;
-                *cumsum = min_index + layer.len();
-                let max_index = *cumsum;
-                Some((min_index, max_index, layer, layer_i))
-            })
-            .filter(|&(min_index, max_index, _layer, _layer_i)| {
-                index >= min_index && index < max_index
-            })
-            .next()
-            .map(|(min_index, _max_index, layer, layer_i)| {
-                layer.get_loc(layer_i as u8, index - min_index)
-            })
+            .filter(move |(_slot, (chunk_layer, _))| *chunk_layer == layer)
+            .flat_map(move |(slot, _)| {
+                let start = num_fixed + slot * CHUNK_AREA;
+                start..start + CHUNK_AREA
+            });
+        self.get_layer_bounds(layer)
+            .into_iter()
             .flatten()
+            .chain(chunks)
     }
 
+    // Smallest and largest corners of the allocated chunks of an
+    // unbounded layer.
+    pub fn allocated_bounds(&self, layer: u8) -> Option<(PixelLoc, PixelLoc)> {
+        let chunks = &self.unbounded.get(layer as usize)?.as_ref()?.chunks;
+        let imin = chunks.keys().map(|&(ci, _)| ci).min()?;
+        let imax = chunks.keys().map(|&(ci, _)| ci).max()?;
+        let jmin = chunks.keys().map(|&(_, cj)| cj).min()?;
+        let jmax = chunks.keys().map(|&(_, cj)| cj).max()?;
+        Some((
+            PixelLoc {
+                layer,
+                i: imin * CHUNK_SIZE,
+                j: jmin * CHUNK_SIZE,
+            },
+            PixelLoc {
+                layer,
+                i: (imax + 1) * CHUNK_SIZE - 1,
+                j: (jmax + 1) * CHUNK_SIZE - 1,
+            },
+        ))
+    }
+
+    pub fn get_loc(&self, index: usize) -> Option<PixelLoc> {
+        if let Some(loc) =
+            self.locations.as_ref().and_then(|table| table.get(index))
+        {
+            return *loc;
+        }
+        if index >= self.num_fixed() {
+            return self.get_chunk_loc(index);
+        }
+        // Number of layers that start at or before the index.  Empty
+        // layers share an offset with the next layer, so the last of
+        // these is the one containing the index.
+        let layer_i = self.layer_offsets.partition_point(|&o| o <= index) - 1;
+        let layer = &self.layers[layer_i];
+        layer
+            .get_loc(layer_i as u8, index - self.layer_offsets[layer_i])
+            .filter(|&loc| self.shapes[layer_i].contains(layer, loc))
+    }
+
+    // Number of indices, including those of pixels outside the shape
+    // of their layer, and those of every allocated chunk.
     pub fn len(&self) -> usize {
-        self.layers.iter().map(|layer| layer.len()).sum()
+        self.num_fixed() + self.chunk_order.len() * CHUNK_AREA
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Size of each layer, with 0x0 for unbounded layers.  Layers can
+    // only be added through add_layer(), which keeps the cached
+    // offsets and tables in sync.
+    pub fn layers(&self) -> &[RectangularArray] {
+        &self.layers
+    }
+
+    // Number of pixels that can be filled, counting only the allocated
+    // chunks of unbounded layers.
+    pub fn num_valid(&self) -> usize {
+        self.num_valid
+    }
+
+    // Number of steps in the shortest path from a to b, moving between
+    // adjacent pixels or through portals, without entering any
+    // forbidden pixel.  Returns None if b cannot be reached.  Use
+    // Region::new(&[]) if no pixels are forbidden.
+    pub fn shortest_path_len(
+        &self,
+        a: PixelLoc,
+        b: PixelLoc,
+        forbidden: &Region,
+    ) -> Option<usize> {
+        let target = self.get_index(b)?;
+        self.breadth_first_search(&[a], forbidden, Some(target))[target]
+            .map(|(dist, _seed)| dist)
+    }
+
+    // Number of steps from the nearest seed to each pixel, indexed as
+    // in get_index(), following the same rules as shortest_path_len().
+    // Unreachable pixels are None.
+    pub fn distance_field_from(
+        &self,
+        seeds: &[PixelLoc],
+        forbidden: &Region,
+    ) -> Vec<Option<usize>> {
+        self.breadth_first_search(seeds, forbidden, None)
+            .into_iter()
+            .map(|res| res.map(|(dist, _seed)| dist))
+            .collect()
+    }
+
+    // Index in seeds of the nearest seed to each pixel, indexed as in
+    // get_index(), measuring distance as in shortest_path_len().  This
+    // partitions the topology into Voronoi cells, with ties going to
+    // the earlier seed.  Unreachable pixels are None.
+    pub fn voronoi_cells(
+        &self,
+        seeds: &[PixelLoc],
+        forbidden: &Region,
+    ) -> Vec<Option<usize>> {
+        self.breadth_first_search(seeds, forbidden, None)
+            .into_iter()
+            .map(|res| res.map(|(_dist, seed)| seed))
+            .collect()
     }
+
+    // Distance to each pixel, along with the index of the seed it was
+    // reached from.
+    fn breadth_first_search(
+        &self,
+        seeds: &[PixelLoc],
+        forbidden: &Region,
+        stop_at: Option<usize>,
+    ) -> Vec<Option<(usize, usize)>> {
+        let mut results = vec![None; self.len()];
+        let mut queue = VecDeque::new();
+        seeds
+            .iter()
+            .enumerate()
+            .filter(|(_seed, &loc)| !forbidden.contains(loc))
+            .flat_map(|(seed, &loc)| {
+                self.get_index(loc).map(|index| (index, seed, loc))
+            })
+            .for_each(|(index, seed, loc)| {
+                if results[index].is_none() {
+                    results[index] = Some((0, seed));
+                    queue.push_back((loc, 0, seed));
+                }
+            });
+
+        while let Some((loc, dist, seed)) = queue.pop_front() {
+            if stop_at.is_some_and(|target| results[target].is_some()) {
+                break;
+            }
+            self.iter_adjacent_indexed(loc).for_each(|(index, adj)| {
+                if results[index].is_none() && !forbidden.contains(adj) {
+                    results[index] = Some((dist + 1, seed));
+                    queue.push_back((adj, dist + 1, seed));
+                }
+            });
+        }
+
+        results
+    }
+}
+
+// Which pixels of a layer's rectangle are part of the layer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LayerShape {
+    #[default]
+    Rectangle,
+
+    // The ellipse inscribed in the rectangle.  Pixels are included if
+    // their center is within the ellipse.
+    Ellipse,
+
+    // The full rectangle, as an equirectangular projection of a
+    // sphere, for 360 degree panoramas and skyboxes.  The left and
+    // right edges are adjacent, as are pixels across each pole.  Rows
+    // near the poles cover less of the sphere, so pixels there are
+    // adjacent to pixels farther along their own and neighboring
+    // rows, roughly 1/cos(latitude) pixels in each direction.
+    Equirectangular,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl LayerShape {
+    pub fn contains(&self, layer: &RectangularArray, loc: PixelLoc) -> bool {
+        match self {
+            LayerShape::Rectangle | LayerShape::Equirectangular => true,
+            LayerShape::Ellipse => {
+                let x = (2.0 * (loc.i as f64) + 1.0) / (layer.width as f64);
+                let y = (2.0 * (loc.j as f64) + 1.0) / (layer.height as f64);
+                (x - 1.0).powi(2) + (y - 1.0).powi(2) <= 1.0
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct RectangularArray {
     pub width: u32,
     pub height: u32,
@@ -183,15 +741,45 @@ impl RectangularArray {
         &self,
         loc: PixelLoc,
     ) -> impl Iterator<Item = PixelLoc> + '_ {
+        surrounding(loc).filter(move |&loc| self.is_valid(loc))
+    }
+
+    // Adjacent pixels for LayerShape::Equirectangular.  Pixels in
+    // adjacent rows are within the larger of the two rows' reach.
+    // Past the top or bottom row, the neighbors are in the same row,
+    // half the width away.
+    pub fn iter_adjacent_equirectangular(
+        &self,
+        loc: PixelLoc,
+    ) -> impl Iterator<Item = PixelLoc> + '_ {
+        let width = self.width as i32;
+        let height = self.height as i32;
+        let reach = self.equirectangular_reach(loc.j);
         (-1..=1)
-            .cartesian_product(-1..=1)
-            .filter(|&(di, dj)| (di != 0) || (dj != 0))
-            .map(move |(di, dj)| PixelLoc {
-                layer: loc.layer,
-                i: loc.i + di,
-                j: loc.j + dj,
+            .flat_map(move |dj| {
+                let j = loc.j + dj;
+                let (j, center, reach) = if j < 0 || j >= height {
+                    (loc.j, loc.i + width / 2, reach)
+                } else {
+                    (j, loc.i, reach.max(self.equirectangular_reach(j)))
+                };
+                (-reach..=reach).map(move |di| PixelLoc {
+                    layer: loc.layer,
+                    i: (center + di).rem_euclid(width),
+                    j,
+                })
             })
-            .filter(move |&loc| self.is_valid(loc))
+            .filter(move |&adj| adj != loc)
+            .unique()
+    }
+
+    // Number of pixels in each direction along a row that are
+    // adjacent, scaling with the inverse of the row's circumference.
+    fn equirectangular_reach(&self, j: i32) -> i32 {
+        let latitude = std::f64::consts::PI
+            * ((j as f64 + 0.5) / (self.height as f64) - 0.5);
+        let reach = (1.0 / latitude.cos()).round() as i32;
+        reach.min(self.width as i32 / 2).max(1)
     }
 
     pub fn get_loc(&self, layer: u8, index: usize) -> Option<PixelLoc> {
@@ -209,6 +797,10 @@ impl RectangularArray {
     pub fn len(&self) -> usize {
         (self.width * self.height) as usize
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 #[cfg(test)]
@@ -394,8 +986,8 @@ mod test {
 
     #[test]
     fn test_topology_index_lookup() -> Result<(), Error> {
-        let topology = Topology {
-            layers: vec![
+        let topology = Topology::new(
+            vec![
                 RectangularArray {
                     width: 10,
                     height: 10,
@@ -405,8 +997,8 @@ mod test {
                     height: 5,
                 },
             ],
-            portals: HashMap::new(),
-        };
+            Arc::new(HashMap::new()),
+        );
 
         assert_eq!(
             topology.get_loc(0),
@@ -428,4 +1020,386 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_topology_empty_layer_round_trip() {
+        let topology = Topology::new(
+            vec![
+                RectangularArray {
+                    width: 10,
+                    height: 10,
+                },
+                RectangularArray {
+                    width: 0,
+                    height: 3,
+                },
+                RectangularArray {
+                    width: 5,
+                    height: 5,
+                },
+            ],
+            Arc::new(HashMap::new()),
+        );
+
+        assert_eq!(
+            topology.get_loc(100),
+            Some(PixelLoc {
+                layer: 2,
+                i: 0,
+                j: 0
+            })
+        );
+        assert_eq!(topology.get_loc(125), None);
+        assert_eq!(topology.len(), 125);
+
+        (0..topology.len()).for_each(|index| {
+            let loc = topology.get_loc(index).unwrap();
+            assert_eq!(topology.get_index(loc), Some(index));
+        });
+        assert_eq!(topology.get_layer_bounds(2), Some(100..125));
+    }
+
+    #[test]
+    fn test_unbounded_layer() {
+        let mut topology = Topology::new(
+            vec![RectangularArray {
+                width: 5,
+                height: 5,
+            }],
+            Arc::new(HashMap::new()),
+        );
+        topology.add_unbounded_layer();
+        assert!(topology.is_unbounded(1));
+        assert!(!topology.is_unbounded(0));
+        assert_eq!(topology.len(), 25 + 4 * CHUNK_AREA);
+        assert_eq!(topology.num_valid(), topology.len());
+
+        let loc = |i, j| PixelLoc { layer: 1, i, j };
+        let round_trip = |topology: &Topology, loc: PixelLoc| {
+            topology
+                .get_index(loc)
+                .and_then(|index| topology.get_loc(index))
+        };
+
+        // Starts with the chunks around the origin.
+        for &(i, j) in [(-1, -1), (0, 0), (-64, 63), (63, -64)].iter() {
+            assert_eq!(round_trip(&topology, loc(i, j)), Some(loc(i, j)));
+        }
+        assert_eq!(topology.get_index(loc(64, 0)), None);
+        assert!(topology.is_valid(loc(64, -1000)));
+        assert_eq!(topology.iter_adjacent_indices(loc(-1, -1)).count(), 8);
+        assert_eq!(topology.iter_adjacent_indices(loc(63, 0)).count(), 5);
+        assert_eq!(
+            topology.allocated_bounds(1),
+            Some((loc(-64, -64), loc(63, 63)))
+        );
+
+        // Tables only cover the pixels when they were built, and
+        // existing indices don't change as chunks are added.
+        topology.build_adjacency_table();
+        topology.build_location_table();
+        let index = topology.get_index(loc(63, 0));
+        assert!(topology.allocate_around(loc(63, 0)));
+        assert!(!topology.allocate_around(loc(63, 0)));
+        assert_eq!(topology.len(), 25 + 6 * CHUNK_AREA);
+        assert_eq!(topology.get_index(loc(63, 0)), index);
+        assert_eq!(round_trip(&topology, loc(64, 0)), Some(loc(64, 0)));
+        assert_eq!(topology.iter_adjacent_indices(loc(63, 0)).count(), 8);
+        assert_eq!(topology.layer_indices(1).count(), 6 * CHUNK_AREA);
+
+        // Portals allocate the chunks at either end.
+        let far = loc(-1000, 500);
+        let near = PixelLoc {
+            layer: 0,
+            i: 2,
+            j: 2,
+        };
+        topology.set_portals(Arc::new(vec![(near, far)].into_iter().collect()));
+        topology.build_adjacency_table();
+        assert_eq!(round_trip(&topology, far), Some(far));
+        let far_index = topology.get_index(far).unwrap();
+        assert!(topology.iter_adjacent_indices(near).any(|i| i == far_index));
+
+        // Fixed-size layers added later come before the chunks.
+        topology.add_layer(RectangularArray {
+            width: 2,
+            height: 2,
+        });
+        assert_eq!(topology.get_layer_bounds(2), Some(25..29));
+        assert_eq!(round_trip(&topology, far), Some(far));
+        assert_eq!(topology.get_index(loc(0, 0)).unwrap() - 29, 3 * CHUNK_AREA);
+    }
+
+    #[test]
+    fn test_elliptical_layer() {
+        let mut topology = Topology::new(Vec::new(), Arc::new(HashMap::new()));
+        topology.add_shaped_layer(
+            RectangularArray {
+                width: 10,
+                height: 6,
+            },
+            LayerShape::Ellipse,
+        );
+        topology.add_layer(RectangularArray {
+            width: 2,
+            height: 2,
+        });
+        let loc = |layer, i, j| PixelLoc { layer, i, j };
+
+        // Corners are outside, but still have an index.
+        assert!(topology.is_valid(loc(0, 5, 3)));
+        assert!(topology.is_valid(loc(0, 0, 3)));
+        assert!(!topology.is_valid(loc(0, 0, 0)));
+        assert!(!topology.is_valid(loc(0, 9, 5)));
+        assert_eq!(topology.get_index(loc(0, 0, 0)), None);
+        assert_eq!(topology.get_loc(0), None);
+        assert_eq!(topology.get_layer_bounds(1), Some(60..64));
+
+        let valid = (0..topology.len())
+            .flat_map(|index| topology.get_loc(index).map(|loc| (index, loc)))
+            .collect::<Vec<_>>();
+        assert_eq!(valid.len(), topology.num_valid());
+        assert!(topology.num_valid() < 64);
+        valid.iter().for_each(|&(index, loc)| {
+            assert_eq!(topology.get_index(loc), Some(index));
+            assert!(topology
+                .iter_adjacent(loc)
+                .all(|adj| topology.is_valid(adj)));
+        });
+
+        // Symmetric about both axes.
+        valid.iter().for_each(|&(_, loc)| {
+            if loc.layer == 0 {
+                assert!(topology.is_valid(PixelLoc {
+                    i: 9 - loc.i,
+                    ..loc
+                }));
+                assert!(topology.is_valid(PixelLoc {
+                    j: 5 - loc.j,
+                    ..loc
+                }));
+            }
+        });
+    }
+
+    #[test]
+    fn test_equirectangular_layer() {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let topology = {
+            let mut topology = Topology::new(Vec::new(), Default::default());
+            topology.add_shaped_layer(
+                RectangularArray {
+                    width: 32,
+                    height: 16,
+                },
+                LayerShape::Equirectangular,
+            );
+            topology
+        };
+        let adjacent = |a| topology.iter_adjacent(a).collect::<Vec<_>>();
+
+        // Near the equator, the eight neighbors, wrapping around the
+        // left and right edges.
+        let near_equator = adjacent(loc(0, 7));
+        assert_eq!(near_equator.len(), 8);
+        assert!(near_equator.contains(&loc(31, 8)));
+
+        // Near the poles, adjacent to more of the row and the rows
+        // beside it.
+        let near_pole = adjacent(loc(0, 1));
+        assert!(near_pole.contains(&loc(3, 1)));
+        assert!(!near_pole.contains(&loc(4, 1)));
+        assert!(near_pole.contains(&loc(22, 0)));
+        assert!(!near_pole.contains(&loc(21, 0)));
+
+        // Across the pole, to the other side of the sphere.
+        assert!(adjacent(loc(0, 0)).contains(&loc(16, 0)));
+        assert!(adjacent(loc(0, 15)).contains(&loc(16, 15)));
+
+        // Adjacency is symmetric.
+        (0..topology.len())
+            .flat_map(|index| topology.get_loc(index))
+            .for_each(|a| {
+                adjacent(a).into_iter().for_each(|b| {
+                    assert!(adjacent(b).contains(&a), "{:?} {:?}", a, b)
+                })
+            });
+    }
+
+    #[test]
+    fn test_location_table() {
+        let mut topology = Topology::new(Vec::new(), Arc::new(HashMap::new()));
+        topology.add_shaped_layer(
+            RectangularArray {
+                width: 10,
+                height: 6,
+            },
+            LayerShape::Ellipse,
+        );
+        topology.add_layer(RectangularArray {
+            width: 0,
+            height: 3,
+        });
+        topology.add_layer(RectangularArray {
+            width: 4,
+            height: 4,
+        });
+
+        let without_table = (0..topology.len() + 2)
+            .map(|index| topology.get_loc(index))
+            .collect::<Vec<_>>();
+        topology.build_location_table();
+        let with_table = (0..topology.len() + 2)
+            .map(|index| topology.get_loc(index))
+            .collect::<Vec<_>>();
+        assert_eq!(with_table, without_table);
+
+        // Adding a layer discards the table.
+        topology.add_layer(RectangularArray {
+            width: 2,
+            height: 2,
+        });
+        assert_eq!(
+            topology.get_loc(76),
+            Some(PixelLoc {
+                layer: 3,
+                i: 0,
+                j: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_adjacency_table() -> Result<(), Error> {
+        let mut portals = HashMap::new();
+        let a = PixelLoc {
+            layer: 0,
+            i: 0,
+            j: 0,
+        };
+        let b = PixelLoc {
+            layer: 1,
+            i: 2,
+            j: 2,
+        };
+        portals.insert(a, b);
+        portals.insert(b, a);
+
+        let mut topology = Topology::new(
+            vec![
+                RectangularArray {
+                    width: 4,
+                    height: 3,
+                },
+                RectangularArray {
+                    width: 5,
+                    height: 5,
+                },
+            ],
+            Arc::new(portals),
+        );
+
+        let without_table = (0..topology.len())
+            .map(|index| {
+                let loc = topology.get_loc(index).unwrap();
+                topology.iter_adjacent_indexed(loc).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        topology.build_adjacency_table();
+        (0..topology.len()).for_each(|index| {
+            let loc = topology.get_loc(index).unwrap();
+            let with_table =
+                topology.iter_adjacent_indexed(loc).collect::<Vec<_>>();
+            assert_eq!(with_table, without_table[index]);
+            assert!(topology
+                .iter_adjacent_indices(loc)
+                .eq(with_table.iter().map(|&(adj_index, _)| adj_index)));
+        });
+
+        let b_index = topology.get_index(b).unwrap();
+        assert!(topology.iter_adjacent_indices(a).any(|i| i == b_index));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shortest_path() -> Result<(), Error> {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let layers = vec![RectangularArray {
+            width: 5,
+            height: 5,
+        }];
+        let topology = Topology::new(layers.clone(), Arc::new(HashMap::new()));
+
+        let nothing_forbidden = Region::new(&[]);
+        assert_eq!(
+            topology.shortest_path_len(
+                loc(0, 0),
+                loc(4, 0),
+                &nothing_forbidden
+            ),
+            Some(4)
+        );
+
+        // A wall with a gap at the bottom.
+        let wall = Region::from_points(&[(5, 5)], (0..4).map(|j| loc(2, j)));
+        assert_eq!(
+            topology.shortest_path_len(loc(0, 0), loc(4, 0), &wall),
+            Some(8)
+        );
+
+        let closed_wall =
+            Region::from_points(&[(5, 5)], (0..5).map(|j| loc(2, j)));
+        assert_eq!(
+            topology.shortest_path_len(loc(0, 0), loc(4, 0), &closed_wall),
+            None
+        );
+
+        // A portal through the wall.
+        let mut portals = HashMap::new();
+        portals.insert(loc(0, 0), loc(4, 0));
+        portals.insert(loc(4, 0), loc(0, 0));
+        let topology = Topology::new(layers, Arc::new(portals));
+        assert_eq!(
+            topology.shortest_path_len(loc(0, 0), loc(4, 0), &closed_wall),
+            Some(1)
+        );
+
+        let field =
+            topology.distance_field_from(&[loc(0, 4)], &nothing_forbidden);
+        assert_eq!(field[topology.get_index(loc(0, 4)).unwrap()], Some(0));
+        assert_eq!(field[topology.get_index(loc(4, 4)).unwrap()], Some(4));
+        assert_eq!(field[topology.get_index(loc(4, 0)).unwrap()], Some(4));
+        assert!(field.iter().all(|dist| dist.is_some()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_voronoi_cells() {
+        let loc = |i, j| PixelLoc { layer: 0, i, j };
+        let layers = vec![RectangularArray {
+            width: 5,
+            height: 5,
+        }];
+        let topology = Topology::new(layers, Arc::new(HashMap::new()));
+        let seeds = [loc(0, 0), loc(4, 4)];
+        let cell_at = |cells: &[Option<usize>], i, j| {
+            cells[topology.get_index(loc(i, j)).unwrap()]
+        };
+
+        let cells = topology.voronoi_cells(&seeds, &Region::new(&[]));
+        assert_eq!(cell_at(&cells, 1, 1), Some(0));
+        assert_eq!(cell_at(&cells, 3, 3), Some(1));
+        // Equidistant from both, so belongs to the first.
+        assert_eq!(cell_at(&cells, 4, 0), Some(0));
+
+        // Distances go around walls, rather than through them.
+        assert_eq!(cell_at(&cells, 0, 2), Some(0));
+        let wall = Region::from_points(&[(5, 5)], (0..4).map(|i| loc(i, 1)));
+        let cells = topology.voronoi_cells(&seeds, &wall);
+        assert_eq!(cell_at(&cells, 0, 2), Some(1));
+        assert_eq!(cell_at(&cells, 0, 1), None);
+    }
 }
@@ -0,0 +1,196 @@
+// Renders small seeded images and compares them against the PNGs in
+// tests/golden, so that changes to the KD-tree, point tracker, or
+// topology can't silently change the output.  After an intended
+// change to the output, regenerate the golden images with
+//
+//     OMNICOLOR_BLESS=1 cargo test --test golden
+//
+// and check the new images by eye before committing them.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use omnicolor_rust::palettes::SphericalPalette;
+use omnicolor_rust::{
+    Error, GrowthImage, GrowthImageBuilder, PixelLoc, SaveImageType, RGB,
+};
+
+// Largest mean squared error over the RGB channels, in [0, 65025],
+// that is still considered a match.  Allows for small differences in
+// floating point between platforms, while any change to the order in
+// which pixels are filled changes most of the image.
+const MAX_MSE: f64 = 4.0;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+fn read_rgba(path: &Path) -> Result<(u32, u32, Vec<u8>), Error> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+    assert_eq!(info.color_type, png::ColorType::RGBA);
+    Ok((info.width, info.height, buf))
+}
+
+// Unfilled pixels are compared as black.
+fn mean_squared_error(a: &[u8], b: &[u8]) -> f64 {
+    let rgb = |p: &[u8]| {
+        let alpha = (p[3] > 0) as u8 as f64;
+        [
+            p[0] as f64 * alpha,
+            p[1] as f64 * alpha,
+            p[2] as f64 * alpha,
+        ]
+    };
+    let num_pixels = (a.len() / 4).max(1);
+    let total = a
+        .chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .map(|(pa, pb)| {
+            let (pa, pb) = (rgb(pa), rgb(pb));
+            (0..3).map(|c| (pa[c] - pb[c]).powi(2)).sum::<f64>()
+        })
+        .sum::<f64>();
+    total / (3 * num_pixels) as f64
+}
+
+fn check_golden(
+    name: &str,
+    builder: &GrowthImageBuilder,
+    num_layers: u8,
+) -> Result<(), Error> {
+    let mut image = builder.build()?;
+    image.fill_until_done();
+
+    (0..num_layers).try_for_each(|layer| -> Result<(), Error> {
+        let filename = format!("{}-layer{}.png", name, layer);
+        let golden = golden_dir().join(&filename);
+        if std::env::var_os("OMNICOLOR_BLESS").is_some() {
+            std::fs::create_dir_all(golden_dir())?;
+            write_layer(&image, golden, layer);
+            return Ok(());
+        }
+
+        let rendered = std::env::temp_dir().join(format!(
+            "omnicolor-golden-{}-{}",
+            std::process::id(),
+            filename
+        ));
+        write_layer(&image, rendered.clone(), layer);
+        let (width, height, actual) = read_rgba(&rendered)?;
+        std::fs::remove_file(&rendered)?;
+
+        let (golden_width, golden_height, expected) = read_rgba(&golden)
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Missing golden image {}, \
+                     run with OMNICOLOR_BLESS=1 to create it",
+                    golden.display()
+                )
+            });
+        assert_eq!((width, height), (golden_width, golden_height));
+
+        let mse = mean_squared_error(&actual, &expected);
+        assert!(
+            mse <= MAX_MSE,
+            "{} differs from the golden image, with MSE {}",
+            filename,
+            mse
+        );
+        Ok(())
+    })
+}
+
+fn write_layer(image: &GrowthImage, filename: PathBuf, layer: u8) {
+    image.write_image(filename, SaveImageType::Generated, layer);
+}
+
+#[test]
+fn golden_flat() -> Result<(), Error> {
+    let mut builder = GrowthImageBuilder::new();
+    builder.add_layer(32, 24).seed(1).epsilon(1.0);
+    builder.new_stage();
+    check_golden("flat", &builder, 1)
+}
+
+#[test]
+fn golden_multi_stage() -> Result<(), Error> {
+    let mut builder = GrowthImageBuilder::new();
+    builder.add_layer(32, 24).seed(2);
+    builder
+        .new_stage()
+        .palette(SphericalPalette {
+            central_color: RGB {
+                vals: [220, 60, 40],
+            },
+            color_radius: 40.0,
+        })
+        .seed_points(vec![PixelLoc {
+            layer: 0,
+            i: 8,
+            j: 12,
+        }])
+        .max_iter(300);
+    builder
+        .new_stage()
+        .palette(SphericalPalette {
+            central_color: RGB {
+                vals: [40, 80, 220],
+            },
+            color_radius: 60.0,
+        })
+        .seed_points(vec![PixelLoc {
+            layer: 0,
+            i: 28,
+            j: 4,
+        }]);
+    check_golden("multi-stage", &builder, 1)
+}
+
+#[test]
+fn golden_multi_layer_portals() -> Result<(), Error> {
+    // A wall down the middle of the main layer, with a bridge layer
+    // connecting the bottom corners on either side of it.
+    let wall = (0..24).map(|j| PixelLoc { layer: 0, i: 16, j }).collect();
+    let portals = (0..4)
+        .flat_map(|j| {
+            vec![
+                (
+                    PixelLoc {
+                        layer: 0,
+                        i: 0,
+                        j: 23 - j,
+                    },
+                    PixelLoc { layer: 1, i: 0, j },
+                ),
+                (
+                    PixelLoc {
+                        layer: 0,
+                        i: 31,
+                        j: 23 - j,
+                    },
+                    PixelLoc { layer: 1, i: 7, j },
+                ),
+            ]
+            .into_iter()
+        })
+        .collect();
+
+    let mut builder = GrowthImageBuilder::new();
+    builder.add_layer(32, 24).add_layer(8, 4).seed(3);
+    builder
+        .new_stage()
+        .seed_points(vec![PixelLoc {
+            layer: 0,
+            i: 4,
+            j: 4,
+        }])
+        .forbidden_points(wall)
+        .connected_points(portals);
+    check_golden("multi-layer-portals", &builder, 2)
+}